@@ -5,7 +5,12 @@ EXPECTED += [
     "Test file0 OK!",
     "Test fstat OK!",
     "Test link OK!",
-    "Test mass open/unlink OK!"
+    "Test mass open/unlink OK!",
+    "Test trap context OK!",
+]
+
+NOT_EXPECTED += [
+    "Should cause error, Test trap context fail!",
 ]
 
 EXPECTED = list(set(EXPECTED) - set([
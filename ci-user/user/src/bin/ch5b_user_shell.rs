@@ -31,7 +31,7 @@ pub fn main() -> i32 {
                     let pid = fork();
                     if pid == 0 {
                         // child process
-                        if exec(line.as_str(), &[0 as *const u8]) == -1 {
+                        if exec(line.as_str(), &[0 as *const u8]) < 0 {
                             println!("Error when executing!");
                             return -4;
                         }
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::errno::{EEXIST, EINVAL};
+use user_lib::{exit, fork, mmap, munmap, waitpid};
+
+/*
+理想结果：输出 Test trap context OK!
+*/
+
+const PAGE_SIZE: usize = 4096;
+const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+
+/// 在子进程中执行 `f`，子进程应当因访问用户不可访问的页而被内核杀死
+fn expect_fault(f: fn()) {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        println!("Should cause error, Test trap context fail!");
+        exit(0);
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -2);
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    expect_fault(|| unsafe {
+        (TRAP_CONTEXT as *const usize).read_volatile();
+    });
+    expect_fault(|| unsafe { (TRAP_CONTEXT as *mut usize).write_volatile(0) });
+    expect_fault(|| unsafe {
+        (TRAMPOLINE as *const usize).read_volatile();
+    });
+    expect_fault(|| unsafe { (TRAMPOLINE as *mut usize).write_volatile(0) });
+    // 也不能通过系统调用改变这两页的映射
+    assert_eq!(munmap(TRAP_CONTEXT, PAGE_SIZE), -EINVAL);
+    assert_eq!(mmap(TRAP_CONTEXT, PAGE_SIZE, 3), -EEXIST);
+    println!("Test trap context OK!");
+    0
+}
@@ -5,6 +5,7 @@
 
 #[macro_use]
 pub mod console;
+pub mod errno;
 mod lang_items;
 mod syscall;
 
@@ -171,6 +172,8 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// bits holding the file type; the rest are permission bits
+        const TYPE_MASK = 0o170000;
     }
 }
 
@@ -1,9 +1,16 @@
+mod manifest;
+
 use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+use easy_fs::{BlockDevice, EasyFileSystem, Inode};
+use manifest::{Entry, EntryKind};
+use std::collections::hash_map::DefaultHasher;
 use std::fs::{read_dir, File, OpenOptions};
+use std::hash::{Hash, Hasher};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Use a block size of 512 bytes
 const BLOCK_SZ: usize = 512;
@@ -29,12 +36,25 @@ impl BlockDevice for BlockFile {
     }
 }
 
+/// Seconds since the Unix epoch of a host time
+fn unix_secs(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as u32)
+}
+
+/// Stamp inodes with the host's wall clock
+fn host_clock() -> u32 {
+    unix_secs(SystemTime::now())
+}
+
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
+    if let Err(err) = run() {
+        eprintln!("easy-fs-fuse: {}", err);
+        std::process::exit(1);
+    }
 }
 
-/// Pack a directory into a easy-fs disk image
-fn easy_fs_pack() -> std::io::Result<()> {
+fn run() -> Result<(), String> {
     let matches = App::new("EasyFileSystem packer")
         .arg(
             Arg::with_name("source")
@@ -48,49 +68,192 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .short("t")
                 .long("target")
                 .takes_value(true)
+                .required(true)
                 .help("Executable target dir(with backslash)"),
         )
+        .arg(
+            Arg::with_name("manifest")
+                .short("m")
+                .long("manifest")
+                .takes_value(true)
+                .help("Pack the entries of a manifest instead of the apps in the source dir"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("Check an existing fs.img against the source instead of packing it"),
+        )
         .get_matches();
-    let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
-    println!("src_path = {}\ntarget_path = {}", src_path, target_path);
-    let block_file = Arc::new(BlockFile(Mutex::new({
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len((BLOCK_NUM * BLOCK_SZ) as u64).unwrap();
-        f
-    })));
-    let efs = EasyFileSystem::create(block_file.clone(), BLOCK_NUM as u32, 1);
+    let entries = match (matches.value_of("manifest"), matches.value_of("source")) {
+        (Some(manifest), _) => {
+            let text = std::fs::read_to_string(manifest)
+                .map_err(|err| format!("{}: {}", manifest, err))?;
+            let base = Path::new(manifest)
+                .parent()
+                .unwrap_or_else(|| Path::new("."));
+            manifest::parse(&text, base).map_err(|err| format!("{}: {}", manifest, err))?
+        }
+        (None, Some(src_path)) => {
+            println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+            app_entries(src_path, target_path).map_err(|err| err.to_string())?
+        }
+        (None, None) => return Err("either --source or --manifest is required".to_string()),
+    };
+    let image = format!("{}{}", target_path, "fs.img");
+    if matches.is_present("verify") {
+        let block_file = open_image(&image, false).map_err(|err| format!("{}: {}", image, err))?;
+        let efs = EasyFileSystem::open(block_file);
+        let mismatches = verify(&EasyFileSystem::root_inode(&efs), &entries)?;
+        efs.lock().unmount();
+        for mismatch in &mismatches {
+            println!("{}", mismatch);
+        }
+        if !mismatches.is_empty() {
+            return Err(format!("{} mismatches in {}", mismatches.len(), image));
+        }
+        println!("{}: {} entries verified", image, entries.len());
+        return Ok(());
+    }
+    let block_file = open_image(&image, true).map_err(|err| format!("{}: {}", image, err))?;
+    let efs = EasyFileSystem::create(block_file, BLOCK_NUM as u32, 1);
+    efs.lock().set_clock(host_clock);
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
-    let apps: Vec<_> = read_dir(src_path)
-        .unwrap()
-        .into_iter()
-        .map(|dir_entry| {
-            let mut name_with_ext = dir_entry.unwrap().file_name().into_string().unwrap();
-            name_with_ext.drain(name_with_ext.find('.').unwrap()..name_with_ext.len());
-            name_with_ext
-        })
-        .collect();
-    for app in apps {
+    pack(&root_inode, &entries)?;
+    // list apps
+    for app in root_inode.ls() {
+        println!("{}", app);
+    }
+    efs.lock().unmount();
+    Ok(())
+}
+
+/// Open the image file, creating it in its full size when `create` is set
+fn open_image(path: &str, create: bool) -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(create)
+        .open(path)?;
+    if create {
+        f.set_len((BLOCK_NUM * BLOCK_SZ) as u64)?;
+    }
+    Ok(Arc::new(BlockFile(Mutex::new(f))))
+}
+
+/// The apps of the source dir, named without extension and taken from the target dir
+fn app_entries(src_path: &str, target_path: &str) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for dir_entry in read_dir(src_path)? {
+        let mut name_with_ext = dir_entry?.file_name().into_string().unwrap();
+        name_with_ext.drain(name_with_ext.find('.').unwrap()..name_with_ext.len());
+        let source = PathBuf::from(format!("{}{}", target_path, name_with_ext));
+        entries.push(Entry::file(&name_with_ext, source, 0o755));
+    }
+    Ok(entries)
+}
+
+/// Create every entry under `root_inode`
+fn pack(root_inode: &Inode, entries: &[Entry]) -> Result<(), String> {
+    for entry in entries {
+        let source = match &entry.kind {
+            EntryKind::Dir => {
+                if (entry.uid, entry.gid) != (0, 0) {
+                    return Err("the root directory is always owned by root".to_string());
+                }
+                root_inode.set_mode(entry.mode);
+                continue;
+            }
+            EntryKind::File(source) => source,
+        };
         // load app data (elf) from host file system
-        let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
+        let mut host_file =
+            File::open(source).map_err(|err| format!("{}: {}", source.display(), err))?;
         let mut all_data: Vec<u8> = Vec::new();
-        host_file.read_to_end(&mut all_data).unwrap();
-        // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
+        host_file
+            .read_to_end(&mut all_data)
+            .map_err(|err| format!("{}: {}", source.display(), err))?;
+        // create an executable file in easy-fs
+        let inode = root_inode
+            .create_with_mode(&entry.name, entry.mode, entry.uid, entry.gid)
+            .ok_or_else(|| format!("{}: already exists", entry.name))?;
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
-    }
-    // list apps
-    for app in root_inode.ls() {
-        println!("{}", app);
+        // keep the modification time of the host file
+        if let Ok(modified) = host_file.metadata().and_then(|meta| meta.modified()) {
+            inode.set_times(None, Some(unix_secs(modified)));
+        }
     }
     Ok(())
 }
 
+/// Hash of a file's contents, to report what differs without dumping the contents
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Read every entry back through easy-fs and compare it with the host. Returns one line per
+/// mismatch; an error means the host side could not be read
+fn verify(root_inode: &Inode, entries: &[Entry]) -> Result<Vec<String>, String> {
+    let mut mismatches = Vec::new();
+    for entry in entries {
+        // The root directory is checked through `root_inode` itself
+        let (inode, source) = match &entry.kind {
+            EntryKind::Dir => (None, None),
+            EntryKind::File(source) => match root_inode.find(&entry.name) {
+                Some(inode) => (Some(inode), Some(source)),
+                None => {
+                    mismatches.push(format!("{}: missing", entry.name));
+                    continue;
+                }
+            },
+        };
+        let (mode, owner) = match &inode {
+            Some(inode) => (inode.mode(), inode.owner()),
+            None => (root_inode.mode(), root_inode.owner()),
+        };
+        if mode != entry.mode {
+            mismatches.push(format!(
+                "{}: mode {:o}, expected {:o}",
+                entry.name, mode, entry.mode
+            ));
+        }
+        if owner != (entry.uid, entry.gid) {
+            mismatches.push(format!(
+                "{}: owner {}:{}, expected {}:{}",
+                entry.name, owner.0, owner.1, entry.uid, entry.gid
+            ));
+        }
+        if let (Some(inode), Some(source)) = (inode, source) {
+            let expected =
+                std::fs::read(source).map_err(|err| format!("{}: {}", source.display(), err))?;
+            let (actual, expected) = (content_hash(&read_all(&inode)), content_hash(&expected));
+            if actual != expected {
+                mismatches.push(format!(
+                    "{}: content hash {:016x}, expected {:016x}",
+                    entry.name, actual, expected
+                ));
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// The whole contents of a file in the image
+fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buffer = [0u8; BLOCK_SZ];
+    loop {
+        let len = inode.read_at(data.len(), &mut buffer);
+        if len == 0 {
+            return data;
+        }
+        data.extend_from_slice(&buffer[..len]);
+    }
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
     let block_file = Arc::new(BlockFile(Mutex::new({
@@ -153,3 +316,24 @@ fn efs_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn verify_reports_changed_files() -> std::io::Result<()> {
+    std::fs::write("target/verify_src", b"original")?;
+    let entries = [Entry::file(
+        "app",
+        PathBuf::from("target/verify_src"),
+        0o755,
+    )];
+    let efs = EasyFileSystem::create(open_image("target/verify.img", true)?, 4096, 1);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    pack(&root_inode, &entries).unwrap();
+    assert!(verify(&root_inode, &entries).unwrap().is_empty());
+    std::fs::write("target/verify_src", b"modified")?;
+    root_inode.find("app").unwrap().set_mode(0o700);
+    let mismatches = verify(&root_inode, &entries).unwrap();
+    assert_eq!(mismatches.len(), 2, "{:?}", mismatches);
+    assert!(mismatches[0].starts_with("app: mode 700"));
+    assert!(mismatches[1].starts_with("app: content hash"));
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! Image manifests
+//!
+//! A manifest lists what goes into the image, one entry per line. Blank lines and lines
+//! starting with `#` are ignored:
+//!
+//! ```text
+//! # kind    image path    host path / target    mode   uid gid
+//! file      /initproc     build/initproc        0755
+//! file      /motd         motd.txt              0644   1   1
+//! dir       /             -                     0755
+//! symlink   /sh           user_shell
+//! ```
+//!
+//! Host paths are relative to the directory of the manifest. Mode, uid and gid are optional
+//! and default to 0644 (0755 for directories) and root.
+//!
+//! easy-fs has a single flat root directory and no symlinks yet, so `dir` only applies to `/`
+//! and nested paths or `symlink` entries are rejected with an error naming the line.
+
+use std::path::{Path, PathBuf};
+
+/// Permission bits of files without an explicit mode
+const DEFAULT_FILE_MODE: u16 = 0o644;
+/// Permission bits of directories without an explicit mode
+const DEFAULT_DIR_MODE: u16 = 0o755;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A regular file copied from the host
+    File(PathBuf),
+    /// A directory; only the root for now
+    Dir,
+}
+
+/// One thing to create in the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Name in the root directory, `/` for the root itself
+    pub name: String,
+    pub kind: EntryKind,
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+}
+
+impl Entry {
+    /// A root-owned file copied from `source`
+    pub fn file(name: &str, source: PathBuf, mode: u16) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: EntryKind::File(source),
+            mode,
+            uid: 0,
+            gid: 0,
+        }
+    }
+}
+
+/// Parse the manifest `text`, resolving host paths against `base`
+pub fn parse(text: &str, base: &Path) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = parse_line(line, base).map_err(|err| format!("line {}: {}", index + 1, err))?;
+        if entries.iter().any(|e: &Entry| e.name == entry.name) {
+            return Err(format!(
+                "line {}: duplicate entry {}",
+                index + 1,
+                entry.name
+            ));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn parse_line(line: &str, base: &Path) -> Result<Entry, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (kind, path) = match fields[..] {
+        [kind, path, ..] => (kind, path),
+        _ => return Err(format!("expected `<kind> <path> ...`, got `{}`", line)),
+    };
+    let name = image_name(path)?;
+    let (kind, default_mode) = match kind {
+        "file" => {
+            let source = fields.get(2).ok_or("file entry without a host path")?;
+            (EntryKind::File(base.join(source)), DEFAULT_FILE_MODE)
+        }
+        "dir" if name == "/" => (EntryKind::Dir, DEFAULT_DIR_MODE),
+        "dir" => return Err(format!("{}: easy-fs has no subdirectories yet", path)),
+        "symlink" => return Err(format!("{}: easy-fs has no symlinks yet", path)),
+        kind => return Err(format!("unknown entry kind `{}`", kind)),
+    };
+    let number = |index: usize, what: &str, radix: u32, default: u16| -> Result<u16, String> {
+        fields.get(index).map_or(Ok(default), |field| {
+            u16::from_str_radix(field, radix).map_err(|_| format!("invalid {} `{}`", what, field))
+        })
+    };
+    let mode = number(3, "mode", 8, default_mode)?;
+    if mode > 0o7777 {
+        return Err(format!("invalid mode `{:o}`", mode));
+    }
+    if fields.len() > 6 {
+        return Err(format!("trailing fields in `{}`", line));
+    }
+    Ok(Entry {
+        name,
+        kind,
+        mode,
+        uid: number(4, "uid", 10, 0)?,
+        gid: number(5, "gid", 10, 0)?,
+    })
+}
+
+/// The name of `path` in the flat root directory
+fn image_name(path: &str) -> Result<String, String> {
+    if path == "/" {
+        return Ok(path.to_string());
+    }
+    let name = path.strip_prefix('/').unwrap_or(path);
+    if name.is_empty() || name.contains('/') {
+        return Err(format!("{}: easy-fs has no subdirectories yet", path));
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_files_with_defaults_and_metadata() {
+        let text =
+            "# comment\n\nfile /init build/init 0755\nfile motd motd.txt 0640 1 2\ndir / - 0700\n";
+        let entries = parse(text, Path::new("/img")).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry::file("init", PathBuf::from("/img/build/init"), 0o755),
+                Entry {
+                    name: "motd".to_string(),
+                    kind: EntryKind::File(PathBuf::from("/img/motd.txt")),
+                    mode: 0o640,
+                    uid: 1,
+                    gid: 2,
+                },
+                Entry {
+                    name: "/".to_string(),
+                    kind: EntryKind::Dir,
+                    mode: 0o700,
+                    uid: 0,
+                    gid: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            parse("file a a.txt", Path::new(".")).unwrap()[0].mode,
+            DEFAULT_FILE_MODE
+        );
+    }
+
+    #[test]
+    fn rejects_what_easy_fs_cannot_store() {
+        let error = |text: &str| parse(text, Path::new(".")).unwrap_err();
+        assert!(error("file /bin/sh sh").starts_with("line 1: /bin/sh"));
+        assert!(error("dir /bin").contains("subdirectories"));
+        assert!(error("\nsymlink /sh user_shell").starts_with("line 2"));
+        assert!(error("file a a.txt 0999").contains("invalid mode"));
+        assert!(error("file a a.txt\nfile /a b.txt").contains("duplicate"));
+        assert!(error("fifo a").contains("unknown entry kind"));
+    }
+}
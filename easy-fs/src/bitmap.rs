@@ -1,5 +1,6 @@
 use super::{block_cache, BlockDevice, BLOCK_SZ};
 use alloc::sync::Arc;
+use core::ops::Range;
 
 /// A bitmap block
 type BitmapBlock = [u64; 64];
@@ -28,6 +29,10 @@ impl Bitmap {
             blocks,
         }
     }
+    /// Ids of the blocks holding the bitmap
+    pub fn block_range(&self) -> Range<u32> {
+        self.start_block_id as u32..(self.start_block_id + self.blocks) as u32
+    }
     /// Allocate a new block from a block device
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
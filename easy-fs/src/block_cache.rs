@@ -1,6 +1,8 @@
 use super::{BlockDevice, BLOCK_SZ};
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
 use lazy_static::*;
 use spin::Mutex;
 
@@ -76,11 +78,19 @@ impl Drop for BlockCache {
     }
 }
 
+/// Whether two handles refer to the same block device. Only the data pointers are
+/// compared, since vtable pointers of the same type may differ between codegen units
+fn same_device(a: &Arc<dyn BlockDevice>, b: &Arc<dyn BlockDevice>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
 /// Use a block cache of 16 blocks
 const BLOCK_CACHE_SIZE: usize = 16;
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// (block id, device, cache). The device is kept outside the cache so that lookups
+    /// never need to lock a cache that another user may be holding
+    queue: VecDeque<(usize, Arc<dyn BlockDevice>, Arc<Mutex<BlockCache>>)>,
 }
 
 impl BlockCacheManager {
@@ -95,8 +105,14 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
+        // Several devices may share the cache (e.g. in host-side tests), so a cached
+        // block must match both the block id and the device
+        if let Some((_, _, cache)) = self
+            .queue
+            .iter()
+            .find(|(id, device, _)| *id == block_id && same_device(device, &block_device))
+        {
+            Arc::clone(cache)
         } else {
             // substitute
             if self.queue.len() == BLOCK_CACHE_SIZE {
@@ -105,7 +121,7 @@ impl BlockCacheManager {
                     .queue
                     .iter()
                     .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
+                    .find(|(_, (_, _, cache))| Arc::strong_count(cache) == 1)
                 {
                     self.queue.drain(idx..=idx);
                 } else {
@@ -117,7 +133,8 @@ impl BlockCacheManager {
                 block_id,
                 Arc::clone(&block_device),
             )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue
+                .push_back((block_id, block_device, Arc::clone(&block_cache)));
             block_cache
         }
     }
@@ -137,10 +154,57 @@ pub fn block_cache(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Arc<M
         .block_cache(block_id, block_device)
 }
 
+/// Sync the cached blocks of `block_device` whose ids fall in one of `ranges`
+pub fn block_cache_sync_ranges(block_device: &Arc<dyn BlockDevice>, ranges: &[Range<u32>]) {
+    let manager = BLOCK_CACHE_MANAGER.lock();
+    for (block_id, device, cache) in manager.queue.iter() {
+        let block_id = *block_id as u32;
+        if same_device(device, block_device) && ranges.iter().any(|r| r.contains(&block_id)) {
+            cache.lock().sync();
+        }
+    }
+    block_device.flush();
+}
+
+/// The distinct devices the cached blocks belong to
+fn cached_devices(manager: &BlockCacheManager) -> Vec<Arc<dyn BlockDevice>> {
+    let mut devices: Vec<Arc<dyn BlockDevice>> = Vec::new();
+    for (_, device, _) in manager.queue.iter() {
+        if !devices.iter().any(|d| same_device(d, device)) {
+            devices.push(Arc::clone(device));
+        }
+    }
+    devices
+}
+
 /// Sync all block cache to block device
 pub fn block_cache_sync_all() {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    for (_, _, cache) in manager.queue.iter() {
         cache.lock().sync();
     }
+    cached_devices(&manager)
+        .iter()
+        .for_each(|device| device.flush());
+}
+
+/// Like [`block_cache_sync_all`], but skip the caches somebody holds the lock of instead of
+/// waiting for them. For a kernel shutting down after a panic, where the holder never comes back.
+/// Returns whether every cache was synced
+pub fn block_cache_try_sync_all() -> bool {
+    let manager = match BLOCK_CACHE_MANAGER.try_lock() {
+        Some(manager) => manager,
+        None => return false,
+    };
+    let mut all = true;
+    for (_, _, cache) in manager.queue.iter() {
+        match cache.try_lock() {
+            Some(mut cache) => cache.sync(),
+            None => all = false,
+        }
+    }
+    cached_devices(&manager)
+        .iter()
+        .for_each(|device| device.flush());
+    all
 }
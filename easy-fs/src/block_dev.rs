@@ -5,4 +5,7 @@ use core::any::Any;
 pub trait BlockDevice: Send + Sync + Any {
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Issue the writes the device is still holding, e.g. in an I/O scheduler queue.
+    /// Called after block caches are synced, so that a sync reaches the disk
+    fn flush(&self) {}
 }
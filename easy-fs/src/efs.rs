@@ -1,9 +1,11 @@
 use super::{
-    block_cache, block_cache_sync_all, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    block_cache, block_cache_sync_all, block_cache_sync_ranges, Bitmap, BlockDevice, DiskInode,
+    DiskInodeType, Inode, SuperBlock, DEFAULT_DIR_MODE,
 };
 use crate::BLOCK_SZ;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
 use spin::Mutex;
 
 /// An easy fs over a block device
@@ -13,6 +15,15 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    /// Source of the timestamps written into inodes
+    clock: fn() -> u32,
+    /// Whether the superblock said the filesystem was unmounted cleanly when it was opened
+    was_clean: bool,
+}
+
+/// The clock of a filesystem nobody gave a clock to: time stands still at 0
+fn no_clock() -> u32 {
+    0
 }
 
 /// A data block of block size
@@ -44,6 +55,8 @@ impl EasyFileSystem {
             data_bitmap,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            clock: no_clock,
+            was_clean: true,
         };
         // clear all blocks
         for i in 0..total_blocks {
@@ -71,17 +84,21 @@ impl EasyFileSystem {
         block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-                disk_inode.initialize(DiskInodeType::Directory);
+                disk_inode.initialize(DiskInodeType::Directory, DEFAULT_DIR_MODE, 0, 0, efs.now());
             });
         block_cache_sync_all();
         Arc::new(Mutex::new(efs))
     }
-    /// Open a block device as a filesystem
+    /// Open a block device as a filesystem.
+    ///
+    /// The superblock is marked dirty on disk right away and stays so until [`Self::unmount`].
+    /// If it was already dirty, the last user of the filesystem never unmounted it, see
+    /// [`Self::was_clean`]
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
         // read SuperBlock
-        block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
+        let efs = block_cache(0, Arc::clone(&block_device)).lock().modify(
+            0,
+            |super_block: &mut SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
@@ -94,9 +111,45 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    clock: no_clock,
+                    was_clean: super_block.is_clean(),
                 };
-                Arc::new(Mutex::new(efs))
-            })
+                super_block.set_clean(false);
+                efs
+            },
+        );
+        efs.sync_super_block();
+        Arc::new(Mutex::new(efs))
+    }
+    /// Whether the filesystem was unmounted cleanly before this mount. If not, its metadata
+    /// may be inconsistent, e.g. a block allocated in a bitmap but used by no inode
+    pub fn was_clean(&self) -> bool {
+        self.was_clean
+    }
+    /// Write back all cached blocks and mark the superblock clean.
+    ///
+    /// Call it last: the superblock is not marked dirty again by later modifications, so
+    /// the filesystem must not be modified afterwards. A plain sync keeps the superblock dirty
+    /// for the same reason
+    pub fn unmount(&self) {
+        block_cache_sync_all();
+        block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .modify(0, |super_block: &mut SuperBlock| {
+                super_block.set_clean(true)
+            });
+        self.sync_super_block();
+    }
+    fn sync_super_block(&self) {
+        block_cache_sync_ranges(&self.block_device, &[0..1]);
+    }
+    /// Use `clock` as the source of inode timestamps, in seconds
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.clock = clock;
+    }
+    /// Current time according to the clock
+    pub fn now(&self) -> u32 {
+        (self.clock)()
     }
     /// Get the root inode of the filesystem
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
@@ -106,6 +159,13 @@ impl EasyFileSystem {
         // release efs lock
         Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
     }
+    /// Ids of the blocks holding the inode and data bitmaps
+    pub fn bitmap_ranges(&self) -> Vec<Range<u32>> {
+        alloc::vec![
+            self.inode_bitmap.block_range(),
+            self.data_bitmap.block_range()
+        ]
+    }
     /// Get inode's block pos and offset by id
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
         const INODE_SIZE: usize = core::mem::size_of::<DiskInode>();
@@ -123,7 +183,7 @@ impl EasyFileSystem {
         const INODES_PER_BLOCK: u32 = BLOCK_SZ as u32 / INODE_SIZE;
         assert!(block_offset % INODE_SIZE == 0);
         assert!(block_offset / INODE_SIZE < INODES_PER_BLOCK);
-        (self.inode_area_start_block - block_id) * INODES_PER_BLOCK + block_offset / INODE_SIZE
+        (block_id - self.inode_area_start_block) * INODES_PER_BLOCK + block_offset / INODE_SIZE
     }
     /// Get data block by id
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
@@ -2,13 +2,21 @@ use super::{block_cache, BlockDevice, BLOCK_SZ};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
+use core::ops::Range;
 
 /// Magic number for sanity check
 const EFS_MAGIC: u32 = 0x3b800001;
+/// On-disk layout version, bumped whenever `SuperBlock` or `DiskInode` changes.
+/// Version 2 added permission bits and owners, version 3 timestamps, version 4 the mount state.
+const EFS_VERSION: u32 = 4;
+/// Mount state of a filesystem that was unmounted cleanly
+const EFS_CLEAN: u32 = 1;
+/// Mount state of a mounted filesystem, or of one that was never unmounted
+const EFS_DIRTY: u32 = 2;
 /// The max number of direct inodes
-const INODE_DIRECT_COUNT: usize = 27;
+const INODE_DIRECT_COUNT: usize = 23;
 /// The max length of inode name
-const NAME_LENGTH_LIMIT: usize = 27;
+pub const NAME_LENGTH_LIMIT: usize = 27;
 /// The max number of indirect1 inodes
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 /// The max number of indirect2 inodes
@@ -25,21 +33,26 @@ const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
 #[repr(C)]
 pub struct SuperBlock {
     magic: u32,
+    version: u32,
     pub total_blocks: u32,
     pub inode_bitmap_blocks: u32,
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// [`EFS_CLEAN`] or [`EFS_DIRTY`]
+    state: u32,
 }
 
 impl Debug for SuperBlock {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         f.debug_struct("SuperBlock")
+            .field("version", &self.version)
             .field("total_blocks", &self.total_blocks)
             .field("inode_bitmap_blocks", &self.inode_bitmap_blocks)
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("clean", &self.is_clean())
             .finish()
     }
 }
@@ -56,19 +69,36 @@ impl SuperBlock {
     ) {
         *self = Self {
             magic: EFS_MAGIC,
+            version: EFS_VERSION,
             total_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            state: EFS_DIRTY,
         }
     }
-    /// Check if a super block is valid using efs magic
+    /// Check if a super block is valid using efs magic and layout version
     pub fn is_valid(&self) -> bool {
-        self.magic == EFS_MAGIC
+        self.magic == EFS_MAGIC && self.version == EFS_VERSION
+    }
+    /// Whether the filesystem was unmounted cleanly, i.e. all its metadata reached the disk
+    pub fn is_clean(&self) -> bool {
+        self.state == EFS_CLEAN
+    }
+    /// Record whether the filesystem is unmounted cleanly
+    pub fn set_clean(&mut self, clean: bool) {
+        self.state = if clean { EFS_CLEAN } else { EFS_DIRTY };
     }
 }
 
+/// Permission bits of a newly created file
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+/// Permission bits of a newly created directory
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+/// Mask of the permission bits kept in a disk inode
+pub const MODE_MASK: u16 = 0o7777;
+
 /// Type of a disk inode
 #[derive(PartialEq)]
 pub enum DiskInodeType {
@@ -89,19 +119,40 @@ pub struct DiskInode {
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
+    /// Time of last access, in seconds of the clock given to [`crate::EasyFileSystem::set_clock`]
+    pub atime: u32,
+    /// Time of last modification of the contents
+    pub mtime: u32,
+    /// Time of last change of the contents or the metadata
+    pub ctime: u32,
     type_: DiskInodeType,
+    /// Permission bits, see [`MODE_MASK`]
+    pub mode: u16,
+    /// Owner user id
+    pub uid: u16,
+    /// Owner group id
+    pub gid: u16,
 }
 
+// Four disk inodes fill a block exactly
+const _: () = assert!(core::mem::size_of::<DiskInode>() == BLOCK_SZ / 4);
+
 impl DiskInode {
     /// Initialize a disk inode, as well as all direct inodes under it
     /// indirect1 and indirect2 block are allocated only when they are needed
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    pub fn initialize(&mut self, type_: DiskInodeType, mode: u16, uid: u16, gid: u16, now: u32) {
         self.size = 0;
         self.link_num = 1;
         self.direct.fill(0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.atime = now;
+        self.mtime = now;
+        self.ctime = now;
         self.type_ = type_;
+        self.mode = mode & MODE_MASK;
+        self.uid = uid;
+        self.gid = gid;
     }
     /// Whether this inode is a directory
     pub fn is_dir(&self) -> bool {
@@ -239,6 +290,77 @@ impl DiskInode {
                 }
             });
     }
+    /// Ids of all blocks owned by this inode, data and index blocks alike,
+    /// merged into ranges of consecutive ids
+    pub fn block_ranges(&self, block_device: &Arc<dyn BlockDevice>) -> Vec<Range<u32>> {
+        let data_blocks = self.data_blocks() as usize;
+        let mut ids: Vec<u32> = (0..data_blocks)
+            .map(|inner_id| self.get_block_id(inner_id as u32, block_device))
+            .collect();
+        if data_blocks > INODE_DIRECT_COUNT {
+            ids.push(self.indirect1);
+        }
+        if data_blocks > INDIRECT1_BOUND {
+            ids.push(self.indirect2);
+            let indirect1_count =
+                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+            block_cache(self.indirect2 as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    ids.extend_from_slice(&indirect2[..indirect1_count]);
+                });
+        }
+        ids.sort_unstable();
+        let mut ranges: Vec<Range<u32>> = Vec::new();
+        for id in ids {
+            match ranges.last_mut() {
+                Some(range) if range.end == id => range.end += 1,
+                _ => ranges.push(id..id + 1),
+            }
+        }
+        ranges
+    }
+    /// Decrease the size of current disk inode and return blocks that should be
+    /// deallocated: data blocks past the new end and index blocks no longer needed
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let old_blocks = self.data_blocks() as usize;
+        let new_blocks = Self::_data_blocks(new_size) as usize;
+        let mut v: Vec<u32> = (new_blocks..old_blocks)
+            .map(|inner_id| self.get_block_id(inner_id as u32, block_device))
+            .collect();
+        // low-level indirect1 blocks under indirect2
+        if old_blocks > INDIRECT1_BOUND {
+            let indirect1_count = |blocks: usize| {
+                (blocks.saturating_sub(INDIRECT1_BOUND) + INODE_INDIRECT1_COUNT - 1)
+                    / INODE_INDIRECT1_COUNT
+            };
+            block_cache(self.indirect2 as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect2: &IndirectBlock| {
+                    v.extend_from_slice(
+                        &indirect2[indirect1_count(new_blocks)..indirect1_count(old_blocks)],
+                    );
+                });
+            if new_blocks <= INDIRECT1_BOUND {
+                v.push(self.indirect2);
+                self.indirect2 = 0;
+            }
+        }
+        if old_blocks > INODE_DIRECT_COUNT && new_blocks <= INODE_DIRECT_COUNT {
+            v.push(self.indirect1);
+            self.indirect1 = 0;
+        }
+        for inner_id in new_blocks..old_blocks.min(INODE_DIRECT_COUNT) {
+            self.direct[inner_id] = 0;
+        }
+        self.size = new_size;
+        v
+    }
     /// Clear size to zero and return blocks that should be deallocated
     /// and clear the block contents to zero later
     pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
@@ -12,8 +12,10 @@ mod vfs;
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache, block_cache_sync_all};
+use block_cache::{block_cache, block_cache_sync_ranges};
+pub use block_cache::{block_cache_sync_all, block_cache_try_sync_all};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
 use layout::*;
+pub use layout::{DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, MODE_MASK};
 pub use vfs::Inode;
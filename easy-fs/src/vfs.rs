@@ -1,6 +1,6 @@
 use super::{
-    block_cache, block_cache_sync_all, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    block_cache, block_cache_sync_all, block_cache_sync_ranges, BlockDevice, DirEntry, DiskInode,
+    DiskInodeType, EasyFileSystem, DEFAULT_FILE_MODE, DIRENT_SZ, MODE_MASK, NAME_LENGTH_LIMIT,
 };
 use alloc::string::String;
 use alloc::sync::Arc;
@@ -30,6 +30,10 @@ impl Inode {
             block_device,
         }
     }
+    /// The filesystem this inode lives on
+    pub fn fs(&self) -> Arc<Mutex<EasyFileSystem>> {
+        Arc::clone(&self.fs)
+    }
     pub fn inode_id(&self) -> usize {
         let fs = self.fs.lock();
         fs.inode_id(self.block_id, self.block_offset) as usize
@@ -51,6 +55,52 @@ impl Inode {
         let _fs = self.fs.lock();
         self.read_disk_inode(|inode| inode.link_num as usize)
     }
+    /// Size of the data in this inode, in bytes
+    pub fn size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|inode| inode.size as usize)
+    }
+    /// Permission bits of this inode
+    pub fn mode(&self) -> u16 {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|inode| inode.mode)
+    }
+    /// Replace the permission bits of this inode
+    pub fn set_mode(&self, mode: u16) {
+        let fs = self.fs.lock();
+        let now = fs.now();
+        self.modify_disk_inode(|inode| {
+            inode.mode = mode & MODE_MASK;
+            inode.ctime = now;
+        });
+        block_cache_sync_all();
+    }
+    /// Owner of this inode as `(uid, gid)`
+    pub fn owner(&self) -> (u16, u16) {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|inode| (inode.uid, inode.gid))
+    }
+    /// Timestamps of this inode as `(atime, mtime, ctime)`
+    pub fn times(&self) -> (u32, u32, u32) {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|inode| (inode.atime, inode.mtime, inode.ctime))
+    }
+    /// Set the access and modification times, leaving those given as `None` alone.
+    /// The change time becomes the current time.
+    pub fn set_times(&self, atime: Option<u32>, mtime: Option<u32>) {
+        let fs = self.fs.lock();
+        let now = fs.now();
+        self.modify_disk_inode(|inode| {
+            if let Some(atime) = atime {
+                inode.atime = atime;
+            }
+            if let Some(mtime) = mtime {
+                inode.mtime = mtime;
+            }
+            inode.ctime = now;
+        });
+        block_cache_sync_all();
+    }
     /// Call a function over a disk inode to read it
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
         block_cache(self.block_id, Arc::clone(&self.block_device))
@@ -111,17 +161,34 @@ impl Inode {
         }
         None
     }
-    /// 应当获取锁后调用
+    /// 应当获取锁后调用。用最后一个目录项覆盖第 `entry_id` 个目录项，返回被移除的目录项的 inode 编号。
     ///
-    /// FIXME: 偷了懒，移除目录项可能导致数据块的回收、inode size 的变化等
-    fn swap_remove(&self, entry_id: usize, disk_inode: &mut DiskInode) -> u32 {
+    /// 目录缩小后不再需要的数据块会被回收
+    fn swap_remove(
+        &self,
+        entry_id: usize,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) -> u32 {
         let offset = entry_id * DIRENT_SZ;
-        let mut dir_entry = DirEntry::empty();
+        let mut removed = DirEntry::empty();
+        disk_inode.read_at(offset, removed.as_bytes_mut(), &self.block_device);
+        let mut last = DirEntry::empty();
         let last_offset = disk_inode.size as usize - DIRENT_SZ;
-        disk_inode.read_at(last_offset, dir_entry.as_bytes_mut(), &self.block_device);
-        disk_inode.write_at(offset, dir_entry.as_bytes(), &self.block_device);
-        disk_inode.size -= DIRENT_SZ as u32;
-        dir_entry.inode_number()
+        disk_inode.read_at(last_offset, last.as_bytes_mut(), &self.block_device);
+        disk_inode.write_at(offset, last.as_bytes(), &self.block_device);
+        // 清空最后一个目录项，回收数据块时整块都应当为零
+        disk_inode.write_at(
+            last_offset,
+            DirEntry::empty().as_bytes(),
+            &self.block_device,
+        );
+        for data_block in disk_inode.decrease_size(last_offset as u32, &self.block_device) {
+            fs.dealloc_data(data_block);
+        }
+        disk_inode.mtime = fs.now();
+        disk_inode.ctime = disk_inode.mtime;
+        removed.inode_number()
     }
     /// Increase the size of a disk inode
     fn increase_size(
@@ -140,10 +207,14 @@ impl Inode {
         }
         disk_inode.increase_size(new_size, v, &self.block_device);
     }
+    /// 为 `old` 创建名为 `new` 的硬链接。`old` 不存在或 `new` 已经存在时返回 false
     pub fn link(&self, old: &str, new: &str) -> bool {
         let mut fs = self.fs.lock();
         if let Some(id) = self.read_disk_inode(|root_inode| {
             assert!(root_inode.is_dir());
+            if self.find_inode_id(new, root_inode).is_some() {
+                return None;
+            }
             self.find_inode_id(old, root_inode)
         }) {
             let dirent = DirEntry::new(new, id);
@@ -156,13 +227,17 @@ impl Inode {
                     dirent.as_bytes(),
                     &self.block_device,
                 );
+                root_inode.mtime = fs.now();
+                root_inode.ctime = root_inode.mtime;
             });
             log::debug!("write link ok");
+            let now = fs.now();
             let (inode_block_id, inode_block_offset) = fs.get_disk_inode_pos(id);
             block_cache(inode_block_id as usize, Arc::clone(&self.block_device))
                 .lock()
                 .modify(inode_block_offset, |inode: &mut DiskInode| {
                     inode.link_num += 1;
+                    inode.ctime = now;
                 });
             true
         } else {
@@ -175,33 +250,109 @@ impl Inode {
             assert!(root_inode.is_dir());
             self.find_entry_id(path, root_inode)
         }) {
-            let inode_id =
-                self.modify_disk_inode(|root_inode| self.swap_remove(id as usize, root_inode));
-            let (inode_block_id, inode_block_offset) = fs.get_disk_inode_pos(inode_id);
-            block_cache(inode_block_id as usize, Arc::clone(&self.block_device))
-                .lock()
-                .modify(inode_block_offset, |inode: &mut DiskInode| {
-                    inode.link_num -= 1;
-                    if inode.link_num == 0 {
-                        let size = inode.size;
-                        let data_blocks_dealloc = inode.clear_size(&self.block_device);
-                        assert!(
-                            data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize
-                        );
-                        for data_block in data_blocks_dealloc {
-                            fs.dealloc_data(data_block);
-                        }
-                        fs.dealloc_inode(inode_id as usize);
-                    }
-                });
+            let inode_id = self
+                .modify_disk_inode(|root_inode| self.swap_remove(id as usize, root_inode, &mut fs));
+            self.release_link(inode_id, &mut fs);
             block_cache_sync_all();
             true
         } else {
             false
         }
     }
-    /// Create inode under current inode by name
+    /// Drop one link to inode `inode_id`, freeing it and its data blocks when no link is left.
+    /// Call with the efs lock held
+    fn release_link(&self, inode_id: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        let (inode_block_id, inode_block_offset) = fs.get_disk_inode_pos(inode_id);
+        block_cache(inode_block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(inode_block_offset, |inode: &mut DiskInode| {
+                inode.link_num -= 1;
+                inode.ctime = fs.now();
+                if inode.link_num == 0 {
+                    let size = inode.size;
+                    let data_blocks_dealloc = inode.clear_size(&self.block_device);
+                    assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
+                    for data_block in data_blocks_dealloc {
+                        fs.dealloc_data(data_block);
+                    }
+                    // Leave the freed slot zeroed like a freshly formatted one
+                    inode.mode = 0;
+                    inode.uid = 0;
+                    inode.gid = 0;
+                    inode.atime = 0;
+                    inode.mtime = 0;
+                    inode.ctime = 0;
+                    fs.dealloc_inode(inode_id as usize);
+                }
+            });
+    }
+    /// Rename the entry `old` to `new`. There is only the root directory, so both names are
+    /// in the same directory.
+    ///
+    /// An existing `new` is replaced atomically: `new` is pointed at the inode of `old` before
+    /// `old` is removed, so `new` always names a valid file, and the inode it used to name loses
+    /// one link. Nothing happens if `old` and `new` are hard links to the same file.
+    /// Returns false if `old` does not exist or `new` is too long
+    pub fn rename(&self, old: &str, new: &str) -> bool {
+        if new.len() > NAME_LENGTH_LIMIT {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let (old_entry, new_entry) = self.read_disk_inode(|root_inode| {
+            assert!(root_inode.is_dir());
+            (
+                self.find_entry_id(old, root_inode),
+                self.find_entry_id(new, root_inode),
+            )
+        });
+        let old_offset = match old_entry {
+            Some(id) => id as usize * DIRENT_SZ,
+            None => return false,
+        };
+        let replaced = self.modify_disk_inode(|root_inode| {
+            let mut dirent = DirEntry::empty();
+            root_inode.read_at(old_offset, dirent.as_bytes_mut(), &self.block_device);
+            let inode_id = dirent.inode_number();
+            match new_entry {
+                None => {
+                    let dirent = DirEntry::new(new, inode_id);
+                    root_inode.write_at(old_offset, dirent.as_bytes(), &self.block_device);
+                    root_inode.mtime = fs.now();
+                    root_inode.ctime = root_inode.mtime;
+                    None
+                }
+                Some(new_entry) => {
+                    let new_offset = new_entry as usize * DIRENT_SZ;
+                    root_inode.read_at(new_offset, dirent.as_bytes_mut(), &self.block_device);
+                    let replaced = dirent.inode_number();
+                    if replaced == inode_id {
+                        return None;
+                    }
+                    let dirent = DirEntry::new(new, inode_id);
+                    root_inode.write_at(new_offset, dirent.as_bytes(), &self.block_device);
+                    self.swap_remove(old_offset / DIRENT_SZ, root_inode, &mut fs);
+                    Some(replaced)
+                }
+            }
+        });
+        if let Some(replaced) = replaced {
+            self.release_link(replaced, &mut fs);
+        }
+        block_cache_sync_all();
+        true
+    }
+    /// Create inode under current inode by name, owned by root with [`DEFAULT_FILE_MODE`]
     pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_with_mode(name, DEFAULT_FILE_MODE, 0, 0)
+    }
+    /// Create inode under current inode by name with the given permission bits and owner
+    pub fn create_with_mode(
+        &self,
+        name: &str,
+        mode: u16,
+        uid: u16,
+        gid: u16,
+    ) -> Option<Arc<Inode>> {
         let mut fs = self.fs.lock();
         if self
             .read_disk_inode(|root_inode| {
@@ -220,7 +371,7 @@ impl Inode {
         block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
+                new_inode.initialize(DiskInodeType::File, mode, uid, gid, fs.now());
             });
         self.modify_disk_inode(|root_inode| {
             // append file in the dirent
@@ -235,6 +386,8 @@ impl Inode {
                 dirent.as_bytes(),
                 &self.block_device,
             );
+            root_inode.mtime = fs.now();
+            root_inode.ctime = root_inode.mtime;
         });
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
@@ -265,21 +418,36 @@ impl Inode {
             ret
         })
     }
-    /// Read data from current inode
+    /// Read data from current inode, updating its access time
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        let fs = self.fs.lock();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.atime = fs.now();
+            disk_inode.read_at(offset, buf, &self.block_device)
+        })
     }
     /// Write data to current inode
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
         let mut fs = self.fs.lock();
         let size = self.modify_disk_inode(|disk_inode| {
             self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
+            disk_inode.mtime = fs.now();
+            disk_inode.ctime = disk_inode.mtime;
             disk_inode.write_at(offset, buf, &self.block_device)
         });
-        block_cache_sync_all();
         size
     }
+    /// Write back the cached blocks of this inode: the disk inode, its data and index
+    /// blocks, and the allocation bitmaps so that none of its blocks is handed out
+    /// again after a crash. Data written by `write_at` stays cached until then.
+    pub fn sync(&self) {
+        let fs = self.fs.lock();
+        let mut ranges =
+            self.read_disk_inode(|disk_inode| disk_inode.block_ranges(&self.block_device));
+        ranges.push(self.block_id as u32..self.block_id as u32 + 1);
+        ranges.extend(fs.bitmap_ranges());
+        block_cache_sync_ranges(&self.block_device, &ranges);
+    }
     /// Clear the data in current inode
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
@@ -290,6 +458,8 @@ impl Inode {
             for data_block in data_blocks_dealloc {
                 fs.dealloc_data(data_block);
             }
+            disk_inode.mtime = fs.now();
+            disk_inode.ctime = disk_inode.mtime;
         });
         block_cache_sync_all();
     }
@@ -0,0 +1,349 @@
+//! Randomized operation sequences against an in-memory block device, cross-checked
+//! with a `HashMap` model of the file system.
+
+use easy_fs::{BlockDevice, EasyFileSystem, Inode, BLOCK_SZ};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+const BLOCK_NUM: usize = 8192;
+const SEEDS: u64 = 16;
+const OPS_PER_SEED: usize = 300;
+/// Enough names to push the root directory past one data block (16 entries)
+const NAMES: usize = 40;
+
+/// A block device backed by memory
+struct MemDisk(Mutex<Vec<[u8; BLOCK_SZ]>>);
+
+impl MemDisk {
+    fn new() -> Self {
+        Self(Mutex::new(vec![[0; BLOCK_SZ]; BLOCK_NUM]))
+    }
+    fn snapshot(&self) -> Vec<[u8; BLOCK_SZ]> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.lock().unwrap()[block_id]);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.0.lock().unwrap()[block_id].copy_from_slice(buf);
+    }
+}
+
+/// What the file system should look like: names map to files, files map to contents
+#[derive(Default)]
+struct Model {
+    names: HashMap<String, usize>,
+    files: HashMap<usize, Vec<u8>>,
+    next_file: usize,
+}
+
+impl Model {
+    fn link_num(&self, file: usize) -> usize {
+        self.names.values().filter(|&&f| f == file).count()
+    }
+}
+
+fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 777];
+    loop {
+        let len = inode.read_at(data.len(), &mut buf);
+        if len == 0 {
+            return data;
+        }
+        data.extend_from_slice(&buf[..len]);
+    }
+}
+
+fn random_bytes(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+/// Compare everything observable through the vfs layer with the model
+fn check(root: &Inode, model: &Model, context: &str) {
+    let mut listed = root.ls();
+    listed.sort();
+    let mut expected: Vec<_> = model.names.keys().cloned().collect();
+    expected.sort();
+    assert_eq!(listed, expected, "{}: directory listing", context);
+    let mut inode_ids: HashMap<usize, usize> = HashMap::new();
+    for (name, &file) in &model.names {
+        let inode = root.find(name).unwrap();
+        assert_eq!(
+            read_all(&inode),
+            model.files[&file],
+            "{}: contents of {}",
+            context,
+            name
+        );
+        assert_eq!(
+            inode.inode_link_num(),
+            model.link_num(file),
+            "{}: link count of {}",
+            context,
+            name
+        );
+        let inode_id = *inode_ids.entry(file).or_insert_with(|| inode.inode_id());
+        assert_eq!(
+            inode.inode_id(),
+            inode_id,
+            "{}: inode id of {}",
+            context,
+            name
+        );
+    }
+    let mut distinct: Vec<_> = inode_ids.values().collect();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(
+        distinct.len(),
+        inode_ids.len(),
+        "{}: shared inode ids",
+        context
+    );
+}
+
+fn step(rng: &mut StdRng, root: &Inode, model: &mut Model) -> String {
+    let name = format!("file{}", rng.gen_range(0..NAMES));
+    match rng.gen_range(0..100) {
+        0..=19 => {
+            let created = root.create(&name).is_some();
+            assert_eq!(created, !model.names.contains_key(&name), "create {}", name);
+            if created {
+                model.names.insert(name.clone(), model.next_file);
+                model.files.insert(model.next_file, Vec::new());
+                model.next_file += 1;
+            }
+            format!("create {}", name)
+        }
+        20..=29 => {
+            let new = format!("file{}", rng.gen_range(0..NAMES));
+            let linked = root.link(&name, &new);
+            let expected = model.names.contains_key(&name) && !model.names.contains_key(&new);
+            assert_eq!(linked, expected, "link {} {}", name, new);
+            if linked {
+                let file = model.names[&name];
+                model.names.insert(new.clone(), file);
+            }
+            format!("link {} {}", name, new)
+        }
+        30..=44 => {
+            let unlinked = root.unlink(&name);
+            assert_eq!(unlinked, model.names.contains_key(&name), "unlink {}", name);
+            if let Some(file) = model.names.remove(&name) {
+                if model.link_num(file) == 0 {
+                    model.files.remove(&file);
+                }
+            }
+            format!("unlink {}", name)
+        }
+        45..=79 => {
+            let file = match model.names.get(&name) {
+                Some(&file) => file,
+                None => return format!("write {} (missing)", name),
+            };
+            let contents = model.files.get_mut(&file).unwrap();
+            // Mostly small writes, sometimes large enough to need indirect blocks
+            let len = if rng.gen_range(0..10) == 0 {
+                rng.gen_range(0..200 * BLOCK_SZ)
+            } else {
+                rng.gen_range(0..3 * BLOCK_SZ)
+            };
+            // Writing past the end leaves a hole that must read back as zeros
+            let offset = rng.gen_range(0..=contents.len() + BLOCK_SZ);
+            let data = random_bytes(rng, len);
+            let inode = root.find(&name).unwrap();
+            assert_eq!(inode.write_at(offset, &data), len, "write {}", name);
+            if contents.len() < offset + len {
+                contents.resize(offset + len, 0);
+            }
+            contents[offset..offset + len].copy_from_slice(&data);
+            format!("write {} at {} len {}", name, offset, len)
+        }
+        80..=84 => {
+            if let Some(&file) = model.names.get(&name) {
+                root.find(&name).unwrap().clear();
+                model.files.get_mut(&file).unwrap().clear();
+            }
+            format!("truncate {}", name)
+        }
+        85..=89 => {
+            let new = format!("file{}", rng.gen_range(0..NAMES));
+            let renamed = root.rename(&name, &new);
+            assert_eq!(
+                renamed,
+                model.names.contains_key(&name),
+                "rename {} {}",
+                name,
+                new
+            );
+            let file = model.names.get(&name).copied();
+            // Renaming between two links of the same file changes nothing
+            if file.is_some() && file != model.names.get(&new).copied() {
+                let file = model.names.remove(&name).unwrap();
+                if let Some(replaced) = model.names.insert(new.clone(), file) {
+                    if model.link_num(replaced) == 0 {
+                        model.files.remove(&replaced);
+                    }
+                }
+            }
+            format!("rename {} {}", name, new)
+        }
+        _ => {
+            if let Some(&file) = model.names.get(&name) {
+                let contents = &model.files[&file];
+                let offset = rng.gen_range(0..=contents.len() + 16);
+                let mut buf = vec![0u8; rng.gen_range(0..2 * BLOCK_SZ)];
+                let len = root.find(&name).unwrap().read_at(offset, &mut buf);
+                let start = offset.min(contents.len());
+                let end = (offset + buf.len()).min(contents.len()).max(start);
+                assert_eq!(&buf[..len], &contents[start..end], "read {}", name);
+            }
+            format!("read {}", name)
+        }
+    }
+}
+
+fn run(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let disk = Arc::new(MemDisk::new());
+    let efs = EasyFileSystem::create(disk.clone(), BLOCK_NUM as u32, 1);
+    let fresh = disk.snapshot();
+    let root = EasyFileSystem::root_inode(&efs);
+    let mut model = Model::default();
+    for i in 0..OPS_PER_SEED {
+        let op = step(&mut rng, &root, &mut model);
+        check(&root, &model, &format!("seed {} op {} ({})", seed, i, op));
+    }
+    // Everything must survive reopening the device
+    let efs = EasyFileSystem::open(disk.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    check(&root, &model, &format!("seed {} after reopen", seed));
+    // Removing every name must release every inode and data block, leaving the
+    // image exactly as it was right after formatting
+    let names: Vec<_> = model.names.keys().cloned().collect();
+    for name in names {
+        assert!(root.unlink(&name));
+    }
+    assert!(disk.snapshot() == fresh, "seed {}: leaked blocks", seed);
+}
+
+#[test]
+fn random_ops_match_model() {
+    for seed in 0..SEEDS {
+        run(seed);
+    }
+}
+
+#[test]
+fn directory_shrinks_and_grows_across_block_boundary() {
+    let disk = Arc::new(MemDisk::new());
+    let efs = EasyFileSystem::create(disk.clone(), BLOCK_NUM as u32, 1);
+    let fresh = disk.snapshot();
+    let root = EasyFileSystem::root_inode(&efs);
+    // 17 entries need two directory blocks
+    for round in 0..4 {
+        for i in 0..17 {
+            root.create(&format!("f{}", i)).unwrap();
+        }
+        for i in (0..17).rev() {
+            assert!(root.unlink(&format!("f{}", i)), "round {}", round);
+        }
+        assert!(root.ls().is_empty());
+    }
+    assert!(disk.snapshot() == fresh, "leaked directory blocks");
+}
+
+#[test]
+fn mode_and_owner_survive_reopen() {
+    let disk = Arc::new(MemDisk::new());
+    let efs = EasyFileSystem::create(disk.clone(), BLOCK_NUM as u32, 1);
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.mode(), easy_fs::DEFAULT_DIR_MODE);
+    assert_eq!(
+        root.create("plain").unwrap().mode(),
+        easy_fs::DEFAULT_FILE_MODE
+    );
+    let inode = root.create_with_mode("owned", 0o170640, 7, 8).unwrap();
+    assert_eq!(inode.mode(), 0o640, "type bits must be masked off");
+    inode.set_mode(0o600);
+    let efs = EasyFileSystem::open(disk.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let inode = root.find("owned").unwrap();
+    assert_eq!(inode.mode(), 0o600);
+    assert_eq!(inode.owner(), (7, 8));
+}
+
+static NOW: AtomicU32 = AtomicU32::new(0);
+
+fn fake_clock() -> u32 {
+    NOW.load(Ordering::Relaxed)
+}
+
+#[test]
+fn timestamps_follow_reads_writes_and_links() {
+    let disk = Arc::new(MemDisk::new());
+    let efs = EasyFileSystem::create(disk.clone(), BLOCK_NUM as u32, 1);
+    efs.lock().set_clock(fake_clock);
+    let root = EasyFileSystem::root_inode(&efs);
+    NOW.store(10, Ordering::Relaxed);
+    let inode = root.create("file").unwrap();
+    assert_eq!(inode.times(), (10, 10, 10));
+    assert_eq!(root.times().1, 10, "creating a file modifies the directory");
+    NOW.store(20, Ordering::Relaxed);
+    inode.write_at(0, b"hello");
+    assert_eq!(inode.times(), (10, 20, 20));
+    NOW.store(30, Ordering::Relaxed);
+    inode.read_at(0, &mut [0; 5]);
+    assert_eq!(inode.times(), (30, 20, 20));
+    NOW.store(40, Ordering::Relaxed);
+    assert!(root.link("file", "alias"));
+    assert_eq!(inode.times(), (30, 20, 40), "linking changes only ctime");
+    NOW.store(50, Ordering::Relaxed);
+    inode.set_times(Some(1), None);
+    assert_eq!(inode.times(), (1, 20, 50));
+    // Timestamps live on disk
+    let efs = EasyFileSystem::open(disk.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.find("alias").unwrap().times(), (1, 20, 50));
+}
+
+#[test]
+fn sync_writes_back_only_that_file() {
+    let disk = Arc::new(MemDisk::new());
+    let efs = EasyFileSystem::create(disk.clone(), BLOCK_NUM as u32, 1);
+    let root = EasyFileSystem::root_inode(&efs);
+    let kept = root.create("kept").unwrap();
+    let lost = root.create("lost").unwrap();
+    kept.write_at(0, b"synced");
+    lost.write_at(0, b"cached");
+    kept.sync();
+    // A copy of the disk sees exactly what has been written back so far
+    let copy = Arc::new(MemDisk(Mutex::new(disk.snapshot())));
+    let efs = EasyFileSystem::open(copy);
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(read_all(&root.find("kept").unwrap()), b"synced");
+    assert_ne!(read_all(&root.find("lost").unwrap()), b"cached");
+}
+
+#[test]
+fn superblock_records_unclean_unmount() {
+    let disk = Arc::new(MemDisk::new());
+    let efs = EasyFileSystem::create(disk.clone(), BLOCK_NUM as u32, 1);
+    EasyFileSystem::root_inode(&efs).create("file").unwrap();
+    // Never unmounted, as if the machine lost power
+    let efs = EasyFileSystem::open(disk.clone());
+    assert!(!efs.lock().was_clean());
+    efs.lock().unmount();
+    let efs = EasyFileSystem::open(disk.clone());
+    assert!(efs.lock().was_clean());
+    // Mounting alone marks it dirty on disk
+    let efs = EasyFileSystem::open(disk.clone());
+    assert!(!efs.lock().was_clean());
+}
@@ -0,0 +1,13 @@
+#!/bin/sh
+# `cargo test` 的 runner：把测试版本的内核转换为二进制镜像并在 QEMU 中运行。
+# 内核通过 SiFive test 设备关闭 QEMU，QEMU 的退出码即为测试结果。
+set -e
+KERNEL_ELF=$1
+KERNEL_BIN=$KERNEL_ELF.bin
+BOOTLOADER=${BOOTLOADER:-../bootloader/rustsbi-qemu.bin}
+rust-objcopy --binary-architecture=riscv64 "$KERNEL_ELF" --strip-all -O binary "$KERNEL_BIN"
+exec qemu-system-riscv64 \
+	-machine virt \
+	-nographic \
+	-bios "$BOOTLOADER" \
+	-device loader,file="$KERNEL_BIN",addr=0x80200000
@@ -0,0 +1,53 @@
+//! Kernel stack backtrace
+//!
+//! 内核以 `-Cforce-frame-pointers=yes` 编译，每个栈帧中 `fp - 8` 处为返回地址，`fp - 16` 处为上一帧的 fp。
+//! 回溯只用于 panic 路径，因此读取栈帧前会先查当前页表，避免在回溯过程中再次触发异常。
+
+use core::arch::asm;
+
+use riscv::register::satp;
+
+use crate::mm::{address::VirtAddr, page_table::PageTable};
+
+/// 最多展开的栈帧数
+const MAX_DEPTH: usize = 32;
+
+/// 沿帧指针链回溯当前内核栈，打印每一帧的返回地址
+pub fn backtrace() {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    let page_table = PageTable::from_satp(satp::read().bits());
+    let read = |va: usize| -> Option<usize> {
+        let va = VirtAddr(va);
+        let pte = page_table.translate(va.floor())?;
+        if !pte.is_valid() {
+            return None;
+        }
+        Some(*pte.ppn().as_mut_at::<usize>(va.page_offset()))
+    };
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    println!("[kernel] backtrace:");
+    for depth in 0..MAX_DEPTH {
+        if fp == 0 || fp % core::mem::size_of::<usize>() != 0 {
+            break;
+        }
+        let (ra, prev_fp) = match (read(fp - 8), read(fp - 16)) {
+            (Some(ra), Some(prev_fp)) => (ra, prev_fp),
+            _ => break,
+        };
+        // 返回地址不在 .text 内，说明已经走出了内核的调用链
+        if !(stext as usize..etext as usize).contains(&ra) {
+            break;
+        }
+        println!("  #{} ra = {:#x}, fp = {:#x}", depth, ra, fp);
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
@@ -0,0 +1,189 @@
+//! Kernel command line
+//!
+//! 命令行由空格分隔的若干选项组成，目前支持：
+//!
+//! - `log=<level>[,<module>=<level>...]`：日志等级，level 为 `off|error|warn|info|debug|trace`，
+//!   可以按模块覆盖，如 `log=debug,mm::page_table=warn`
+//! - `logcolor=<on|off>`：日志是否按等级上色，默认开启
+//! - `logtime`：在日志前加上启动以来的时间
+//! - `sched=<stride|fifo>`：调度策略
+//! - `init=<name>`：第一个用户进程的可执行文件，与 `exec` 一样按搜索路径查找
+//! - `path=<dir>:<dir>...`：initproc 的 `PATH` 环境变量，`exec` 和 `spawn` 查找不含 `/` 的程序名时
+//!   依次搜索其中的目录，默认为 [`DEFAULT_EXEC_PATH`]
+//! - `selftest`：启动时运行内核自检
+//! - `aslr=<on|off>`：是否随机化用户地址空间布局，默认开启。需要可复现的运行结果时关闭
+//! - `profile`：开启采样分析，结果见 `/proc/profile`
+//! - `ktrace`：记录任务切换、系统调用等调度事件，用 `sys_ktrace_read` 读出
+//! - `oops=<kill|panic>`：内核替用户任务工作时发现只与它有关的错误时，杀死这个任务（默认）还是 panic
+//! - `consoletag`：在用户程序输出的每一行前加上 `[pid] `，也可以用 `sys_console_mode` 切换
+//! - `wx=<on|off>`：是否拒绝同时可写可执行的用户映射（W^X），默认开启
+//! - `ptecheck`：建立用户地址空间后逐页检查页表项的标志位，用于调试内存管理
+//! - `iosched=<elevator|noop>`：块设备写请求是否经过电梯调度，默认经过。`noop` 时直接发给设备
+
+use alloc::string::String;
+
+use crate::{
+    config::{DEFAULT_CMDLINE, DEFAULT_EXEC_PATH},
+    logging::{self, LogFilter},
+    oops::OopsPolicy,
+    sync::UPSafeCell,
+};
+
+/// 调度策略
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// 按 stride 调度
+    Stride,
+    /// 按就绪顺序轮转
+    Fifo,
+}
+
+/// 解析后的内核命令行
+pub struct Cmdline {
+    pub log_filter: Option<LogFilter>,
+    pub log_color: bool,
+    pub log_time: bool,
+    pub sched: SchedPolicy,
+    pub init: String,
+    pub exec_path: String,
+    pub selftest: bool,
+    pub aslr: bool,
+    pub profile: bool,
+    pub ktrace: bool,
+    pub console_tag: bool,
+    pub oops: OopsPolicy,
+    pub wx: bool,
+    pub pte_check: bool,
+    pub io_elevator: bool,
+}
+
+impl Cmdline {
+    const fn new() -> Self {
+        Self {
+            log_filter: None,
+            log_color: true,
+            log_time: false,
+            sched: SchedPolicy::Stride,
+            init: String::new(),
+            exec_path: String::new(),
+            selftest: false,
+            aslr: true,
+            profile: false,
+            ktrace: false,
+            console_tag: false,
+            oops: OopsPolicy::Kill,
+            wx: true,
+            pte_check: false,
+            io_elevator: true,
+        }
+    }
+    fn parse(&mut self, cmdline: &str) {
+        for option in cmdline.split_whitespace() {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+            match (key, value) {
+                ("log", Some(spec)) => match LogFilter::parse(spec) {
+                    Some(filter) => self.log_filter = Some(filter),
+                    None => log::warn!("[kernel] invalid log level: {}", spec),
+                },
+                ("logcolor", Some("on")) => self.log_color = true,
+                ("logcolor", Some("off")) => self.log_color = false,
+                ("logtime", None) => self.log_time = true,
+                ("sched", Some("stride")) => self.sched = SchedPolicy::Stride,
+                ("sched", Some("fifo")) => self.sched = SchedPolicy::Fifo,
+                ("init", Some(init)) => self.init = String::from(init),
+                ("path", Some(path)) => self.exec_path = String::from(path),
+                ("selftest", None) => self.selftest = true,
+                ("aslr", Some("on")) => self.aslr = true,
+                ("aslr", Some("off")) => self.aslr = false,
+                ("profile", None) => self.profile = true,
+                ("ktrace", None) => self.ktrace = true,
+                ("consoletag", None) => self.console_tag = true,
+                ("oops", Some("kill")) => self.oops = OopsPolicy::Kill,
+                ("oops", Some("panic")) => self.oops = OopsPolicy::Panic,
+                ("wx", Some("on")) => self.wx = true,
+                ("wx", Some("off")) => self.wx = false,
+                ("ptecheck", None) => self.pte_check = true,
+                ("iosched", Some("elevator")) => self.io_elevator = true,
+                ("iosched", Some("noop")) => self.io_elevator = false,
+                _ => log::warn!("[kernel] unknown kernel option: {}", option),
+            }
+        }
+    }
+}
+
+static CMDLINE: UPSafeCell<Cmdline> = unsafe { UPSafeCell::new(Cmdline::new()) };
+
+/// 解析内核命令行，需要在堆分配器初始化之后调用。
+///
+/// `bootargs` 为 `None` 时使用编译时的 `CMDLINE` 环境变量，没有则使用 `DEFAULT_CMDLINE`
+pub fn init(bootargs: Option<&str>) {
+    let raw = bootargs
+        .or(option_env!("CMDLINE"))
+        .unwrap_or(DEFAULT_CMDLINE);
+    println!("[kernel] cmdline: {}", raw);
+    let mut cmdline = CMDLINE.exclusive_access();
+    cmdline.parse(raw);
+    logging::set_style(cmdline.log_color, cmdline.log_time);
+    if let Some(filter) = cmdline.log_filter.take() {
+        logging::set_filter(filter);
+    }
+}
+
+pub fn sched_policy() -> SchedPolicy {
+    CMDLINE.exclusive_access().sched
+}
+
+pub fn init_proc() -> String {
+    CMDLINE.exclusive_access().init.clone()
+}
+
+/// 可执行文件的搜索路径
+pub fn exec_path() -> String {
+    let cmdline = CMDLINE.exclusive_access();
+    if cmdline.exec_path.is_empty() {
+        String::from(DEFAULT_EXEC_PATH)
+    } else {
+        cmdline.exec_path.clone()
+    }
+}
+
+pub fn selftest() -> bool {
+    CMDLINE.exclusive_access().selftest
+}
+
+pub fn aslr() -> bool {
+    CMDLINE.exclusive_access().aslr
+}
+
+pub fn profile() -> bool {
+    CMDLINE.exclusive_access().profile
+}
+
+pub fn ktrace() -> bool {
+    CMDLINE.exclusive_access().ktrace
+}
+
+pub fn console_tag() -> bool {
+    CMDLINE.exclusive_access().console_tag
+}
+
+pub fn oops_policy() -> OopsPolicy {
+    CMDLINE.exclusive_access().oops
+}
+
+/// 是否强制 W^X
+pub fn wx() -> bool {
+    CMDLINE.exclusive_access().wx
+}
+
+pub fn pte_check() -> bool {
+    CMDLINE.exclusive_access().pte_check
+}
+
+/// 块设备的写请求是否经过电梯调度
+pub fn io_elevator() -> bool {
+    CMDLINE.exclusive_access().io_elevator
+}
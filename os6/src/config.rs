@@ -1,16 +1,74 @@
 //! Constants used in rCore
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// 用户栈自动增长的上限
+pub const USER_STACK_MAX_SIZE: usize = 4096 * 16;
+/// 缺页地址位于用户栈下方多少页以内时，尝试自动增长用户栈
+pub const USER_STACK_GROW_PAGES: usize = 4;
+/// exec 时 argv 和 envp 的字符串与指针总共最多占用的字节数，它们放在初始的用户栈中，
+/// 因此只能占用栈的一部分
+pub const ARG_MAX: usize = USER_STACK_SIZE / 2;
+/// 系统调用参数中的路径包括结尾的 `\0` 最多占用的字节数，与 Linux 相同
+pub const PATH_MAX: usize = 4096;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 20;
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+/// 设备树不可用时使用的物理内存结束地址
 pub const MEMORY_END: usize = 0x88000000;
+/// 内核把物理地址 `pa` 映射到虚拟地址 `pa + PHYS_VIRT_OFFSET`，即 Sv39 高半部的起点。
+/// 内核映像也链接在这个线性映射区中，`entry.asm` 中的启动页表使用同一个值
+pub const PHYS_VIRT_OFFSET: usize = 0xffff_ffc0_0000_0000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS;
 pub const PTE_PER_PAGE: usize = PAGE_SIZE / core::mem::size_of::<usize>();
 pub const MAX_SYSCALL_NUM: usize = 500;
-pub const BIG_STRIDE: usize = usize::MAX;
+/// 任务运行一个时钟中断周期增加的 pass 为 `BIG_STRIDE / priority`。
+/// pass 只增不减，按这个速度 64 位的 pass 不会溢出
+pub const BIG_STRIDE: usize = 1 << 20;
+/// 新任务的优先级，它的时间片为一个时钟中断周期
+pub const DEFAULT_PRIORITY: usize = 16;
+/// 时间片最长的时钟中断周期数
+pub const MAX_TIME_SLICE: usize = 10;
+/// 没有任务可运行时，idle 控制流最多等待多少个时钟中断周期。控制台还没有中断，
+/// 需要定期醒来检查输入，否则所有任务都阻塞时无法用 Ctrl-C 打断
+pub const IDLE_POLL_TICKS: usize = 5;
 
+/// 位置无关的可执行文件（ET_DYN）的加载基址
+pub const PIE_LOAD_BASE: usize = 0x4000_0000;
+/// 用户栈栈顶的位置，远离可执行文件和用户自己选择的 mmap 地址
+pub const USER_STACK_TOP: usize = 0x3f_0000_0000;
+/// mmap 区域的顶端与用户栈增长上限之间的空隙
+pub const MMAP_STACK_GAP: usize = 0x1000_0000;
+/// 启用 ASLR 时，用户栈、mmap 区域和 PIE 加载基址各自随机偏移的最大页数，即 256 MiB
+pub const ASLR_MAX_PAGES: usize = 1 << 16;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const CLOCK_FREQ: usize = 12500000;
+/// 内核 panic 后等待多少毫秒再通过 SBI 重启。为 `None` 时直接关机
+pub const PANIC_REBOOT_DELAY_MS: Option<usize> = None;
+/// 默认的可执行文件搜索路径，用冒号分隔
+pub const DEFAULT_EXEC_PATH: &str = "/bin:/";
+/// initproc 的初始环境变量。此外还有由内核命令行 `path=` 选项决定的 `PATH`
+pub const INIT_ENV: &[&str] = &["HOME=/", "TERM=vt100"];
+/// 默认的内核命令行，选项含义见 `cmdline` 模块
+pub const DEFAULT_CMDLINE: &str = "init=ch6b_initproc selftest";
+/// 设备树不可用时使用的 virtio-mmio 设备区间
 pub const MMIO: &[(usize, usize)] = &[(0x10001000, 0x1000)];
+/// 内核支持的 hart 数，hart 编号需要小于它
+pub const MAX_HARTS: usize = 8;
+/// 调度跟踪缓冲区最多保存的事件数，满后覆盖最旧的事件
+pub const KTRACE_BUFFER_LEN: usize = 4096;
+/// 每个进程最多同时打开的文件数
+pub const MAX_FD: usize = 256;
+/// 每个进程最多创建的 POSIX 定时器数
+pub const MAX_POSIX_TIMERS: usize = 32;
+/// 初始进程的文件创建掩码，子进程继承父进程的掩码
+pub const DEFAULT_UMASK: u16 = 0o022;
+/// 本机的 IPv4 地址和默认网关，与 QEMU 用户模式网络（slirp）的默认配置一致
+pub const NET_LOCAL_IP: [u8; 4] = [10, 0, 2, 15];
+pub const NET_GATEWAY: [u8; 4] = [10, 0, 2, 2];
+/// 本机所在网段的前缀长度，网段之外的地址经网关转发
+pub const NET_PREFIX_LEN: u32 = 24;
+/// 每个 UDP 套接字最多缓存的未读数据报数，再收到的数据报被丢弃
+pub const UDP_RECV_QUEUE_LEN: usize = 64;
+/// 本机字节流连接（TCP 和 UNIX 域套接字）每个方向的缓冲区大小，缓冲区满时写者阻塞
+pub const STREAM_BUFFER_SIZE: usize = 4096;
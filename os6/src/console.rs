@@ -1,21 +1,162 @@
-//! SBI console driver, for text output
+//! Console output
+//!
+//! 内核的 `print!` 和用户程序写标准输出都经过这里，最终通过 SBI 逐字节输出。
+//!
+//! 用户程序的输出按任务缓存到换行为止再整行输出，多个进程同时输出时不会在一行中间交错。
+//! 任务退出、读标准输入或者缓存的部分超过 [`LINE_MAX`] 时不等换行直接输出。
+//! 打开 pid 标记后，用户程序输出的每一行前加上 `[pid] `。两者都可以用 `sys_console_mode`
+//! 在运行时切换，启动时默认按行缓存，命令行带有 `consoletag` 选项时打开 pid 标记。
 
-use crate::sbi::console_putchar;
+use alloc::{collections::BTreeMap, vec::Vec};
+use bitflags::bitflags;
 use core::fmt::{self, Write};
+use lazy_static::lazy_static;
 
-struct Stdout;
+use crate::{cmdline, sbi::console_putchar, sync::KSpinLock};
 
-impl Write for Stdout {
+/// 每个任务最多缓存的不完整的行的字节数
+const LINE_MAX: usize = 1024;
+
+bitflags! {
+    /// 用户程序输出的处理方式
+    pub struct ConsoleMode: usize {
+        /// 按行缓存每个任务的输出
+        const LINE_BUFFERED = 1;
+        /// 每行前加上输出它的进程的 pid
+        const TAG_PID = 2;
+    }
+}
+
+struct Console {
+    mode: ConsoleMode,
+    /// 各任务尚未输出的不完整的行，按 pid 存放
+    pending: BTreeMap<usize, Vec<u8>>,
+    /// 控制台上最后输出的是否为换行，即下一个字节位于行首
+    at_line_start: bool,
+}
+
+impl Console {
+    fn put(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            console_putchar(byte as usize);
+        }
+        if let Some(&last) = bytes.last() {
+            self.at_line_start = last == b'\n';
+        }
+    }
+    /// 输出进程 `pid` 的输出，需要时在行首加上标记
+    fn put_task(&mut self, pid: usize, bytes: &[u8]) {
+        for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+            if self.at_line_start && self.mode.contains(ConsoleMode::TAG_PID) {
+                write!(self, "[{}] ", pid).unwrap();
+            }
+            self.put(line);
+        }
+    }
+    fn flush(&mut self, pid: usize) {
+        if let Some(pending) = self.pending.remove(&pid) {
+            self.put_task(pid, &pending);
+        }
+    }
+}
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.put(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// 不经过 [`CONSOLE`] 直接输出，用于 `CONSOLE` 已被持有时（例如输出过程中 panic）
+struct RawStdout;
+
+impl Write for RawStdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
+        for &byte in s.as_bytes() {
+            console_putchar(byte as usize);
         }
         Ok(())
     }
 }
 
+lazy_static! {
+    static ref CONSOLE: KSpinLock<Console> = unsafe {
+        KSpinLock::new(
+            "CONSOLE",
+            Console {
+                mode: ConsoleMode::LINE_BUFFERED,
+                pending: BTreeMap::new(),
+                at_line_start: true,
+            },
+        )
+    };
+}
+
+/// 按命令行设置 pid 标记，需要在 `cmdline::init` 之后调用
+pub fn init() {
+    if cmdline::console_tag() {
+        CONSOLE.lock().mode |= ConsoleMode::TAG_PID;
+    }
+}
+
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    match CONSOLE.try_lock() {
+        Some(mut console) => console.write_fmt(args).unwrap(),
+        None => RawStdout.write_fmt(args).unwrap(),
+    }
+}
+
+/// 缓存的输出中现在就应该输出的字节数：到最后一个换行为止，剩下的部分过长时全部输出
+fn ready_len(pending: &[u8]) -> usize {
+    let complete = pending
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |i| i + 1);
+    if pending.len() - complete > LINE_MAX {
+        pending.len()
+    } else {
+        complete
+    }
+}
+
+/// 进程 `pid` 写标准输出
+pub fn write_task(pid: usize, bytes: &[u8]) {
+    let mut console = CONSOLE.lock();
+    if !console.mode.contains(ConsoleMode::LINE_BUFFERED) {
+        console.put_task(pid, bytes);
+        return;
+    }
+    let mut pending = console.pending.remove(&pid).unwrap_or_default();
+    pending.extend_from_slice(bytes);
+    let complete = ready_len(&pending);
+    console.put_task(pid, &pending[..complete]);
+    pending.drain(..complete);
+    if !pending.is_empty() {
+        console.pending.insert(pid, pending);
+    }
+}
+
+/// 输出进程 `pid` 缓存的不完整的行，在它退出或者等待输入之前调用
+pub fn flush_task(pid: usize) {
+    CONSOLE.lock().flush(pid);
+}
+
+pub fn mode() -> ConsoleMode {
+    CONSOLE.lock().mode
+}
+
+/// 设置用户程序输出的处理方式，返回原先的设置。关闭按行缓存时输出所有缓存的内容
+pub fn set_mode(mode: ConsoleMode) -> ConsoleMode {
+    let mut console = CONSOLE.lock();
+    let old = console.mode;
+    console.mode = mode;
+    if !mode.contains(ConsoleMode::LINE_BUFFERED) {
+        let pids: Vec<usize> = console.pending.keys().copied().collect();
+        for pid in pids {
+            console.flush(pid);
+        }
+    }
+    old
 }
 
 #[macro_export]
@@ -61,9 +202,7 @@ pub fn print_colorized(
     foreground_color: impl Into<u8>,
     background_color: impl Into<u8>,
 ) {
-    Stdout
-        .write_fmt(colorize!(args, foreground_color, background_color))
-        .unwrap();
+    print(colorize!(args, foreground_color, background_color));
 }
 
 #[macro_export]
@@ -128,3 +267,23 @@ impl From<ANSICON> for u8 {
         con as Self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test_case]
+    fn only_complete_lines_are_ready() {
+        assert_eq!(ready_len(b"$ "), 0);
+        assert_eq!(ready_len(b"a\nb\nc"), 4);
+        assert_eq!(ready_len(b"line\n"), 5);
+        let long = vec![b'x'; LINE_MAX + 1];
+        assert_eq!(
+            ready_len(&long),
+            LINE_MAX + 1,
+            "overlong lines are not held back"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! 块设备的 I/O 调度器（电梯算法）
+//!
+//! 块缓存换出或同步一个块时立即调用设备的 `write_block`，多个进程交替读写文件时，
+//! 发给设备的请求在磁盘上来回跳动。调度器位于块缓存与 virtio 驱动之间：
+//!
+//! - 写请求先进入按 LBA 排序的队列。同一个块的多次写入合并为最后一次
+//! - 读请求优先，直接发给设备；要读的块还在队列中时直接从队列复制，不访问设备
+//! - 队列满了，或者块缓存同步后调用 [`BlockDevice::flush`] 时，按 C-LOOK 顺序派发队列：
+//!   从上一次访问的位置向 LBA 增大的方向依次写入，到头后回到最小的 LBA 继续
+//!
+//! virtio-drivers 每次只能读写一个块，所以 LBA 连续的请求仍然逐块发出，`runs` 统计这样的连续段，
+//! 用来衡量能否进一步合并。命令行选项 `iosched=noop` 关闭调度，写请求直接发给设备，便于对比。
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use easy_fs::{BlockDevice, BLOCK_SZ};
+
+use crate::{cmdline, sync::UPSafeCell};
+
+/// 队列中最多积压的写请求数
+const MAX_PENDING: usize = 32;
+
+/// 调度器的统计，可以从 `/proc/iosched` 读出
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// 收到的读请求数
+    pub reads: usize,
+    /// 收到的写请求数
+    pub writes: usize,
+    /// 直接从队列中满足的读请求数
+    pub read_hits: usize,
+    /// 被之后对同一个块的写入合并掉的写请求数
+    pub write_merges: usize,
+    /// 实际发给设备的读写请求数
+    pub dispatched: usize,
+    /// 派发队列的次数
+    pub batches: usize,
+    /// 派发时 LBA 连续的段数
+    pub runs: usize,
+    /// 相邻两次设备请求的 LBA 之差的总和，近似于磁头移动的距离
+    pub seek_distance: usize,
+    /// 队列曾经达到的最大长度
+    pub max_depth: usize,
+}
+
+struct Queue {
+    /// 以 LBA 为键的待写入的块
+    pending: BTreeMap<usize, Box<[u8; BLOCK_SZ]>>,
+    /// 最近一次设备请求的 LBA
+    head: usize,
+    stats: IoStats,
+}
+
+impl Queue {
+    /// 记录一次发给设备的请求
+    fn account(&mut self, block_id: usize) {
+        self.stats.dispatched += 1;
+        self.stats.seek_distance += block_id.abs_diff(self.head);
+        self.head = block_id;
+    }
+}
+
+/// 在块设备 `D` 之上调度写请求
+pub struct Elevator<D> {
+    device: D,
+    queue: UPSafeCell<Queue>,
+}
+
+impl<D: BlockDevice> Elevator<D> {
+    pub fn new(device: D) -> Self {
+        Self {
+            device,
+            queue: unsafe {
+                UPSafeCell::new(Queue {
+                    pending: BTreeMap::new(),
+                    head: 0,
+                    stats: IoStats::default(),
+                })
+            },
+        }
+    }
+    pub fn stats(&self) -> IoStats {
+        self.queue.exclusive_access().stats
+    }
+    /// 按 C-LOOK 顺序写入队列中所有的块
+    fn dispatch(&self, queue: &mut Queue) {
+        if queue.pending.is_empty() {
+            return;
+        }
+        queue.stats.batches += 1;
+        // 队列按 LBA 从小到大迭代，先写 head 及其之上的部分，再回到最小的 LBA
+        let head = queue.head;
+        let (above, below): (Vec<_>, Vec<_>) = core::mem::take(&mut queue.pending)
+            .into_iter()
+            .partition(|&(block_id, _)| block_id >= head);
+        let mut last = None;
+        for (block_id, data) in above.into_iter().chain(below) {
+            if last.map_or(true, |last| last + 1 != block_id) {
+                queue.stats.runs += 1;
+            }
+            last = Some(block_id);
+            queue.account(block_id);
+            self.device.write_block(block_id, &data[..]);
+        }
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Elevator<D> {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let mut queue = self.queue.exclusive_access();
+        queue.stats.reads += 1;
+        if let Some(data) = queue.pending.get(&block_id) {
+            buf.copy_from_slice(&data[..]);
+            queue.stats.read_hits += 1;
+            return;
+        }
+        queue.account(block_id);
+        self.device.read_block(block_id, buf);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut queue = self.queue.exclusive_access();
+        queue.stats.writes += 1;
+        if !cmdline::io_elevator() {
+            queue.account(block_id);
+            self.device.write_block(block_id, buf);
+            return;
+        }
+        let mut data = Box::new([0u8; BLOCK_SZ]);
+        data.copy_from_slice(buf);
+        if queue.pending.insert(block_id, data).is_some() {
+            queue.stats.write_merges += 1;
+        }
+        queue.stats.max_depth = queue.stats.max_depth.max(queue.pending.len());
+        if queue.pending.len() >= MAX_PENDING {
+            self.dispatch(&mut queue);
+        }
+    }
+    /// panic 后关机时调度器可能正被使用，这时跳过而不是重入
+    fn flush(&self) {
+        if let Some(mut queue) = self.queue.try_exclusive_access() {
+            self.dispatch(&mut queue);
+        }
+        self.device.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 记录写入顺序的内存磁盘
+    struct MemDisk {
+        blocks: UPSafeCell<BTreeMap<usize, [u8; BLOCK_SZ]>>,
+        order: UPSafeCell<Vec<usize>>,
+    }
+
+    impl BlockDevice for MemDisk {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            let blocks = self.blocks.exclusive_access();
+            buf.copy_from_slice(blocks.get(&block_id).unwrap_or(&[0; BLOCK_SZ]));
+        }
+        fn write_block(&self, block_id: usize, buf: &[u8]) {
+            let mut data = [0; BLOCK_SZ];
+            data.copy_from_slice(buf);
+            self.blocks.exclusive_access().insert(block_id, data);
+            self.order.exclusive_access().push(block_id);
+        }
+    }
+
+    #[test_case]
+    fn dispatches_in_c_look_order_and_merges_writes() {
+        let elevator = Elevator::new(MemDisk {
+            blocks: unsafe { UPSafeCell::new(BTreeMap::new()) },
+            order: unsafe { UPSafeCell::new(Vec::new()) },
+        });
+        let mut buf = [0u8; BLOCK_SZ];
+        // 读请求把 head 移到 50
+        elevator.read_block(50, &mut buf);
+        for (block_id, byte) in [(70, 1), (10, 2), (51, 3), (70, 4), (11, 5)] {
+            elevator.write_block(block_id, &[byte; BLOCK_SZ]);
+        }
+        // 还在队列中的块从队列中读出
+        elevator.read_block(70, &mut buf);
+        assert_eq!(buf[0], 4);
+        assert!(elevator.device.order.exclusive_access().is_empty());
+        elevator.flush();
+        assert_eq!(*elevator.device.order.exclusive_access(), [51, 70, 10, 11]);
+        let stats = elevator.stats();
+        assert_eq!((stats.reads, stats.read_hits), (2, 1));
+        assert_eq!(
+            (stats.writes, stats.write_merges, stats.dispatched),
+            (5, 1, 5)
+        );
+        assert_eq!((stats.batches, stats.runs, stats.max_depth), (1, 3, 4));
+    }
+}
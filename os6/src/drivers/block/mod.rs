@@ -1,12 +1,90 @@
+mod elevator;
+mod partition;
 mod virtio_blk;
 
 use alloc::sync::Arc;
-use easy_fs::BlockDevice;
+use alloc::vec::Vec;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+use elevator::Elevator;
 use lazy_static::*;
+use partition::{Partition, PartitionEntry, PART_TYPE_EASY_FS};
 type BlockDeviceImpl = virtio_blk::VirtIOBlock;
 
+pub use elevator::IoStats;
+
+use super::{virtio, DeviceId, Driver};
+use crate::{dtb::Device, sync::UPSafeCell};
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-blk",
+    ids: &[DeviceId::VirtIO(virtio::DEVICE_BLOCK)],
+    probe,
+};
+
+/// 探测到的第一个块设备，[`ELEVATOR`] 初始化时取走
+static PROBED: UPSafeCell<Option<BlockDeviceImpl>> = unsafe { UPSafeCell::new(None) };
+
+/// 只使用第一个块设备
+fn probe(device: &Device) -> bool {
+    let mut probed = PROBED.exclusive_access();
+    if probed.is_some() {
+        return false;
+    }
+    let transport = match virtio::MmioTransport::new(device.base) {
+        Some(transport) => transport,
+        None => return false,
+    };
+    *probed = BlockDeviceImpl::new(transport);
+    if probed.is_some() {
+        virtio::register_irq(device, transport, || {});
+    }
+    probed.is_some()
+}
+
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// 经过 I/O 调度器的块设备
+    static ref ELEVATOR: Arc<Elevator<BlockDeviceImpl>> = Arc::new(Elevator::new(
+        PROBED
+            .exclusive_access()
+            .take()
+            .expect("no virtio block device found")
+    ));
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = ELEVATOR.clone();
+    /// 磁盘的分区表，没有分区表时为空
+    static ref PARTITIONS: Vec<PartitionEntry> = {
+        let mut sector = [0u8; BLOCK_SZ];
+        BLOCK_DEVICE.read_block(0, &mut sector);
+        let entries = partition::parse_mbr(&sector).unwrap_or_default();
+        for entry in entries.iter() {
+            log::info!(
+                "[kernel] partition {}: type {:#04x}, start {}, {} blocks",
+                entry.index,
+                entry.kind,
+                entry.start,
+                entry.blocks
+            );
+        }
+        entries
+    };
+}
+
+/// 第一个类型为 `kind` 的分区
+pub fn find_partition(kind: u8) -> Option<Arc<dyn BlockDevice>> {
+    let entry = PARTITIONS.iter().find(|entry| entry.kind == kind)?;
+    Some(Arc::new(Partition::new(BLOCK_DEVICE.clone(), entry)))
+}
+
+/// 根文件系统所在的块设备：easy-fs 分区，磁盘没有分区表时是整个磁盘
+pub fn root_device() -> Arc<dyn BlockDevice> {
+    if PARTITIONS.is_empty() {
+        return BLOCK_DEVICE.clone();
+    }
+    find_partition(PART_TYPE_EASY_FS).expect("no easy-fs partition on the disk")
+}
+
+/// I/O 调度器的统计
+pub fn io_stats() -> IoStats {
+    ELEVATOR.stats()
 }
 
 #[allow(unused)]
@@ -0,0 +1,181 @@
+//! 块设备分区
+//!
+//! 磁盘的第 0 个扇区可以是 MBR 分区表：偏移 446 处的 4 个 16 字节的表项，
+//! 以及偏移 510 处的签名 `55 AA`。每个表项中与分区有关的只有类型（偏移 4）、
+//! 起始 LBA（偏移 8）和扇区数（偏移 12），后两者为小端序。不支持扩展分区和 GPT，
+//! GPT 的保护性 MBR 只有一个类型为 0xEE 的表项，不会被当作任何一种分区。
+//!
+//! 约定的分区类型：
+//!
+//! - 0x83：easy-fs 根文件系统
+//! - 0x82：交换区，目前还没有使用者
+//! - 0xda：不含文件系统的暂存区
+//!
+//! 没有分区表的磁盘整个作为根文件系统，`easy-fs-fuse` 打包的镜像就是这样。
+
+use alloc::{sync::Arc, vec::Vec};
+use core::convert::TryInto;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+
+/// easy-fs 根文件系统的分区类型
+pub const PART_TYPE_EASY_FS: u8 = 0x83;
+/// 交换区的分区类型
+#[allow(unused)]
+pub const PART_TYPE_SWAP: u8 = 0x82;
+/// 暂存区的分区类型
+#[allow(unused)]
+pub const PART_TYPE_SCRATCH: u8 = 0xda;
+
+/// 分区表中的一项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// 表项的序号，从 1 开始
+    pub index: usize,
+    pub kind: u8,
+    /// 起始扇区
+    pub start: usize,
+    /// 扇区数
+    pub blocks: usize,
+}
+
+/// 解析第 0 个扇区中的 MBR 分区表，没有签名时返回 `None`。忽略类型为 0 或扇区数为 0 的空表项
+pub fn parse_mbr(sector: &[u8; BLOCK_SZ]) -> Option<Vec<PartitionEntry>> {
+    if sector[510..512] != [0x55, 0xaa] {
+        return None;
+    }
+    let le32 = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap()) as usize;
+    let entries = sector[446..510]
+        .chunks_exact(16)
+        .enumerate()
+        .map(|(i, entry)| PartitionEntry {
+            index: i + 1,
+            kind: entry[4],
+            start: le32(&entry[8..12]),
+            blocks: le32(&entry[12..16]),
+        })
+        .filter(|entry| entry.kind != 0 && entry.blocks != 0)
+        .collect();
+    Some(entries)
+}
+
+/// 块设备上的一个分区，块号相对于分区的起始扇区
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    start: usize,
+    blocks: usize,
+}
+
+impl Partition {
+    pub fn new(device: Arc<dyn BlockDevice>, entry: &PartitionEntry) -> Self {
+        Self {
+            device,
+            start: entry.start,
+            blocks: entry.blocks,
+        }
+    }
+    /// 分区内的块号对应的设备块号。越过分区的末尾说明文件系统已经损坏，记录错误并返回 `None`，
+    /// 由调用者放弃这次访问，以免写坏相邻的分区
+    fn device_block(&self, block_id: usize) -> Option<usize> {
+        if block_id < self.blocks {
+            Some(self.start + block_id)
+        } else {
+            log::error!(
+                "[kernel] block {} is beyond the partition of {} blocks",
+                block_id,
+                self.blocks
+            );
+            None
+        }
+    }
+}
+
+/// 越过分区末尾的读得到全 0 的块，写被丢弃
+impl BlockDevice for Partition {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        match self.device_block(block_id) {
+            Some(block_id) => self.device.read_block(block_id, buf),
+            None => buf.fill(0),
+        }
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        if let Some(block_id) = self.device_block(block_id) {
+            self.device.write_block(block_id, buf)
+        }
+    }
+    fn flush(&self) {
+        self.device.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::UPSafeCell;
+
+    /// 记录访问过哪些块、每个块的内容都是块号的磁盘
+    struct Disk(UPSafeCell<Vec<usize>>);
+
+    impl BlockDevice for Disk {
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+            self.0.exclusive_access().push(block_id);
+            buf.fill(block_id as u8);
+        }
+        fn write_block(&self, block_id: usize, _buf: &[u8]) {
+            self.0.exclusive_access().push(block_id);
+        }
+    }
+
+    #[test_case]
+    fn accesses_beyond_the_partition_are_dropped() {
+        let disk = Arc::new(Disk(unsafe { UPSafeCell::new(Vec::new()) }));
+        let entry = PartitionEntry {
+            index: 1,
+            kind: PART_TYPE_EASY_FS,
+            start: 8,
+            blocks: 4,
+        };
+        let partition = Partition::new(disk.clone(), &entry);
+        let mut buf = [0xffu8; BLOCK_SZ];
+        partition.read_block(3, &mut buf);
+        assert_eq!(buf[0], 11);
+        partition.read_block(4, &mut buf);
+        assert_eq!(buf, [0; BLOCK_SZ]);
+        partition.write_block(4, &buf);
+        assert_eq!(*disk.0.exclusive_access(), [11]);
+    }
+
+    #[test_case]
+    fn parses_mbr_entries() {
+        let mut sector = [0u8; BLOCK_SZ];
+        assert_eq!(parse_mbr(&sector), None);
+        sector[510] = 0x55;
+        sector[511] = 0xaa;
+        let mut set_entry = |i: usize, kind: u8, start: u32, blocks: u32| {
+            let entry = &mut sector[446 + 16 * i..462 + 16 * i];
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&blocks.to_le_bytes());
+        };
+        set_entry(0, PART_TYPE_EASY_FS, 2048, 32768);
+        set_entry(2, PART_TYPE_SWAP, 34816, 8192);
+        set_entry(3, PART_TYPE_SCRATCH, 43008, 0);
+        let entries = parse_mbr(&sector).unwrap();
+        assert_eq!(
+            entries,
+            [
+                PartitionEntry {
+                    index: 1,
+                    kind: PART_TYPE_EASY_FS,
+                    start: 2048,
+                    blocks: 32768
+                },
+                PartitionEntry {
+                    index: 3,
+                    kind: PART_TYPE_SWAP,
+                    start: 34816,
+                    blocks: 8192
+                },
+            ]
+        );
+    }
+}
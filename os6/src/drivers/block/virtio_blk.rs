@@ -1,24 +1,9 @@
 use super::BlockDevice;
-use crate::mm::{
-    address::{PhysAddr, PhysPageNum, VirtAddr},
-    frame_allocator::{self, FrameTracker},
-    memory_set,
-    page_table::PageTable,
-};
-use crate::sync::UPSafeCell;
-use alloc::vec::Vec;
-use lazy_static::*;
-use virtio_drivers::{VirtIOBlk, VirtIOHeader};
-
-#[allow(unused)]
-const VIRTIO0: usize = 0x10001000;
+use crate::{drivers::virtio::MmioTransport, sync::UPSafeCell};
+use virtio_drivers::VirtIOBlk;
 
 pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static>>);
 
-lazy_static! {
-    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = unsafe { UPSafeCell::new(Vec::new()) };
-}
-
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
         self.0
@@ -35,46 +20,9 @@ impl BlockDevice for VirtIOBlock {
 }
 
 impl VirtIOBlock {
-    #[allow(unused)]
-    pub fn new() -> Self {
-        unsafe {
-            Self(UPSafeCell::new(
-                VirtIOBlk::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            ))
-        }
+    /// 初始化 `transport` 处的 virtio 块设备，失败时返回 `None`
+    pub fn new(transport: MmioTransport) -> Option<Self> {
+        let blk = VirtIOBlk::new(transport.header()).ok()?;
+        Some(Self(unsafe { UPSafeCell::new(blk) }))
     }
 }
-
-#[no_mangle]
-pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
-    let mut ppn_base = PhysPageNum(0);
-    for i in 0..pages {
-        let frame = frame_allocator::frame_alloc().unwrap();
-        if i == 0 {
-            ppn_base = frame.ppn;
-        }
-        assert_eq!(frame.ppn.0, ppn_base.0 + i);
-        QUEUE_FRAMES.exclusive_access().push(frame);
-    }
-    ppn_base.page_start()
-}
-
-#[no_mangle]
-pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, pages: usize) -> i32 {
-    let mut ppn_base: PhysPageNum = pa.ppn();
-    for _ in 0..pages {
-        frame_allocator::frame_dealloc(ppn_base);
-        ppn_base.0 += 1;
-    }
-    0
-}
-
-#[no_mangle]
-pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
-    VirtAddr(paddr.0)
-}
-
-#[no_mangle]
-pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
-    PageTable::from_satp(memory_set::kernel_stap()).translate_va_to_pa(vaddr)
-}
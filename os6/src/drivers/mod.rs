@@ -1,3 +1,60 @@
+//! 设备驱动
+//!
+//! 每个驱动在自己的模块里定义一个 [`Driver`]，写明它能驱动的设备和探测函数，再登记到 [`DRIVERS`] 中。
+//! [`init`] 在解析设备树之后遍历所有设备，交给第一个匹配且探测成功的驱动，
+//! 驱动在探测函数中把设备登记到自己的子系统里。新增驱动时不需要修改 `main` 中的初始化代码。
+
 mod block;
+pub mod net;
+pub mod plic;
+pub mod rng;
+mod virtio;
+
+pub use block::{io_stats, root_device, BLOCK_DEVICE};
+pub use net::net_device;
+pub use rng::rng_device;
+
+use crate::dtb::{self, Device, DeviceKind};
+
+/// 驱动用来匹配设备的标识
+pub enum DeviceId {
+    /// 设备树节点的 `compatible`
+    Compatible(&'static str),
+    /// virtio-mmio 设备的设备 ID，见 [`virtio`] 中的 `DEVICE_*`
+    VirtIO(u32),
+}
+
+impl DeviceId {
+    fn matches(&self, device: &Device) -> bool {
+        match *self {
+            Self::Compatible(compatible) => device.compatible == compatible,
+            Self::VirtIO(id) => {
+                device.kind == DeviceKind::VirtIO
+                    && virtio::MmioTransport::new(device.base)
+                        .map_or(false, |transport| transport.device_id() == id)
+            }
+        }
+    }
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub ids: &'static [DeviceId],
+    /// 初始化设备并登记到子系统中。设备不能使用，或者子系统只需要一个设备而已经有了时返回 false
+    pub probe: fn(&Device) -> bool,
+}
+
+/// 所有驱动
+static DRIVERS: &[&Driver] = &[&plic::DRIVER, &block::DRIVER, &net::DRIVER, &rng::DRIVER];
 
-pub use block::BLOCK_DEVICE;
+/// 为设备树中的每个设备探测驱动。需要在 MMIO 设备映射到内核地址空间、页帧分配器可用之后调用
+pub fn init() {
+    for device in dtb::devices() {
+        let driver = DRIVERS.iter().find(|driver| {
+            driver.ids.iter().any(|id| id.matches(&device)) && (driver.probe)(&device)
+        });
+        if let Some(driver) = driver {
+            log::info!("[kernel] {} at {:#x}", driver.name, device.base);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+mod virtio_net;
+
+use alloc::sync::Arc;
+
+use super::{virtio, DeviceId, Driver};
+use crate::{dtb::Device, sync::UPSafeCell};
+
+/// 以太网帧的收发设备
+pub trait NetDevice: Send + Sync {
+    /// 设备的 MAC 地址
+    fn mac(&self) -> [u8; 6];
+    /// 发送一个以太网帧
+    fn send(&self, frame: &[u8]);
+    /// 不阻塞地接收一个以太网帧，返回帧的长度。没有收到帧时返回 `None`
+    fn recv(&self, buf: &mut [u8]) -> Option<usize>;
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-net",
+    ids: &[DeviceId::VirtIO(virtio::DEVICE_NET)],
+    probe,
+};
+
+static NET_DEVICE: UPSafeCell<Option<Arc<dyn NetDevice>>> = unsafe { UPSafeCell::new(None) };
+
+/// 只使用第一块网卡
+fn probe(device: &Device) -> bool {
+    let mut net = NET_DEVICE.exclusive_access();
+    if net.is_some() {
+        return false;
+    }
+    let transport = match virtio::MmioTransport::new(device.base) {
+        Some(transport) => transport,
+        None => return false,
+    };
+    *net = virtio_net::VirtIONetDevice::new(transport)
+        .map(|device| Arc::new(device) as Arc<dyn NetDevice>);
+    if net.is_some() {
+        // 收到帧时立即交给协议栈，不必等到下一次时钟中断
+        virtio::register_irq(device, transport, crate::net::poll);
+    }
+    net.is_some()
+}
+
+/// 探测到的第一块网卡，QEMU 没有配置网卡时为 `None`
+pub fn net_device() -> Option<Arc<dyn NetDevice>> {
+    NET_DEVICE.exclusive_access().clone()
+}
@@ -0,0 +1,33 @@
+use virtio_drivers::VirtIONet;
+
+use super::NetDevice;
+use crate::{drivers::virtio::MmioTransport, sync::UPSafeCell};
+
+pub struct VirtIONetDevice(UPSafeCell<VirtIONet<'static>>);
+
+impl VirtIONetDevice {
+    /// 初始化 `transport` 处的 virtio 网卡，失败时返回 `None`
+    pub fn new(transport: MmioTransport) -> Option<Self> {
+        let net = VirtIONet::new(transport.header()).ok()?;
+        Some(Self(unsafe { UPSafeCell::new(net) }))
+    }
+}
+
+impl NetDevice for VirtIONetDevice {
+    fn mac(&self) -> [u8; 6] {
+        self.0.exclusive_access().mac()
+    }
+    fn send(&self, frame: &[u8]) {
+        self.0
+            .exclusive_access()
+            .send(frame)
+            .expect("Error when sending with VirtIONet");
+    }
+    fn recv(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut net = self.0.exclusive_access();
+        if !net.can_recv() {
+            return None;
+        }
+        net.recv(buf).ok()
+    }
+}
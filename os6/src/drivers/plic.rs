@@ -0,0 +1,132 @@
+//! PLIC（平台级中断控制器）
+//!
+//! 外部设备的中断线都接到 PLIC 上。PLIC 为每个 hart 的每种特权级各提供一个上下文，
+//! QEMU virt 上 hart `h` 的 S 态上下文编号为 `2h + 1`。每个上下文有一组中断使能位和一个优先级阈值，
+//! 已使能且优先级高于阈值的中断到来时，PLIC 向这个上下文发出外部中断。
+//! 处理时先读 claim 寄存器得到中断号，PLIC 在处理完成前不会再次发出这个中断；
+//! 处理完后把中断号写回同一个寄存器（complete）。
+//!
+//! 驱动用 [`register_irq`] 为设备的中断号登记处理函数，没有处理函数的中断保持屏蔽，
+//! 例如还没有中断驱动的串口。系统调用执行期间外部中断被屏蔽（见 [`crate::task::preempt`]），
+//! 外部中断在用户态陷入时由 `trap_handler` 处理，idle 控制流从 `wfi` 醒来后也会处理一次。
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use lazy_static::*;
+use riscv::register::sie;
+
+use super::{DeviceId, Driver};
+use crate::{dtb::Device, mm::address::phys_to_virt, sync::UPSafeCell};
+
+// 寄存器的偏移
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM: usize = 0x4;
+
+/// 登记的中断的优先级。设备之间不区分优先级，只要高于阈值 0 即可
+const IRQ_PRIORITY: u32 = 1;
+/// 当前 hart 的上下文还没有初始化
+const NO_CONTEXT: usize = usize::MAX;
+
+pub static DRIVER: Driver = Driver {
+    name: "plic",
+    ids: &[
+        DeviceId::Compatible("riscv,plic0"),
+        DeviceId::Compatible("sifive,plic-1.0.0"),
+    ],
+    probe,
+};
+
+/// 中断处理函数。调用时已经持有 PLIC 的 claim，不需要再访问 PLIC
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+/// PLIC 的寄存器在线性映射区中的地址，为 0 表示没有 PLIC
+static BASE: AtomicUsize = AtomicUsize::new(0);
+/// 当前 hart 的 S 态上下文编号
+static HART_CONTEXT: AtomicUsize = AtomicUsize::new(NO_CONTEXT);
+
+lazy_static! {
+    /// 中断号到处理函数的映射
+    static ref HANDLERS: UPSafeCell<BTreeMap<u32, IrqHandler>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// 只使用第一个 PLIC
+fn probe(device: &Device) -> bool {
+    BASE.compare_exchange(
+        0,
+        phys_to_virt(device.base),
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    )
+    .is_ok()
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { ptr::read_volatile((BASE.load(Ordering::Relaxed) + offset) as *const u32) }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { ptr::write_volatile((BASE.load(Ordering::Relaxed) + offset) as *mut u32, value) }
+}
+
+/// 设置 irq 的优先级，并在上下文 `context` 中使能它
+fn enable(context: usize, irq: u32) {
+    write(PRIORITY + 4 * irq as usize, IRQ_PRIORITY);
+    let offset = ENABLE + ENABLE_STRIDE * context + 4 * (irq as usize / 32);
+    write(offset, read(offset) | 1 << (irq % 32));
+}
+
+/// 初始化 hart `hartid` 的 S 态上下文并打开外部中断，之前登记的中断在这里使能。
+/// 需要在 [`super::init`] 之后调用，没有 PLIC 时什么也不做
+pub fn init_hart(hartid: usize) {
+    if BASE.load(Ordering::Relaxed) == 0 {
+        log::warn!("[kernel] no PLIC, external interrupts stay disabled");
+        return;
+    }
+    let context = 2 * hartid + 1;
+    write(CONTEXT + CONTEXT_STRIDE * context + THRESHOLD, 0);
+    for &irq in HANDLERS.exclusive_access().keys() {
+        enable(context, irq);
+    }
+    HART_CONTEXT.store(context, Ordering::Relaxed);
+    unsafe { sie::set_sext() };
+}
+
+/// 为中断号 irq 登记处理函数，替换原有的处理函数。当前 hart 的上下文已经初始化时立即使能
+pub fn register_irq(irq: u32, handler: IrqHandler) {
+    HANDLERS.exclusive_access().insert(irq, handler);
+    let context = HART_CONTEXT.load(Ordering::Relaxed);
+    if context != NO_CONTEXT {
+        enable(context, irq);
+    }
+}
+
+/// 处理所有待处理的外部中断
+pub fn handle_interrupts() {
+    let context = HART_CONTEXT.load(Ordering::Relaxed);
+    if context == NO_CONTEXT {
+        return;
+    }
+    let claim = CONTEXT + CONTEXT_STRIDE * context + CLAIM;
+    loop {
+        let irq = read(claim);
+        if irq == 0 {
+            break;
+        }
+        // 处理函数可能登记新的中断，调用前释放处理函数表
+        let handler = HANDLERS.exclusive_access().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => log::warn!("[kernel] unexpected external interrupt {}", irq),
+        }
+        write(claim, irq);
+    }
+}
@@ -0,0 +1,43 @@
+mod virtio_rng;
+
+use alloc::sync::Arc;
+
+use super::{virtio, DeviceId, Driver};
+use crate::{dtb::Device, sync::UPSafeCell};
+
+/// 硬件随机数发生器
+pub trait RngDevice: Send + Sync {
+    /// 用设备产生的随机字节填充 buf 的开头，返回填充的字节数。设备出错时可能少于 buf 的长度
+    fn fill(&self, buf: &mut [u8]) -> usize;
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-rng",
+    ids: &[DeviceId::VirtIO(virtio::DEVICE_ENTROPY)],
+    probe,
+};
+
+static RNG_DEVICE: UPSafeCell<Option<Arc<dyn RngDevice>>> = unsafe { UPSafeCell::new(None) };
+
+/// 只使用第一个熵源设备
+fn probe(device: &Device) -> bool {
+    let mut rng = RNG_DEVICE.exclusive_access();
+    if rng.is_some() {
+        return false;
+    }
+    let transport = match virtio::MmioTransport::new(device.base) {
+        Some(transport) => transport,
+        None => return false,
+    };
+    *rng = virtio::probe::<virtio_rng::VirtIORngDevice>(transport)
+        .map(|device| Arc::new(device) as Arc<dyn RngDevice>);
+    if rng.is_some() {
+        virtio::register_irq(device, transport, || {});
+    }
+    rng.is_some()
+}
+
+/// 探测到的第一个熵源设备，QEMU 没有配置时为 `None`
+pub fn rng_device() -> Option<Arc<dyn RngDevice>> {
+    RNG_DEVICE.exclusive_access().clone()
+}
@@ -0,0 +1,91 @@
+//! virtio 熵源设备（virtio-rng）
+//!
+//! virtio-drivers 没有提供这种设备，这里用 [`crate::drivers::virtio`] 中的传输层和队列实现。
+//! 设备只有一个请求队列：驱动放入一块设备可写的缓冲区，设备填入随机字节后把它放回已用环。
+//! 一次只有一个请求。系统调用执行期间外部中断被屏蔽（见 [`crate::task::preempt`]），所以轮询已用环等待设备完成。
+
+use super::RngDevice;
+use crate::{
+    config::PAGE_SIZE,
+    drivers::virtio::{self, DmaBuffer, MmioTransport, VirtQueue, VirtioDevice},
+    sync::UPSafeCell,
+};
+
+/// 队列的描述符个数
+const QUEUE_SIZE: u16 = 4;
+/// 等待设备完成一次请求时最多轮询的次数，超过后认为设备已经坏了
+const POLL_LIMIT: usize = 1 << 24;
+
+struct Inner {
+    transport: MmioTransport,
+    queue: VirtQueue,
+    /// 设备写入随机字节的缓冲区
+    buf: DmaBuffer,
+    /// 设备没有在限定时间内完成请求，之后不再使用它
+    broken: bool,
+}
+
+impl Inner {
+    /// 请求 len 个随机字节，放在缓冲区的开头，返回设备实际写入的字节数
+    fn request(&mut self, len: usize) -> Option<usize> {
+        if self.broken {
+            return None;
+        }
+        self.queue.add(&[(self.buf.paddr(), len, true)])?;
+        self.transport.notify(0);
+        let mut spins = 0;
+        while !self.queue.can_pop() {
+            spins += 1;
+            if spins == POLL_LIMIT {
+                log::warn!("[kernel] virtio-rng does not respond, disabled");
+                self.broken = true;
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+        let (_, written) = self.queue.pop_used()?;
+        self.transport.ack_interrupt();
+        Some((written as usize).min(len))
+    }
+}
+
+pub struct VirtIORngDevice(UPSafeCell<Inner>);
+
+impl VirtioDevice for VirtIORngDevice {
+    const DEVICE_ID: u32 = virtio::DEVICE_ENTROPY;
+    fn init(transport: MmioTransport, _features: u64) -> Option<Self> {
+        let queue = VirtQueue::new(QUEUE_SIZE)?;
+        if !transport.setup_queue(0, &queue) {
+            return None;
+        }
+        let buf = DmaBuffer::alloc(1)?;
+        Some(Self(unsafe {
+            UPSafeCell::new(Inner {
+                transport,
+                queue,
+                buf,
+                broken: false,
+            })
+        }))
+    }
+}
+
+impl RngDevice for VirtIORngDevice {
+    fn fill(&self, buf: &mut [u8]) -> usize {
+        let mut inner = self.0.exclusive_access();
+        let mut filled = 0;
+        for chunk in buf.chunks_mut(PAGE_SIZE) {
+            let len = match inner.request(chunk.len()) {
+                Some(len) => len,
+                None => break,
+            };
+            let src = unsafe { core::slice::from_raw_parts(inner.buf.as_ptr(), len) };
+            chunk[..len].copy_from_slice(src);
+            filled += len;
+            if len < chunk.len() {
+                break;
+            }
+        }
+        filled
+    }
+}
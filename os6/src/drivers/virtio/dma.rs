@@ -0,0 +1,69 @@
+//! 设备 DMA 使用的内存
+//!
+//! 设备直接访问物理内存，缓冲区必须物理上连续，所以从页帧分配器按页分配，而不使用内核堆。
+//! 内核经线性映射访问缓冲区，虚拟地址等于物理地址加上 `PHYS_VIRT_OFFSET`。
+
+use alloc::collections::BTreeMap;
+use lazy_static::*;
+
+use crate::{
+    mm::{
+        address::{phys_to_virt, PhysAddr, VirtAddr},
+        frame_allocator::{self, FrameTracker},
+        memory_set,
+        page_table::PageTable,
+    },
+    sync::UPSafeCell,
+};
+
+/// 物理连续、清零的若干页，释放时归还页帧分配器
+pub struct DmaBuffer {
+    frame: FrameTracker,
+}
+
+impl DmaBuffer {
+    pub fn alloc(pages: usize) -> Option<Self> {
+        let frame = frame_allocator::frame_alloc_contiguous(pages)?;
+        Some(Self { frame })
+    }
+    /// 设备看到的地址
+    pub fn paddr(&self) -> usize {
+        self.frame.ppn.page_start().0
+    }
+    /// 内核访问缓冲区用的指针
+    pub fn as_ptr(&self) -> *mut u8 {
+        phys_to_virt(self.paddr()) as *mut u8
+    }
+}
+
+lazy_static! {
+    /// virtio-drivers 中的驱动申请的 DMA 内存，以物理地址为键
+    static ref DRIVER_BUFFERS: UPSafeCell<BTreeMap<usize, DmaBuffer>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_dma_alloc(pages: usize) -> PhysAddr {
+    let buffer = DmaBuffer::alloc(pages).expect("no contiguous frames for virtio DMA");
+    let paddr = buffer.paddr();
+    DRIVER_BUFFERS.exclusive_access().insert(paddr, buffer);
+    PhysAddr(paddr)
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_dma_dealloc(pa: PhysAddr, _pages: usize) -> i32 {
+    match DRIVER_BUFFERS.exclusive_access().remove(&pa.0) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    paddr.to_virt()
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
+    PageTable::from_satp(memory_set::kernel_stap()).translate_va_to_pa(vaddr)
+}
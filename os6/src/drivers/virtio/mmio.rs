@@ -0,0 +1,118 @@
+//! legacy（版本 1）virtio-mmio 传输层
+//!
+//! QEMU 默认提供 legacy 接口，virtio-drivers 也只支持这一版本。
+
+use core::ptr;
+
+use virtio_drivers::VirtIOHeader;
+
+use super::VirtQueue;
+use crate::{config::PAGE_SIZE, mm::address::phys_to_virt};
+
+/// "virt"
+const MAGIC: u32 = 0x7472_6976;
+
+// 寄存器的偏移
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const HOST_FEATURES: usize = 0x010;
+const HOST_FEATURES_SEL: usize = 0x014;
+const GUEST_FEATURES: usize = 0x020;
+const GUEST_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FAILED: u32 = 128;
+
+/// 一个 virtio-mmio 设备的寄存器。内核地址空间线性映射了设备树中的所有 MMIO 设备，
+/// 寄存器经线性映射访问
+#[derive(Clone, Copy, Debug)]
+pub struct MmioTransport {
+    base: usize,
+}
+
+impl MmioTransport {
+    /// 物理地址 `base` 处是 legacy virtio-mmio 设备时返回它的传输层
+    pub fn new(base: usize) -> Option<Self> {
+        let transport = Self {
+            base: phys_to_virt(base),
+        };
+        if transport.read(MAGIC_VALUE) == MAGIC && transport.read(VERSION) == 1 {
+            Some(transport)
+        } else {
+            None
+        }
+    }
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+    /// 设备 ID，空槽位为 0
+    pub fn device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+    /// 复位设备并协商特性，返回设备和驱动都支持的特性
+    pub fn begin_init(&self, supported: u64) -> u64 {
+        self.write(STATUS, 0);
+        self.write(STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let mut features = 0;
+        for sel in 0..2 {
+            self.write(HOST_FEATURES_SEL, sel);
+            features |= (self.read(HOST_FEATURES) as u64) << (32 * sel);
+        }
+        features &= supported;
+        for sel in 0..2 {
+            self.write(GUEST_FEATURES_SEL, sel);
+            self.write(GUEST_FEATURES, (features >> (32 * sel)) as u32);
+        }
+        self.write(GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        features
+    }
+    /// 设备可以开始工作
+    pub fn finish_init(&self) {
+        self.write(STATUS, self.read(STATUS) | STATUS_DRIVER_OK);
+    }
+    /// 告诉设备驱动放弃了它
+    pub fn fail(&self) {
+        self.write(STATUS, self.read(STATUS) | STATUS_FAILED);
+    }
+    /// 把 `queue` 作为设备的第 `index` 个队列。队列已经在使用或者设备不支持这么长的队列时返回 false
+    pub fn setup_queue(&self, index: u32, queue: &VirtQueue) -> bool {
+        self.write(QUEUE_SEL, index);
+        if self.read(QUEUE_PFN) != 0 || self.read(QUEUE_NUM_MAX) < queue.size() as u32 {
+            return false;
+        }
+        self.write(QUEUE_NUM, queue.size() as u32);
+        self.write(QUEUE_ALIGN, VirtQueue::ALIGN as u32);
+        self.write(QUEUE_PFN, queue.pfn() as u32);
+        true
+    }
+    /// 通知设备第 `index` 个队列中有新的请求
+    pub fn notify(&self, index: u32) {
+        self.write(QUEUE_NOTIFY, index);
+    }
+    /// 确认设备发出的中断，返回中断的原因
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+        status
+    }
+    /// 交给 virtio-drivers 中的驱动使用的寄存器，由它们自己完成初始化
+    pub fn header(&self) -> &'static mut VirtIOHeader {
+        unsafe { &mut *(self.base as *mut VirtIOHeader) }
+    }
+}
@@ -0,0 +1,71 @@
+//! virtio-mmio 设备的公共部分
+//!
+//! - [`mmio`]：legacy virtio-mmio 传输层，负责设备的查找、复位和特性协商
+//! - [`queue`]：split virtqueue
+//! - [`dma`]：设备可以直接访问的物理连续内存，以及 virtio-drivers 需要的内存分配函数
+//!
+//! 设备由 [`super::init`] 按设备 ID 分给各个驱动。块设备和网卡仍然使用 virtio-drivers 中的驱动，
+//! 只借用这里的传输层和 DMA 内存；virtio-drivers 没有的设备实现 [`VirtioDevice`]，
+//! 由 [`probe`] 完成初始化流程，自己只需要建立队列。
+
+mod dma;
+mod mmio;
+mod queue;
+
+pub use dma::DmaBuffer;
+pub use mmio::MmioTransport;
+pub use queue::VirtQueue;
+
+use alloc::sync::Arc;
+
+use super::plic;
+use crate::dtb::Device;
+
+/// virtio 规范中的设备 ID
+pub const DEVICE_NET: u32 = 1;
+pub const DEVICE_BLOCK: u32 = 2;
+pub const DEVICE_ENTROPY: u32 = 4;
+
+/// 由本模块完成初始化的 virtio 设备驱动
+pub trait VirtioDevice: Sized {
+    /// 驱动的设备 ID
+    const DEVICE_ID: u32;
+    /// 驱动支持的特性位
+    const FEATURES: u64 = 0;
+    /// 特性协商之后、设备开始工作之前调用，在这里建立队列。
+    /// `features` 是设备和驱动都支持的特性，失败时返回 `None`
+    fn init(transport: MmioTransport, features: u64) -> Option<Self>;
+}
+
+/// 为 `device` 的中断登记处理函数：确认设备的中断，然后调用 `then`。
+///
+/// 驱动都是轮询队列的，中断只起通知的作用，但不确认的话设备的中断线一直有效，PLIC 会不停地发出中断
+pub fn register_irq(device: &Device, transport: MmioTransport, then: fn()) {
+    if let Some(irq) = device.irq {
+        plic::register_irq(
+            irq,
+            Arc::new(move || {
+                transport.ack_interrupt();
+                then();
+            }),
+        );
+    }
+}
+
+/// 用驱动 `D` 初始化 `transport` 处的设备，设备 ID 不符或者初始化失败时返回 `None`
+pub fn probe<D: VirtioDevice>(transport: MmioTransport) -> Option<D> {
+    if transport.device_id() != D::DEVICE_ID {
+        return None;
+    }
+    let features = transport.begin_init(D::FEATURES);
+    match D::init(transport, features) {
+        Some(device) => {
+            transport.finish_init();
+            Some(device)
+        }
+        None => {
+            transport.fail();
+            None
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! split virtqueue
+//!
+//! legacy 接口要求描述符表、可用环和已用环在物理上连续：描述符表之后紧跟可用环，
+//! 已用环从下一个 [`VirtQueue::ALIGN`] 对齐的地址开始。
+
+use core::{
+    mem::size_of,
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+use super::DmaBuffer;
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+
+/// 描述符链中还有下一个描述符
+const DESC_F_NEXT: u16 = 1;
+/// 缓冲区由设备写入
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+pub struct VirtQueue {
+    mem: DmaBuffer,
+    size: u16,
+    /// 空闲描述符组成的链表的表头
+    free_head: u16,
+    num_free: u16,
+    /// 下一个请求放入可用环的位置
+    avail_idx: u16,
+    /// 下一个要取出的已用环的位置
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// 已用环的对齐要求，与 Linux 一致
+    pub const ALIGN: usize = PAGE_SIZE;
+
+    fn avail_offset(size: usize) -> usize {
+        size_of::<Descriptor>() * size
+    }
+    fn used_offset(size: usize) -> usize {
+        let avail_end = Self::avail_offset(size) + 2 * (3 + size);
+        (avail_end + Self::ALIGN - 1) / Self::ALIGN * Self::ALIGN
+    }
+    /// 新建一个有 `size` 个描述符的队列，`size` 必须是 2 的幂
+    pub fn new(size: u16) -> Option<Self> {
+        assert!(
+            size.is_power_of_two(),
+            "virtqueue size must be a power of 2"
+        );
+        let n = size as usize;
+        let total = Self::used_offset(n) + 2 * 3 + size_of::<UsedElem>() * n;
+        let mem = DmaBuffer::alloc((total + PAGE_SIZE - 1) / PAGE_SIZE)?;
+        let queue = Self {
+            mem,
+            size,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            unsafe { (*queue.desc(i)).next = i + 1 };
+        }
+        Some(queue)
+    }
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+    /// 队列起始处的物理页号
+    pub fn pfn(&self) -> usize {
+        self.mem.paddr() >> PAGE_SIZE_BITS
+    }
+    fn desc(&self, i: u16) -> *mut Descriptor {
+        unsafe { (self.mem.as_ptr() as *mut Descriptor).add(i as usize) }
+    }
+    /// 可用环中的第 `i` 个 u16：0 为 flags，1 为 idx，之后是环本身
+    fn avail(&self, i: usize) -> *mut u16 {
+        let offset = Self::avail_offset(self.size as usize);
+        unsafe { (self.mem.as_ptr().add(offset) as *mut u16).add(i) }
+    }
+    fn used_idx(&self) -> u16 {
+        let offset = Self::used_offset(self.size as usize);
+        unsafe { ptr::read_volatile((self.mem.as_ptr().add(offset) as *const u16).add(1)) }
+    }
+    fn used_elem(&self, i: u16) -> *const UsedElem {
+        let offset = Self::used_offset(self.size as usize) + 4;
+        let i = (i % self.size) as usize;
+        unsafe { (self.mem.as_ptr().add(offset) as *const UsedElem).add(i) }
+    }
+    /// 放入一个由若干缓冲区组成的请求，返回请求的标识。缓冲区以 (物理地址, 长度, 是否由设备写入)
+    /// 给出，按照规范，设备读取的缓冲区都要排在设备写入的缓冲区之前。描述符不够时返回 `None`
+    pub fn add(&mut self, buffers: &[(usize, usize, bool)]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        for (i, &(addr, len, writable)) in buffers.iter().enumerate() {
+            let desc = self.desc(self.free_head);
+            unsafe {
+                let next = (*desc).next;
+                let mut flags = if writable { DESC_F_WRITE } else { 0 };
+                if i + 1 < buffers.len() {
+                    flags |= DESC_F_NEXT;
+                }
+                ptr::write_volatile(
+                    desc,
+                    Descriptor {
+                        addr: addr as u64,
+                        len: len as u32,
+                        flags,
+                        next,
+                    },
+                );
+                self.free_head = next;
+            }
+        }
+        self.num_free -= buffers.len() as u16;
+        unsafe {
+            ptr::write_volatile(self.avail(2 + (self.avail_idx % self.size) as usize), head);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            ptr::write_volatile(self.avail(1), self.avail_idx);
+            fence(Ordering::SeqCst);
+        }
+        Some(head)
+    }
+    /// 设备是否处理完了至少一个请求
+    pub fn can_pop(&self) -> bool {
+        self.used_idx() != self.last_used_idx
+    }
+    /// 取出一个设备已经处理完的请求，返回它的标识和设备写入的字节数
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.can_pop() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = unsafe { ptr::read_volatile(self.used_elem(self.last_used_idx)) };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        // 把请求的描述符链放回空闲链表
+        let head = elem.id as u16;
+        let mut i = head;
+        loop {
+            let desc = self.desc(i);
+            self.num_free += 1;
+            let flags = unsafe { (*desc).flags };
+            if flags & DESC_F_NEXT == 0 {
+                unsafe { (*desc).next = self.free_head };
+                break;
+            }
+            i = unsafe { (*desc).next };
+        }
+        self.free_head = head;
+        Some((head, elem.len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 模拟设备处理完请求 `id`，写入了 `len` 个字节
+    fn complete(queue: &VirtQueue, id: u16, len: u32) {
+        let offset = VirtQueue::used_offset(queue.size as usize);
+        let used = unsafe { queue.mem.as_ptr().add(offset) as *mut u16 };
+        let idx = queue.used_idx();
+        unsafe {
+            let elem = queue.used_elem(idx) as *mut UsedElem;
+            ptr::write_volatile(elem, UsedElem { id: id as u32, len });
+            ptr::write_volatile(used.add(1), idx.wrapping_add(1));
+        }
+    }
+
+    #[test_case]
+    fn descriptor_chains_return_to_the_free_list() {
+        let mut queue = VirtQueue::new(4).unwrap();
+        let first = queue
+            .add(&[(0x1000, 16, false), (0x2000, 512, true)])
+            .unwrap();
+        let second = queue.add(&[(0x3000, 1, true), (0x4000, 1, true)]).unwrap();
+        assert_eq!(
+            queue.add(&[(0x5000, 1, true)]),
+            None,
+            "all descriptors in use"
+        );
+        assert!(!queue.can_pop());
+        complete(&queue, second, 2);
+        assert_eq!(queue.pop_used(), Some((second, 2)));
+        complete(&queue, first, 512);
+        assert_eq!(queue.pop_used(), Some((first, 512)));
+        assert_eq!(queue.pop_used(), None);
+        assert_eq!(queue.num_free, 4);
+        let chain = queue.add(&[(0, 1, true); 4]).unwrap();
+        complete(&queue, chain, 4);
+        assert_eq!(queue.pop_used(), Some((chain, 4)));
+    }
+}
@@ -0,0 +1,345 @@
+//! Flattened device tree parsing
+//!
+//! OpenSBI 跳转到内核时通过 `a1` 传入设备树（DTB）的物理地址。这里只解析内核
+//! 需要的一小部分信息：物理内存范围、保留内存、`/chosen` 下的 `bootargs`、`/cpus` 下的
+//! `timebase-frequency`，以及串口、PLIC、
+//! CLINT、virtio-mmio 和 SiFive test 设备的寄存器区间。
+//!
+//! 保留内存来自 DTB 头部之后的内存保留块和 `/reserved-memory` 的子节点，
+//! 例如 OpenSBI 自己占用的内存，它们由 `mm::init` 登记到页帧分配器中。
+//!
+//! 设备树所在的物理页位于 `ekernel` 之后，稍后会被页帧分配器分配出去，因此必须在
+//! 初始化页帧分配器之前调用 [`init`]，并把需要的信息复制到内核堆上。
+
+use alloc::{string::String, vec::Vec};
+use core::{convert::TryInto, ops::Range};
+
+use crate::{
+    config::{CLOCK_FREQ, MEMORY_END, MMIO},
+    mm::address::phys_to_virt,
+    sync::UPSafeCell,
+};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/// DTB 头部的大小，共 10 个 u32
+const FDT_HEADER_SIZE: usize = 40;
+/// 物理内存的起始地址，QEMU virt 平台上总是 0x80000000
+const MEMORY_START: usize = 0x8000_0000;
+
+/// 内核关心的设备种类
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Uart,
+    Plic,
+    Clint,
+    VirtIO,
+    /// SiFive test 设备，写入特定的值可以关闭 QEMU
+    Test,
+}
+
+/// 内核认识的 `compatible` 及其设备种类
+const KNOWN_COMPATIBLE: &[(&str, DeviceKind)] = &[
+    ("ns16550a", DeviceKind::Uart),
+    ("riscv,plic0", DeviceKind::Plic),
+    ("sifive,plic-1.0.0", DeviceKind::Plic),
+    ("riscv,clint0", DeviceKind::Clint),
+    ("sifive,clint0", DeviceKind::Clint),
+    ("virtio,mmio", DeviceKind::VirtIO),
+    ("sifive,test0", DeviceKind::Test),
+    ("sifive,test1", DeviceKind::Test),
+];
+
+/// 根据节点的一项 `compatible` 判断设备种类，同时得到这一项的静态副本
+fn from_compatible(compatible: &str) -> Option<(&'static str, DeviceKind)> {
+    KNOWN_COMPATIBLE
+        .iter()
+        .find(|&&(known, _)| known == compatible)
+        .copied()
+}
+
+/// 一个 MMIO 设备的寄存器区间
+#[derive(Copy, Clone, Debug)]
+pub struct Device {
+    pub kind: DeviceKind,
+    /// 节点的 `compatible` 中第一个内核认识的值，驱动注册表按它匹配驱动
+    pub compatible: &'static str,
+    pub base: usize,
+    pub size: usize,
+    /// 设备接到 PLIC 上的中断号，来自节点的 `interrupts`
+    pub irq: Option<u32>,
+}
+
+/// 从设备树中得到的机器信息
+pub struct MachineInfo {
+    pub memory: Range<usize>,
+    /// 不能作为普通页帧使用的物理内存
+    pub reserved: Vec<Range<usize>>,
+    pub bootargs: Option<String>,
+    /// `time` 寄存器每秒增加的次数
+    pub timebase_frequency: usize,
+    pub devices: Vec<Device>,
+}
+
+impl MachineInfo {
+    const fn new() -> Self {
+        Self {
+            memory: MEMORY_START..MEMORY_END,
+            reserved: Vec::new(),
+            bootargs: None,
+            timebase_frequency: CLOCK_FREQ,
+            devices: Vec::new(),
+        }
+    }
+    /// 设备树不可用时，使用 config 中写死的内存大小和 MMIO 区间
+    fn fallback() -> Self {
+        let mut info = Self::new();
+        info.devices = MMIO
+            .iter()
+            .map(|&(base, size)| Device {
+                kind: DeviceKind::VirtIO,
+                compatible: "virtio,mmio",
+                base,
+                size,
+                irq: None,
+            })
+            .collect();
+        info
+    }
+}
+
+static MACHINE: UPSafeCell<MachineInfo> = unsafe { UPSafeCell::new(MachineInfo::new()) };
+
+/// 按大端序读取 `off` 处的 u32
+fn be32(data: &[u8], off: usize) -> Option<u32> {
+    let bytes = data.get(off..off + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// 读取 `off` 处以 `\0` 结尾的字符串
+fn cstr(data: &[u8], off: usize) -> Option<&str> {
+    let bytes = data.get(off..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// 读取由 `cells` 个 u32 组成的数
+fn read_cells(data: &[u8], cells: u32) -> Option<usize> {
+    (0..cells as usize).try_fold(0usize, |acc, i| {
+        Some((acc << 32) | be32(data, i * 4)? as usize)
+    })
+}
+
+fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+/// 正在解析的节点。设备树规定属性总是出现在子节点之前
+struct Node<'a> {
+    name: &'a str,
+    /// 子节点 `reg` 属性中地址和大小所占的 cell 数
+    address_cells: u32,
+    size_cells: u32,
+    kind: Option<(&'static str, DeviceKind)>,
+    is_memory: bool,
+    /// 是 `/reserved-memory` 的子节点
+    is_reserved: bool,
+    reg: Option<(usize, usize)>,
+    irq: Option<u32>,
+}
+
+impl<'a> Node<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            // 设备树规范中的默认值
+            address_cells: 2,
+            size_cells: 1,
+            kind: None,
+            is_memory: name == "memory" || name.starts_with("memory@"),
+            is_reserved: false,
+            reg: None,
+            irq: None,
+        }
+    }
+}
+
+/// 解析设备树，格式错误时返回 `None`
+fn parse(data: &[u8]) -> Option<MachineInfo> {
+    let struct_off = be32(data, 8)? as usize;
+    let strings_off = be32(data, 12)? as usize;
+    let mut info = MachineInfo::new();
+    // 内存保留块由大端序的 (地址, 大小) 对组成，以两个 0 结尾
+    let mut rsv_off = be32(data, 16)? as usize;
+    loop {
+        let base = read_cells(data.get(rsv_off..)?, 2)?;
+        let size = read_cells(data.get(rsv_off + 8..)?, 2)?;
+        if size == 0 {
+            break;
+        }
+        info.reserved.push(base..base + size);
+        rsv_off += 16;
+    }
+    let mut stack: Vec<Node> = Vec::new();
+    let mut off = struct_off;
+    loop {
+        let token = be32(data, off)?;
+        off += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(data, off)?;
+                off = align4(off + name.len() + 1);
+                let mut node = Node::new(name);
+                node.is_reserved = stack.len() == 2 && stack[1].name == "reserved-memory";
+                stack.push(node);
+            }
+            FDT_END_NODE => {
+                let node = stack.pop()?;
+                if let Some((base, size)) = node.reg {
+                    if node.is_reserved {
+                        info.reserved.push(base..base + size);
+                    } else if node.is_memory {
+                        info.memory = base..base + size;
+                    } else if let Some((compatible, kind)) = node.kind {
+                        info.devices.push(Device {
+                            kind,
+                            compatible,
+                            base,
+                            size,
+                            irq: node.irq,
+                        });
+                    }
+                }
+            }
+            FDT_PROP => {
+                let len = be32(data, off)? as usize;
+                let name = cstr(data, strings_off + be32(data, off + 4)? as usize)?;
+                let value = data.get(off + 8..off + 8 + len)?;
+                off = align4(off + 8 + len);
+                let depth = stack.len();
+                // reg 的格式由父节点的 #address-cells 和 #size-cells 决定
+                let (address_cells, size_cells) = match depth {
+                    0 => return None,
+                    1 => (2, 1),
+                    _ => (stack[depth - 2].address_cells, stack[depth - 2].size_cells),
+                };
+                let in_chosen = depth == 2 && stack[1].name == "chosen";
+                let in_cpus = depth >= 2 && stack[1].name == "cpus";
+                let node = stack.last_mut().unwrap();
+                match name {
+                    "#address-cells" => node.address_cells = be32(value, 0)?,
+                    "#size-cells" => node.size_cells = be32(value, 0)?,
+                    "compatible" => {
+                        node.kind = value
+                            .split(|&b| b == 0)
+                            .filter_map(|s| core::str::from_utf8(s).ok())
+                            .find_map(from_compatible);
+                    }
+                    "device_type" => node.is_memory |= cstr(value, 0)? == "memory",
+                    "reg" if node.reg.is_none() => {
+                        let base = read_cells(value, address_cells)?;
+                        let size =
+                            read_cells(value.get(address_cells as usize * 4..)?, size_cells)?;
+                        node.reg = Some((base, size));
+                    }
+                    // QEMU virt 上设备的中断都只有一个 cell
+                    "interrupts" => node.irq = Some(be32(value, 0)?),
+                    "timebase-frequency" if in_cpus => {
+                        info.timebase_frequency = read_cells(value, len as u32 / 4)?;
+                    }
+                    "bootargs" if in_chosen => {
+                        let bootargs = cstr(value, 0)?;
+                        if !bootargs.is_empty() {
+                            info.bootargs = Some(String::from(bootargs));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => return Some(info),
+            _ => return None,
+        }
+    }
+}
+
+/// 解析物理地址 `dtb_pa` 处的设备树。需要在初始化内核堆之后、初始化页帧分配器之前调用
+pub fn init(dtb_pa: usize) {
+    let info = if dtb_pa == 0 {
+        None
+    } else {
+        // 启动页表线性映射了低 4 GiB 物理地址，经线性映射访问设备树
+        let dtb = phys_to_virt(dtb_pa) as *const u8;
+        let header = unsafe { core::slice::from_raw_parts(dtb, FDT_HEADER_SIZE) };
+        if be32(header, 0) != Some(FDT_MAGIC) {
+            None
+        } else {
+            let total_size = be32(header, 4).unwrap() as usize;
+            parse(unsafe { core::slice::from_raw_parts(dtb, total_size) })
+        }
+    };
+    let info = info.unwrap_or_else(|| {
+        log::warn!(
+            "[kernel] invalid device tree at {:#x}, use defaults",
+            dtb_pa
+        );
+        MachineInfo::fallback()
+    });
+    println!(
+        "[kernel] memory: [{:#x}, {:#x})",
+        info.memory.start, info.memory.end
+    );
+    for region in &info.reserved {
+        log::info!(
+            "[kernel] reserved memory: [{:#x}, {:#x})",
+            region.start,
+            region.end
+        );
+    }
+    log::info!(
+        "[kernel] timebase frequency: {} Hz",
+        info.timebase_frequency
+    );
+    for device in &info.devices {
+        log::info!(
+            "[kernel] {:?} at [{:#x}, {:#x})",
+            device.kind,
+            device.base,
+            device.base + device.size
+        );
+    }
+    *MACHINE.exclusive_access() = info;
+}
+
+/// 物理内存的起始地址
+pub fn memory_start() -> usize {
+    MACHINE.exclusive_access().memory.start
+}
+
+/// 物理内存的结束地址
+pub fn memory_end() -> usize {
+    MACHINE.exclusive_access().memory.end
+}
+
+/// 设备树中登记的保留内存，可能互相重叠
+pub fn reserved_regions() -> Vec<Range<usize>> {
+    MACHINE.exclusive_access().reserved.clone()
+}
+
+pub fn bootargs() -> Option<String> {
+    MACHINE.exclusive_access().bootargs.clone()
+}
+
+/// `time` 寄存器的频率，设备树中没有时使用 `config::CLOCK_FREQ`
+pub fn timebase_frequency() -> usize {
+    MACHINE.exclusive_access().timebase_frequency
+}
+
+/// 所有需要映射到内核地址空间的 MMIO 设备
+pub fn devices() -> Vec<Device> {
+    MACHINE.exclusive_access().devices.clone()
+}
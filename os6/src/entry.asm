@@ -1,12 +1,42 @@
     .section .text.entry
     .globl _start
 _start:
-    la sp, boot_stack_top
-    call rust_main
+    # SBI 在物理地址上跳转到这里，此时还没有开启分页，`lla` 得到的都是物理地址。
+    # 先用启动页表开启分页，再跳到内核链接时的高半部虚拟地址继续执行
+    lla t0, boot_page_table
+    srli t0, t0, 12
+    li t1, 8 << 60
+    or t0, t0, t1
+    csrw satp, t0
+    sfence.vma
+    # 与 config::PHYS_VIRT_OFFSET 一致
+    li t1, 0xffffffc000000000
+    lla sp, boot_stack_top
+    add sp, sp, t1
+    lla t0, rust_main
+    add t0, t0, t1
+    jr t0
 
     .section .bss.stack
     .globl boot_stack
 boot_stack:
     .space 4096 * 16
     .globl boot_stack_top
-boot_stack_top:
\ No newline at end of file
+boot_stack_top:
+
+    # 只在启动时使用的页表，全部由 1 GiB 的大页组成，权限为 VRWXAD。
+    # `mm::init` 启用内核地址空间后不再使用
+    .section .data
+    .align 12
+boot_page_table:
+    # [0x8000_0000, 0xc000_0000) 的恒等映射，开启分页后、跳到高地址之前的几条指令还在这里执行
+    .quad 0
+    .quad 0
+    .quad (0x80000 << 10) | 0xcf
+    .zero 8 * 253
+    # 从 PHYS_VIRT_OFFSET 开始线性映射物理地址 [0, 4 GiB)，包括设备树和 MMIO 设备
+    .quad (0x00000 << 10) | 0xcf
+    .quad (0x40000 << 10) | 0xcf
+    .quad (0x80000 << 10) | 0xcf
+    .quad (0xc0000 << 10) | 0xcf
+    .zero 8 * 252
@@ -0,0 +1,213 @@
+//! 事件通知对象（eventfd）
+//!
+//! 内核维护一个 64 位计数器：写入 8 字节的整数把它加到计数器上，读取取走计数器的值并清零，
+//! 计数器为零时读取阻塞。可以配合 `ppoll` 等待，用来在任务之间传递通知。
+
+use alloc::sync::Arc;
+
+use super::{File, IoError, PollEvents, Stat, StatMode};
+use crate::{
+    mm::page_table::UserBuffer,
+    sync::{
+        wait_queue::{self, WaitQueue},
+        UPSafeCell,
+    },
+    task::{self, TaskControlBlock},
+};
+
+/// 计数器的最大值，再加就会阻塞写入者
+const COUNTER_MAX: u64 = u64::MAX - 1;
+
+struct EventFdInner {
+    counter: u64,
+    /// 阻塞在读取上的任务。写入后只唤醒一个，它读完后计数器不为零时再唤醒下一个
+    readers: WaitQueue,
+    /// 等待计数器变化的其他任务，包括阻塞的写者和 `poll` 的等待者
+    wakers: WaitQueue,
+}
+
+pub struct EventFd {
+    /// 信号量模式：每次读取只取走 1
+    semaphore: bool,
+    inner: UPSafeCell<EventFdInner>,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, semaphore: bool) -> Self {
+        Self {
+            semaphore,
+            inner: unsafe {
+                UPSafeCell::new(EventFdInner {
+                    counter: initval,
+                    readers: WaitQueue::new(),
+                    wakers: WaitQueue::new(),
+                })
+            },
+        }
+    }
+    /// 在 `queue` 选出的等待队列上阻塞当前任务，直到计数器变化。有待处理的信号时不等待，返回 `false`
+    fn wait(&self, queue: impl Fn(&mut EventFdInner) -> &mut WaitQueue) -> bool {
+        if task::signal_pending() {
+            return false;
+        }
+        wait_queue::wait_on(&self.inner, queue);
+        true
+    }
+    /// 计数器为零时等待，`nonblock` 时则返回 `IoError::WouldBlock`。
+    /// 缓冲区不足 8 字节时返回 0，等待时被信号打断返回 `IoError::Interrupted`
+    fn read_inner(&self, buf: UserBuffer, nonblock: bool) -> Result<usize, IoError> {
+        if buf.len() < 8 {
+            return Ok(0);
+        }
+        let value = loop {
+            let mut inner = self.inner.exclusive_access();
+            if inner.counter > 0 {
+                let value = if self.semaphore { 1 } else { inner.counter };
+                inner.counter -= value;
+                if inner.counter > 0 {
+                    inner.readers.wake_one();
+                }
+                inner.wakers.wake_all();
+                break value;
+            }
+            if nonblock {
+                return Err(IoError::WouldBlock);
+            }
+            drop(inner);
+            // 被信号打断时返回，让进程回到 trap 处理信号
+            if !self.wait(|inner| &mut inner.readers) {
+                return Err(IoError::Interrupted);
+            }
+        };
+        for (dst, byte) in buf.into_iter().zip(value.to_ne_bytes()) {
+            unsafe { *dst = byte };
+        }
+        Ok(8)
+    }
+    /// 计数器会超过 `COUNTER_MAX` 时等待，`nonblock` 时则返回 `IoError::WouldBlock`。
+    /// 缓冲区不足 8 字节或写入的值为 `u64::MAX` 时返回 0，等待时被信号打断返回 `IoError::Interrupted`
+    fn write_inner(&self, buf: UserBuffer, nonblock: bool) -> Result<usize, IoError> {
+        if buf.len() < 8 {
+            return Ok(0);
+        }
+        let mut bytes = [0u8; 8];
+        for (byte, src) in bytes.iter_mut().zip(buf.into_iter()) {
+            *byte = unsafe { *src };
+        }
+        let value = u64::from_ne_bytes(bytes);
+        if value == u64::MAX {
+            return Ok(0);
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if COUNTER_MAX - inner.counter >= value {
+                inner.counter += value;
+                inner.readers.wake_one();
+                inner.wakers.wake_all();
+                return Ok(8);
+            }
+            if nonblock {
+                return Err(IoError::WouldBlock);
+            }
+            drop(inner);
+            if !self.wait(|inner| &mut inner.wakers) {
+                return Err(IoError::Interrupted);
+            }
+        }
+    }
+}
+
+impl File for EventFd {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.read_inner(buf, false)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.write_inner(buf, false)
+    }
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.read_inner(buf, true)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.write_inner(buf, true)
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+    fn poll(&self) -> PollEvents {
+        let inner = self.inner.exclusive_access();
+        let mut events = PollEvents::empty();
+        if inner.counter > 0 {
+            events |= PollEvents::POLLIN;
+        }
+        if inner.counter < COUNTER_MAX {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+    fn register_waker(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.inner
+            .exclusive_access()
+            .wakers
+            .add_waiter(Arc::clone(task));
+        true
+    }
+    fn unregister_waker(&self, task: &Arc<TaskControlBlock>) {
+        self.inner.exclusive_access().wakers.remove_waiter(task);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::page_table::{buffer, output_buffer};
+
+    fn buffer_with(value: u64) -> UserBuffer {
+        buffer(&value.to_ne_bytes())
+    }
+
+    fn read_value(eventfd: &EventFd) -> Result<u64, IoError> {
+        let (bytes, ptr) = output_buffer(8);
+        eventfd.try_read(bytes)?;
+        Ok(u64::from_ne_bytes(unsafe { *(ptr as *const [u8; 8]) }))
+    }
+
+    #[test_case]
+    fn eventfd_counts_writes_and_resets_on_read() {
+        let eventfd = EventFd::new(0, false);
+        assert_eq!(read_value(&eventfd), Err(IoError::WouldBlock));
+        assert_eq!(eventfd.try_write(buffer_with(2)), Ok(8));
+        assert_eq!(eventfd.try_write(buffer_with(3)), Ok(8));
+        assert!(eventfd.poll().contains(PollEvents::POLLIN));
+        assert_eq!(read_value(&eventfd), Ok(5));
+        assert!(!eventfd.poll().contains(PollEvents::POLLIN));
+    }
+
+    #[test_case]
+    fn eventfd_semaphore_and_overflow() {
+        let eventfd = EventFd::new(2, true);
+        assert_eq!(read_value(&eventfd), Ok(1));
+        assert_eq!(read_value(&eventfd), Ok(1));
+        assert_eq!(read_value(&eventfd), Err(IoError::WouldBlock));
+        assert_eq!(eventfd.try_write(buffer_with(u64::MAX)), Ok(0));
+        assert_eq!(eventfd.try_write(buffer_with(COUNTER_MAX)), Ok(8));
+        assert_eq!(eventfd.try_write(buffer_with(1)), Err(IoError::WouldBlock));
+        assert!(!eventfd.poll().contains(PollEvents::POLLOUT));
+    }
+}
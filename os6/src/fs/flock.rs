@@ -0,0 +1,163 @@
+//! 整个文件的劝告锁（flock）
+//!
+//! 锁登记在一张以 inode 编号为键的全局表中，持有者是打开的文件（`OSInode`）本身：
+//! fork 继承或同一进程中复制的描述符共享同一把锁，所有引用关闭后锁自动释放。
+//! 拿不到锁的任务阻塞在对应表项的等待队列上，锁被释放时全部唤醒并重新尝试。
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use lazy_static::lazy_static;
+
+use crate::{
+    sync::{wait_queue::WaitQueue, UPSafeCell},
+    task::{self, Processor},
+};
+
+/// 锁的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// 共享锁，可以有多个持有者
+    Shared,
+    /// 排他锁，只能有一个持有者
+    Exclusive,
+}
+
+/// `File::flock` 失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlockError {
+    /// 文件不支持加锁
+    Unsupported,
+    /// 指定了不阻塞，而锁被他人持有
+    WouldBlock,
+}
+
+/// 一个 inode 上的锁
+#[derive(Default)]
+struct FileLock {
+    /// 持有者及其持有的锁
+    holders: Vec<(usize, LockKind)>,
+    /// 等待这个锁的任务
+    waiters: WaitQueue,
+}
+
+impl FileLock {
+    /// 除 `owner` 之外的持有者是否与 `kind` 冲突
+    fn conflicts(&self, owner: usize, kind: LockKind) -> bool {
+        self.holders.iter().any(|&(holder, held)| {
+            holder != owner && (kind == LockKind::Exclusive || held == LockKind::Exclusive)
+        })
+    }
+    fn held_by(&self, owner: usize) -> Option<LockKind> {
+        self.holders
+            .iter()
+            .find(|&&(holder, _)| holder == owner)
+            .map(|&(_, kind)| kind)
+    }
+    /// 释放 `owner` 持有的锁并唤醒所有等待者
+    fn release(&mut self, owner: usize) {
+        let len = self.holders.len();
+        self.holders.retain(|&(holder, _)| holder != owner);
+        if self.holders.len() != len {
+            self.waiters.wake_all();
+        }
+    }
+    fn is_idle(&self) -> bool {
+        self.holders.is_empty() && self.waiters.is_empty()
+    }
+}
+
+lazy_static! {
+    static ref FILE_LOCKS: UPSafeCell<BTreeMap<usize, FileLock>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// 以 `owner` 的身份给 inode `ino` 加锁，锁被他人持有时阻塞，`nonblock` 时则返回
+/// [`FlockError::WouldBlock`]。
+///
+/// 与 Linux 相同，改变已持有的锁的种类时先释放原来的锁再重新加锁，因此不是原子的，
+/// 但两个持有共享锁的任务同时升级不会死锁
+pub fn lock(ino: usize, owner: usize, kind: LockKind, nonblock: bool) -> Result<(), FlockError> {
+    let mut locks = FILE_LOCKS.exclusive_access();
+    let lock = locks.entry(ino).or_default();
+    match lock.held_by(owner) {
+        Some(held) if held == kind => return Ok(()),
+        Some(_) => lock.release(owner),
+        None => {}
+    }
+    loop {
+        let lock = locks.entry(ino).or_default();
+        if !lock.conflicts(owner, kind) {
+            lock.holders.push((owner, kind));
+            return Ok(());
+        }
+        if nonblock {
+            if lock.is_idle() {
+                locks.remove(&ino);
+            }
+            return Err(FlockError::WouldBlock);
+        }
+        let current = Processor::current_task().unwrap();
+        lock.waiters.add_waiter(current.clone());
+        drop(locks);
+        task::block_current_and_run_next();
+        locks = FILE_LOCKS.exclusive_access();
+        // 可能因信号等原因提前醒来，这时还在等待队列中
+        if let Some(lock) = locks.get_mut(&ino) {
+            lock.waiters.remove_waiter(&current);
+        }
+    }
+}
+
+/// 释放 `owner` 在 inode `ino` 上持有的锁，没有持有时什么也不做
+pub fn unlock(ino: usize, owner: usize) {
+    let mut locks = FILE_LOCKS.exclusive_access();
+    if let Some(lock) = locks.get_mut(&ino) {
+        lock.release(owner);
+        if lock.is_idle() {
+            locks.remove(&ino);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn shared_locks_coexist_and_exclude_writers() {
+        let mut lock = FileLock::default();
+        assert!(!lock.conflicts(1, LockKind::Shared));
+        lock.holders.push((1, LockKind::Shared));
+        assert!(!lock.conflicts(2, LockKind::Shared));
+        lock.holders.push((2, LockKind::Shared));
+        assert!(lock.conflicts(3, LockKind::Exclusive));
+        // 只有自己持有时可以升级
+        lock.release(2);
+        assert!(!lock.conflicts(1, LockKind::Exclusive));
+        lock.release(1);
+        assert!(lock.is_idle());
+    }
+
+    #[test_case]
+    fn exclusive_lock_blocks_everyone_else() {
+        let mut lock = FileLock::default();
+        lock.holders.push((1, LockKind::Exclusive));
+        assert!(lock.conflicts(2, LockKind::Shared));
+        assert!(lock.conflicts(2, LockKind::Exclusive));
+        assert_eq!(lock.held_by(1), Some(LockKind::Exclusive));
+        assert_eq!(lock.held_by(2), None);
+    }
+
+    #[test_case]
+    fn nonblocking_lock_fails_and_cleans_up() {
+        let ino = usize::MAX;
+        assert_eq!(lock(ino, 1, LockKind::Exclusive, true), Ok(()));
+        assert_eq!(
+            lock(ino, 2, LockKind::Shared, true),
+            Err(FlockError::WouldBlock)
+        );
+        unlock(ino, 1);
+        assert_eq!(lock(ino, 2, LockKind::Shared, true), Ok(()));
+        unlock(ino, 2);
+        assert!(!FILE_LOCKS.exclusive_access().contains_key(&ino));
+    }
+}
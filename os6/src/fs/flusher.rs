@@ -0,0 +1,28 @@
+//! 定期写回块缓存
+//!
+//! 块缓存中被修改的块只在被替换出缓存或者显式同步时才写回磁盘。后台的 flusher 内核线程
+//! 每隔 `FLUSH_INTERVAL_US` 微秒写回一次所有块缓存，其间在定时器上睡眠，
+//! 这样即使程序没有正常关闭文件、QEMU 被直接杀掉，丢失的也只是最近一段时间的修改。
+
+use crate::{drivers::BLOCK_DEVICE, task, timer::MICRO_PER_SEC};
+
+/// 两次写回之间的时间，即 5 秒
+const FLUSH_INTERVAL_US: usize = 5 * MICRO_PER_SEC;
+
+/// 启动 flusher 线程
+pub fn init() {
+    task::kthread_spawn(flusher_main);
+}
+
+/// 把所有块缓存写回磁盘。已经被换出缓存、还在 I/O 调度器队列中的块也一并写入
+pub fn sync_all() {
+    easy_fs::block_cache_sync_all();
+    BLOCK_DEVICE.flush();
+}
+
+fn flusher_main() {
+    loop {
+        sync_all();
+        task::sleep_us(FLUSH_INTERVAL_US);
+    }
+}
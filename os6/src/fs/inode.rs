@@ -1,11 +1,16 @@
-use super::{File, Stat, StatMode};
-use crate::drivers::BLOCK_DEVICE;
-use crate::mm::page_table::UserBuffer;
+use super::flock::{self, FlockError, LockKind};
+use super::{File, IoError, Stat, StatMode};
+use crate::drivers;
+use crate::mm::{memory_set::ElfSource, page_table::UserBuffer};
 use crate::sync::UPSafeCell;
+use crate::task;
+use crate::timer;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
+use easy_fs::{EasyFileSystem, Inode, DEFAULT_FILE_MODE};
 use lazy_static::*;
 
 /// A wrapper around a filesystem inode
@@ -31,31 +36,57 @@ impl OSInode {
             inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
         }
     }
-    /// Read all data inside a inode into vector
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
-        let mut buffer = [0u8; 512];
-        let mut v: Vec<u8> = Vec::new();
-        loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
-            if len == 0 {
-                break;
-            }
-            inner.offset += len;
-            v.extend_from_slice(&buffer[..len]);
-        }
-        v
+    /// The inode and the current offset, without keeping `inner` borrowed
+    fn inode_and_offset(&self) -> (Arc<Inode>, usize) {
+        let inner = self.inner.exclusive_access();
+        (inner.inode.clone(), inner.offset)
+    }
+    /// The identity of this open file as a lock holder
+    fn lock_owner(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+/// Programs are loaded straight from the inode through the block cache, see [`ElfSource`]
+impl ElfSource for OSInode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inode = self.inner.exclusive_access().inode.clone();
+        inode.read_at(offset, buf)
+    }
+}
+
+impl Drop for OSInode {
+    /// Closing the last reference to an open file releases its lock
+    fn drop(&mut self) {
+        let ino = self.inner.exclusive_access().inode.inode_id();
+        flock::unlock(ino, self.lock_owner());
     }
 }
 
 lazy_static! {
     /// The root of all inodes, or '/' in short
     pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        let efs = EasyFileSystem::open(drivers::root_device());
+        efs.lock().set_clock(now);
+        if !efs.lock().was_clean() {
+            log::warn!("[kernel] easy-fs was not unmounted cleanly, its metadata may be inconsistent");
+        }
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
 }
 
+/// Write back everything and mark the filesystem clean. Only for shutdown: nothing may be
+/// written to the filesystem afterwards
+pub fn unmount() {
+    ROOT_INODE.fs().lock().unmount();
+}
+
+/// The clock of inode timestamps. Without a real-time clock it counts seconds since boot,
+/// while files packed on the host carry the host's Unix time
+pub fn now() -> u32 {
+    (timer::get_time_ms() / 1000) as u32
+}
+
 /// List all files in the filesystems
 pub fn list_apps() {
     println!("/**** APPS ****");
@@ -65,23 +96,50 @@ pub fn list_apps() {
     println!("**************/");
 }
 
+/// The only user: every process runs as root
+pub const ROOT_UID: u16 = 0;
+/// The only group
+pub const ROOT_GID: u16 = 0;
+
 bitflags! {
     /// Flags for opening files
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
         const WRONLY = 1 << 0;
         const RDWR = 1 << 1;
+        /// Fail with [`OpenError::Exists`] if `CREATE` is set and the file already exists
+        const EXCL = 1 << 7;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        /// Reads and writes through the new fd don't block, see [`super::FdFlags`]
+        const NONBLOCK = 1 << 11;
+        /// Fail with [`OpenError::NotDir`] unless the path is a directory
+        const DIRECTORY = 1 << 16;
     }
 }
 
+/// Why [`open_file_with_mode`] failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    /// The file does not exist and `CREATE` is not set
+    NotFound,
+    /// `CREATE | EXCL` is set but the file already exists
+    Exists,
+    /// `DIRECTORY` is set but the file is not a directory
+    NotDir,
+    /// The permission bits forbid the requested access
+    PermissionDenied,
+    /// The flags make no sense together, e.g. `CREATE | DIRECTORY`
+    InvalidFlags,
+}
+
 impl OpenFlags {
     /// Get the current read write permission on an inode
     /// does not check validity for simplicity
     /// returns (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
+        // EXCL and DIRECTORY only affect the lookup, NONBLOCK the fd, not the access mode
+        if (*self - Self::EXCL - Self::DIRECTORY - Self::NONBLOCK).is_empty() {
             (true, false)
         } else if self.contains(Self::WRONLY) {
             (false, true)
@@ -91,30 +149,147 @@ impl OpenFlags {
     }
 }
 
-/// Open a file by path
+/// Whether the owner permission bits of `inode` allow the requested access.
+/// Every process runs as root's uid but, unlike Linux, root is still bound by
+/// the owner bits so that `chmod` has a visible effect.
+fn permits(inode: &Inode, readable: bool, writable: bool) -> bool {
+    let mode = StatMode::from_bits_truncate(inode.mode() as u32);
+    (!readable || mode.contains(StatMode::OWNER_R))
+        && (!writable || mode.contains(StatMode::OWNER_W))
+}
+
+/// Open a file by path, creating it with [`DEFAULT_FILE_MODE`] if needed
 pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+    open_file_with_mode(name, flags, DEFAULT_FILE_MODE).ok()
+}
+
+/// Open a file by path, creating it with permission bits `mode` if needed.
+/// Fails if the permission bits of an existing file forbid the access.
+pub fn open_file_with_mode(
+    name: &str,
+    flags: OpenFlags,
+    mode: u16,
+) -> Result<Arc<OSInode>, OpenError> {
+    if flags.contains(OpenFlags::CREATE | OpenFlags::DIRECTORY) {
+        return Err(OpenError::InvalidFlags);
+    }
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+    if let Some(inode) = ROOT_INODE.find(name) {
+        if flags.contains(OpenFlags::CREATE | OpenFlags::EXCL) {
+            return Err(OpenError::Exists);
+        }
+        if flags.contains(OpenFlags::DIRECTORY) && inode.inode_type() != 1 {
+            return Err(OpenError::NotDir);
+        }
+        let truncate = flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC);
+        if !permits(&inode, readable, writable || truncate) {
+            return Err(OpenError::PermissionDenied);
+        }
+        if truncate {
             // clear size
             inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
-            // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
         }
+        Ok(Arc::new(OSInode::new(readable, writable, inode)))
+    } else if flags.contains(OpenFlags::CREATE) {
+        // create file
+        ROOT_INODE
+            .create_with_mode(name, mode, ROOT_UID, ROOT_GID)
+            .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            .ok_or(OpenError::Exists)
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        Err(OpenError::NotFound)
+    }
+}
+
+/// The name in the root directory that `path` refers to. easy-fs has no subdirectories yet,
+/// so only `name` and `/name` resolve
+fn root_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix('/').unwrap_or(path);
+    if name.is_empty() || name.contains('/') {
+        None
+    } else {
+        Some(name)
     }
 }
 
+/// The paths tried when executing `name`: `name` itself if it contains a `/`, otherwise
+/// `name` in each directory of `search_path`, which is colon separated like `PATH`
+fn exec_candidates(name: &str, search_path: &str) -> Vec<String> {
+    if name.contains('/') {
+        return alloc::vec![String::from(name)];
+    }
+    search_path
+        .split(':')
+        .map(|dir| format!("{}/{}", dir.trim_end_matches('/'), name))
+        .collect()
+}
+
+/// Open a program to execute, see [`exec_candidates`] for how `name` is looked up
+pub fn open_executable(name: &str, search_path: &str) -> Option<Arc<OSInode>> {
+    exec_candidates(name, search_path)
+        .iter()
+        .filter_map(|path| root_name(path))
+        .find_map(|name| open_file(name, OpenFlags::RDONLY))
+}
+
+/// The inode number of the file at `name`
+pub fn inode_id(name: &str) -> Option<usize> {
+    ROOT_INODE.find(name).map(|inode| inode.inode_id())
+}
+
+/// Create an empty file at `name` to stand for a UNIX domain socket bound there, returning
+/// its inode number. easy-fs has no socket inodes, so it is an ordinary file nobody reads
+pub fn create_socket_file(name: &str, mode: u16) -> Result<usize, OpenError> {
+    ROOT_INODE
+        .create_with_mode(name, mode, ROOT_UID, ROOT_GID)
+        .map(|inode| inode.inode_id())
+        .ok_or(OpenError::Exists)
+}
+
+/// Set the access and modification times of a file by path, leaving `None` alone
+pub fn utimes(name: &str, atime: Option<u32>, mtime: Option<u32>) -> bool {
+    ROOT_INODE
+        .find(name)
+        .map(|inode| inode.set_times(atime, mtime))
+        .is_some()
+}
+
+/// Change the permission bits of a file by path
+pub fn chmod(name: &str, mode: u16) -> bool {
+    ROOT_INODE
+        .find(name)
+        .map(|inode| inode.set_mode(mode))
+        .is_some()
+}
+
+/// Read from `offset` into the segments of `buf` until one comes up short
+fn read_buffers(inode: &Inode, mut offset: usize, mut buf: UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = inode.read_at(offset, *slice);
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+        task::preempt::cond_resched();
+    }
+    total_read_size
+}
+
+/// Write all segments of `buf` starting at `offset`
+fn write_buffers(inode: &Inode, mut offset: usize, buf: UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = inode.write_at(offset, *slice);
+        assert_eq!(write_size, slice.len());
+        offset += write_size;
+        total_write_size += write_size;
+        task::preempt::cond_resched();
+    }
+    total_write_size
+}
+
 impl File for OSInode {
     fn readable(&self) -> bool {
         self.readable
@@ -122,29 +297,44 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
-                break;
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        // Don't hold `inner` while copying, so that a big read can be preempted between segments
+        let (inode, offset) = self.inode_and_offset();
+        let read_size = read_buffers(&inode, offset, buf);
+        self.inner.exclusive_access().offset = offset + read_size;
+        Ok(read_size)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        let (inode, offset) = self.inode_and_offset();
+        let write_size = write_buffers(&inode, offset, buf);
+        self.inner.exclusive_access().offset = offset + write_size;
+        Ok(write_size)
+    }
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        let inode = self.inner.exclusive_access().inode.clone();
+        Some(read_buffers(&inode, offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        let inode = self.inner.exclusive_access().inode.clone();
+        Some(write_buffers(&inode, offset, buf))
+    }
+    fn fsync(&self) -> bool {
+        let inode = self.inner.exclusive_access().inode.clone();
+        inode.sync();
+        true
+    }
+    fn flock(&self, kind: Option<LockKind>, nonblock: bool) -> Result<(), FlockError> {
+        let ino = self.inner.exclusive_access().inode.inode_id();
+        match kind {
+            Some(kind) => flock::lock(ino, self.lock_owner(), kind, nonblock),
+            None => {
+                flock::unlock(ino, self.lock_owner());
+                Ok(())
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
-        }
-        total_read_size
-    }
-    fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
         }
-        total_write_size
+    }
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(self.inner.exclusive_access().inode.clone())
     }
     fn stat(&self) -> Stat {
         let inner = self.inner.exclusive_access();
@@ -159,13 +349,36 @@ impl File for OSInode {
         } else {
             unreachable!()
         };
+        let mode = mode | StatMode::from_bits_truncate(inner.inode.mode() as u32);
         let link_num = inner.inode.inode_link_num();
+        let (uid, gid) = inner.inode.owner();
+        let (atime, mtime, ctime) = inner.inode.times();
         Stat {
             dev: 0,
             ino,
             mode,
             nlink: link_num as u32,
-            pad: [0; 7],
+            uid: uid as u32,
+            gid: gid as u32,
+            atime: atime as u64,
+            mtime: mtime as u64,
+            ctime: ctime as u64,
+            pad: [0; 3],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn exec_searches_path_for_bare_names() {
+        assert_eq!(exec_candidates("sh", "/bin:/"), ["/bin/sh", "/sh"]);
+        assert_eq!(exec_candidates("/bin/sh", "/"), ["/bin/sh"]);
+        assert_eq!(root_name("/sh"), Some("sh"));
+        assert_eq!(root_name("sh"), Some("sh"));
+        assert_eq!(root_name("/bin/sh"), None);
+        assert_eq!(root_name("/"), None);
+    }
+}
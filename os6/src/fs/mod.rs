@@ -1,8 +1,20 @@
+pub mod eventfd;
+pub mod flock;
+pub mod flusher;
 pub mod inode;
+pub mod pipe;
+pub mod proc;
 pub mod stdio;
 
-use crate::mm::page_table::UserBuffer;
+use crate::{
+    mm::page_table::UserBuffer,
+    net::socket::{Socket, SocketError},
+    task::TaskControlBlock,
+};
+use alloc::sync::Arc;
 use bitflags::bitflags;
+use easy_fs::Inode;
+use flock::{FlockError, LockKind};
 
 bitflags! {
     /// StatMode 定义：
@@ -12,6 +24,28 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// 套接字
+        const SOCK  = 0o140000;
+        /// 文件类型所占的位
+        const TYPE_MASK = 0o170000;
+        /// 所有者可读
+        const OWNER_R = 0o400;
+        /// 所有者可写
+        const OWNER_W = 0o200;
+        /// 所有者可执行
+        const OWNER_X = 0o100;
+        /// 同组用户可读
+        const GROUP_R = 0o040;
+        /// 同组用户可写
+        const GROUP_W = 0o020;
+        /// 同组用户可执行
+        const GROUP_X = 0o010;
+        /// 其他用户可读
+        const OTHER_R = 0o004;
+        /// 其他用户可写
+        const OTHER_W = 0o002;
+        /// 其他用户可执行
+        const OTHER_X = 0o001;
     }
 }
 
@@ -21,20 +55,155 @@ pub struct Stat {
     pub dev: u64,
     /// inode 文件所在 inode 编号
     pub ino: u64,
-    /// 文件类型
+    /// 文件类型和权限位
     pub mode: StatMode,
     /// 硬链接数量，初始为 1
     pub nlink: u32,
+    /// 所有者的用户 id
+    pub uid: u32,
+    /// 所有者的组 id
+    pub gid: u32,
+    /// 最后访问时间，单位为秒
+    pub atime: u64,
+    /// 最后修改内容的时间
+    pub mtime: u64,
+    /// 最后修改内容或元数据的时间
+    pub ctime: u64,
     /// 无需考虑，为了兼容性设计
-    pub pad: [u64; 7],
+    pub pad: [u64; 3],
+}
+
+bitflags! {
+    /// `poll` 关心和返回的事件，与 Linux 的 `struct pollfd` 一致
+    pub struct PollEvents: u16 {
+        /// 有数据可读
+        const POLLIN = 0x001;
+        /// 可以写入而不阻塞
+        const POLLOUT = 0x004;
+        /// 出错，如管道的读端已全部关闭。总是会报告
+        const POLLERR = 0x008;
+        /// 对端已挂断，如管道的写端已全部关闭。总是会报告
+        const POLLHUP = 0x010;
+        /// fd 无效。总是会报告
+        const POLLNVAL = 0x020;
+    }
+}
+
+/// 读写文件失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoError {
+    /// 不阻塞的读写本会阻塞
+    WouldBlock,
+    /// 阻塞时收到了信号，一个字节也没有读写
+    Interrupted,
+    /// 套接字的读写失败，例如没有目的地址或者对方已经关闭了连接
+    Socket(SocketError),
 }
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
-    fn read(&self, buf: UserBuffer) -> usize;
-    fn write(&self, buf: UserBuffer) -> usize;
+    /// 读取，可能阻塞。阻塞时收到信号而一个字节也没有读到时返回 `IoError::Interrupted`
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError>;
+    /// 写入，可能阻塞。阻塞时收到信号而一个字节也没有写入时返回 `IoError::Interrupted`
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError>;
     fn stat(&self) -> Stat;
+    /// 不阻塞地读取，暂时没有数据可读时返回 `IoError::WouldBlock`。默认读取从不阻塞
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.read(buf)
+    }
+    /// 不阻塞地写入，能写多少写多少，一个字节也写不进去时返回 `IoError::WouldBlock`。默认写入从不阻塞
+    fn try_write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.write(buf)
+    }
+    /// 从 offset 处读取，不使用也不移动文件自身的读写位置。
+    ///
+    /// 返回 `None` 表示不支持定位读写，如管道和标准输入输出
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// 写入 offset 处，不使用也不移动文件自身的读写位置。返回值同 `read_at`
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// 是否是终端，即控制台
+    fn is_tty(&self) -> bool {
+        false
+    }
+    /// 把文件在块缓存中的修改写回磁盘。返回 false 表示文件不在磁盘上，如管道和标准输入输出
+    fn fsync(&self) -> bool {
+        false
+    }
+    /// 给整个文件加劝告锁，`kind` 为 `None` 时解锁。锁被他人持有时阻塞，`nonblock` 时则失败
+    fn flock(&self, _kind: Option<LockKind>, _nonblock: bool) -> Result<(), FlockError> {
+        Err(FlockError::Unsupported)
+    }
+    /// 查询当前就绪的事件，不会阻塞。默认可读的文件总能读，可写的文件总能写
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable() {
+            events |= PollEvents::POLLIN;
+        }
+        if self.writable() {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+    /// 登记 `task`，在就绪状态可能变化时用 `task::wakeup_task` 唤醒它。
+    ///
+    /// 返回 false 表示不支持唤醒，此时等待者只能轮询
+    fn register_waker(&self, _task: &Arc<TaskControlBlock>) -> bool {
+        false
+    }
+    /// 撤销 `register_waker` 的登记
+    fn unregister_waker(&self, _task: &Arc<TaskControlBlock>) {}
+    /// 文件是套接字时返回套接字特有的操作
+    fn socket(&self) -> Option<&dyn Socket> {
+        None
+    }
+    /// 文件在磁盘上时返回它的 inode，供文件映射直接读写
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
 }
 
 pub use inode::{list_apps, open_file};
+
+bitflags! {
+    /// 文件描述符自己的标志，与打开文件时的 flags 中对应的位相同
+    pub struct FdFlags: u32 {
+        /// 读写不阻塞，本会阻塞时返回 -EAGAIN
+        const NONBLOCK = 1 << 11;
+    }
+}
+
+/// 文件描述符表中的一项：打开的文件和这个描述符的标志。
+///
+/// 与 Linux 不同，标志属于描述符而不是打开的文件，fork 时随描述符表复制，之后互不影响
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub file: Arc<dyn File + Send + Sync>,
+    pub flags: FdFlags,
+}
+
+impl FileDescriptor {
+    pub fn new(file: Arc<dyn File + Send + Sync>, flags: FdFlags) -> Self {
+        Self { file, flags }
+    }
+    /// 按照描述符的标志读取，设置了 `NONBLOCK` 时不阻塞
+    pub fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        if self.flags.contains(FdFlags::NONBLOCK) {
+            self.file.try_read(buf)
+        } else {
+            self.file.read(buf)
+        }
+    }
+    /// 按照描述符的标志写入，设置了 `NONBLOCK` 时不阻塞
+    pub fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        if self.flags.contains(FdFlags::NONBLOCK) {
+            self.file.try_write(buf)
+        } else {
+            self.file.write(buf)
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use alloc::sync::{Arc, Weak};
+
+use super::{File, IoError, PollEvents, Stat, StatMode};
+use crate::{
+    mm::page_table::UserBuffer,
+    sync::{
+        wait_queue::{self, WaitQueue},
+        UPSafeCell,
+    },
+    task::{self, TaskControlBlock},
+};
+
+const RING_BUFFER_SIZE: usize = 256;
+
+/// 管道两端共享的环形缓冲区
+pub struct PipeRingBuffer {
+    arr: [u8; RING_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    /// 两端各自的弱引用，用于判断另一端是否已经全部关闭
+    read_end: Option<Weak<Pipe>>,
+    write_end: Option<Weak<Pipe>>,
+    /// 阻塞在读取上的任务。写入后只唤醒一个，它读完后缓冲区中还有数据时再唤醒下一个
+    readers: WaitQueue,
+    /// 等待这个管道状态变化的其他任务，包括阻塞的写者和 `poll` 的等待者
+    wakers: WaitQueue,
+}
+
+impl PipeRingBuffer {
+    fn new() -> Self {
+        Self {
+            arr: [0; RING_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            read_end: None,
+            write_end: None,
+            readers: WaitQueue::new(),
+            wakers: WaitQueue::new(),
+        }
+    }
+    fn read_byte(&mut self) -> u8 {
+        let c = self.arr[self.head];
+        self.head = (self.head + 1) % RING_BUFFER_SIZE;
+        self.len -= 1;
+        c
+    }
+    fn write_byte(&mut self, c: u8) {
+        self.arr[(self.head + self.len) % RING_BUFFER_SIZE] = c;
+        self.len += 1;
+    }
+    fn all_read_ends_closed(&self) -> bool {
+        self.read_end.as_ref().unwrap().upgrade().is_none()
+    }
+    fn all_write_ends_closed(&self) -> bool {
+        self.write_end.as_ref().unwrap().upgrade().is_none()
+    }
+}
+
+/// 管道的一端
+pub struct Pipe {
+    readable: bool,
+    writable: bool,
+    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+}
+
+/// 创建一个管道，返回 (读端, 写端)
+pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
+    let buffer = Arc::new(unsafe { UPSafeCell::new(PipeRingBuffer::new()) });
+    let read_end = Arc::new(Pipe {
+        readable: true,
+        writable: false,
+        buffer: buffer.clone(),
+    });
+    let write_end = Arc::new(Pipe {
+        readable: false,
+        writable: true,
+        buffer: buffer.clone(),
+    });
+    let mut inner = buffer.exclusive_access();
+    inner.read_end = Some(Arc::downgrade(&read_end));
+    inner.write_end = Some(Arc::downgrade(&write_end));
+    drop(inner);
+    (read_end, write_end)
+}
+
+impl Pipe {
+    /// 在 `queue` 选出的等待队列上阻塞当前任务，直到管道的状态变化。有待处理的信号时不等待，返回 `false`
+    fn wait(&self, queue: impl Fn(&mut PipeRingBuffer) -> &mut WaitQueue) -> bool {
+        if task::signal_pending() {
+            return false;
+        }
+        wait_queue::wait_on(&self.buffer, queue);
+        true
+    }
+    /// 缓冲区为空时等待，读到至少一个字节后立即返回。写端全部关闭后返回 0，
+    /// 等待时被信号打断返回 `IoError::Interrupted`。
+    /// `nonblock` 时不等待，缓冲区为空时返回 `IoError::WouldBlock`
+    fn read_inner(&self, buf: UserBuffer, nonblock: bool) -> Result<usize, IoError> {
+        assert!(self.readable);
+        let want = buf.len();
+        let mut buf_iter = buf.into_iter();
+        let mut read_size = 0;
+        loop {
+            let mut ring = self.buffer.exclusive_access();
+            if ring.len == 0 {
+                if ring.all_write_ends_closed() {
+                    return Ok(0);
+                }
+                if nonblock {
+                    return Err(IoError::WouldBlock);
+                }
+                drop(ring);
+                // 被信号打断时返回，让进程回到 trap 处理信号
+                if !self.wait(|ring| &mut ring.readers) {
+                    return Err(IoError::Interrupted);
+                }
+                continue;
+            }
+            while ring.len > 0 && read_size < want {
+                let byte = ring.read_byte();
+                unsafe { *buf_iter.next().unwrap() = byte };
+                read_size += 1;
+            }
+            if ring.len > 0 {
+                ring.readers.wake_one();
+            }
+            ring.wakers.wake_all();
+            return Ok(read_size);
+        }
+    }
+    /// 缓冲区满时等待，直到全部写入。读端全部关闭后返回已写入的字节数，
+    /// 等待时被信号打断返回已写入的字节数，一个字节也没有写入时返回 `IoError::Interrupted`。
+    /// `nonblock` 时不等待，返回已写入的字节数，一个字节也没有写入时返回 `IoError::WouldBlock`
+    fn write_inner(&self, buf: UserBuffer, nonblock: bool) -> Result<usize, IoError> {
+        assert!(self.writable);
+        let want = buf.len();
+        let mut buf_iter = buf.into_iter();
+        let mut write_size = 0;
+        loop {
+            let mut ring = self.buffer.exclusive_access();
+            if ring.all_read_ends_closed() {
+                return Ok(write_size);
+            }
+            while ring.len < RING_BUFFER_SIZE && write_size < want {
+                ring.write_byte(unsafe { *buf_iter.next().unwrap() });
+                write_size += 1;
+            }
+            ring.readers.wake_one();
+            ring.wakers.wake_all();
+            if write_size == want {
+                return Ok(write_size);
+            }
+            if nonblock {
+                return if write_size == 0 {
+                    Err(IoError::WouldBlock)
+                } else {
+                    Ok(write_size)
+                };
+            }
+            drop(ring);
+            if !self.wait(|ring| &mut ring.wakers) {
+                return if write_size == 0 {
+                    Err(IoError::Interrupted)
+                } else {
+                    Ok(write_size)
+                };
+            }
+        }
+    }
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.read_inner(buf, false)
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.write_inner(buf, false)
+    }
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.read_inner(buf, true)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        self.write_inner(buf, true)
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+    fn poll(&self) -> PollEvents {
+        let ring = self.buffer.exclusive_access();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if ring.len > 0 {
+                events |= PollEvents::POLLIN;
+            }
+            if ring.all_write_ends_closed() {
+                events |= PollEvents::POLLHUP;
+            }
+        }
+        if self.writable {
+            if ring.all_read_ends_closed() {
+                events |= PollEvents::POLLERR;
+            } else if ring.len < RING_BUFFER_SIZE {
+                events |= PollEvents::POLLOUT;
+            }
+        }
+        events
+    }
+    fn register_waker(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.buffer
+            .exclusive_access()
+            .wakers
+            .add_waiter(Arc::clone(task));
+        true
+    }
+    fn unregister_waker(&self, task: &Arc<TaskControlBlock>) {
+        self.buffer.exclusive_access().wakers.remove_waiter(task);
+    }
+}
+
+impl Drop for Pipe {
+    /// 关闭一端后另一端的状态可能发生变化（读到 EOF 或写入失败）
+    fn drop(&mut self) {
+        let mut ring = self.buffer.exclusive_access();
+        ring.readers.wake_all();
+        ring.wakers.wake_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::page_table::buffer;
+
+    #[test_case]
+    fn nonblocking_pipe_never_waits() {
+        let (read_end, write_end) = make_pipe();
+        assert_eq!(read_end.try_read(buffer(&[0; 8])), Err(IoError::WouldBlock));
+        assert_eq!(
+            write_end.try_write(buffer(&[0; RING_BUFFER_SIZE + 10])),
+            Ok(RING_BUFFER_SIZE)
+        );
+        assert_eq!(
+            write_end.try_write(buffer(&[0; 1])),
+            Err(IoError::WouldBlock)
+        );
+        assert_eq!(read_end.try_read(buffer(&[0; 8])), Ok(8));
+        drop(write_end);
+        assert_eq!(
+            read_end.try_read(buffer(&[0; RING_BUFFER_SIZE])),
+            Ok(RING_BUFFER_SIZE - 8)
+        );
+        assert_eq!(read_end.try_read(buffer(&[0; 8])), Ok(0), "EOF, not EAGAIN");
+    }
+}
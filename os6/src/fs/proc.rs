@@ -0,0 +1,153 @@
+//! 进程信息文件（procfs）
+//!
+//! 文件系统没有目录，`/proc/<pid>/statm` 这样的路径在打开时由 `sys_open` 交给这里处理，
+//! 打开时生成内容的快照，之后像只读的内存文件一样读取。`<pid>` 也可以是 `self`。
+//! 每个进程有 `statm`，以及工作集估计 `wss`，见 [`wss`](crate::mm::wss) 模块。
+//!
+//! 此外还有与进程无关的 `/proc/uptime`，内容为启动以来的秒数和 idle 控制流等待中断的秒数；
+//! `/proc/profile`，内容为采样分析的结果，见 [`profile`](crate::profile) 模块；
+//! 以及 `/proc/iosched`，内容为块设备 I/O 调度器的统计。
+
+use alloc::{format, string::String, sync::Arc};
+
+use super::{inode::OpenError, File, IoError, Stat, StatMode};
+use crate::{
+    drivers,
+    mm::page_table::UserBuffer,
+    profile,
+    sync::UPSafeCell,
+    task::{self, Processor},
+    timer,
+};
+
+/// 内容在打开时生成的只读文件
+pub struct ProcFile {
+    content: String,
+    offset: UPSafeCell<usize>,
+}
+
+impl ProcFile {
+    fn new(content: String) -> Self {
+        Self {
+            content,
+            offset: unsafe { UPSafeCell::new(0) },
+        }
+    }
+}
+
+/// `path` 不在 `/proc` 下时返回 `None`，否则返回打开的结果
+pub fn open(path: &str, writable: bool) -> Option<Result<Arc<ProcFile>, OpenError>> {
+    let rest = path.strip_prefix("/proc/")?;
+    let global = match rest {
+        "uptime" => Some(uptime as fn() -> String),
+        "profile" => Some(profile::report as fn() -> String),
+        "iosched" => Some(iosched as fn() -> String),
+        _ => None,
+    };
+    if let Some(content) = global {
+        return Some(if writable {
+            Err(OpenError::PermissionDenied)
+        } else {
+            Ok(Arc::new(ProcFile::new(content())))
+        });
+    }
+    let (pid, name) = match rest.split_once('/') {
+        Some(parts) => parts,
+        None => return Some(Err(OpenError::NotFound)),
+    };
+    let task = if pid == "self" {
+        Processor::current_task()
+    } else {
+        pid.parse().ok().and_then(task::pid2task)
+    };
+    Some(match (task, name) {
+        (Some(_), "statm" | "wss") if writable => Err(OpenError::PermissionDenied),
+        (Some(task), "statm") => {
+            let usage = task.inner_exclusive_access().memory_set.usage();
+            // 与 Linux 相同的 7 个字段：size resident shared text data lib dt
+            let content = format!(
+                "{} {} {} {} {} 0 0\n",
+                usage.size, usage.resident, usage.shared, usage.text, usage.data
+            );
+            Ok(Arc::new(ProcFile::new(content)))
+        }
+        (Some(task), "wss") => {
+            let ws = task.inner_exclusive_access().memory_set.working_set();
+            // 单位为页：工作集估计 最近一个间隔内访问的页数 写入的页数 采样次数
+            let content = format!(
+                "{} {} {} {}\n",
+                ws.estimate, ws.accessed, ws.dirty, ws.samples
+            );
+            Ok(Arc::new(ProcFile::new(content)))
+        }
+        _ => Err(OpenError::NotFound),
+    })
+}
+
+/// 与 Linux 相同的格式，两个以秒为单位、保留两位小数的时间
+fn uptime() -> String {
+    let centis = |cycles: usize| (cycles as u128 * 100 / timer::clock_freq() as u128) as usize;
+    let up = centis(timer::get_time());
+    let idle = centis(Processor::idle_stats().cycles);
+    format!(
+        "{}.{:02} {}.{:02}\n",
+        up / 100,
+        up % 100,
+        idle / 100,
+        idle % 100
+    )
+}
+
+/// 每行一项 I/O 调度器的统计
+fn iosched() -> String {
+    let stats = drivers::io_stats();
+    format!(
+        "reads {}\nwrites {}\nread_hits {}\nwrite_merges {}\ndispatched {}\nbatches {}\nruns {}\nseek_distance {}\nmax_depth {}\n",
+        stats.reads,
+        stats.writes,
+        stats.read_hits,
+        stats.write_merges,
+        stats.dispatched,
+        stats.batches,
+        stats.runs,
+        stats.seek_distance,
+        stats.max_depth
+    )
+}
+
+impl File for ProcFile {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        let mut offset = self.offset.exclusive_access();
+        let rest = &self.content.as_bytes()[*offset..];
+        let mut read_size = 0;
+        for (dst, &byte) in buf.into_iter().zip(rest) {
+            unsafe { *dst = byte };
+            read_size += 1;
+        }
+        *offset += read_size;
+        Ok(read_size)
+    }
+    fn write(&self, _buf: UserBuffer) -> Result<usize, IoError> {
+        panic!("Cannot write to a proc file");
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::FILE | StatMode::OWNER_R | StatMode::GROUP_R | StatMode::OTHER_R,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+}
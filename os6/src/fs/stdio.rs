@@ -1,40 +1,175 @@
-use crate::{mm::page_table::UserBuffer, sbi, task};
+use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use lazy_static::lazy_static;
 
-use super::{File, Stat, StatMode};
+use crate::{
+    console,
+    mm::page_table::UserBuffer,
+    sbi,
+    sync::UPSafeCell,
+    task::{self, signal::SignalFlags, Processor},
+};
+
+use super::{FdFlags, File, FileDescriptor, IoError, PollEvents, Stat, StatMode};
 
 pub struct Stdin;
 pub struct Stdout;
 
+/// 新进程的文件描述符表，0、1、2 分别为标准输入、标准输出和标准错误输出
+pub fn initial_fd_table() -> Vec<Option<FileDescriptor>> {
+    vec![
+        Some(FileDescriptor::new(Arc::new(Stdin), FdFlags::empty())),
+        Some(FileDescriptor::new(Arc::new(Stdout), FdFlags::empty())),
+        Some(FileDescriptor::new(Arc::new(Stdout), FdFlags::empty())),
+    ]
+}
+
+/// 控制台的前台进程组，在控制台上按下 Ctrl-C 等键时向它发送信号
+static FOREGROUND_PGRP: UPSafeCell<usize> = unsafe { UPSafeCell::new(0) };
+
+pub fn foreground_pgrp() -> usize {
+    *FOREGROUND_PGRP.exclusive_access()
+}
+
+pub fn set_foreground_pgrp(pgid: usize) {
+    *FOREGROUND_PGRP.exclusive_access() = pgid;
+}
+
+/// 向前台进程组发送信号，返回收到信号的进程数
+pub fn signal_foreground(signal: SignalFlags) -> usize {
+    task::signal_pgrp(foreground_pgrp(), signal)
+}
+
+/// 中断字符 Ctrl-C，向前台进程组发送 SIGINT
+const CTRL_C: u8 = 0x03;
+/// 暂停字符 Ctrl-Z，向前台进程组发送 SIGTSTP
+const CTRL_Z: u8 = 0x1a;
+/// 控制台输入缓冲区最多缓存的字符数
+const INPUT_BUFFER_SIZE: usize = 256;
+
+lazy_static! {
+    /// 控制台输入缓冲区。SBI 无法查看而不取走输入的字符，取到的字符暂存在这里，留给之后的 `read`
+    static ref STDIN_BUFFER: UPSafeCell<VecDeque<u8>> = unsafe { UPSafeCell::new(VecDeque::new()) };
+}
+
+fn sbi_getchar() -> Option<u8> {
+    match sbi::console_getchar() as u8 {
+        0 => None,
+        c => Some(c),
+    }
+}
+
+/// 从 SBI 取出已经输入的字符放入输入缓冲区，直到没有输入或者缓冲区已满。
+///
+/// Ctrl-C 和 Ctrl-Z 不作为数据，而是分别向前台进程组发送 SIGINT 和 SIGTSTP。
+/// 除了读取标准输入时，时钟中断中也会调用，这样前台进程不读取输入时也能被 Ctrl-C 打断
+pub fn poll_console() {
+    while STDIN_BUFFER.exclusive_access().len() < INPUT_BUFFER_SIZE {
+        let c = match sbi_getchar() {
+            Some(c) => c,
+            None => break,
+        };
+        match c {
+            CTRL_C => {
+                signal_foreground(SignalFlags::SIGINT);
+            }
+            CTRL_Z => {
+                signal_foreground(SignalFlags::SIGTSTP);
+            }
+            c => STDIN_BUFFER.exclusive_access().push_back(c),
+        }
+    }
+}
+
+/// 输出当前任务缓存在控制台中的不完整的行
+fn flush_current() {
+    if let Some(task) = Processor::current_task() {
+        console::flush_task(task.pid());
+    }
+}
+
+/// 取一个输入的字符，没有输入时返回 `None`
+fn try_getchar() -> Option<u8> {
+    poll_console();
+    STDIN_BUFFER.exclusive_access().pop_front()
+}
+
+/// 把已经取出的字符 `first` 和输入缓冲区中其余的字符依次填入 `buf`，直到填满或者没有更多输入，
+/// 返回填入的字节数
+fn fill_input(first: u8, buf: UserBuffer) -> usize {
+    let mut input = STDIN_BUFFER.exclusive_access();
+    let mut next = Some(first);
+    let mut count = 0;
+    for ptr in buf {
+        let c = match next.take().or_else(|| input.pop_front()) {
+            Some(c) => c,
+            None => break,
+        };
+        unsafe { ptr.write_volatile(c) }
+        count += 1;
+    }
+    count
+}
+
 impl File for Stdin {
     fn readable(&self) -> bool {
         true
     }
     fn writable(&self) -> bool {
-        true
+        false
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
-        assert_eq!(buf.len(), 1);
+    /// 至少有一个字符可读时返回，`buf` 较长时一并取走已经输入的其余字符
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        // 等待输入之前把提示符等不完整的行输出
+        flush_current();
         let c = loop {
-            let c = sbi::console_getchar() as u8;
-            if c == 0 {
-                task::suspend_current_and_run_next();
-            } else {
+            if let Some(c) = try_getchar() {
                 break c;
             }
+            // 有待处理的信号时返回，让进程回到 trap 处理信号
+            if task::signal_pending() {
+                return Err(IoError::Interrupted);
+            }
+            task::suspend_current_and_run_next();
         };
-        unsafe { buf.buffers[0].as_mut_ptr().write_volatile(c) }
-        1
+        Ok(fill_input(c, buf))
     }
-    fn write(&self, _buf: UserBuffer) -> usize {
+    fn write(&self, _buf: UserBuffer) -> Result<usize, IoError> {
         panic!("Cannot write to stdin");
     }
+    fn is_tty(&self) -> bool {
+        true
+    }
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        if buf.len() == 0 {
+            return Ok(0);
+        }
+        flush_current();
+        let c = try_getchar().ok_or(IoError::WouldBlock)?;
+        Ok(fill_input(c, buf))
+    }
     fn stat(&self) -> Stat {
         Stat {
             dev: 0,
             ino: 0,
             mode: StatMode::NULL,
             nlink: 1,
-            pad: [0; 7],
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+    fn poll(&self) -> PollEvents {
+        poll_console();
+        if !STDIN_BUFFER.exclusive_access().is_empty() {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
         }
     }
 }
@@ -46,14 +181,18 @@ impl File for Stdout {
     fn writable(&self) -> bool {
         true
     }
-    fn read(&self, mut _buf: UserBuffer) -> usize {
+    fn read(&self, mut _buf: UserBuffer) -> Result<usize, IoError> {
         panic!("Cannot read from stdout");
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn is_tty(&self) -> bool {
+        true
+    }
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        let pid = Processor::current_task().map_or(0, |task| task.pid());
         for buffer in &buf.buffers {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
+            console::write_task(pid, buffer);
         }
-        buf.len()
+        Ok(buf.len())
     }
     fn stat(&self) -> Stat {
         super::Stat {
@@ -61,7 +200,12 @@ impl File for Stdout {
             ino: 0,
             mode: StatMode::NULL,
             nlink: 1,
-            pad: [0; 7],
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
         }
     }
 }
@@ -0,0 +1,160 @@
+//! 处理器间中断（IPI）
+//!
+//! 通过 SBI 的 IPI 扩展向其它 hart 发送 S 态软件中断，目标 hart 在 `trap_handler` 中以
+//! `Interrupt::SupervisorSoft` 收到。每个 hart 有一个消息队列：发送方先放入消息再发中断，
+//! 接收方清除 `sip.SSIP` 之后取出队列中的所有消息依次处理，所以多条消息可以共用一次中断。
+//!
+//! 内核目前只在启动的 hart 上运行，这里先搭好多核调度和跨 hart 刷新 TLB 所需的机制。
+//! 消息队列用 [`KSpinLock`] 保护，真正多核之后需要换成能跨核等待的锁。
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use lazy_static::*;
+use riscv::register::{sie, sip};
+
+use crate::{config::MAX_HARTS, mm::asid, sbi, sync::KSpinLock};
+
+#[derive(Clone, Debug)]
+pub enum IpiMessage {
+    /// 让目标 hart 尽快重新调度
+    Reschedule,
+    /// 刷新目标 hart 的 TLB。`asid` 为 `None` 时刷新所有地址空间，`va` 为 `None` 时刷新所有地址。
+    /// 刷新后把 `pending` 减一，发送方等它减到 0，见 [`shootdown`]
+    TlbShootdown {
+        asid: Option<usize>,
+        va: Option<Range<usize>>,
+        pending: Arc<AtomicUsize>,
+    },
+}
+
+lazy_static! {
+    static ref MAILBOXES: Vec<KSpinLock<VecDeque<IpiMessage>>> = (0..MAX_HARTS)
+        .map(|_| unsafe { KSpinLock::new("IPI_MAILBOX", VecDeque::new()) })
+        .collect();
+}
+
+/// 已经启动、可以接收 IPI 的 hart 的位图
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+/// 启动的 hart，也是目前唯一运行内核的 hart
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+
+/// 登记启动的 hart 并打开软件中断
+pub fn init(hartid: usize) {
+    assert!(hartid < MAX_HARTS, "hart {} exceeds MAX_HARTS", hartid);
+    BOOT_HART.store(hartid, Ordering::Relaxed);
+    ONLINE.fetch_or(1 << hartid, Ordering::Relaxed);
+    unsafe { sie::set_ssoft() };
+}
+
+/// 当前 hart 的编号
+pub fn current_hart() -> usize {
+    BOOT_HART.load(Ordering::Relaxed)
+}
+
+/// 向 `hart` 发送一条消息
+pub fn send(hart: usize, message: IpiMessage) {
+    MAILBOXES[hart].lock().push_back(message);
+    if let Err(error) = sbi::send_ipi(1 << hart, 0) {
+        log::warn!(
+            "[kernel] failed to send IPI to hart {}, SBI error {}",
+            hart,
+            error
+        );
+    }
+}
+
+/// 向除自己以外的所有在线的 hart 发送同一条消息，返回发送的个数
+pub fn broadcast(message: IpiMessage) -> usize {
+    let others = ONLINE.load(Ordering::Relaxed) & !(1 << current_hart());
+    let harts: Vec<usize> = (0..MAX_HARTS)
+        .filter(|hart| others & 1 << hart != 0)
+        .collect();
+    for &hart in harts.iter() {
+        send(hart, message.clone());
+    }
+    harts.len()
+}
+
+/// 请求位图 `harts` 中的各个 hart 刷新 TLB，等它们都刷新完后返回。
+///
+/// 调用者随后可能释放被解除映射的页帧，所以必须等待。内核态不响应软件中断，
+/// 两个 hart 同时向对方发起刷新时会互相等待，因此一边等待一边处理发给自己的消息
+pub fn shootdown(harts: usize, asid: Option<usize>, va: Option<Range<usize>>) {
+    let targets: Vec<usize> = (0..MAX_HARTS)
+        .filter(|hart| harts & 1 << hart != 0)
+        .collect();
+    if targets.is_empty() {
+        return;
+    }
+    let pending = Arc::new(AtomicUsize::new(targets.len()));
+    for hart in targets {
+        send(
+            hart,
+            IpiMessage::TlbShootdown {
+                asid,
+                va: va.clone(),
+                pending: Arc::clone(&pending),
+            },
+        );
+    }
+    let mut reschedule = false;
+    while pending.load(Ordering::Acquire) != 0 {
+        if sip::read().ssoft() {
+            reschedule |= handle();
+        }
+        core::hint::spin_loop();
+    }
+    if reschedule {
+        // 调度请求留到回到 trap 时再处理
+        send(current_hart(), IpiMessage::Reschedule);
+    }
+}
+
+/// 处理当前 hart 收到的所有消息，返回是否需要重新调度
+pub fn handle() -> bool {
+    // 先清除挂起位再取消息，处理期间到来的消息会再次置位，不会丢失
+    unsafe { sip::clear_ssoft() };
+    let messages = core::mem::take(&mut *MAILBOXES[current_hart()].lock());
+    let mut reschedule = false;
+    for message in messages {
+        match message {
+            IpiMessage::Reschedule => reschedule = true,
+            IpiMessage::TlbShootdown { asid, va, pending } => {
+                asid::flush_local(asid, va);
+                pending.fetch_sub(1, Ordering::Release);
+            }
+        }
+    }
+    reschedule
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn messages_to_self_are_delivered() {
+        let hart = current_hart();
+        let pending = Arc::new(AtomicUsize::new(1));
+        send(
+            hart,
+            IpiMessage::TlbShootdown {
+                asid: None,
+                va: Some(0x1000..0x3000),
+                pending: Arc::clone(&pending),
+            },
+        );
+        send(hart, IpiMessage::Reschedule);
+        assert!(sip::read().ssoft(), "IPI is pending");
+        assert!(handle());
+        assert_eq!(pending.load(Ordering::Relaxed), 0, "shootdown acknowledged");
+        assert!(!sip::read().ssoft());
+        assert!(MAILBOXES[hart].lock().is_empty());
+        assert!(!handle(), "nothing left to handle");
+        // 目标为空时立即返回
+        shootdown(0, None, None);
+    }
+}
@@ -0,0 +1,60 @@
+//! Kernel symbol table
+//!
+//! 内核镜像中预留了一段只读的 `.ksyms` 段。`make` 在链接之后用 `rust-nm -n` 列出内核中的函数，
+//! 再用 `rust-objcopy --update-section` 写入这段空间：每行 `<十六进制地址> <函数名>`，
+//! 按地址从小到大排列，其余部分为 0。`cargo test` 构建的内核不经过这一步，符号表为空。
+
+/// 预留给符号表的空间，与 Makefile 中的 `KSYMS_SIZE` 相同
+const KSYMS_SIZE: usize = 512 * 1024;
+
+/// 占据 `.ksyms` 段。内容在编译之后才写入，编译器认为它全为 0，所以只通过链接脚本中的
+/// `sksyms` 和 `eksyms` 访问
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+/// 按地址从小到大列出内核中的函数及其起始地址
+pub fn symbols() -> impl Iterator<Item = (usize, &'static str)> {
+    extern "C" {
+        fn sksyms();
+        fn eksyms();
+    }
+    let table = unsafe {
+        core::slice::from_raw_parts(
+            sksyms as usize as *const u8,
+            eksyms as usize - sksyms as usize,
+        )
+    };
+    let len = table.iter().position(|&b| b == 0).unwrap_or(table.len());
+    parse(core::str::from_utf8(&table[..len]).unwrap_or(""))
+}
+
+fn parse(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines().filter_map(|line| {
+        let (addr, name) = line.split_once(' ')?;
+        Some((usize::from_str_radix(addr, 16).ok()?, name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test_case]
+    fn symbol_lines_are_parsed() {
+        let text =
+            "ffffffc080200000 _start\ngarbage\nffffffc080201000 <T as core::fmt::Debug>::fmt\n";
+        let parsed: Vec<_> = parse(text).collect();
+        assert_eq!(
+            parsed,
+            [
+                (0xffff_ffc0_8020_0000, "_start"),
+                (0xffff_ffc0_8020_1000, "<T as core::fmt::Debug>::fmt"),
+            ]
+        );
+        // `cargo test` 构建的内核没有写入符号表
+        assert_eq!(symbols().count(), 0);
+    }
+}
@@ -0,0 +1,28 @@
+//! Kernel test harness
+//!
+//! `make test` 以 `cargo test` 构建内核的测试版本，并通过 `.cargo/config` 中的 runner
+//! 在 QEMU 中运行。各模块中以 `#[test_case]` 标注的函数在内核完成内存初始化后依次执行，
+//! 全部通过后经 SiFive test 设备以退出码 0 关闭 QEMU，任何一个测试 panic 都以非零退出码关闭 QEMU。
+
+use crate::sbi::exit::exit_qemu;
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        print!("test {} ... ", core::any::type_name::<T>());
+        self();
+        println!("ok");
+    }
+}
+
+pub fn test_runner(tests: &[&dyn Testable]) {
+    println!("[kernel] running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    println!("[kernel] all tests passed");
+    exit_qemu(0)
+}
@@ -0,0 +1,164 @@
+//! Scheduler tracing
+//!
+//! 命令行带有 `ktrace` 选项时，任务切换、系统调用的进入和返回、缺页以及任务进入就绪队列等事件
+//! 连同时间戳记录到一个固定大小的环形缓冲区中，缓冲区满后覆盖最旧的事件。
+//! 用户程序通过 `sys_ktrace_read` 把事件读出，在宿主机上转换成 Perfetto 等工具可以显示的格式。
+//!
+//! 记录事件不加锁：写者用 `fetch_add` 取得一个序号，写入序号对应的槽位后再写入槽位的序号。
+//! 读者在读取前后检查槽位的序号，不一致说明读取期间被覆盖，丢弃这个事件。
+
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+use crate::{cmdline, config::KTRACE_BUFFER_LEN, ipi, task::Processor, timer};
+
+/// 事件类型，取值即 [`TraceEvent::kind`]
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// 任务开始运行
+    SwitchIn = 1,
+    /// 任务让出处理器，`arg` 为它让出后的状态：0 就绪，1 阻塞，2 退出
+    SwitchOut = 2,
+    /// 进入系统调用，`arg` 为 syscall ID
+    SyscallEnter = 3,
+    /// 系统调用返回，`arg` 为返回值
+    SyscallExit = 4,
+    /// 用户态缺页，`arg` 为出错的地址
+    Fault = 5,
+    /// 任务进入就绪队列，包括新建、被唤醒和主动让出的任务
+    Wakeup = 6,
+}
+
+/// 交给用户的一个事件
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// 事件发生的时间，单位为微秒
+    pub time_us: u64,
+    pub kind: u32,
+    pub hart: u32,
+    /// 事件所属任务的进程号和线程号，idle 控制流中为 0
+    pub pid: u32,
+    pub tid: u32,
+    pub arg: u64,
+}
+
+/// 环形缓冲区中的一个槽位
+struct Slot {
+    /// 槽位中事件的序号，正在写入时为 `usize::MAX`
+    seq: AtomicUsize,
+    time_us: AtomicUsize,
+    /// 低 32 位为事件类型，高 32 位为 hart
+    kind_hart: AtomicUsize,
+    /// 低 32 位为进程号，高 32 位为线程号
+    ids: AtomicUsize,
+    arg: AtomicUsize,
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Slot = Slot {
+        seq: AtomicUsize::new(usize::MAX),
+        time_us: AtomicUsize::new(0),
+        kind_hart: AtomicUsize::new(0),
+        ids: AtomicUsize::new(0),
+        arg: AtomicUsize::new(0),
+    };
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// 下一个事件的序号，即累计记录的事件数
+static HEAD: AtomicUsize = AtomicUsize::new(0);
+static BUFFER: [Slot; KTRACE_BUFFER_LEN] = [Slot::EMPTY; KTRACE_BUFFER_LEN];
+
+/// 命令行带有 `ktrace` 选项时开始记录，需要在 `cmdline::init` 之后调用
+pub fn init() {
+    ENABLED.store(cmdline::ktrace(), Ordering::Relaxed);
+}
+
+/// 记录任务 (`pid`, `tid`) 的一个事件
+pub fn record(kind: EventKind, pid: usize, tid: usize, arg: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let seq = HEAD.fetch_add(1, Ordering::Relaxed);
+    let slot = &BUFFER[seq % KTRACE_BUFFER_LEN];
+    slot.seq.store(usize::MAX, Ordering::Relaxed);
+    fence(Ordering::Release);
+    slot.time_us.store(timer::get_time_us(), Ordering::Relaxed);
+    slot.kind_hart
+        .store(kind as usize | ipi::current_hart() << 32, Ordering::Relaxed);
+    slot.ids.store(pid | tid << 32, Ordering::Relaxed);
+    slot.arg.store(arg, Ordering::Relaxed);
+    slot.seq.store(seq, Ordering::Release);
+}
+
+/// 记录当前任务的一个事件。即使 `PROCESSOR` 已被借用也可以调用
+pub fn record_current(kind: EventKind, arg: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let (pid, tid) = Processor::try_current_ids().unwrap_or((0, 0));
+    record(kind, pid, tid, arg);
+}
+
+/// 读出序号不小于 `cursor` 的事件，最多 `max` 个，返回读出的事件和下一次读取时使用的 `cursor`。
+///
+/// `cursor` 之后的事件已经被覆盖时从仍在缓冲区中的最旧的事件开始
+pub fn read(cursor: usize, max: usize) -> (Vec<TraceEvent>, usize) {
+    let head = HEAD.load(Ordering::Acquire);
+    let start = cursor.max(head.saturating_sub(KTRACE_BUFFER_LEN));
+    let end = head.min(start.saturating_add(max));
+    let mut events = Vec::new();
+    for seq in start..end {
+        let slot = &BUFFER[seq % KTRACE_BUFFER_LEN];
+        if slot.seq.load(Ordering::Acquire) != seq {
+            continue;
+        }
+        let kind_hart = slot.kind_hart.load(Ordering::Relaxed);
+        let ids = slot.ids.load(Ordering::Relaxed);
+        let event = TraceEvent {
+            time_us: slot.time_us.load(Ordering::Relaxed) as u64,
+            kind: kind_hart as u32,
+            hart: (kind_hart >> 32) as u32,
+            pid: ids as u32,
+            tid: (ids >> 32) as u32,
+            arg: slot.arg.load(Ordering::Relaxed) as u64,
+        };
+        fence(Ordering::Acquire);
+        if slot.seq.load(Ordering::Relaxed) == seq {
+            events.push(event);
+        }
+    }
+    (events, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn events_are_read_back_in_order_and_overwritten_when_full() {
+        let was_enabled = ENABLED.swap(true, Ordering::Relaxed);
+        let cursor = HEAD.load(Ordering::Relaxed);
+        record(EventKind::SyscallEnter, 2, 3, 64);
+        record(EventKind::SyscallExit, 2, 3, -9isize as usize);
+        let (events, next) = read(cursor, usize::MAX);
+        assert_eq!(next, cursor + 2);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, EventKind::SyscallEnter as u32);
+        assert_eq!((events[0].pid, events[0].tid, events[0].arg), (2, 3, 64));
+        assert_eq!(events[1].arg as i64, -9);
+        assert!(events[0].time_us <= events[1].time_us);
+        assert!(read(next, usize::MAX).0.is_empty());
+
+        for i in 0..KTRACE_BUFFER_LEN + 1 {
+            record(EventKind::Wakeup, 1, 1, i);
+        }
+        let (events, next) = read(cursor, 1);
+        assert_eq!(events[0].arg, 1, "the oldest surviving event");
+        assert_eq!(next, cursor + 4);
+        ENABLED.store(was_enabled, Ordering::Relaxed);
+    }
+}
@@ -1,12 +1,25 @@
 //! The panic handler
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::PANIC_REBOOT_DELAY_MS;
 use crate::console::ANSICON;
-use crate::sbi::shutdown;
+use crate::sbi::{exit::exit_qemu, reboot, shutdown};
+use crate::task::Processor;
+use crate::{backtrace, timer};
 
 use core::panic::PanicInfo;
+use riscv::register::{satp, sstatus};
+
+/// 是否已经处于 panic 流程中。回溯等诊断过程本身也可能 panic，此时不再重复诊断
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// 内核 panic 时 QEMU 的退出码
+const PANIC_EXIT_CODE: u16 = 1;
 
 #[panic_handler]
 /// panic handler
+#[cfg_attr(test, allow(unreachable_code))]
 fn panic(info: &PanicInfo) -> ! {
     if let Some(location) = info.location() {
         println_colorized!(
@@ -25,5 +38,33 @@ fn panic(info: &PanicInfo) -> ! {
             info.message().unwrap()
         );
     }
-    shutdown()
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        println!("[kernel] Panicked while panicking, shutting down");
+        shutdown()
+    }
+    println!(
+        "[kernel] satp = {:#x}, sstatus = {:?}",
+        satp::read().bits(),
+        sstatus::read()
+    );
+    // 这里不能分配内存，堆可能正是 panic 的原因
+    if let Some((pid, tid)) = Processor::try_current_ids() {
+        println!("[kernel] current pid.tid = {}.{}", pid, tid);
+    } else {
+        println!("[kernel] no current task");
+    }
+    backtrace::backtrace();
+    #[cfg(not(test))]
+    crate::power::sync_after_panic();
+    // 内核测试中 panic 即测试失败，直接以失败的退出码关闭 QEMU
+    #[cfg(test)]
+    exit_qemu(PANIC_EXIT_CODE);
+    if let Some(delay) = PANIC_REBOOT_DELAY_MS {
+        println!("[kernel] Rebooting in {} ms", delay);
+        let deadline = timer::get_time_ms() + delay;
+        while timer::get_time_ms() < deadline {}
+        reboot()
+    }
+    // 以非零的退出码关闭 QEMU，让脚本知道内核 panic 了
+    exit_qemu(PANIC_EXIT_CODE)
 }
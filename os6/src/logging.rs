@@ -1,13 +1,129 @@
 //! Global logger
+//!
+//! 日志除了输出到控制台，还会写入一个内存中的环形缓冲区，用户程序可以通过 `sys_syslog` 读取最近的内核日志。
+//!
+//! 日志等级可以按模块设置，见 [`LogFilter::parse`]，例如 `log=trace,mm::page_table=info`
+//! 在打开 trace 日志的同时略去页表的逐页映射记录。高于编译时等级上限的日志在编译时就被去掉，
+//! 上限由 `log-max-*` feature 决定，默认不限制。此外还可以关闭颜色、加上启动以来的时间。
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use log::{self, Level, LevelFilter, Log, Metadata, Record};
 
-/// a simple logger
-struct SimpleLogger;
+use crate::{mm::page_table::UserBuffer, sync::UPSafeCell, task::Processor, timer};
+
+/// 内核日志环形缓冲区的大小
+const LOG_BUFFER_SIZE: usize = 16 * 1024;
+
+/// 保存最近 `LOG_BUFFER_SIZE` 字节日志的环形缓冲区
+struct LogBuffer {
+    buf: [u8; LOG_BUFFER_SIZE],
+    /// 累计写入的字节数，`written % LOG_BUFFER_SIZE` 即下一个写入位置
+    written: usize,
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buf[self.written % LOG_BUFFER_SIZE] = byte;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+static LOG_BUFFER: UPSafeCell<LogBuffer> = unsafe {
+    UPSafeCell::new(LogBuffer {
+        buf: [0; LOG_BUFFER_SIZE],
+        written: 0,
+    })
+};
+
+/// 日志中显示的 ` [pid.tid]`
+struct TaskIds(usize, usize);
+
+impl fmt::Display for TaskIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, " [{}.{}]", self.0, self.1)
+    }
+}
+
+/// 日志的过滤规则：默认等级，以及按模块覆盖的等级
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    pub default: LevelFilter,
+    /// 模块路径及其等级，模块路径不含 crate 名，如 `mm::page_table`。同时覆盖其子模块
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    const fn new(default: LevelFilter) -> Self {
+        Self {
+            default,
+            modules: Vec::new(),
+        }
+    }
+    /// 解析形如 `debug,mm::page_table=warn,task=trace` 的规则，各项用逗号分隔，
+    /// 不带 `=` 的一项为默认等级，没有时默认为 Off。有不合法的项时返回 `None`
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut filter = Self::new(LevelFilter::Off);
+        for item in spec.split(',').filter(|item| !item.is_empty()) {
+            match item.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.strip_prefix("os::").unwrap_or(module);
+                    filter
+                        .modules
+                        .push((String::from(module), parse_level(level)?));
+                }
+                None => filter.default = parse_level(item)?,
+            }
+        }
+        Some(filter)
+    }
+    /// `target` 中的日志的等级上限。`target` 默认为记录日志的模块的完整路径，如 `os::mm::page_table`，
+    /// 按匹配的最长的模块路径决定
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let module = target.split_once("::").map_or("", |(_, path)| path);
+        self.modules
+            .iter()
+            .filter(|(path, _)| {
+                module == path
+                    || module
+                        .strip_prefix(path.as_str())
+                        .map_or(false, |rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(path, _)| path.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+    /// 所有规则中最高的等级。`log` 先按 `log::max_level` 过滤，因此它需要不低于任何一条规则
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+static FILTER: UPSafeCell<LogFilter> = unsafe { UPSafeCell::new(LogFilter::new(LevelFilter::Off)) };
+/// 是否用 ANSI 转义序列按等级给日志上色
+static COLOR: AtomicBool = AtomicBool::new(true);
+/// 是否在日志前加上启动以来的时间
+static TIMESTAMP: AtomicBool = AtomicBool::new(false);
+
+struct KernelLogger;
 
-impl Log for SimpleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // 正在修改过滤规则时只按全局的等级过滤
+        let max = match FILTER.try_exclusive_access() {
+            Some(filter) => filter.level_for(metadata.target()),
+            None => log::max_level(),
+        };
+        metadata.level() <= max
     }
     fn log(&self, record: &Record) {
         if !self.enabled(record.metadata()) {
@@ -20,26 +136,150 @@ impl Log for SimpleLogger {
             Level::Debug => 32, // Green
             Level::Trace => 90, // BrightBlack
         };
-        println!(
-            "\u{1B}[{}m[{:>5}] {}\u{1B}[0m",
-            color,
-            record.level(),
-            record.args(),
-        );
+        // 有当前任务时在等级后标出它的 pid.tid
+        let ids = Processor::try_current_ids();
+        let ids = ids.as_ref().map(|(pid, tid)| TaskIds(*pid, *tid));
+        let ids: &dyn fmt::Display = match &ids {
+            Some(ids) => ids,
+            None => &"",
+        };
+        let time = Timestamp(timer::get_time_us());
+        let time: &dyn fmt::Display = if TIMESTAMP.load(Ordering::Relaxed) {
+            &time
+        } else {
+            &""
+        };
+        if COLOR.load(Ordering::Relaxed) {
+            println!(
+                "\u{1B}[{}m{}[{:>5}]{} {}\u{1B}[0m",
+                color,
+                time,
+                record.level(),
+                ids,
+                record.args(),
+            );
+        } else {
+            println!("{}[{:>5}]{} {}", time, record.level(), ids, record.args());
+        }
+        // 如果日志缓冲区正被读取（例如读取过程中又打印了日志），就放弃写入这一条
+        if let Some(mut log_buffer) = LOG_BUFFER.try_exclusive_access() {
+            writeln!(
+                log_buffer,
+                "{}[{:>5}]{} {}",
+                time,
+                record.level(),
+                ids,
+                record.args()
+            )
+            .unwrap();
+        }
     }
     fn flush(&self) {}
 }
 
+/// 日志前的 `[秒.微秒] `
+struct Timestamp(usize);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:>5}.{:06}] ", self.0 / 1_000_000, self.0 % 1_000_000)
+    }
+}
+
 /// initiate logger
 pub fn init() {
-    static LOGGER: SimpleLogger = SimpleLogger;
+    static LOGGER: KernelLogger = KernelLogger;
     log::set_logger(&LOGGER).unwrap();
-    log::set_max_level(match option_env!("LOG") {
-        Some("ERROR") => LevelFilter::Error,
-        Some("WARN") => LevelFilter::Warn,
-        Some("INFO") => LevelFilter::Info,
-        Some("DEBUG") => LevelFilter::Debug,
-        Some("TRACE") => LevelFilter::Trace,
-        _ => LevelFilter::Off,
-    });
+    set_level(
+        option_env!("LOG")
+            .and_then(parse_level)
+            .unwrap_or(LevelFilter::Off),
+    );
+}
+
+/// 解析日志等级，不区分大小写
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    [
+        ("OFF", LevelFilter::Off),
+        ("ERROR", LevelFilter::Error),
+        ("WARN", LevelFilter::Warn),
+        ("INFO", LevelFilter::Info),
+        ("DEBUG", LevelFilter::Debug),
+        ("TRACE", LevelFilter::Trace),
+    ]
+    .iter()
+    .find(|(name, _)| name.eq_ignore_ascii_case(level))
+    .map(|&(_, filter)| filter)
+}
+
+/// 将最近的日志按时间顺序复制到 `buf` 中，返回复制的字节数
+pub fn read_log(buf: UserBuffer) -> usize {
+    let log_buffer = LOG_BUFFER.exclusive_access();
+    let len = buf.len().min(log_buffer.written.min(LOG_BUFFER_SIZE));
+    let start = log_buffer.written - len;
+    for (i, byte) in buf.into_iter().take(len).enumerate() {
+        unsafe {
+            *byte = log_buffer.buf[(start + i) % LOG_BUFFER_SIZE];
+        }
+    }
+    len
+}
+
+/// 替换全部过滤规则
+pub fn set_filter(filter: LogFilter) {
+    let max = filter.max_level();
+    *FILTER.exclusive_access() = filter;
+    log::set_max_level(max);
+}
+
+/// 设置默认的日志等级，按模块覆盖的等级保持不变
+fn set_level(level: LevelFilter) {
+    let mut filter = FILTER.exclusive_access();
+    filter.default = level;
+    log::set_max_level(filter.max_level());
+}
+
+/// 设置是否给日志上色以及是否加上时间
+pub fn set_style(color: bool, timestamp: bool) {
+    COLOR.store(color, Ordering::Relaxed);
+    TIMESTAMP.store(timestamp, Ordering::Relaxed);
+}
+
+/// 设置默认的日志等级。0~5 依次为 Off、Error、Warn、Info、Debug、Trace，不合法时返回 false
+pub fn set_default_level(level: usize) -> bool {
+    let filter = match level {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        5 => LevelFilter::Trace,
+        _ => return false,
+    };
+    set_level(filter);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn module_overrides_pick_the_longest_match() {
+        let filter = LogFilter::parse("debug,mm=info,os::mm::page_table=warn").unwrap();
+        assert_eq!(filter.level_for("os::task::manager"), LevelFilter::Debug);
+        assert_eq!(
+            filter.level_for("os::mm::frame_allocator"),
+            LevelFilter::Info
+        );
+        assert_eq!(filter.level_for("os::mm::page_table"), LevelFilter::Warn);
+        // 只匹配完整的模块名
+        assert_eq!(filter.level_for("os::mmio"), LevelFilter::Debug);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+        assert_eq!(
+            LogFilter::parse("mm=trace").unwrap().default,
+            LevelFilter::Off
+        );
+        assert_eq!(LogFilter::parse("mm=loud"), None);
+    }
 }
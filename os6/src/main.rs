@@ -19,7 +19,9 @@
 #![no_main]
 #![feature(panic_info_message)]
 #![feature(alloc_error_handler)]
-#![feature(step_trait)]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::ktest::test_runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 extern crate bitflags;
 extern crate log;
@@ -28,12 +30,26 @@ extern crate alloc;
 
 #[macro_use]
 mod console;
+#[macro_use]
+mod oops;
+mod backtrace;
+mod cmdline;
 mod config;
 mod drivers;
+mod dtb;
 mod fs;
+mod ipi;
+mod ksyms;
+#[cfg(test)]
+mod ktest;
+mod ktrace;
 mod lang_items;
 mod logging;
 mod mm;
+mod net;
+mod power;
+mod profile;
+mod random;
 mod sbi;
 mod sync;
 mod syscall;
@@ -57,16 +73,36 @@ fn clear_bss() {
 
 #[no_mangle]
 /// the rust entry-point of os
-pub fn rust_main() -> ! {
+///
+/// OpenSBI 通过 `a0` 和 `a1` 传入当前的 hart id 和设备树的物理地址
+pub fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
     clear_bss();
     logging::init();
     println!("[kernel] Hello, world!");
+    mm::heap_allocator::init_heap();
+    dtb::init(dtb_pa);
+    sbi::exit::init();
+    cmdline::init(dtb::bootargs().as_deref());
+    ktrace::init();
+    console::init();
+    timer::init();
     mm::init();
-    mm::remap_test();
+    drivers::init();
+    if cmdline::selftest() {
+        mm::remap_test();
+        mm::trap_context_test();
+    }
+    #[cfg(test)]
+    test_main();
+    random::init();
     trap::init();
     trap::enable_timer_interrupt();
-    timer::set_next_trigger();
+    drivers::plic::init_hart(hartid);
+    ipi::init(hartid);
     fs::list_apps();
+    fs::flusher::init();
+    mm::wss::init();
+    profile::init();
     task::add_initproc();
     task::run_tasks();
     // unreachable!("Unreachable in rust_main!");
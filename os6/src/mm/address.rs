@@ -1,8 +1,20 @@
-use core::iter::Step;
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Bound, RangeBounds, Sub},
+};
 
-use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS, PTE_PER_PAGE};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS, PHYS_VIRT_OFFSET, PTE_PER_PAGE};
 
-use super::page_table::PageTableEntry;
+use super::page_table::{PageSize, PageTableEntry};
+
+/// Sv39 中物理地址的位数
+const PA_WIDTH_SV39: usize = 56;
+/// Sv39 中虚拟地址的有效位数，更高的位与第 38 位一致
+const VA_WIDTH_SV39: usize = 39;
+/// 物理页号的上界（不含）
+const PPN_LIMIT: usize = 1 << (PA_WIDTH_SV39 - PAGE_SIZE_BITS);
+/// 虚拟页号的上界（不含）。虚拟页号由 64 位的虚拟地址右移得到，高位的符号扩展也计入页号
+const VPN_LIMIT: usize = 1 << (usize::BITS as usize - PAGE_SIZE_BITS);
 
 /// 物理地址。在 Sv39 页表机制中，虚拟地址转化得到的物理地址总共为 56 位，其中页号 44 位，页内偏移 12 位。
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -25,7 +37,15 @@ impl PhysAddr {
         self.floor()
     }
     pub fn as_mut<T>(&self) -> &'static mut T {
-        unsafe { (self.0 as *mut T).as_mut().unwrap() }
+        unsafe { (self.to_virt().0 as *mut T).as_mut().unwrap() }
+    }
+    /// 是否按页对齐
+    pub const fn is_aligned(&self) -> bool {
+        self.0 % PAGE_SIZE == 0
+    }
+    /// 内核经线性映射访问这个物理地址时使用的虚拟地址
+    pub const fn to_virt(&self) -> VirtAddr {
+        VirtAddr(phys_to_virt(self.0))
     }
 }
 
@@ -36,7 +56,7 @@ pub struct PhysPageNum(pub(crate) usize);
 impl PhysPageNum {
     /// 清空该页帧
     pub fn clear(&self) {
-        unsafe { core::ptr::write_bytes(self.page_start().0 as *mut u8, 0, PAGE_SIZE) }
+        unsafe { core::ptr::write_bytes(self.page_start().to_virt().0 as *mut u8, 0, PAGE_SIZE) }
     }
     pub fn page_start(&self) -> PhysAddr {
         PhysAddr(self.0 << PAGE_SIZE_BITS)
@@ -51,27 +71,21 @@ impl PhysPageNum {
         self.as_mut()
     }
     pub fn as_ref<T>(&self) -> &'static T {
-        let pa = self.page_start();
-        unsafe { (pa.0 as *mut T).as_ref().unwrap() }
+        let va = self.page_start().to_virt();
+        unsafe { (va.0 as *mut T).as_ref().unwrap() }
     }
     pub fn as_mut<T>(&mut self) -> &'static mut T {
-        let pa = self.page_start();
-        unsafe { (pa.0 as *mut T).as_mut().unwrap() }
+        let va = self.page_start().to_virt();
+        unsafe { (va.0 as *mut T).as_mut().unwrap() }
     }
     /// 将页中 offset 处作为一个结构体返回，请注意内存布局
     pub fn as_mut_at<T>(&mut self, offset: usize) -> &'static mut T {
-        let pa = self.page_start();
-        unsafe { ((pa.0 + offset) as *mut T).as_mut().unwrap() }
-    }
-    /// 将 `src` 中的数据复制到该页中。
-    ///
-    /// 需要保证 `src` 与该页不相交且长度不超过页长
-    pub fn copy_from(&mut self, src: &[u8]) {
-        let pa = self.page_start();
-        unsafe {
-            let dst = core::slice::from_raw_parts_mut(pa.0 as _, src.len());
-            dst.copy_from_slice(src);
-        };
+        let va = self.page_start().to_virt();
+        unsafe { ((va.0 + offset) as *mut T).as_mut().unwrap() }
+    }
+    /// 线性映射区中映射到这个页帧的虚拟页
+    pub const fn to_virt(self) -> VirtPageNum {
+        VirtPageNum(self.0 + (PHYS_VIRT_OFFSET >> PAGE_SIZE_BITS))
     }
 }
 
@@ -95,7 +109,28 @@ impl VirtAddr {
     }
     /// 向上取整页号
     pub const fn ceil(&self) -> VirtPageNum {
-        VirtPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
+        if self.0 == 0 {
+            VirtPageNum(0)
+        } else {
+            VirtPageNum((self.0 - 1) / PAGE_SIZE + 1)
+        }
+    }
+    /// 是否按页对齐
+    pub const fn is_aligned(&self) -> bool {
+        self.page_offset() == 0
+    }
+    /// 线性映射区中的虚拟地址对应的物理地址，见 [`virt_to_phys`]
+    pub fn to_phys(&self) -> PhysAddr {
+        PhysAddr(virt_to_phys(self.0))
+    }
+    /// 向下对齐到 `align` 字节，`align` 必须是 2 的幂
+    pub const fn align_down(&self, align: usize) -> Self {
+        VirtAddr(self.0 & !(align - 1))
+    }
+    /// 向上对齐到 `align` 字节，`align` 必须是 2 的幂。溢出时返回 `None`
+    pub fn checked_align_up(&self, align: usize) -> Option<Self> {
+        let va = self.0.checked_add(align - 1)?;
+        Some(VirtAddr(va & !(align - 1)))
     }
 }
 
@@ -117,31 +152,357 @@ impl VirtPageNum {
     pub fn page_start(&self) -> VirtAddr {
         VirtAddr(self.0 << PAGE_SIZE_BITS)
     }
+    /// 线性映射区中的虚拟页映射到的页帧
+    pub fn to_phys(self) -> PhysPageNum {
+        PhysPageNum(virt_to_phys(self.page_start().0) >> PAGE_SIZE_BITS)
+    }
 }
 
-impl Step for VirtPageNum {
-    fn steps_between(start: &Self, end: &Self) -> Option<usize> {
-        if start > end {
-            None
-        } else {
-            Some(end.0 - start.0)
+/// 内核把所有物理内存线性映射到 `PHYS_VIRT_OFFSET` 开始的高半部，物理地址 `pa` 在其中的虚拟地址。
+///
+/// 内核访问页帧、设备寄存器和 DMA 缓冲区都经过线性映射
+pub const fn phys_to_virt(pa: usize) -> usize {
+    pa + PHYS_VIRT_OFFSET
+}
+
+/// 线性映射区中的虚拟地址 `va` 对应的物理地址。内核映像链接在线性映射区中，也可以用它转换；
+/// 内核栈等单独映射的地址需要查页表
+pub fn virt_to_phys(va: usize) -> usize {
+    debug_assert!(
+        va >= PHYS_VIRT_OFFSET,
+        "{:#x} is not in the linear mapping",
+        va
+    );
+    va - PHYS_VIRT_OFFSET
+}
+
+/// 为页号实现检查溢出的加减、对齐和与 `usize` 的相互转换
+macro_rules! impl_page_num {
+    ($name: ident, $limit: expr) => {
+        impl $name {
+            /// 向后数 `pages` 页，超出页号的范围时返回 `None`。结果可以等于上界，作为范围的结尾
+            pub fn checked_add(self, pages: usize) -> Option<Self> {
+                self.0
+                    .checked_add(pages)
+                    .filter(|&page| page <= $limit)
+                    .map(Self)
+            }
+            /// 向前数 `pages` 页，小于 0 时返回 `None`
+            pub fn checked_sub(self, pages: usize) -> Option<Self> {
+                self.0.checked_sub(pages).map(Self)
+            }
+            /// 是否按 `size` 大小的页对齐
+            pub const fn is_aligned(self, size: PageSize) -> bool {
+                self.0 % size.pages() == 0
+            }
+            /// 向下对齐到 `size` 大小的页
+            pub const fn align_down(self, size: PageSize) -> Self {
+                Self(self.0 - self.0 % size.pages())
+            }
+        }
+
+        impl From<usize> for $name {
+            fn from(page: usize) -> Self {
+                debug_assert!(
+                    page <= $limit,
+                    concat!("{:#x} is not a valid ", stringify!($name)),
+                    page
+                );
+                Self(page)
+            }
+        }
+
+        impl From<$name> for usize {
+            fn from(page: $name) -> usize {
+                page.0
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = Self;
+            fn add(self, pages: usize) -> Self {
+                self.checked_add(pages)
+                    .unwrap_or_else(|| panic!("{:?} + {:#x} overflows", self, pages))
+            }
+        }
+
+        impl AddAssign<usize> for $name {
+            fn add_assign(&mut self, pages: usize) {
+                *self = *self + pages;
+            }
+        }
+
+        impl Sub<usize> for $name {
+            type Output = Self;
+            fn sub(self, pages: usize) -> Self {
+                self.checked_sub(pages)
+                    .unwrap_or_else(|| panic!("{:?} - {:#x} overflows", self, pages))
+            }
+        }
+
+        /// 两个页号之间的页数，`self` 不能小于 `other`
+        impl Sub for $name {
+            type Output = usize;
+            fn sub(self, other: Self) -> usize {
+                self.0
+                    .checked_sub(other.0)
+                    .unwrap_or_else(|| panic!("{:?} is before {:?}", self, other))
+            }
+        }
+
+        impl PageNum for $name {}
+    };
+}
+
+impl_page_num!(PhysPageNum, PPN_LIMIT);
+impl_page_num!(VirtPageNum, VPN_LIMIT);
+
+impl From<usize> for PhysAddr {
+    fn from(pa: usize) -> Self {
+        debug_assert!(
+            pa < 1 << PA_WIDTH_SV39,
+            "{:#x} is not a valid physical address",
+            pa
+        );
+        PhysAddr(pa)
+    }
+}
+
+impl From<PhysAddr> for usize {
+    fn from(pa: PhysAddr) -> usize {
+        pa.0
+    }
+}
+
+impl From<PhysPageNum> for PhysAddr {
+    fn from(ppn: PhysPageNum) -> Self {
+        ppn.page_start()
+    }
+}
+
+/// 物理地址必须按页对齐
+impl From<PhysAddr> for PhysPageNum {
+    fn from(pa: PhysAddr) -> Self {
+        debug_assert!(pa.is_aligned(), "{:#x} is not page aligned", pa.0);
+        pa.floor()
+    }
+}
+
+impl From<usize> for VirtAddr {
+    fn from(va: usize) -> Self {
+        let high = va >> (VA_WIDTH_SV39 - 1);
+        debug_assert!(
+            high == 0 || high == usize::MAX >> (VA_WIDTH_SV39 - 1),
+            "{:#x} is not a canonical Sv39 address",
+            va
+        );
+        VirtAddr(va)
+    }
+}
+
+impl From<VirtAddr> for usize {
+    fn from(va: VirtAddr) -> usize {
+        va.0
+    }
+}
+
+impl From<VirtPageNum> for VirtAddr {
+    fn from(vpn: VirtPageNum) -> Self {
+        vpn.page_start()
+    }
+}
+
+/// 虚拟地址必须按页对齐
+impl From<VirtAddr> for VirtPageNum {
+    fn from(va: VirtAddr) -> Self {
+        debug_assert!(va.is_aligned(), "{:#x} is not page aligned", va.0);
+        va.floor()
+    }
+}
+
+/// 物理页号和虚拟页号共有的操作，供 [`PageRange`] 使用
+pub trait PageNum:
+    Copy + Ord + fmt::Debug + Add<usize, Output = Self> + Sub<Output = usize>
+{
+}
+
+/// 左闭右开的页号范围 `[start, end)`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PageRange<T> {
+    pub start: T,
+    pub end: T,
+}
+
+/// 虚拟页号的范围
+pub type VPNRange = PageRange<VirtPageNum>;
+/// 物理页号的范围
+pub type PPNRange = PageRange<PhysPageNum>;
+
+impl<T: PageNum> PageRange<T> {
+    pub fn new(start: T, end: T) -> Self {
+        debug_assert!(start <= end, "invalid page range {:?}..{:?}", start, end);
+        Self { start, end }
+    }
+    /// 从 `start` 开始的 `pages` 页
+    pub fn from_len(start: T, pages: usize) -> Self {
+        Self::new(start, start + pages)
+    }
+    /// 只包含 `page` 一页
+    pub fn single(page: T) -> Self {
+        Self::from_len(page, 1)
+    }
+    /// 范围内的页数
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+    pub fn contains(&self, page: T) -> bool {
+        self.start <= page && page < self.end
+    }
+    /// 与 `other` 的交集，不相交时为空
+    pub fn intersection(&self, other: &Self) -> Self {
+        let start = self.start.max(other.start);
+        Self {
+            start,
+            end: self.end.min(other.end).max(start),
         }
     }
-    fn forward_checked(start: Self, count: usize) -> Option<Self> {
-        // 0x0~0x3ff_ffff、0xffff_fc00_0000~0xffff_ffff_ffff 都是合法的虚拟页号
-        if start.0 + count < 1 << 26
-            || (start.0 + count >= (1 << 52) - (1 << 26) && start.0 + count < (1 << 52))
-        {
-            Some(Self(start.0 + count))
+    /// 是否与 `other` 相交
+    pub fn overlaps(&self, other: &Self) -> bool {
+        !self.intersection(other).is_empty()
+    }
+    /// 是否完全包含 `other`
+    pub fn covers(&self, other: &Self) -> bool {
+        other.is_empty() || (self.start <= other.start && other.end <= self.end)
+    }
+    pub fn iter(&self) -> PageRangeIter<T> {
+        PageRangeIter {
+            next: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl VPNRange {
+    /// 覆盖虚拟地址 `[start, end)` 的最小的页范围
+    pub fn covering(start: VirtAddr, end: VirtAddr) -> Self {
+        Self::new(start.floor(), end.ceil())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PageRange<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}..{:?}", self.start, self.end)
+    }
+}
+
+/// 使 `PageRange` 可以用于 `BTreeMap::range` 等接受范围的方法
+impl<T> RangeBounds<T> for PageRange<T> {
+    fn start_bound(&self) -> Bound<&T> {
+        Bound::Included(&self.start)
+    }
+    fn end_bound(&self) -> Bound<&T> {
+        Bound::Excluded(&self.end)
+    }
+}
+
+impl<T: PageNum> IntoIterator for PageRange<T> {
+    type Item = T;
+    type IntoIter = PageRangeIter<T>;
+    fn into_iter(self) -> PageRangeIter<T> {
+        self.iter()
+    }
+}
+
+/// 按地址从低到高遍历 [`PageRange`] 中的页号
+#[derive(Clone, Debug)]
+pub struct PageRangeIter<T> {
+    next: T,
+    end: T,
+}
+
+impl<T: PageNum> Iterator for PageRangeIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        if self.next < self.end {
+            let page = self.next;
+            self.next = page + 1;
+            Some(page)
         } else {
             None
         }
     }
-    fn backward_checked(start: Self, count: usize) -> Option<Self> {
-        if start.0 >= count {
-            Some(Self(start.0 - count))
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = if self.next < self.end {
+            self.end - self.next
         } else {
-            None
+            0
+        };
+        (len, Some(len))
+    }
+}
+
+impl<T: PageNum> ExactSizeIterator for PageRangeIter<T> {}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test_case]
+    fn page_ranges_iterate_and_intersect() {
+        let range = VPNRange::covering(VirtAddr(0x1800), VirtAddr(0x4001));
+        assert_eq!(range, VPNRange::new(VirtPageNum(1), VirtPageNum(5)));
+        assert_eq!(range.len(), 4);
+        assert_eq!(range.into_iter().len(), 4);
+        let pages: Vec<_> = range.into_iter().map(usize::from).collect();
+        assert_eq!(pages, [1, 2, 3, 4]);
+        let other = VPNRange::from_len(VirtPageNum(4), 3);
+        assert_eq!(range.intersection(&other), VPNRange::single(VirtPageNum(4)));
+        let disjoint = VPNRange::new(VirtPageNum(8), VirtPageNum(9));
+        assert!(range.intersection(&disjoint).is_empty());
+        assert!(!range.overlaps(&disjoint));
+        assert!(range.covers(&VPNRange::new(VirtPageNum(2), VirtPageNum(4))));
+        assert_eq!(VirtAddr(0).ceil(), VirtPageNum(0));
+    }
+
+    #[test_case]
+    fn page_arithmetic_is_checked_and_aligned() {
+        assert_eq!(VirtPageNum(0).checked_sub(1), None);
+        assert_eq!(VirtPageNum(VPN_LIMIT).checked_add(1), None);
+        assert_eq!(PhysPageNum(7) - PhysPageNum(3), 4);
+        let vpn = VirtPageNum(0x201);
+        assert!(!vpn.is_aligned(PageSize::Size2M));
+        assert_eq!(vpn.align_down(PageSize::Size2M), VirtPageNum(0x200));
+        let va = VirtAddr(0x1234);
+        assert_eq!(va.align_down(PAGE_SIZE).0, 0x1000);
+        assert_eq!(
+            va.checked_align_up(PAGE_SIZE).map(usize::from),
+            Some(0x2000)
+        );
+        assert!(VirtAddr(usize::MAX).checked_align_up(PAGE_SIZE).is_none());
+    }
+
+    #[test_case]
+    fn kernel_image_is_linearly_mapped() {
+        extern "C" {
+            fn stext();
         }
+        let pa = PhysAddr(0x8020_1234);
+        assert_eq!(pa.to_virt().0, 0xffff_ffc0_8020_1234);
+        assert_eq!(pa.to_virt().to_phys().0, pa.0);
+        let ppn = pa.floor();
+        assert_eq!(ppn.to_virt().to_phys(), ppn);
+        assert_eq!(ppn.to_virt().page_start().0, 0xffff_ffc0_8020_1000);
+        // 内核链接在线性映射区中，经线性映射读到的就是内核自己的代码
+        let text = stext as usize;
+        let ppn = VirtAddr(text).to_phys().floor();
+        assert_eq!(
+            ppn.as_ref::<usize>() as *const usize as usize,
+            VirtAddr(text).floor().page_start().0
+        );
     }
 }
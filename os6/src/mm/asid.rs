@@ -0,0 +1,146 @@
+//! Address space identifiers
+//!
+//! 每个用户地址空间分配一个 ASID 写入 `satp`，TLB 表项按 ASID 区分，因此在内核与
+//! 用户地址空间之间切换时不需要刷新整个 TLB（见 `trap.S`）。
+//!
+//! ASID 按代（generation）分配：同一代内 ASID 只增不减，用完后开始新的一代并刷新
+//! 整个 TLB，上一代的地址空间在下次使用时重新分配。因此回收 ASID 时不需要刷新 TLB。
+//!
+//! 内核地址空间固定使用 0 号 ASID。硬件不支持 ASID 或者关闭了 `asid` feature 时，
+//! 所有地址空间都使用 0 号 ASID，此时 `trap.S` 会在每次切换时刷新 TLB。
+
+use core::{cell::Cell, ops::Range};
+
+use riscv::register::satp;
+
+use crate::{config::PAGE_SIZE, sync::UPSafeCell};
+
+/// `satp` 中 ASID 字段的位置
+const SATP_ASID_SHIFT: usize = 44;
+const SATP_ASID_MASK: usize = 0xffff;
+/// 内核地址空间使用的代，永不过期
+const KERNEL_GENERATION: usize = usize::MAX;
+
+/// 某个地址空间分配到的 ASID 及其所属的代
+#[derive(Copy, Clone, Debug)]
+pub struct Asid {
+    generation: usize,
+    value: usize,
+}
+
+impl Asid {
+    /// 尚未分配。代从 1 开始，所以它总是过期的
+    pub const UNALLOCATED: Self = Self {
+        generation: 0,
+        value: 0,
+    };
+    pub const KERNEL: Self = Self {
+        generation: KERNEL_GENERATION,
+        value: 0,
+    };
+}
+
+struct AsidAllocator {
+    generation: usize,
+    next: usize,
+    /// 硬件支持的最大 ASID，为 0 表示不使用 ASID
+    max: usize,
+}
+
+static ASID_ALLOCATOR: UPSafeCell<AsidAllocator> = unsafe {
+    UPSafeCell::new(AsidAllocator {
+        generation: 1,
+        next: 1,
+        max: 0,
+    })
+};
+
+/// 探测硬件支持的 ASID 位数。需要在启用内核地址空间之后调用
+pub fn init() {
+    if cfg!(feature = "asid") {
+        // 向 ASID 字段写入全 1，读回的值即为硬件支持的最大 ASID
+        let kernel_satp = satp::read().bits();
+        unsafe {
+            satp::write(kernel_satp | SATP_ASID_MASK << SATP_ASID_SHIFT);
+            let max = satp::read().bits() >> SATP_ASID_SHIFT & SATP_ASID_MASK;
+            satp::write(kernel_satp);
+            core::arch::asm!("sfence.vma");
+            ASID_ALLOCATOR.exclusive_access().max = max;
+        }
+    }
+    log::info!(
+        "[kernel] max ASID: {}",
+        ASID_ALLOCATOR.exclusive_access().max
+    );
+}
+
+/// 返回 `asid` 在当前代中的值，过期时重新分配，结果用于填入 `satp`
+pub fn satp_bits(asid: &Cell<Asid>) -> usize {
+    let current = asid.get();
+    if current.generation == KERNEL_GENERATION {
+        return 0;
+    }
+    let mut allocator = ASID_ALLOCATOR.exclusive_access();
+    if allocator.max == 0 {
+        return 0;
+    }
+    if current.generation != allocator.generation {
+        if allocator.next > allocator.max {
+            // 这一代的 ASID 用完了，开始新的一代
+            allocator.generation += 1;
+            allocator.next = 1;
+            unsafe {
+                core::arch::asm!("sfence.vma");
+            }
+        }
+        asid.set(Asid {
+            generation: allocator.generation,
+            value: allocator.next,
+        });
+        allocator.next += 1;
+    }
+    asid.get().value << SATP_ASID_SHIFT
+}
+
+/// 按页刷新时最多逐页执行 `sfence.vma` 的页数，更大的范围直接刷新整个地址空间
+const FLUSH_PAGES_MAX: usize = 64;
+
+/// 刷新本 hart 的 TLB：`asid` 为 `None` 时包括所有地址空间，`va` 为 `None` 时包括所有地址。
+/// `asid` 是写入 `satp` 的值
+pub fn flush_local(asid: Option<usize>, va: Option<Range<usize>>) {
+    let pages = va
+        .map(|va| (va.start & !(PAGE_SIZE - 1)..va.end).step_by(PAGE_SIZE))
+        .filter(|pages| pages.len() <= FLUSH_PAGES_MAX);
+    unsafe {
+        match (pages, asid) {
+            (Some(pages), Some(asid)) => {
+                for va in pages {
+                    core::arch::asm!("sfence.vma {}, {}", in(reg) va, in(reg) asid);
+                }
+            }
+            (Some(pages), None) => {
+                for va in pages {
+                    core::arch::asm!("sfence.vma {}, zero", in(reg) va);
+                }
+            }
+            (None, Some(asid)) => core::arch::asm!("sfence.vma zero, {}", in(reg) asid),
+            (None, None) => core::arch::asm!("sfence.vma"),
+        }
+    }
+}
+
+/// 刷新 TLB 中属于 `asid` 的表项时传给 [`flush_local`] 的 ASID。
+///
+/// 内核地址空间或者不使用 ASID 时为 `Some(None)`，需要刷新所有地址空间；
+/// 这个地址空间在当前代中还没有使用过时为 `None`，TLB 中不会有它的表项，不需要刷新
+pub fn flush_target(asid: &Cell<Asid>) -> Option<Option<usize>> {
+    let current = asid.get();
+    let allocator = ASID_ALLOCATOR.exclusive_access();
+    if current.generation == KERNEL_GENERATION || allocator.max == 0 {
+        Some(None)
+    } else if current.generation == allocator.generation {
+        Some(Some(current.value))
+    } else {
+        None
+    }
+}
@@ -1,8 +1,12 @@
 use alloc::vec::Vec;
+use core::ops::Range;
 
-use crate::{config::MEMORY_END, mm::address::PhysAddr, sync::UPSafeCell};
+use crate::{dtb, mm::address::PhysAddr, sync::UPSafeCell};
 
-use super::address::PhysPageNum;
+use super::{
+    address::{PPNRange, PhysPageNum, VirtAddr},
+    page_table::PageSize,
+};
 
 /// 物理页帧管理器
 pub trait FrameAllocator {
@@ -10,53 +14,153 @@ pub trait FrameAllocator {
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
+/// 预留的一段物理内存，页帧分配器不会把其中的页帧分配出去
+#[derive(Debug, Clone, Copy)]
+pub struct Reservation {
+    pub range: PPNRange,
+    /// 预留的原因，打印内存布局时显示
+    pub reason: &'static str,
+}
+
+/// 无法预留一段物理内存的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReserveError {
+    /// 区间中可能有页帧已经分配出去
+    InUse,
+    /// 与已经预留的区间相交
+    Overlap,
+}
+
 /// 朴素的栈式管理
+///
+/// 预留的区间可以在初始化之前或之后登记，`current` 越过它们而不分配其中的页帧
 pub struct StackFrameAllocator {
+    start: PhysPageNum,
     current: PhysPageNum,
     end: PhysPageNum,
     recycled: Vec<PhysPageNum>,
+    /// 按起始页号排列的预留区间，互不相交
+    reserved: Vec<Reservation>,
+    total: usize,
 }
 
 impl Default for StackFrameAllocator {
     fn default() -> Self {
-        Self {
-            current: PhysPageNum(0),
-            end: PhysPageNum(0),
-            recycled: Vec::new(),
-        }
+        Self::new()
     }
 }
 
 impl StackFrameAllocator {
     pub const fn new() -> Self {
         Self {
+            start: PhysPageNum(0),
             current: PhysPageNum(0),
             end: PhysPageNum(0),
             recycled: Vec::new(),
+            reserved: Vec::new(),
+            total: 0,
         }
     }
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
         assert!(l < r, "PPN range invalid(l:{}, r:{})", l.0, r.0);
+        self.start = l;
         self.current = l;
         self.end = r;
+        self.total = r - l - self.reserved_pages(PPNRange::new(l, r));
+    }
+    /// 尚未分配的页帧数，包括回收的页帧
+    pub fn remaining(&self) -> usize {
+        let ahead = PPNRange::new(self.current, self.end);
+        ahead.len() - self.reserved_pages(ahead) + self.recycled.len()
+    }
+    /// 预留 `range` 中的页帧。已经越过的页帧可能已经分配出去，不能再预留
+    pub fn reserve(&mut self, range: PPNRange, reason: &'static str) -> Result<(), ReserveError> {
+        if self.reservation_in(range).is_some() {
+            return Err(ReserveError::Overlap);
+        }
+        if range.overlaps(&PPNRange::new(self.start, self.current)) {
+            return Err(ReserveError::InUse);
+        }
+        let managed = PPNRange::new(self.start, self.end);
+        self.total -= range.intersection(&managed).len();
+        let index = self
+            .reserved
+            .partition_point(|region| region.range.start < range.start);
+        self.reserved.insert(index, Reservation { range, reason });
+        Ok(())
+    }
+    /// 所有预留的区间
+    pub fn reservations(&self) -> &[Reservation] {
+        &self.reserved
+    }
+    /// `range` 中被预留的页帧数
+    fn reserved_pages(&self, range: PPNRange) -> usize {
+        self.reserved
+            .iter()
+            .map(|region| region.range.intersection(&range).len())
+            .sum()
+    }
+    /// 与 `range` 相交的第一个预留区间
+    fn reservation_in(&self, range: PPNRange) -> Option<PPNRange> {
+        self.reserved
+            .iter()
+            .map(|region| region.range)
+            .find(|reserved| reserved.overlaps(&range))
+    }
+    /// 把 `current` 推进到 `to`，越过的页帧中没有预留的放入回收栈
+    fn advance_to(&mut self, to: PhysPageNum) {
+        let to = to.min(self.end);
+        for ppn in PPNRange::new(self.current, to) {
+            if self.reservation_in(PPNRange::single(ppn)).is_none() {
+                self.recycled.push(ppn);
+            }
+        }
+        self.current = to;
+    }
+    /// 分配 `count` 个连续且按 `count` 对齐的页帧，返回第一个页帧。
+    ///
+    /// 回收的页帧不一定连续，因此只从尚未分配过的区间中分配，为了对齐或者避开预留区间而跳过的页帧放入回收栈
+    pub fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        loop {
+            let start = PhysPageNum((self.current.0 + count - 1) / count * count);
+            let end = start.checked_add(count).filter(|&end| end <= self.end)?;
+            match self.reservation_in(PPNRange::new(start, end)) {
+                Some(reserved) => self.advance_to(reserved.end),
+                None => {
+                    self.advance_to(start);
+                    self.current = end;
+                    return Some(start);
+                }
+            }
+        }
     }
 }
 
 impl FrameAllocator for StackFrameAllocator {
-    /// 如果有回收的物理页，则出栈并返回。否则从区间左侧弹出。
+    /// 如果有回收的物理页，则出栈并返回。否则从区间左侧弹出，跳过预留的区间。
     fn alloc(&mut self) -> Option<PhysPageNum> {
         self.recycled.pop().or_else(|| {
+            while self.current < self.end {
+                match self.reservation_in(PPNRange::single(self.current)) {
+                    Some(reserved) => self.current = reserved.end.min(self.end),
+                    None => break,
+                }
+            }
             if self.current == self.end {
                 None
             } else {
-                self.current.0 += 1;
-                Some(PhysPageNum(self.current.0 - 1))
+                let ppn = self.current;
+                self.current += 1;
+                Some(ppn)
             }
         })
     }
 
     fn dealloc(&mut self, ppn: PhysPageNum) {
-        if ppn >= self.current || self.recycled.iter().any(|&n| n == ppn) {
+        if ppn >= self.current
+            || self.recycled.iter().any(|&n| n == ppn)
+            || self.reservation_in(PPNRange::single(ppn)).is_some()
+        {
             panic!("Frame ppn={:#x} has not been allocated!", ppn.0);
         }
         self.recycled.push(ppn);
@@ -66,34 +170,78 @@ impl FrameAllocator for StackFrameAllocator {
 static FRAME_ALLOCATOR: UPSafeCell<StackFrameAllocator> =
     unsafe { UPSafeCell::new(StackFrameAllocator::new()) };
 
-/// initiate the frame allocator using `ekernel` and `MEMORY_END`
+/// initiate the frame allocator using `ekernel` and the memory size from device tree
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    // ekernel 之前都是系统使用的内存，之后的内存则可以分配给应用
+    // ekernel 之前都是系统使用的内存，之后的内存则可以分配给应用。ekernel 是链接地址，需要换算成物理地址
     FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr(ekernel as usize).ceil(),
-        PhysAddr(MEMORY_END).floor(),
+        VirtAddr(ekernel as usize).to_phys().ceil(),
+        PhysAddr(dtb::memory_end()).floor(),
     );
 }
 
+/// 预留物理地址 `pa_range` 所在的页帧，页帧分配器不会把它们分配出去。
+///
+/// 用于设备树中登记的保留内存、固定地址的 DMA 缓冲区等。最好在初始化页帧分配器之前调用，
+/// 之后调用时区间中不能有页帧已经被分配器越过
+pub fn reserve_region(pa_range: Range<usize>, reason: &'static str) -> Result<(), ReserveError> {
+    let range = PPNRange::new(
+        PhysAddr(pa_range.start).floor(),
+        PhysAddr(pa_range.end).ceil(),
+    );
+    log::debug!(
+        "[kernel] reserve [{:#x}, {:#x}) for {}",
+        pa_range.start,
+        pa_range.end,
+        reason
+    );
+    FRAME_ALLOCATOR.exclusive_access().reserve(range, reason)
+}
+
+/// 所有预留的物理内存区间，按地址从低到高排列
+pub fn reservations() -> Vec<Reservation> {
+    FRAME_ALLOCATOR.exclusive_access().reservations().to_vec()
+}
+
+/// 页帧分配器管理的物理页号范围，包括其中预留的页帧
+pub fn managed_range() -> PPNRange {
+    let allocator = FRAME_ALLOCATOR.exclusive_access();
+    PPNRange::new(allocator.start, allocator.end)
+}
+
+/// 持有一个或一组连续的页帧，drop 时归还分配器。
+///
+/// 需要在多个逻辑段之间共享的页帧（写时复制、共享内存）放在 `Arc` 中，最后一个引用消失时才归还
 #[derive(Debug)]
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
+    /// 从 `ppn` 开始连续的页帧数，大页对应多个页帧
+    pages: usize,
 }
 
 impl FrameTracker {
     pub fn new(ppn: PhysPageNum) -> Self {
         log::trace!("clear frame: {:#x}", ppn.0);
         ppn.clear();
-        Self { ppn }
+        Self { ppn, pages: 1 }
+    }
+    fn new_contiguous(ppn: PhysPageNum, pages: usize) -> Self {
+        log::trace!("clear {} frames: {:#x}", pages, ppn.0);
+        for ppn in PPNRange::from_len(ppn, pages) {
+            ppn.clear();
+        }
+        Self { ppn, pages }
     }
 }
 
 impl Drop for FrameTracker {
     fn drop(&mut self) {
-        FRAME_ALLOCATOR.exclusive_access().dealloc(self.ppn)
+        let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+        for ppn in PPNRange::from_len(self.ppn, self.pages) {
+            allocator.dealloc(ppn);
+        }
     }
 }
 
@@ -105,7 +253,110 @@ pub fn frame_alloc() -> Option<FrameTracker> {
         .map(FrameTracker::new)
 }
 
+/// 分配一个大小为 `size` 的页所需的连续页帧，它们按 `size` 对齐
+pub fn frame_alloc_huge(size: PageSize) -> Option<FrameTracker> {
+    if size == PageSize::Size4K {
+        return frame_alloc();
+    }
+    log::trace!("allocate {:?} frame", size);
+    let ppn = FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc_contiguous(size.pages())?;
+    Some(FrameTracker::new_contiguous(ppn, size.pages()))
+}
+
+/// 分配 `pages` 个物理上连续的页帧，供设备 DMA 使用
+pub fn frame_alloc_contiguous(pages: usize) -> Option<FrameTracker> {
+    if pages == 1 {
+        return frame_alloc();
+    }
+    log::trace!("allocate {} contiguous frames", pages);
+    let ppn = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(pages)?;
+    Some(FrameTracker::new_contiguous(ppn, pages))
+}
+
+/// 可供分配的页帧总数
+pub fn frame_total() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().total
+}
+
+/// 当前剩余的页帧数
+pub fn frame_remaining() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().remaining()
+}
+
 pub fn frame_dealloc(ppn: PhysPageNum) {
     log::trace!("deallocate frame");
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn alloc_and_dealloc_restore_remaining() {
+        let before = frame_remaining();
+        let frames: Vec<_> = (0..8).map(|_| frame_alloc().unwrap()).collect();
+        assert_eq!(frame_remaining(), before - 8);
+        for (i, a) in frames.iter().enumerate() {
+            assert!(frames[i + 1..].iter().all(|b| a.ppn != b.ppn));
+        }
+        drop(frames);
+        assert_eq!(frame_remaining(), before);
+    }
+
+    #[test_case]
+    fn allocated_frames_are_zeroed() {
+        let frame = frame_alloc().unwrap();
+        let mut ppn = frame.ppn;
+        ppn.as_page_bytes_mut()[0] = 0xff;
+        drop(frame);
+        // 栈式分配器会先复用刚释放的页帧
+        let frame = frame_alloc().unwrap();
+        assert_eq!(frame.ppn, ppn);
+        assert!(frame.ppn.as_page_bytes().iter().all(|&b| b == 0));
+    }
+
+    #[test_case]
+    fn reserved_frames_are_skipped() {
+        // 只使用分配器的记账，不访问这些页帧
+        let mut allocator = StackFrameAllocator::new();
+        let range = |start: usize, end: usize| PPNRange::new(PhysPageNum(start), PhysPageNum(end));
+        allocator.reserve(range(0x102, 0x104), "test").unwrap();
+        allocator.init(PhysPageNum(0x100), PhysPageNum(0x110));
+        assert_eq!(allocator.total, 14);
+        assert_eq!(allocator.remaining(), 14);
+        let first: Vec<_> = (0..3).map(|_| allocator.alloc().unwrap().0).collect();
+        assert_eq!(first, [0x100, 0x101, 0x104]);
+        // 已经越过的页帧不能再预留，与已有预留区间相交的也不能
+        assert_eq!(
+            allocator.reserve(range(0x100, 0x101), "test"),
+            Err(ReserveError::InUse)
+        );
+        assert_eq!(
+            allocator.reserve(range(0x103, 0x106), "test"),
+            Err(ReserveError::Overlap)
+        );
+        allocator.reserve(range(0x108, 0x10a), "test").unwrap();
+        assert_eq!(allocator.remaining(), 9);
+        // 连续分配避开预留区间，跳过的页帧仍然可以分配
+        assert_eq!(allocator.alloc_contiguous(4), Some(PhysPageNum(0x10c)));
+        assert_eq!(allocator.remaining(), 5);
+        let rest: Vec<_> = (0..5).map(|_| allocator.alloc().unwrap().0).collect();
+        assert!(rest.iter().all(|&ppn| !(0x108..0x10a).contains(&ppn)));
+        assert_eq!(allocator.alloc(), None);
+    }
+
+    #[test_case]
+    fn huge_frame_is_aligned_and_released() {
+        let before = frame_remaining();
+        let frame = frame_alloc_huge(PageSize::Size2M).unwrap();
+        assert_eq!(frame.ppn.0 % PageSize::Size2M.pages(), 0);
+        // 为了对齐而跳过的页帧仍然可以分配
+        assert_eq!(frame_remaining(), before - PageSize::Size2M.pages());
+        drop(frame);
+        assert_eq!(frame_remaining(), before);
+    }
+}
@@ -1,10 +1,55 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use crate::config::KERNEL_HEAP_SIZE;
 
 use buddy_system_allocator::LockedHeap;
 
+/// 在 [`LockedHeap`] 的基础上统计峰值用量和分配失败的情况
+struct TracedHeap {
+    heap: LockedHeap,
+    /// 用户请求的字节数的峰值
+    peak: AtomicUsize,
+    alloc_count: AtomicUsize,
+    failed_count: AtomicUsize,
+    /// 最近一次分配失败时请求的大小和对齐
+    last_failed_size: AtomicUsize,
+    last_failed_align: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for TracedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+        if ptr.is_null() {
+            self.failed_count.fetch_add(1, Ordering::Relaxed);
+            self.last_failed_size
+                .store(layout.size(), Ordering::Relaxed);
+            self.last_failed_align
+                .store(layout.align(), Ordering::Relaxed);
+        } else {
+            self.alloc_count.fetch_add(1, Ordering::Relaxed);
+            let used = self.heap.lock().stats_alloc_user();
+            self.peak.fetch_max(used, Ordering::Relaxed);
+        }
+        ptr
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
 #[global_allocator]
 /// heap allocator instance
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: TracedHeap = TracedHeap {
+    heap: LockedHeap::empty(),
+    peak: AtomicUsize::new(0),
+    alloc_count: AtomicUsize::new(0),
+    failed_count: AtomicUsize::new(0),
+    last_failed_size: AtomicUsize::new(0),
+    last_failed_align: AtomicUsize::new(0),
+};
 
 /// heap space ([u8; KERNEL_HEAP_SIZE])
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
@@ -13,13 +58,56 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap
             .lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+/// 内核堆的使用情况，单位均为字节。`sys_kernel_meminfo` 会把它复制给用户
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize,
+    /// 用户请求的字节数
+    pub used: usize,
+    /// 考虑伙伴分配器的取整后实际占用的字节数
+    pub actual: usize,
+    pub peak: usize,
+    pub alloc_count: usize,
+    pub failed_count: usize,
+    pub last_failed_size: usize,
+    pub last_failed_align: usize,
+}
+
+pub fn stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.heap.lock();
+    HeapStats {
+        total: heap.stats_total_bytes(),
+        used: heap.stats_alloc_user(),
+        actual: heap.stats_alloc_actual(),
+        peak: HEAP_ALLOCATOR.peak.load(Ordering::Relaxed),
+        alloc_count: HEAP_ALLOCATOR.alloc_count.load(Ordering::Relaxed),
+        failed_count: HEAP_ALLOCATOR.failed_count.load(Ordering::Relaxed),
+        last_failed_size: HEAP_ALLOCATOR.last_failed_size.load(Ordering::Relaxed),
+        last_failed_align: HEAP_ALLOCATOR.last_failed_align.load(Ordering::Relaxed),
+    }
+}
+
 #[alloc_error_handler]
 /// panic when heap allocation error occurs
+///
+/// panic 之前先打印堆的使用情况，分配失败的位置见 panic 时打印的调用栈
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+    let stats = stats();
+    println!(
+        "[kernel] Heap exhausted: used {} / {} bytes (actual {}, peak {}), {} allocations, {} failed",
+        stats.used,
+        stats.total,
+        stats.actual,
+        stats.peak,
+        stats.alloc_count,
+        stats.failed_count
+    );
     panic!("Heap allocation error, layout = {:?}", layout);
 }
@@ -1,75 +1,228 @@
-use core::ops::Range;
+use core::{
+    cell::Cell,
+    convert::TryInto,
+    ops::{Deref, Range},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
 use bitflags::bitflags;
+use easy_fs::Inode;
 use lazy_static::lazy_static;
 use riscv::register::satp;
-use xmas_elf::{program, ElfFile};
+use xmas_elf::{header, program, ElfFile};
 
 use crate::{
-    config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE},
-    sync::UPSafeCell,
+    cmdline,
+    config::{
+        ARG_MAX, ASLR_MAX_PAGES, MMAP_STACK_GAP, PAGE_SIZE, PIE_LOAD_BASE, TRAMPOLINE,
+        TRAP_CONTEXT, USER_STACK_MAX_SIZE, USER_STACK_SIZE, USER_STACK_TOP,
+    },
+    dtb, ipi, random,
+    sync::KSpinLock,
+    task::preempt,
 };
 
 use super::{
-    address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
-    frame_allocator::{frame_alloc, FrameTracker},
-    page_table::{PTEFlags, PageTable, PageTableEntry},
+    address::{PhysAddr, PhysPageNum, VPNRange, VirtAddr, VirtPageNum},
+    asid::{self, Asid},
+    frame_allocator::{frame_alloc, frame_alloc_huge, FrameTracker},
+    page_table::{PTEFlags, PageSize, PageTable, PageTableEntry},
+    user::{with_user_access, Access},
+    wss::WorkingSet,
 };
 
+/// 映射逻辑段失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// 与已有的逻辑段相交
+    Overlap,
+    /// 物理页帧不足
+    OutOfMemory,
+    /// 启用 W^X 时，用户映射同时可写可执行
+    WriteExecute,
+    /// 启用 `ptecheck` 时，发现页表项的标志位与逻辑段的权限不一致
+    BadPte,
+}
+
+/// 加载 ELF 失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// 不是 ELF 文件，或者 ELF 头无法解析
+    NotElf,
+    /// 程序头无法解析
+    BadProgramHeader,
+    /// 两个 LOAD 段占用了同一页
+    OverlappingSegments,
+    /// 段的文件范围超出了 ELF 文件，或者文件大小大于内存大小
+    BadSegment,
+    /// 物理页帧不足
+    OutOfMemory,
+    /// 需要动态链接器，或者包含除 `R_RISCV_RELATIVE` 以外的重定位
+    Unsupported,
+    /// 重定位的目标不在已加载的段中
+    BadRelocation,
+    /// argv 和 envp 总共超过了 `ARG_MAX` 字节
+    ArgsTooLong,
+    /// 启用 W^X 时，有 LOAD 段同时可写可执行
+    WriteExecute,
+    /// 启用 `ptecheck` 时，发现页表项的标志位与逻辑段的权限不一致
+    BadPte,
+}
+
+/// 加载 ELF 时读取文件内容的方式。`from_elf` 只把 ELF 头和程序头表读到内核中，
+/// LOAD 段直接读入新映射的页帧，这样加载大的程序时不需要先把整个文件读到内核堆上
+pub trait ElfSource {
+    /// 从 `offset` 处读取数据填满 `buf`，返回实际读到的字节数，到达文件末尾时小于 `buf.len()`
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+}
+
+/// 已经在内存中的 ELF
+impl ElfSource for Vec<u8> {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let data = self.get(offset..).unwrap_or(&[]);
+        let len = buf.len().min(data.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        len
+    }
+}
+
+/// `from_elf` 加载得到的应用镜像
+pub struct ElfImage {
+    pub memory_set: MemorySet,
+    /// 用户栈的栈顶
+    pub user_stack_top: usize,
+    /// 初始的 `sp`。栈顶处已经放好了 argc、argv、envp 和辅助向量
+    pub user_sp: usize,
+    pub argc: usize,
+    pub entry: usize,
+    /// 初始的 `tp`，没有 TLS 段时为 0
+    pub tp: usize,
+}
+
 bitflags! {
     /// 控制一个逻辑段的访问方式。是 `PTEFlags` 的严格子集。
     ///
-    /// 包括 R/W/X 和 U
+    /// 包括 R/W/X/U 和 G。G 只用于内核自身的线性映射，这些地址不能再被用户映射
     pub struct MapPermission: u8 {
         const R = 1 << 1;
         const W = 1 << 2;
         const X = 1 << 3;
         const U = 1 << 4;
+        const G = 1 << 5;
     }
 }
 
 lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
-        Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
+    pub static ref KERNEL_SPACE: Arc<KSpinLock<MemorySet>> =
+        Arc::new(unsafe { KSpinLock::new("KERNEL_SPACE", MemorySet::new_kernel()) });
+    /// 所有地址空间共享的零页，总是以只读方式映射
+    static ref ZERO_FRAME: FrameTracker = frame_alloc().expect("no frame for the zero page");
+}
+
+/// 共享零页的物理页号
+pub fn zero_ppn() -> PhysPageNum {
+    ZERO_FRAME.ppn
+}
+
+bitflags! {
+    /// 用户页的状态，由 `mincore` 报告
+    pub struct PageState: u8 {
+        /// 页有自己的页帧
+        const RESIDENT = 1 << 0;
+        /// 页还没有分配页帧，映射到共享的零页，第一次写入时才分配
+        const LAZY = 1 << 1;
+        /// 页帧与其它地址空间共享，写入时复制。fork 目前复制所有的页，不会出现
+        const COW = 1 << 2;
+        /// 页已换出到交换区。目前没有交换区，不会出现
+        const SWAPPED = 1 << 3;
+    }
 }
 
 /// 用于描述逻辑上连续的虚拟内存段。
 ///
 /// 段中的每一页都具有相同的 flag。
 ///
-/// 内核自己的代码、数据等以 `Identical` 方式映射
+/// 内核自己的代码、数据等以 `Linear` 方式映射
 ///
 /// 而 APP 以及内核中和 APP 相关的
 #[derive(Debug)]
 pub struct MapArea {
-    pub vpn_range: Range<VirtPageNum>,
+    pub vpn_range: VPNRange,
     map_type: MapType,
     map_perm: MapPermission,
+    /// 映射时先把各页映射到只读的共享零页，第一次写入时才分配页帧。
+    ///
+    /// `Framed` 逻辑段中不在 `data_frames` 里的页都映射到零页
+    zero_fill: bool,
+    /// `Framed` 逻辑段使用的页大小。使用大页时逻辑段按大页对齐，`data_frames` 以各个大页的起始页为键
+    page_size: PageSize,
+    /// 共享的文件映射。映射时读入文件内容，fork 时父子进程共享页帧，
+    /// D 位置位的页由 [`MemorySet::write_back`] 写回文件
+    file: Option<FileBacking>,
+}
+
+/// 文件映射的逻辑段对应的文件，逻辑段的第一页对应文件中的 `offset` 处
+#[derive(Clone)]
+pub struct FileBacking {
+    pub inode: Arc<Inode>,
+    /// 按页对齐
+    pub offset: usize,
+}
+
+impl core::fmt::Debug for FileBacking {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FileBacking")
+            .field("ino", &self.inode.inode_id())
+            .field("offset", &self.offset)
+            .finish()
+    }
 }
 
 /// 描述逻辑段内所有虚拟页映射到物理页的方式
 pub enum MapType {
-    /// 恒等映射，或者说直接以物理地址访问
-    Identical,
+    /// 线性映射，虚拟地址减去 `PHYS_VIRT_OFFSET` 即为物理地址。只用于内核地址空间
+    Linear,
     /// 需要分配物理页帧
     Framed {
         /// 这些保存的物理页帧用于存放实际的内存数据
         ///
-        /// 而 PageTable 所拥有的的物理页仅用于存放页表节点数据，因此不会冲突
-        data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+        /// 而 PageTable 所拥有的的物理页仅用于存放页表节点数据，因此不会冲突。
+        /// 页帧可以由多个逻辑段共享，最后一个持有者解除映射时才释放
+        data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     },
 }
 
 impl core::fmt::Debug for MapType {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            MapType::Identical => write!(f, "MapType::Identical"),
+            MapType::Linear => write!(f, "MapType::Linear"),
             MapType::Framed { data_frames: _ } => write!(f, "MapType::Framed"),
         }
     }
 }
 
+/// 逻辑段的映射方式，见 [`AreaInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaKind {
+    /// 线性映射，不持有页帧
+    Linear,
+    /// 映射时即为每页分配页帧
+    Framed,
+    /// 先映射到共享的零页，第一次写入时才分配页帧
+    ZeroFill,
+    /// 以大页映射，页帧在映射时全部分配
+    Huge(PageSize),
+}
+
+/// 一个逻辑段的元数据，供系统调用和缺页处理查询映射情况，而不必直接访问逻辑段本身
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AreaInfo {
+    pub range: VPNRange,
+    pub perm: MapPermission,
+    pub kind: AreaKind,
+}
+
 impl MapArea {
     pub fn new(
         start_va: VirtAddr,
@@ -77,28 +230,50 @@ impl MapArea {
         map_type: MapType,
         map_perm: MapPermission,
     ) -> Self {
-        let start_vpn = start_va.floor();
-        let end_va = end_va.ceil();
         Self {
-            vpn_range: start_vpn..end_va,
+            vpn_range: VPNRange::covering(start_va, end_va),
             map_type,
             map_perm,
+            zero_fill: false,
+            page_size: PageSize::Size4K,
+            file: None,
         }
     }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
-            vpn_range: another.vpn_range.clone(),
+            vpn_range: another.vpn_range,
             map_type: match another.map_type {
-                MapType::Identical => MapType::Identical,
+                MapType::Linear => MapType::Linear,
                 MapType::Framed { .. } => MapType::Framed {
                     data_frames: BTreeMap::new(),
                 },
             },
             map_perm: another.map_perm,
+            zero_fill: another.zero_fill,
+            page_size: another.page_size,
+            file: another.file.clone(),
         }
     }
-    // 在 `page_table` 中将本逻辑段映射
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    // 在 `page_table` 中将本逻辑段映射。页帧不足时撤销已经建立的映射
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), MapError> {
+        if let MapType::Linear = self.map_type {
+            self.map_linear(page_table);
+            return Ok(());
+        }
+        let first_zero = if self.zero_fill {
+            self.vpn_range.start
+        } else {
+            self.vpn_range.end
+        };
+        self.map_with_zero_pages(page_table, first_zero)
+    }
+    /// 约定：当前逻辑段必须是 `Framed` 的。为 `first_zero` 之前的页分配页帧，之后的页映射到零页。
+    /// 页帧不足时撤销已经建立的映射
+    fn map_with_zero_pages(
+        &mut self,
+        page_table: &mut PageTable,
+        first_zero: VirtPageNum,
+    ) -> Result<(), MapError> {
         log::trace!(
             "{}:{}, vpn_range: {:#x}~{:#x}",
             file!(),
@@ -106,37 +281,195 @@ impl MapArea {
             self.vpn_range.start.0,
             self.vpn_range.end.0
         );
-        for vpn in self.vpn_range.clone() {
-            self.map_one(page_table, vpn);
+        for vpn in self.pages() {
+            if vpn >= first_zero {
+                page_table.map(vpn, zero_ppn(), self.pte_flags(vpn));
+            } else if let Err(err) = self.map_one(page_table, vpn) {
+                for mapped in self.pages().take_while(|&mapped| mapped < vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+    /// 本段中各页的起始虚拟页号，使用大页时每个大页只出现一次
+    fn pages(&self) -> impl Iterator<Item = VirtPageNum> {
+        self.vpn_range.iter().step_by(self.page_size.pages())
+    }
+    /// `vpn` 是否映射到零页
+    fn is_zero_page(&self, vpn: VirtPageNum) -> bool {
+        match &self.map_type {
+            MapType::Framed { data_frames } if self.zero_fill => !data_frames.contains_key(&vpn),
+            _ => false,
+        }
+    }
+    /// `vpn` 的页表项标志位。零页不可写，写入时触发缺页
+    fn pte_flags(&self, vpn: VirtPageNum) -> PTEFlags {
+        let mut flags = PTEFlags::from_bits_truncate(self.map_perm.bits);
+        if self.is_zero_page(vpn) {
+            flags.remove(PTEFlags::W);
+        }
+        flags
+    }
+    /// 约定：`vpn` 映射到零页。为它分配私有的页帧并以逻辑段的权限重新映射
+    fn fault_in(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Result<(), MapError> {
+        let frame = frame_alloc().ok_or(MapError::OutOfMemory)?;
+        page_table.unmap(vpn);
+        page_table.map(
+            vpn,
+            frame.ppn,
+            PTEFlags::from_bits_truncate(self.map_perm.bits),
+        );
+        if let MapType::Framed { data_frames } = &mut self.map_type {
+            data_frames.insert(vpn, Arc::new(frame));
+        }
+        Ok(())
+    }
+    /// 线性映射的逻辑段尽量使用大页。`PHYS_VIRT_OFFSET` 按 1 GiB 对齐，虚拟页和页帧的对齐情况相同
+    fn map_linear(&self, page_table: &mut PageTable) {
+        let flags = PTEFlags::from_bits_truncate(self.map_perm.bits);
+        let mut vpn = self.vpn_range.start;
+        while vpn < self.vpn_range.end {
+            let size = PageSize::largest_fit(vpn, self.vpn_range.end);
+            page_table.map_huge(vpn, vpn.to_phys(), size, flags);
+            vpn += size.pages();
         }
     }
     // 在 `page_table` 中将本逻辑段解除映射
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range.clone() {
+        if let MapType::Linear = self.map_type {
+            let mut vpn = self.vpn_range.start;
+            while vpn < self.vpn_range.end {
+                vpn += page_table.unmap(vpn).pages();
+            }
+            return;
+        }
+        for vpn in self.pages() {
             self.unmap_one(page_table, vpn);
         }
     }
-    /// 约定：当前逻辑段必须是 `Framed` 的。而且 `data` 的长度不得超过逻辑段长度。
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    /// 约定：当前逻辑段必须是 `Framed` 的。`data` 从起始页内偏移 `offset` 处开始存放，且不得超出逻辑段。
+    ///
+    /// `data` 最后一页中剩余的部分会被清零，即使 bss 与数据共用一页也能保证 bss 为零
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8], offset: usize) {
         let mut curr_vpn = self.vpn_range.start;
-        for chunk in data.chunks(PAGE_SIZE) {
-            let mut dst = page_table.translate(curr_vpn).unwrap().ppn();
-            dst.copy_from(chunk);
-            curr_vpn.0 += 1;
+        let mut page_offset = offset;
+        let mut copied = 0;
+        while copied < data.len() {
+            let len = (PAGE_SIZE - page_offset).min(data.len() - copied);
+            let dst = &mut page_table
+                .translate(curr_vpn)
+                .unwrap()
+                .ppn()
+                .as_page_bytes_mut()[page_offset..];
+            dst[..len].copy_from_slice(&data[copied..copied + len]);
+            dst[len..].fill(0);
+            copied += len;
+            page_offset = 0;
+            curr_vpn += 1;
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// 与 [`Self::copy_data`] 相同，但数据是 `source` 中的 `file_range`，直接读入页帧而不经过中间的缓冲区。
+    ///
+    /// 文件比 `file_range` 短时返回 `false`
+    fn read_data(
+        &mut self,
+        page_table: &mut PageTable,
+        source: &dyn ElfSource,
+        file_range: Range<usize>,
+        offset: usize,
+    ) -> bool {
+        let mut curr_vpn = self.vpn_range.start;
+        let mut page_offset = offset;
+        let mut pos = file_range.start;
+        while pos < file_range.end {
+            let len = (PAGE_SIZE - page_offset).min(file_range.end - pos);
+            let dst = &mut page_table
+                .translate(curr_vpn)
+                .unwrap()
+                .ppn()
+                .as_page_bytes_mut()[page_offset..];
+            if source.read_at(pos, &mut dst[..len]) != len {
+                return false;
+            }
+            dst[len..].fill(0);
+            pos += len;
+            page_offset = 0;
+            curr_vpn += 1;
+        }
+        true
+    }
+    /// 约定：当前逻辑段是文件映射，各页已经分配了页帧。从文件读入各页，文件末尾之后的部分保持为零
+    fn read_file(&self, page_table: &PageTable) {
+        let file = self.file.as_ref().unwrap();
+        for (i, vpn) in self.vpn_range.iter().enumerate() {
+            let dst = page_table.translate(vpn).unwrap().ppn().as_page_bytes_mut();
+            file.inode.read_at(file.offset + i * PAGE_SIZE, dst);
+        }
+    }
+    /// 把本段在 `vpn_range` 中 D 位置位的页写回文件并清除 D 位，返回写回的页数。本段不是文件映射时什么也不做。
+    ///
+    /// 与 Linux 相同，文件末尾之后的部分不写回，文件不会因此变长。调用者需要随后刷新 TLB
+    fn write_back(&self, page_table: &mut PageTable, vpn_range: VPNRange) -> usize {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return 0,
+        };
+        let size = file.inode.size();
+        let mut written = 0;
+        for vpn in self.intersection(&vpn_range) {
+            let dirty = page_table
+                .take_flags(vpn, PTEFlags::D)
+                .map_or(false, |flags| !flags.is_empty());
+            if !dirty {
+                continue;
+            }
+            let pos = file.offset + (vpn.0 - self.vpn_range.start.0) * PAGE_SIZE;
+            if pos < size {
+                let src = page_table.translate(vpn).unwrap().ppn().as_page_bytes();
+                file.inode.write_at(pos, &src[..PAGE_SIZE.min(size - pos)]);
+            }
+            written += 1;
+        }
+        written
+    }
+    /// 约定：当前逻辑段必须是 `Framed` 的。在 `page_table` 中把各页映射到本段的页帧，
+    /// 返回与本段共享这些页帧的逻辑段
+    fn share(&self, page_table: &mut PageTable) -> MapArea {
+        let mut shared = MapArea::from_another(self);
+        if let (MapType::Framed { data_frames }, MapType::Framed { data_frames: dst }) =
+            (&self.map_type, &mut shared.map_type)
+        {
+            for (&vpn, frame) in data_frames {
+                page_table.map_huge(vpn, frame.ppn, self.page_size, self.pte_flags(vpn));
+                dst.insert(vpn, Arc::clone(frame));
+            }
+        }
+        shared
+    }
+    pub fn map_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Result<(), MapError> {
         let ppn;
         match &mut self.map_type {
-            MapType::Identical => ppn = PhysPageNum(vpn.0),
+            MapType::Linear => ppn = vpn.to_phys(),
             MapType::Framed { data_frames } => {
-                let frame = frame_alloc().expect("Should have enough memory");
+                let frame = frame_alloc_huge(self.page_size).ok_or(MapError::OutOfMemory)?;
                 ppn = frame.ppn;
-                data_frames.insert(vpn, frame);
+                data_frames.insert(vpn, Arc::new(frame));
             }
         };
-        page_table.map(vpn, ppn, PTEFlags::from_bits_truncate(self.map_perm.bits));
+        let flags = PTEFlags::from_bits_truncate(self.map_perm.bits);
+        match self.map_type {
+            MapType::Linear => page_table.map(vpn, ppn, flags),
+            MapType::Framed { .. } => page_table.map_huge(vpn, ppn, self.page_size, flags),
+        }
+        Ok(())
     }
+    /// 解除 `vpn` 的映射。页帧还被其它逻辑段共享时只放弃本段的引用
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         if let MapType::Framed { data_frames } = &mut self.map_type {
             data_frames.remove(&vpn);
@@ -144,17 +477,119 @@ impl MapArea {
         page_table.unmap(vpn);
     }
 
+    /// 把本段从 `at` 处一分为二，本段保留 `at` 之前的部分，返回 `at` 及之后的部分。
+    /// 使用大页时 `at` 需要按大页对齐
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        assert!(
+            at.is_aligned(self.page_size),
+            "cannot split a {:?} page at vpn {:#x}",
+            self.page_size,
+            at.0
+        );
+        let map_type = match &mut self.map_type {
+            MapType::Linear => MapType::Linear,
+            MapType::Framed { data_frames } => MapType::Framed {
+                data_frames: data_frames.split_off(&at),
+            },
+        };
+        let file = self.file.as_ref().map(|file| FileBacking {
+            inode: file.inode.clone(),
+            offset: file.offset + (at.0 - self.vpn_range.start.0) * PAGE_SIZE,
+        });
+        let tail = MapArea {
+            vpn_range: VPNRange::new(at, self.vpn_range.end),
+            map_type,
+            map_perm: self.map_perm,
+            zero_fill: self.zero_fill,
+            page_size: self.page_size,
+            file,
+        };
+        self.vpn_range.end = at;
+        tail
+    }
+    /// 用户能否访问本段
+    pub fn is_user(&self) -> bool {
+        self.map_perm.contains(MapPermission::U)
+    }
+    /// 本段的虚拟页数
+    pub fn page_count(&self) -> usize {
+        self.vpn_range.len()
+    }
+    /// 本段持有的物理页帧数，线性映射的段不持有页帧
+    pub fn resident_pages(&self) -> usize {
+        match &self.map_type {
+            MapType::Linear => 0,
+            MapType::Framed { data_frames } => data_frames.len() * self.page_size.pages(),
+        }
+    }
+    /// 本段持有的页帧中同时被其它逻辑段持有的页帧数
+    pub fn shared_pages(&self) -> usize {
+        match &self.map_type {
+            MapType::Linear => 0,
+            MapType::Framed { data_frames } => {
+                data_frames
+                    .values()
+                    .filter(|frame| Arc::strong_count(frame) > 1)
+                    .count()
+                    * self.page_size.pages()
+            }
+        }
+    }
+    /// 本段的元数据
+    pub fn info(&self) -> AreaInfo {
+        let kind = match self.map_type {
+            MapType::Linear => AreaKind::Linear,
+            MapType::Framed { .. } if self.page_size != PageSize::Size4K => {
+                AreaKind::Huge(self.page_size)
+            }
+            MapType::Framed { .. } if self.zero_fill => AreaKind::ZeroFill,
+            MapType::Framed { .. } => AreaKind::Framed,
+        };
+        AreaInfo {
+            range: self.vpn_range,
+            perm: self.map_perm,
+            kind,
+        }
+    }
     /// 判断 `r` 是否与本段相交——前提是 `r` 是一个有效的范围
-    pub fn intersection(&self, r: &Range<VirtPageNum>) -> Range<VirtPageNum> {
-        self.vpn_range.start.max(r.start)..self.vpn_range.end.min(r.end)
+    pub fn intersection(&self, r: &VPNRange) -> VPNRange {
+        self.vpn_range.intersection(r)
     }
 }
 
+/// 一个地址空间占用的内存，单位均为页
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemUsage {
+    /// 所有逻辑段的虚拟页数
+    pub size: usize,
+    /// 常驻内存的页帧数，即逻辑段持有的数据页帧与页表页帧之和
+    pub resident: usize,
+    /// 常驻内存的页帧中与其它逻辑段共享的页帧数，已计入 `resident`
+    pub shared: usize,
+    /// 可执行逻辑段的虚拟页数
+    pub text: usize,
+    /// 可写逻辑段（数据、堆和栈）的虚拟页数
+    pub data: usize,
+    /// 页表节点占用的页帧数，已计入 `resident`
+    pub page_table: usize,
+}
+
 /// 地址空间是一系列有关联的逻辑段，这些逻辑段一般属于同一个进程
 #[derive(Debug)]
+///
+/// 页表和逻辑段都是私有的，外部通过 [`MemorySet::areas`]、[`MemorySet::area_containing`]
+/// 等方法查询映射情况，通过 [`MemorySet::protect`] 等方法修改映射
 pub struct MemorySet {
-    pub page_table: PageTable,
-    pub areas: Vec<MapArea>,
+    page_table: PageTable,
+    /// 以起始页号为键的逻辑段。逻辑段互不相交，因此它们的结束页号也是有序的
+    areas: BTreeMap<VirtPageNum, MapArea>,
+    asid: Cell<Asid>,
+    /// 用过这个地址空间、TLB 中可能还有它的表项的 hart 的位图
+    harts: AtomicUsize,
+    /// mmap 区域的顶端，由内核选择地址的映射位于它之下
+    pub mmap_base: usize,
+    /// 由 wss 线程定期更新的工作集统计
+    working_set: WorkingSet,
 }
 
 extern "C" {
@@ -174,27 +609,99 @@ impl MemorySet {
     pub fn new_bare() -> Self {
         Self {
             page_table: PageTable::new(),
-            areas: Vec::new(),
+            areas: BTreeMap::new(),
+            asid: Cell::new(Asid::UNALLOCATED),
+            harts: AtomicUsize::new(0),
+            mmap_base: 0,
+            working_set: WorkingSet::default(),
+        }
+    }
+    /// 不持有任何页帧的空地址空间，供没有用户地址空间的内核线程使用，
+    /// 其 `satp` 指向内核地址空间的页表
+    pub fn new_kernel_thread() -> Self {
+        Self {
+            page_table: PageTable::from_satp(kernel_stap()),
+            areas: BTreeMap::new(),
+            asid: Cell::new(Asid::KERNEL),
+            harts: AtomicUsize::new(0),
+            mmap_base: 0,
+            working_set: WorkingSet::default(),
         }
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> Self {
+    /// 复制用户地址空间，供 fork 使用。页帧不足或者启用 `ptecheck` 时发现页表项不一致返回错误，
+    /// 已经复制的部分随返回的错误一起释放。
+    ///
+    /// `lock` 锁住并返回原地址空间。复制很大的地址空间需要很长时间，每复制完一个逻辑段就放开锁，
+    /// 经过一个抢占点；其间消失的逻辑段不再复制
+    pub fn from_existed_user<G: Deref<Target = MemorySet>>(
+        lock: impl Fn() -> G,
+    ) -> Result<Self, MapError> {
         let mut memory_set = Self::new_bare();
         memory_set.map_trampoline();
-        for area in &user_space.areas {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            for vpn in area.vpn_range.clone() {
+        let starts: Vec<VirtPageNum> = {
+            let user_space = lock();
+            memory_set.mmap_base = user_space.mmap_base;
+            user_space.areas.keys().copied().collect()
+        };
+        for start in starts {
+            preempt::cond_resched();
+            let user_space = lock();
+            let area = match user_space.areas.get(&start) {
+                Some(area) => area,
+                None => continue,
+            };
+            // 共享的文件映射在子进程中映射到同样的页帧
+            if area.file.is_some() {
+                let shared = area.share(&mut memory_set.page_table);
+                memory_set.insert_area(shared);
+                continue;
+            }
+            let mut new_area = MapArea::from_another(area);
+            new_area.map(&mut memory_set.page_table)?;
+            // 零页在子进程中仍是零页，其余的页复制一份
+            for vpn in area.vpn_range {
+                if area.is_zero_page(vpn) {
+                    continue;
+                }
+                if new_area.is_zero_page(vpn) {
+                    new_area.fault_in(&mut memory_set.page_table, vpn)?;
+                }
                 let src_ppn = user_space.translate(vpn).unwrap().ppn();
                 let mut dst_ppn = memory_set.translate(vpn).unwrap().ppn();
                 dst_ppn
                     .as_page_bytes_mut()
                     .copy_from_slice(src_ppn.as_page_bytes());
             }
+            memory_set.insert_area(new_area);
         }
-        memory_set
+        if cmdline::pte_check() {
+            memory_set.audit()?;
+        }
+        Ok(memory_set)
+    }
+    /// 统计本地址空间占用的内存
+    pub fn usage(&self) -> MemUsage {
+        let mut usage = MemUsage {
+            page_table: self.page_table.frame_count(),
+            ..MemUsage::default()
+        };
+        usage.resident = usage.page_table;
+        for area in self.areas.values() {
+            usage.size += area.page_count();
+            usage.resident += area.resident_pages();
+            usage.shared += area.shared_pages();
+            if area.map_perm.contains(MapPermission::X) {
+                usage.text += area.page_count();
+            } else if area.map_perm.contains(MapPermission::W) {
+                usage.data += area.page_count();
+            }
+        }
+        usage
     }
     // 启动虚拟内存机制
     pub fn activate(&self) {
+        self.harts
+            .fetch_or(1 << ipi::current_hart(), Ordering::Relaxed);
         let satp = self.page_table.satp();
         satp::write(satp);
         unsafe {
@@ -203,31 +710,220 @@ impl MemorySet {
         }
     }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
-        if let Some((idx, area)) = self
-            .areas
-            .iter_mut()
-            .enumerate()
-            .find(|(_, area)| area.vpn_range.start == start_vpn)
-        {
+        if let Some(mut area) = self.areas.remove(&start_vpn) {
+            area.unmap(&mut self.page_table);
+            self.flush_range(area.vpn_range);
+        }
+    }
+    /// 加入一个已经映射好的逻辑段。空的逻辑段不占用地址，不需要记录
+    fn insert_area(&mut self, area: MapArea) {
+        if !area.vpn_range.is_empty() {
+            self.areas.insert(area.vpn_range.start, area);
+        }
+    }
+    /// 与 `vpn_range` 相交的逻辑段的起始页号所在的范围
+    fn intersecting_keys(&self, vpn_range: &VPNRange) -> VPNRange {
+        if vpn_range.is_empty() {
+            return VPNRange::new(vpn_range.start, vpn_range.start);
+        }
+        // 起始页号在 `vpn_range.start` 之前的逻辑段中，只有最后一个可能与之相交
+        let first = match self.areas.range(..=vpn_range.start).next_back() {
+            Some((&start, area)) if area.vpn_range.end > vpn_range.start => start,
+            _ => vpn_range.start,
+        };
+        VPNRange::new(first, vpn_range.end)
+    }
+    /// 与 `vpn_range` 相交的逻辑段，按地址从低到高排列
+    pub fn areas_intersecting(&self, vpn_range: &VPNRange) -> impl Iterator<Item = &MapArea> {
+        self.areas
+            .range(self.intersecting_keys(vpn_range))
+            .map(|(_, area)| area)
+    }
+    /// 所有逻辑段的元数据，按地址从低到高排列
+    pub fn areas(&self) -> impl Iterator<Item = AreaInfo> + '_ {
+        self.areas.values().map(MapArea::info)
+    }
+    /// 包含虚拟地址 `va` 的逻辑段的元数据
+    pub fn area_containing(&self, va: VirtAddr) -> Option<AreaInfo> {
+        let vpn = va.floor();
+        self.areas
+            .range(..=vpn)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.vpn_range.contains(vpn))
+            .map(MapArea::info)
+    }
+    /// 取消映射完全落在 `vpn_range` 内的逻辑段，部分相交的逻辑段保持不变。文件映射中被修改的页先写回文件。
+    ///
+    /// 返回取消映射的页数
+    pub fn remove_areas_within(&mut self, vpn_range: VPNRange) -> usize {
+        let contained: Vec<_> = self
+            .areas_intersecting(&vpn_range)
+            .filter(|area| area.intersection(&vpn_range) == area.vpn_range)
+            .map(|area| area.vpn_range.start)
+            .collect();
+        let mut removed = 0;
+        for start_vpn in contained {
+            let mut area = self.areas.remove(&start_vpn).unwrap();
+            removed += area.page_count();
+            area.write_back(&mut self.page_table, area.vpn_range);
             area.unmap(&mut self.page_table);
-            self.areas.swap_remove(idx);
         }
+        self.flush_range(vpn_range);
+        removed
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// 用户页 `vpn` 的状态，它不在用户可访问的逻辑段中时返回 `None`
+    pub fn page_state(&self, vpn: VirtPageNum) -> Option<PageState> {
+        let area = self
+            .areas_intersecting(&VPNRange::single(vpn))
+            .next()
+            .filter(|area| area.is_user())?;
+        if area.is_zero_page(vpn) {
+            return Some(PageState::LAZY);
+        }
+        let resident = self.translate(vpn).map_or(false, |pte| pte.is_valid());
+        Some(if resident {
+            PageState::RESIDENT
+        } else {
+            PageState::empty()
+        })
+    }
+    /// 最近一次采样得到的工作集统计
+    pub fn working_set(&self) -> WorkingSet {
+        self.working_set
+    }
+    /// 统计用户页中自上次采样以来被访问和被写入的页数并记入工作集统计，然后清除 A 和 D 位。
+    /// 映射到零页的页不计入。
+    ///
+    /// 文件映射的页的 D 位表示尚未写回，留给 [`Self::write_back`] 清除，这些页也就不计入被写入的页数
+    pub fn sample_access(&mut self) {
+        let (mut accessed, mut dirty) = (0, 0);
+        for area in self.areas.values().filter(|area| area.is_user()) {
+            let mask = if area.file.is_some() {
+                PTEFlags::A
+            } else {
+                PTEFlags::A | PTEFlags::D
+            };
+            for vpn in area.pages().filter(|&vpn| !area.is_zero_page(vpn)) {
+                let flags = match self.page_table.take_flags(vpn, mask) {
+                    Some(flags) => flags,
+                    None => continue,
+                };
+                if flags.contains(PTEFlags::A) {
+                    accessed += area.page_size.pages();
+                }
+                if flags.contains(PTEFlags::D) {
+                    dirty += area.page_size.pages();
+                }
+            }
+        }
+        self.flush_tlb();
+        self.working_set.record(accessed, dirty);
+    }
+    /// `vpn_range` 是否与已有的逻辑段相交
+    pub fn overlaps(&self, vpn_range: &VPNRange) -> bool {
+        self.areas_intersecting(vpn_range).next().is_some()
+    }
+
+    /// 将起始页号为 `start_vpn` 的逻辑段向下扩展到 `new_start_vpn`，扩展出的部分不能与其它逻辑段相交。
+    ///
+    /// 成功返回 true
+    pub fn extend_area_down(&mut self, start_vpn: VirtPageNum, new_start_vpn: VirtPageNum) -> bool {
+        let extended = VPNRange::new(new_start_vpn, start_vpn);
+        if self.overlaps(&extended) {
+            return false;
+        }
+        // 起始页号是逻辑段的键，扩展时先取出再放回
+        if let Some(mut area) = self.areas.remove(&start_vpn) {
+            for vpn in extended {
+                if area.map_one(&mut self.page_table, vpn).is_err() {
+                    for mapped in VPNRange::new(new_start_vpn, vpn) {
+                        area.unmap_one(&mut self.page_table, mapped);
+                    }
+                    self.insert_area(area);
+                    return false;
+                }
+            }
+            area.vpn_range.start = new_start_vpn;
+            self.insert_area(area);
+            self.flush_range(extended);
+            true
+        } else {
+            false
+        }
+    }
+    /// 将 `vpn_range` 内各页的权限改为 `perm`，必要时拆分逻辑段。
+    ///
+    /// `vpn_range` 必须完全被用户可访问的逻辑段覆盖，否则不做任何修改并返回 false
+    pub fn protect(&mut self, vpn_range: VPNRange, perm: MapPermission) -> bool {
+        let mut covered = 0;
+        for area in self.areas_intersecting(&vpn_range) {
+            let intersection = area.intersection(&vpn_range);
+            if !area.map_perm.contains(MapPermission::U) {
+                return false;
+            }
+            // 不拆分大页
+            if !intersection.start.is_aligned(area.page_size)
+                || !intersection.end.is_aligned(area.page_size)
+            {
+                return false;
+            }
+            covered += intersection.len();
+        }
+        if covered != vpn_range.len() {
+            return false;
+        }
+        let keys = self.intersecting_keys(&vpn_range);
+        let page_table = &mut self.page_table;
+        let mut split = Vec::new();
+        for (_, area) in self.areas.range_mut(keys) {
+            let intersection = area.intersection(&vpn_range);
+            // 先拆出与 `vpn_range` 不相交的两端，再修改中间部分的权限
+            if area.vpn_range.end > intersection.end {
+                split.push(area.split_off(intersection.end));
+            }
+            let middle = if area.vpn_range.start < intersection.start {
+                split.push(area.split_off(intersection.start));
+                split.last_mut().unwrap()
+            } else {
+                area
+            };
+            middle.map_perm = perm;
+            for vpn in middle.pages() {
+                page_table.set_flags(vpn, middle.pte_flags(vpn));
+            }
+        }
+        for area in split {
+            self.insert_area(area);
+        }
+        self.flush_range(vpn_range);
+        true
+    }
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        self.try_push(map_area, data)
+            .expect("Should have enough memory");
+    }
+    /// 映射并插入一个逻辑段。与已有的逻辑段相交时在映射任何页之前返回 `MapError::Overlap`
+    fn try_push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Result<(), MapError> {
+        check_wx(map_area.map_perm)?;
+        if self.overlaps(&map_area.vpn_range) {
+            return Err(MapError::Overlap);
+        }
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            map_area.copy_data(&mut self.page_table, data, 0);
         }
-        self.areas.push(map_area);
+        self.insert_area(map_area);
+        Ok(())
     }
-    /// 在当前地址空间插入一个 `Framed` 方式映射的逻辑段。需要保证同一地址空间内的两个逻辑段不能相交
+    /// 在当前地址空间插入一个 `Framed` 方式映射的逻辑段。与已有的逻辑段相交时返回 `MapError::Overlap`
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
         end_va: VirtAddr,
         map_perm: MapPermission,
-    ) {
-        self.push(
+    ) -> Result<(), MapError> {
+        self.try_push(
             MapArea::new(
                 start_va,
                 end_va,
@@ -237,14 +933,187 @@ impl MemorySet {
                 map_perm,
             ),
             None,
+        )?;
+        self.flush_tlb();
+        Ok(())
+    }
+    /// 在当前地址空间插入一个 `Framed` 方式映射的匿名逻辑段，各页在第一次写入时才分配页帧。
+    /// 与已有的逻辑段相交时返回 `MapError::Overlap`
+    pub fn insert_zeroed_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+    ) -> Result<(), MapError> {
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Framed {
+                data_frames: Default::default(),
+            },
+            map_perm,
+        );
+        map_area.zero_fill = true;
+        self.try_push(map_area, None)?;
+        self.flush_tlb();
+        Ok(())
+    }
+    /// 在当前地址空间插入一个共享的文件映射逻辑段，页帧在映射时全部分配并读入文件的内容。
+    /// 与已有的逻辑段相交时返回 `MapError::Overlap`
+    pub fn insert_file_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+        file: FileBacking,
+    ) -> Result<(), MapError> {
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Framed {
+                data_frames: Default::default(),
+            },
+            map_perm,
+        );
+        map_area.file = Some(file);
+        let start_vpn = map_area.vpn_range.start;
+        self.try_push(map_area, None)?;
+        if let Some(area) = self.areas.get(&start_vpn) {
+            area.read_file(&self.page_table);
+        }
+        self.flush_tlb();
+        Ok(())
+    }
+    /// 把 `vpn_range` 中文件映射的页里被修改过（D 位置位）的页写回文件，返回写回的页数。
+    ///
+    /// 数据只写入块缓存，需要落盘时调用者再调用 `easy_fs::block_cache_sync_all`
+    pub fn write_back(&mut self, vpn_range: VPNRange) -> usize {
+        let mut written = 0;
+        for (_, area) in self.areas.range(self.intersecting_keys(&vpn_range)) {
+            written += area.write_back(&mut self.page_table, vpn_range);
+        }
+        // 缓存了 D 位的 TLB 表项不会再次置位 D 位
+        self.flush_range(vpn_range);
+        written
+    }
+    /// 在 mmap 区域中寻找 `len` 字节尚未映射的虚拟地址，返回其起始地址。
+    ///
+    /// `hint` 不为 0、按页对齐且从它开始的区间位于 `mmap_base` 之下并且空闲时直接使用它，
+    /// 否则从 `mmap_base` 向下寻找第一个足够大的空隙。长度不小于 2 MiB 时起始地址按 2 MiB 对齐，
+    /// 以便使用大页。找不到时返回 `None`
+    pub fn find_free_range(&self, len: usize, hint: usize) -> Option<usize> {
+        let pages = VirtAddr(len).ceil().0;
+        let top = VirtAddr(self.mmap_base).floor().0;
+        if pages == 0 || pages > top {
+            return None;
+        }
+        if hint != 0 && hint % PAGE_SIZE == 0 {
+            let start = VirtAddr(hint).floor();
+            if let Some(range) = start
+                .checked_add(pages)
+                .map(|end| VPNRange::new(start, end))
+            {
+                if range.end.0 <= top && !self.overlaps(&range) && !overlaps_kernel_global(&range) {
+                    return Some(hint);
+                }
+            }
+        }
+        let align = if pages >= PageSize::Size2M.pages() {
+            PageSize::Size2M.pages()
+        } else {
+            1
+        };
+        // 在 `[low, high)` 中放下这段区间时的起始页号
+        let fit = |low: usize, high: usize| {
+            high.checked_sub(pages)
+                .map(|start| start / align * align)
+                .filter(|&start| start >= low)
+        };
+        let mut occupied: Vec<_> = self
+            .areas
+            .values()
+            .map(|area| area.vpn_range.start.0..area.vpn_range.end.0)
+            .collect();
+        occupied.extend(
+            KERNEL_SPACE
+                .lock()
+                .areas
+                .values()
+                .filter(|area| area.map_perm.contains(MapPermission::G))
+                .map(|area| area.vpn_range.start.0..area.vpn_range.end.0),
+        );
+        // 逻辑段互不相交，从高到低依次检查各个空隙。第 0 页不用于映射
+        occupied.sort_unstable_by_key(|range| core::cmp::Reverse(range.start));
+        let mut high = top;
+        for range in occupied {
+            if range.end <= high {
+                if let Some(start) = fit(range.end, high) {
+                    return Some(start * PAGE_SIZE);
+                }
+            }
+            high = high.min(range.start);
+        }
+        fit(1, high).map(|start| start * PAGE_SIZE)
+    }
+    /// 在当前地址空间插入一个以大小为 `page_size` 的页映射的匿名逻辑段，页帧在映射时全部分配。
+    /// `start_va` 和 `end_va` 都需要按 `page_size` 对齐。
+    ///
+    /// 需要保证同一地址空间内的两个逻辑段不能相交。找不到足够的连续页帧时返回 `MapError::OutOfMemory`
+    pub fn insert_huge_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+        page_size: PageSize,
+    ) -> Result<(), MapError> {
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Framed {
+                data_frames: Default::default(),
+            },
+            map_perm,
         );
+        map_area.page_size = page_size;
+        self.try_push(map_area, None)?;
+        self.flush_tlb();
+        Ok(())
     }
+    /// 处理写入零页引发的缺页：为 `vpn` 分配私有的页帧。
+    ///
+    /// `vpn` 不是可写逻辑段中的零页，或者页帧不足时返回 false
+    pub fn fault_in_zero_page(&mut self, vpn: VirtPageNum) -> bool {
+        let area = match area_containing_mut(&mut self.areas, vpn) {
+            Some(area) => area,
+            None => return false,
+        };
+        if !area.map_perm.contains(MapPermission::W) || !area.is_zero_page(vpn) {
+            return false;
+        }
+        if area.fault_in(&mut self.page_table, vpn).is_err() {
+            return false;
+        }
+        self.flush_range(VPNRange::single(vpn));
+        true
+    }
+    /// 释放所有逻辑段，文件映射中被修改的页先写回文件
     pub fn recycle_data_pages(&mut self) {
+        for area in self.areas.values() {
+            area.write_back(&mut self.page_table, area.vpn_range);
+        }
         self.areas.clear();
     }
+    /// 回收数据页以及存放页表项的页。之后这个地址空间不能再启用，
+    /// 调用者需要保证当前没有使用它的页表
+    pub fn recycle_page_table(&mut self) {
+        self.recycle_data_pages();
+        self.flush_tlb();
+        self.page_table.release_frames();
+    }
     /// 生成内核的地址空间
     pub fn new_kernel() -> Self {
         let mut memory_set = Self::new_bare();
+        memory_set.asid.set(Asid::KERNEL);
         // map trampoline
         memory_set.map_trampoline();
         // map kernel sections
@@ -261,8 +1130,8 @@ impl MemorySet {
             MapArea::new(
                 VirtAddr(stext as usize),
                 VirtAddr(etext as usize),
-                MapType::Identical,
-                MapPermission::R | MapPermission::X,
+                MapType::Linear,
+                MapPermission::R | MapPermission::X | MapPermission::G,
             ),
             None,
         );
@@ -271,8 +1140,8 @@ impl MemorySet {
             MapArea::new(
                 VirtAddr(srodata as usize),
                 VirtAddr(erodata as usize),
-                MapType::Identical,
-                MapPermission::R,
+                MapType::Linear,
+                MapPermission::R | MapPermission::G,
             ),
             None,
         );
@@ -281,8 +1150,8 @@ impl MemorySet {
             MapArea::new(
                 VirtAddr(sdata as usize),
                 VirtAddr(edata as usize),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
+                MapType::Linear,
+                MapPermission::R | MapPermission::W | MapPermission::G,
             ),
             None,
         );
@@ -291,8 +1160,8 @@ impl MemorySet {
             MapArea::new(
                 VirtAddr(sbss_with_stack as usize),
                 VirtAddr(ebss as usize),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
+                MapType::Linear,
+                MapPermission::R | MapPermission::W | MapPermission::G,
             ),
             None,
         );
@@ -300,19 +1169,19 @@ impl MemorySet {
         memory_set.push(
             MapArea::new(
                 VirtAddr(ekernel as usize),
-                VirtAddr(MEMORY_END),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
+                PhysAddr(dtb::memory_end()).to_virt(),
+                MapType::Linear,
+                MapPermission::R | MapPermission::W | MapPermission::G,
             ),
             None,
         );
         log::info!("mapping memory-mapped registers");
-        for pair in MMIO {
+        for device in dtb::devices() {
             memory_set.push(
                 MapArea::new(
-                    VirtAddr(pair.0),
-                    VirtAddr(pair.0 + pair.1),
-                    MapType::Identical,
+                    PhysAddr(device.base).to_virt(),
+                    PhysAddr(device.base + device.size).to_virt(),
+                    MapType::Linear,
                     MapPermission::R | MapPermission::W,
                 ),
                 None,
@@ -320,22 +1189,58 @@ impl MemorySet {
         }
         memory_set
     }
-    /// 从 ELF 数据中解析出各类数据段并对应生成应用的地址空间、用户栈和入口
+    /// 从 `source` 中的 ELF 解析出各类数据段并对应生成应用的地址空间、用户栈和入口。
+    /// LOAD 段的数据直接从 `source` 读入页帧，见 [`ElfSource`]
+    ///
+    /// 如果有 TLS 段，还会在数据段之后为其分配一块内存，并将它的地址作为 `tp` 的初始值。
+    ///
+    /// 位置无关的可执行文件（ET_DYN）加载到 `PIE_LOAD_BASE`，并处理其中的 `R_RISCV_RELATIVE` 重定位。
+    /// 不支持需要动态链接器的程序。
     ///
-    /// 返回 (memory_set, user_stack_top, entry)
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// `argv` 和 `envp` 按 SysV ABI 放在用户栈顶，见 [`Self::push_initial_stack`]
+    pub fn from_elf(
+        source: &dyn ElfSource,
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<ElfImage, ElfError> {
         let mut memory_set = Self::new_bare();
         memory_set.map_trampoline();
-        let elf = ElfFile::new(elf_data).unwrap();
+        let headers = read_elf_headers(source)?;
+        let elf = ElfFile::new(&headers).map_err(|_| ElfError::NotElf)?;
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        if magic != [0x7f, 0x45, 0x4c, 0x46] {
+            return Err(ElfError::NotElf);
+        }
+        let base = match elf_header.pt2.type_().as_type() {
+            header::Type::Executable => 0,
+            header::Type::SharedObject => PIE_LOAD_BASE + aslr_offset(),
+            _ => return Err(ElfError::NotElf),
+        };
         let ph_count = elf_header.pt2.ph_count();
+        let ph_offset = elf_header.pt2.ph_offset() as usize;
         let mut max_end_vpn = VirtPageNum(0);
+        let mut tls = None;
+        let mut dynamic = None;
+        // 程序头表被加载到的虚拟地址，用于辅助向量中的 AT_PHDR
+        let mut phdr = 0;
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == program::Type::Load {
-                let start_va = VirtAddr(ph.virtual_addr() as usize);
+            let ph = elf
+                .program_header(i)
+                .map_err(|_| ElfError::BadProgramHeader)?;
+            let ph_type = ph.get_type().map_err(|_| ElfError::BadProgramHeader)?;
+            match ph_type {
+                program::Type::Tls => tls = Some(ph),
+                program::Type::Dynamic => dynamic = Some(ph),
+                program::Type::Interp => return Err(ElfError::Unsupported),
+                _ => {}
+            }
+            if ph_type == program::Type::Load {
+                let file_range = segment_range(ph.offset(), ph.file_size(), ph.mem_size())?;
+                let start_va = VirtAddr(base + ph.virtual_addr() as usize);
+                if file_range.contains(&ph_offset) {
+                    phdr = start_va.0 + ph_offset - file_range.start;
+                }
                 let end_va = VirtAddr(start_va.0 + ph.mem_size() as usize);
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
@@ -348,7 +1253,8 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(
+                check_wx(map_perm).map_err(|_| ElfError::WriteExecute)?;
+                let mut map_area = MapArea::new(
                     start_va,
                     end_va,
                     MapType::Framed {
@@ -356,31 +1262,224 @@ impl MemorySet {
                     },
                     map_perm,
                 );
-                max_end_vpn = map_area.vpn_range.end;
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                )
+                map_area.zero_fill = true;
+                if memory_set.overlaps(&map_area.vpn_range)
+                    || overlaps_kernel_global(&map_area.vpn_range)
+                {
+                    return Err(ElfError::OverlappingSegments);
+                }
+                max_end_vpn = max_end_vpn.max(map_area.vpn_range.end);
+                // 文件中的数据之后的整页都属于 bss，先映射到零页
+                let data_end = VirtAddr(start_va.0 + file_range.len()).ceil();
+                map_area
+                    .map_with_zero_pages(&mut memory_set.page_table, data_end)
+                    .map_err(|_| ElfError::OutOfMemory)?;
+                let complete = map_area.read_data(
+                    &mut memory_set.page_table,
+                    source,
+                    file_range,
+                    start_va.page_offset(),
+                );
+                memory_set.insert_area(map_area);
+                if !complete {
+                    return Err(ElfError::BadSegment);
+                }
             }
         }
-        let max_end_va = max_end_vpn.page_start();
-        let mut user_stack_bottom = max_end_va.0;
-        // 作为 Guard Page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(
-            MapArea::new(
-                VirtAddr(user_stack_bottom),
-                VirtAddr(user_stack_top),
+        if let Some(ph) = dynamic {
+            let data = read_segment(source, ph.offset(), ph.file_size(), ph.file_size())?;
+            memory_set.relocate(&data, base)?;
+        }
+        // TLS 段的初始化数据一般也位于某个 LOAD 段中，这里另外复制一份作为唯一线程的 TLS 块。
+        // RISC-V 中 `tp` 直接指向 TLS 块的起始位置
+        let mut tp = 0;
+        if let Some(ph) = tls {
+            let data = read_segment(source, ph.offset(), ph.file_size(), ph.mem_size())?;
+            let tls_start = max_end_vpn.page_start();
+            let tls_end = VirtAddr(tls_start.0 + ph.mem_size() as usize);
+            let mut map_area = MapArea::new(
+                tls_start,
+                tls_end,
                 MapType::Framed {
                     data_frames: Default::default(),
                 },
                 MapPermission::R | MapPermission::W | MapPermission::U,
-            ),
-            None,
+            );
+            max_end_vpn = map_area.vpn_range.end;
+            map_area
+                .map(&mut memory_set.page_table)
+                .map_err(|_| ElfError::OutOfMemory)?;
+            map_area.copy_data(&mut memory_set.page_table, &data, 0);
+            memory_set.insert_area(map_area);
+            tp = tls_start.0;
+        }
+        // 用户栈位于地址空间高处，下方依次是栈增长的空间、Guard Page 和 mmap 区域
+        let user_stack_top = USER_STACK_TOP - aslr_offset();
+        let user_stack_bottom = user_stack_top - USER_STACK_SIZE;
+        let stack_limit = VirtAddr(user_stack_top - USER_STACK_MAX_SIZE - PAGE_SIZE).floor();
+        if max_end_vpn > stack_limit {
+            return Err(ElfError::OverlappingSegments);
+        }
+        memory_set
+            .try_push(
+                MapArea::new(
+                    VirtAddr(user_stack_bottom),
+                    VirtAddr(user_stack_top),
+                    MapType::Framed {
+                        data_frames: Default::default(),
+                    },
+                    MapPermission::R | MapPermission::W | MapPermission::U,
+                ),
+                None,
+            )
+            .map_err(|_| ElfError::OutOfMemory)?;
+        memory_set.mmap_base = stack_limit.page_start().0 - MMAP_STACK_GAP - aslr_offset();
+        log::debug!(
+            "load base: {:#x}, stack top: {:#x}, mmap base: {:#x}",
+            base,
+            user_stack_top,
+            memory_set.mmap_base
         );
-        // Trap Context
-        memory_set.push(
+        let entry = base + elf_header.pt2.entry_point() as usize;
+        let auxv = [
+            (AT_PHDR, phdr),
+            (AT_PHENT, elf_header.pt2.ph_entry_size() as usize),
+            (AT_PHNUM, ph_count as usize),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, 0),
+            (AT_ENTRY, entry),
+            (AT_NULL, 0),
+        ];
+        let user_sp = memory_set.push_initial_stack(user_stack_top, argv, envp, &auxv)?;
+        memory_set
+            .map_trap_context()
+            .map_err(|_| ElfError::OutOfMemory)?;
+        if cmdline::pte_check() {
+            memory_set.audit().map_err(|_| ElfError::BadPte)?;
+        }
+        Ok(ElfImage {
+            memory_set,
+            user_stack_top,
+            user_sp,
+            argc: argv.len(),
+            entry,
+            tp,
+        })
+    }
+    /// 根据 `.dynamic` 段的内容处理 RELA 重定位，`base` 为加载基址
+    fn relocate(&mut self, dynamic: &[u8], base: usize) -> Result<(), ElfError> {
+        let mut rela = None;
+        let mut rela_size = 0;
+        let mut rela_ent = RELA_ENTRY_SIZE;
+        for entry in dynamic.chunks_exact(16) {
+            let tag = read_usize(&entry[..8]);
+            let value = read_usize(&entry[8..]);
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(base + value),
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_ent = value,
+                DT_REL => return Err(ElfError::Unsupported),
+                _ => {}
+            }
+        }
+        let rela = match rela {
+            Some(rela) => rela,
+            None => return Ok(()),
+        };
+        if rela_ent != RELA_ENTRY_SIZE {
+            return Err(ElfError::BadRelocation);
+        }
+        for i in 0..rela_size / RELA_ENTRY_SIZE {
+            let entry = rela + i * RELA_ENTRY_SIZE;
+            let offset = *self.user_u64(entry).ok_or(ElfError::BadRelocation)?;
+            let info = *self.user_u64(entry + 8).ok_or(ElfError::BadRelocation)?;
+            let addend = *self.user_u64(entry + 16).ok_or(ElfError::BadRelocation)?;
+            match info & 0xffff_ffff {
+                R_RISCV_NONE => {}
+                R_RISCV_RELATIVE => {
+                    let target = self
+                        .user_u64(base + offset as usize)
+                        .ok_or(ElfError::BadRelocation)?;
+                    *target = (base as u64).wrapping_add(addend);
+                }
+                _ => return Err(ElfError::Unsupported),
+            }
+        }
+        Ok(())
+    }
+    /// 按 SysV ABI 在用户栈顶放入初始的栈，返回初始的 `sp`。从 `sp` 开始依次为：
+    ///
+    /// - argc
+    /// - argv 的各个指针，以 NULL 结尾
+    /// - envp 的各个指针，以 NULL 结尾
+    /// - 辅助向量
+    ///
+    /// 指针指向的字符串放在栈顶，以 `\0` 结尾
+    fn push_initial_stack(
+        &mut self,
+        user_stack_top: usize,
+        argv: &[String],
+        envp: &[String],
+        auxv: &[(usize, usize)],
+    ) -> Result<usize, ElfError> {
+        let strings_len: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+        // argc、argv 及其 NULL、envp 及其 NULL，之后是辅助向量
+        let words = 3 + argv.len() + envp.len() + auxv.len() * 2;
+        if strings_len + words * 8 > ARG_MAX {
+            return Err(ElfError::ArgsTooLong);
+        }
+        // 字符串区按 8 字节对齐，这样可以和指针一样逐个 u64 写入
+        let strings_start = (user_stack_top - strings_len) & !0x7;
+        let mut strings = Vec::with_capacity(user_stack_top - strings_start);
+        let mut pointers = Vec::with_capacity(argv.len() + envp.len());
+        for s in argv.iter().chain(envp) {
+            pointers.push(strings_start + strings.len());
+            strings.extend_from_slice(s.as_bytes());
+            strings.push(0);
+        }
+        strings.resize(user_stack_top - strings_start, 0);
+        // RISC-V 要求 sp 按 16 字节对齐
+        let user_sp = (strings_start - words * 8) & !0xf;
+        let (argv_ptrs, envp_ptrs) = pointers.split_at(argv.len());
+        let values = core::iter::once(argv.len())
+            .chain(argv_ptrs.iter().copied())
+            .chain(core::iter::once(0))
+            .chain(envp_ptrs.iter().copied())
+            .chain(core::iter::once(0))
+            .chain(auxv.iter().flat_map(|&(key, value)| [key, value]));
+        for (i, value) in values.enumerate() {
+            *self.user_u64(user_sp + i * 8).unwrap() = value as u64;
+        }
+        for (i, chunk) in strings.chunks(8).enumerate() {
+            *self.user_u64(strings_start + i * 8).unwrap() =
+                u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Ok(user_sp)
+    }
+    /// 本地址空间中 `va` 处的 u64，`va` 必须按 8 字节对齐且已经映射。
+    /// 调用者可能写入它，因此它位于零页时先为这一页分配页帧
+    fn user_u64(&mut self, va: usize) -> Option<&'static mut u64> {
+        if va % 8 != 0 {
+            return None;
+        }
+        let va = VirtAddr(va);
+        let mut pte = self
+            .translate(va.floor())
+            .filter(PageTableEntry::is_valid)?;
+        if pte.ppn() == zero_ppn() {
+            let area = area_containing_mut(&mut self.areas, va.floor())?;
+            area.fault_in(&mut self.page_table, va.floor()).ok()?;
+            pte = self.translate(va.floor())?;
+        }
+        Some(pte.ppn().as_mut_at(va.page_offset()))
+    }
+    /// 映射保存 Trap 上下文的页，它紧挨着跳板的下方。
+    ///
+    /// 这一页是内核数据，只在跳板中以用户的 `satp` 访问，因此没有 U 标志，也不可执行。
+    /// 用户不能读写它，也不能用 mmap、munmap 和 mprotect 改变它
+    fn map_trap_context(&mut self) -> Result<(), MapError> {
+        self.try_push(
             MapArea::new(
                 VirtAddr(TRAP_CONTEXT),
                 VirtAddr(TRAMPOLINE),
@@ -390,11 +1489,6 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
-        (
-            memory_set,
-            user_stack_top,
-            elf_header.pt2.entry_point() as usize,
         )
     }
     /// 映射跳板，也就是进入和退出异常处理的地方。
@@ -406,21 +1500,206 @@ impl MemorySet {
         log::trace!("mapping trampoline");
         self.page_table.map(
             VirtAddr(TRAMPOLINE).floor(),
-            PhysAddr(strampoline as usize).floor(),
+            VirtAddr(strampoline as usize).to_phys().floor(),
             PTEFlags::R | PTEFlags::X,
         )
     }
+    /// 包含 ASID 的 `satp`，ASID 过期时会重新分配。
+    ///
+    /// 调用者会用它访问这个地址空间，所以当前 hart 从此可能缓存它的表项
     pub fn satp(&self) -> usize {
-        self.page_table.satp()
+        self.harts
+            .fetch_or(1 << ipi::current_hart(), Ordering::Relaxed);
+        self.page_table.satp() | asid::satp_bits(&self.asid)
+    }
+    /// 修改页表后刷新 TLB 中属于本地址空间的表项
+    pub fn flush_tlb(&self) {
+        self.flush(None);
+    }
+    /// 修改了 `vpn_range` 中的页表项后刷新 TLB。
+    ///
+    /// 当前 hart 直接执行 `sfence.vma`；其它用过这个地址空间的 hart 通过 IPI 请求刷新，
+    /// 等它们都刷新完才返回，之后才能释放被解除映射的页帧
+    pub fn flush_range(&self, vpn_range: VPNRange) {
+        self.flush(Some(
+            vpn_range.start.page_start().0..vpn_range.end.page_start().0,
+        ));
+    }
+    fn flush(&self, va: Option<Range<usize>>) {
+        let asid = match asid::flush_target(&self.asid) {
+            Some(asid) => asid,
+            None => return,
+        };
+        asid::flush_local(asid, va.clone());
+        let others = self.harts.load(Ordering::Relaxed) & !(1 << ipi::current_hart());
+        ipi::shootdown(others, asid, va);
     }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// 逐页检查各逻辑段的页表项，出错时打印第一个有问题的页并返回 `MapError::BadPte`：
+    ///
+    /// - 页表项的标志位与逻辑段的权限一致，只有映射到零页的页去掉了 W。不检查 A 和 D
+    /// - 可写的页也可读，RISC-V 中只写的页表项是保留的编码
+    /// - 用户页满足 W^X
+    /// - 跳板用户不可访问，且不可写
+    pub fn audit(&self) -> Result<(), MapError> {
+        let ignored = PTEFlags::A | PTEFlags::D;
+        for area in self.areas.values() {
+            for vpn in area.pages() {
+                let flags = self
+                    .translate(vpn)
+                    .filter(PageTableEntry::is_valid)
+                    .map(|pte| pte.flags() - ignored);
+                let expected = area.pte_flags(vpn) | PTEFlags::V;
+                let ok = flags == Some(expected)
+                    && (!expected.contains(PTEFlags::W) || expected.contains(PTEFlags::R))
+                    && check_wx(area.map_perm).is_ok();
+                if !ok {
+                    log::error!(
+                        "[kernel] bad pte at vpn {:#x}: {:?}, expected {:?}",
+                        vpn.0,
+                        flags,
+                        expected
+                    );
+                    return Err(MapError::BadPte);
+                }
+            }
+        }
+        let trampoline = self
+            .translate(VirtAddr(TRAMPOLINE).floor())
+            .map(|pte| pte.flags() - ignored);
+        if trampoline != Some(PTEFlags::V | PTEFlags::R | PTEFlags::X) {
+            log::error!("[kernel] bad trampoline pte: {:?}", trampoline);
+            return Err(MapError::BadPte);
+        }
+        Ok(())
+    }
+}
+
+/// 启用 W^X 时，拒绝同时可写可执行的用户映射
+pub fn check_wx(perm: MapPermission) -> Result<(), MapError> {
+    let wx = MapPermission::W | MapPermission::X | MapPermission::U;
+    if perm.contains(wx) && cmdline::wx() {
+        Err(MapError::WriteExecute)
+    } else {
+        Ok(())
+    }
+}
+
+/// ELF64 头的大小
+const ELF_HEADER_SIZE: usize = 64;
+/// ELF 头和程序头表最多占用文件开头的字节数
+const ELF_HEADERS_MAX: usize = 4 * PAGE_SIZE;
+/// 读到内核中的 `.dynamic` 段和 TLS 初始化数据的最大字节数
+const ELF_SMALL_SEGMENT_MAX: usize = 16 * PAGE_SIZE;
+
+// 辅助向量的类型
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_BASE: usize = 7;
+const AT_ENTRY: usize = 9;
+
+// `.dynamic` 段中的标签
+const DT_NULL: usize = 0;
+const DT_RELA: usize = 7;
+const DT_RELASZ: usize = 8;
+const DT_RELAENT: usize = 9;
+const DT_REL: usize = 17;
+
+const RELA_ENTRY_SIZE: usize = 24;
+const R_RISCV_NONE: u64 = 0;
+const R_RISCV_RELATIVE: u64 = 3;
+
+fn read_usize(bytes: &[u8]) -> usize {
+    usize::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// `areas` 中包含 `vpn` 的逻辑段
+fn area_containing_mut(
+    areas: &mut BTreeMap<VirtPageNum, MapArea>,
+    vpn: VirtPageNum,
+) -> Option<&mut MapArea> {
+    areas
+        .range_mut(..=vpn)
+        .next_back()
+        .map(|(_, area)| area)
+        .filter(|area| area.vpn_range.contains(vpn))
+}
+
+/// 检查 ELF 中一个段的大小是否合理，返回它在文件中的范围
+fn segment_range(offset: u64, file_size: u64, mem_size: u64) -> Result<Range<usize>, ElfError> {
+    if file_size > mem_size {
+        return Err(ElfError::BadSegment);
+    }
+    let start = offset as usize;
+    let end = start
+        .checked_add(file_size as usize)
+        .ok_or(ElfError::BadSegment)?;
+    Ok(start..end)
+}
+
+/// 把 ELF 中一个段在文件中的数据读到内核堆上。只用于 `.dynamic` 和 TLS 这样的小段，
+/// 超过 `ELF_SMALL_SEGMENT_MAX` 字节时视为无效
+fn read_segment(
+    source: &dyn ElfSource,
+    offset: u64,
+    file_size: u64,
+    mem_size: u64,
+) -> Result<Vec<u8>, ElfError> {
+    let range = segment_range(offset, file_size, mem_size)?;
+    if range.len() > ELF_SMALL_SEGMENT_MAX {
+        return Err(ElfError::BadSegment);
+    }
+    let mut data = vec![0; range.len()];
+    if source.read_at(range.start, &mut data) != data.len() {
+        return Err(ElfError::BadSegment);
+    }
+    Ok(data)
+}
+
+/// 读出位于文件开头的 ELF 头和程序头表。程序头表超出前 `ELF_HEADERS_MAX` 字节时视为无效
+fn read_elf_headers(source: &dyn ElfSource) -> Result<Vec<u8>, ElfError> {
+    let mut headers = vec![0; ELF_HEADER_SIZE];
+    if source.read_at(0, &mut headers) != headers.len() {
+        return Err(ElfError::NotElf);
+    }
+    let ph_end = {
+        let elf = ElfFile::new(&headers).map_err(|_| ElfError::NotElf)?;
+        let pt2 = elf.header.pt2;
+        (pt2.ph_count() as usize)
+            .checked_mul(pt2.ph_entry_size() as usize)
+            .and_then(|size| size.checked_add(pt2.ph_offset() as usize))
+            .ok_or(ElfError::BadProgramHeader)?
+    };
+    if ph_end > ELF_HEADERS_MAX {
+        return Err(ElfError::BadProgramHeader);
+    }
+    if ph_end > headers.len() {
+        let start = headers.len();
+        headers.resize(ph_end, 0);
+        if source.read_at(start, &mut headers[start..]) != ph_end - start {
+            return Err(ElfError::BadProgramHeader);
+        }
+    }
+    Ok(headers)
+}
+
+/// 启用 ASLR 时返回一个 `ASLR_MAX_PAGES` 页以内的随机偏移，按页对齐；否则返回 0
+fn aslr_offset() -> usize {
+    if cmdline::aslr() {
+        random::next_usize() % ASLR_MAX_PAGES * PAGE_SIZE
+    } else {
+        0
+    }
 }
 
 #[allow(unused)]
 pub fn remap_test() {
-    let mut kernel_space = KERNEL_SPACE.exclusive_access();
+    let mut kernel_space = KERNEL_SPACE.lock();
     let mid_text: VirtAddr = VirtAddr((stext as usize + etext as usize) / 2);
     let mid_rodata: VirtAddr = VirtAddr((srodata as usize + erodata as usize) / 2);
     let mid_data: VirtAddr = VirtAddr((sdata as usize + edata as usize) / 2);
@@ -442,7 +1721,567 @@ pub fn remap_test() {
     log::info!("remap_test passed!");
 }
 
+/// 检查跳板和 Trap 上下文页的标志位：跳板只读可执行，Trap 上下文可读写但不可执行，二者用户都不可访问。
+/// 内核地址空间中的跳板也一并检查
+pub fn trap_context_test() {
+    let mut user_space = MemorySet::new_bare();
+    user_space.map_trampoline();
+    user_space
+        .map_trap_context()
+        .expect("no frame for the trap context");
+    let trampoline = VirtAddr(TRAMPOLINE).floor();
+    let trap_ctx = VirtAddr(TRAP_CONTEXT).floor();
+    for space in [&user_space, &*KERNEL_SPACE.lock()] {
+        let pte = space.translate(trampoline).unwrap();
+        assert!(pte.readable() && pte.executable() && !pte.writable());
+        assert!(!pte.flags().contains(PTEFlags::U));
+    }
+    let pte = user_space.translate(trap_ctx).unwrap();
+    assert!(pte.readable() && pte.writable() && !pte.executable());
+    assert!(!pte.flags().contains(PTEFlags::U));
+    // 用户指针指向这两页时，内核也不会替用户访问它们
+    with_user_access(user_space.page_table.satp(), |user| {
+        for vpn in [trampoline, trap_ctx] {
+            assert!(user.translate(vpn.page_start(), Access::Read).is_none());
+        }
+    });
+    log::info!("trap_context_test passed!");
+}
+
+/// 内核代码、数据和物理内存的线性映射带有 G 位，在所有地址空间中都有效，因此用户不能映射与之相交的地址。
+///
+/// 线性映射区位于高半部，用户地址本来就不会与之相交，这里的检查只是防御性的
+pub fn overlaps_kernel_global(vpn_range: &VPNRange) -> bool {
+    KERNEL_SPACE
+        .lock()
+        .areas_intersecting(vpn_range)
+        .any(|area| area.map_perm.contains(MapPermission::G))
+}
+
 /// Get the token of the kernel memory space
 pub fn kernel_stap() -> usize {
-    KERNEL_SPACE.exclusive_access().satp()
+    KERNEL_SPACE.lock().satp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fs::inode::ROOT_INODE, mm::frame_allocator::frame_remaining};
+
+    #[test_case]
+    fn kernel_remap() {
+        remap_test();
+    }
+
+    #[test_case]
+    fn trap_context_is_kernel_only() {
+        trap_context_test();
+    }
+
+    #[test_case]
+    fn framed_area_overlap_is_rejected() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        assert!(memory_set
+            .insert_framed_area(VirtAddr(0x1000), VirtAddr(0x3000), perm)
+            .is_ok());
+        assert!(matches!(
+            memory_set.insert_framed_area(VirtAddr(0x2000), VirtAddr(0x4000), perm),
+            Err(MapError::Overlap)
+        ));
+        // 相交时什么也不映射，第二个逻辑段的页不应出现在页表中
+        assert!(memory_set
+            .translate(VirtAddr(0x3000).floor())
+            .map_or(true, |pte| !pte.is_valid()));
+    }
+
+    #[test_case]
+    fn intersecting_areas_are_found_by_range() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::U;
+        for (start, end) in [(0x1000, 0x3000), (0x5000, 0x6000), (0x8000, 0xa000)] {
+            memory_set
+                .insert_framed_area(VirtAddr(start), VirtAddr(end), perm)
+                .unwrap();
+        }
+        let range = |start: usize, end: usize| VPNRange::covering(VirtAddr(start), VirtAddr(end));
+        let starts = |start: usize, end: usize| {
+            memory_set
+                .areas_intersecting(&range(start, end))
+                .map(|area| area.vpn_range.start.page_start().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(0x2000, 0x9000), [0x1000, 0x5000, 0x8000]);
+        assert_eq!(starts(0x3000, 0x5000), []);
+        assert_eq!(starts(0x5000, 0x5000), []);
+        assert!(memory_set.overlaps(&range(0x9000, 0xb000)));
+        assert!(!memory_set.overlaps(&range(0x6000, 0x8000)));
+    }
+
+    #[test_case]
+    fn area_metadata_is_reported() {
+        let mut memory_set = MemorySet::new_bare();
+        let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+        memory_set
+            .insert_framed_area(VirtAddr(0x1000), VirtAddr(0x3000), rw)
+            .unwrap();
+        memory_set
+            .insert_zeroed_area(VirtAddr(0x5000), VirtAddr(0x6000), rw)
+            .unwrap();
+        let info = memory_set.area_containing(VirtAddr(0x2fff)).unwrap();
+        assert_eq!(info.range, VPNRange::new(VirtPageNum(1), VirtPageNum(3)));
+        assert_eq!(info.perm, rw);
+        assert_eq!(info.kind, AreaKind::Framed);
+        assert!(memory_set.area_containing(VirtAddr(0x3000)).is_none());
+        let kinds: Vec<_> = memory_set.areas().map(|info| info.kind).collect();
+        assert_eq!(kinds, [AreaKind::Framed, AreaKind::ZeroFill]);
+
+        let all = VPNRange::covering(VirtAddr(0x1000), VirtAddr(0x5800));
+        assert_eq!(memory_set.remove_areas_within(all), 3);
+        assert_eq!(memory_set.areas().count(), 0);
+    }
+
+    #[test_case]
+    fn protect_splits_area() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        memory_set
+            .insert_framed_area(VirtAddr(0x1000), VirtAddr(0x5000), perm)
+            .unwrap();
+        let middle = VPNRange::covering(VirtAddr(0x2000), VirtAddr(0x3000));
+        assert!(memory_set.protect(middle, MapPermission::R | MapPermission::U));
+        assert_eq!(memory_set.areas.len(), 3);
+        let writable = |va: usize| {
+            memory_set
+                .translate(VirtAddr(va).floor())
+                .unwrap()
+                .writable()
+        };
+        assert!(writable(0x1000));
+        assert!(!writable(0x2000));
+        assert!(writable(0x3000));
+        // 未映射的范围不能修改权限
+        let unmapped = VPNRange::covering(VirtAddr(0x4000), VirtAddr(0x6000));
+        assert!(!memory_set.protect(unmapped, MapPermission::R | MapPermission::U));
+    }
+
+    #[test_case]
+    fn memory_set_frames_are_released() {
+        let before = frame_remaining();
+        let mut memory_set = MemorySet::new_bare();
+        memory_set
+            .insert_framed_area(
+                VirtAddr(0x1000),
+                VirtAddr(0x9000),
+                MapPermission::R | MapPermission::U,
+            )
+            .unwrap();
+        drop(memory_set);
+        assert_eq!(frame_remaining(), before);
+    }
+
+    #[test_case]
+    fn usage_counts_data_and_page_table_frames() {
+        let before = frame_remaining();
+        let mut memory_set = MemorySet::new_bare();
+        let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+        let rx = MapPermission::R | MapPermission::X | MapPermission::U;
+        memory_set
+            .insert_framed_area(VirtAddr(0x1000), VirtAddr(0x4000), rx)
+            .unwrap();
+        memory_set
+            .insert_framed_area(VirtAddr(0x10000), VirtAddr(0x12000), rw)
+            .unwrap();
+        let usage = memory_set.usage();
+        assert_eq!(usage.size, 5);
+        assert_eq!(usage.text, 3);
+        assert_eq!(usage.data, 2);
+        // 根节点加上两级中间节点
+        assert_eq!(usage.page_table, 3);
+        assert_eq!(usage.resident, before - frame_remaining());
+    }
+
+    #[test_case]
+    fn shared_frame_is_freed_by_its_last_holder() {
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        let mut first = MemorySet::new_bare();
+        first
+            .insert_framed_area(VirtAddr(0x1000), VirtAddr(0x2000), perm)
+            .unwrap();
+        let frame = match &first.areas[&VirtPageNum(1)].map_type {
+            MapType::Framed { data_frames } => Arc::clone(&data_frames[&VirtPageNum(1)]),
+            MapType::Linear => unreachable!(),
+        };
+        let mut second = MemorySet::new_bare();
+        let mut area = MapArea::new(
+            VirtAddr(0x5000),
+            VirtAddr(0x6000),
+            MapType::Framed {
+                data_frames: BTreeMap::new(),
+            },
+            perm,
+        );
+        second.page_table.map(
+            VirtPageNum(5),
+            frame.ppn,
+            PTEFlags::from_bits_truncate(perm.bits),
+        );
+        if let MapType::Framed { data_frames } = &mut area.map_type {
+            data_frames.insert(VirtPageNum(5), frame);
+        }
+        second.insert_area(area);
+        assert_eq!(first.usage().shared, 1);
+        assert_eq!(second.usage().shared, 1);
+
+        let first_page_table = first.usage().page_table;
+        let before = frame_remaining();
+        drop(first);
+        // 页帧仍被 `second` 持有，只有 `first` 的页表被释放
+        assert_eq!(frame_remaining(), before + first_page_table);
+        assert_eq!(second.usage().shared, 0);
+        let second_page_table = second.usage().page_table;
+        drop(second);
+        assert_eq!(
+            frame_remaining(),
+            before + first_page_table + second_page_table + 1
+        );
+    }
+
+    #[test_case]
+    fn recycle_page_table_frees_all_frames() {
+        let before = frame_remaining();
+        let mut memory_set = MemorySet::new_bare();
+        memory_set
+            .insert_framed_area(
+                VirtAddr(0x1000),
+                VirtAddr(0x3000),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            )
+            .unwrap();
+        memory_set.recycle_page_table();
+        assert_eq!(memory_set.usage().page_table, 0);
+        assert_eq!(frame_remaining(), before);
+    }
+
+    #[test_case]
+    fn aslr_offsets_are_page_aligned() {
+        let offsets: Vec<_> = (0..4).map(|_| aslr_offset()).collect();
+        for &offset in &offsets {
+            assert_eq!(offset % PAGE_SIZE, 0);
+            assert!(offset < ASLR_MAX_PAGES * PAGE_SIZE);
+        }
+        if cmdline::aslr() {
+            assert!(offsets.iter().any(|&offset| offset != offsets[0]));
+        } else {
+            assert!(offsets.iter().all(|&offset| offset == 0));
+        }
+    }
+
+    #[test_case]
+    fn zeroed_area_allocates_on_first_write() {
+        let mut memory_set = MemorySet::new_bare();
+        memory_set
+            .insert_zeroed_area(
+                VirtAddr(0x1000),
+                VirtAddr(0x5000),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            )
+            .unwrap();
+        let pte =
+            |memory_set: &MemorySet, va: usize| memory_set.translate(VirtAddr(va).floor()).unwrap();
+        let data_frames = |memory_set: &MemorySet| {
+            let usage = memory_set.usage();
+            usage.resident - usage.page_table
+        };
+        assert_eq!(data_frames(&memory_set), 0);
+        assert_eq!(pte(&memory_set, 0x2000).ppn(), zero_ppn());
+        assert!(!pte(&memory_set, 0x2000).writable());
+
+        assert!(memory_set.fault_in_zero_page(VirtAddr(0x2000).floor()));
+        assert_eq!(data_frames(&memory_set), 1);
+        assert_ne!(pte(&memory_set, 0x2000).ppn(), zero_ppn());
+        assert!(pte(&memory_set, 0x2000).writable());
+        assert_eq!(pte(&memory_set, 0x3000).ppn(), zero_ppn());
+        // 已经分配过的页不再是零页
+        assert!(!memory_set.fault_in_zero_page(VirtAddr(0x2000).floor()));
+    }
+
+    #[test_case]
+    fn initial_stack_follows_sysv_layout() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        memory_set
+            .insert_framed_area(VirtAddr(0x1000), VirtAddr(0x3000), perm)
+            .unwrap();
+        let argv = [String::from("ls"), String::from("-l")];
+        let envp = [String::from("PATH=/bin")];
+        let sp = memory_set
+            .push_initial_stack(0x3000, &argv, &envp, &[(6, 4096)])
+            .unwrap();
+        assert_eq!(sp % 16, 0);
+        let mut word = |i: usize| *memory_set.user_u64(sp + i * 8).unwrap() as usize;
+        let words: Vec<usize> = (0..9).map(&mut word).collect();
+        assert_eq!(words[0], 2);
+        assert_eq!((words[3], words[5], words[6], words[7]), (0, 0, 6, 4096));
+        let string = |va: usize| {
+            let bytes = (va..)
+                .map(VirtAddr)
+                .map(|va| {
+                    let pte = memory_set.translate(va.floor()).unwrap();
+                    *pte.ppn().as_mut_at::<u8>(va.page_offset())
+                })
+                .take_while(|&b| b != 0)
+                .collect::<Vec<u8>>();
+            String::from_utf8(bytes).unwrap()
+        };
+        assert_eq!(string(words[1]), "ls");
+        assert_eq!(string(words[2]), "-l");
+        assert_eq!(string(words[4]), "PATH=/bin");
+        // 超过 ARG_MAX 时拒绝
+        let long = [String::from_utf8(alloc::vec![b'x'; ARG_MAX]).unwrap()];
+        assert!(matches!(
+            memory_set.push_initial_stack(0x3000, &long, &[], &[]),
+            Err(ElfError::ArgsTooLong)
+        ));
+    }
+
+    #[test_case]
+    fn free_range_is_found_below_mmap_base() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        memory_set.mmap_base = 0x1000_0000;
+        memory_set
+            .insert_framed_area(VirtAddr(0xfff_e000), VirtAddr(0x1000_0000), perm)
+            .unwrap();
+        // 从 mmap_base 向下，跳过已经映射的逻辑段
+        assert_eq!(memory_set.find_free_range(0x3000, 0), Some(0xfffb000));
+        // 可用的 hint 直接采用，与已有映射相交的 hint 被忽略
+        assert_eq!(
+            memory_set.find_free_range(0x1000, 0x100_0000),
+            Some(0x100_0000)
+        );
+        assert_eq!(
+            memory_set.find_free_range(0x1000, 0xfff_f000),
+            Some(0xfffd000)
+        );
+        // 大的映射按 2 MiB 对齐
+        assert_eq!(memory_set.find_free_range(0x20_0000, 0), Some(0xfc0_0000));
+        assert_eq!(memory_set.find_free_range(0x1000_0000, 0), None);
+    }
+
+    #[test_case]
+    fn huge_area_maps_contiguous_frames() {
+        let before = frame_remaining();
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        // 先用小页映射再解除，留下一个空的叶子页表
+        memory_set
+            .insert_framed_area(VirtAddr(0x20_0000), VirtAddr(0x20_1000), perm)
+            .unwrap();
+        let mut area = memory_set
+            .areas
+            .remove(&VirtAddr(0x20_0000).floor())
+            .unwrap();
+        area.unmap(&mut memory_set.page_table);
+        drop(area);
+
+        memory_set
+            .insert_huge_area(
+                VirtAddr(0x20_0000),
+                VirtAddr(0x60_0000),
+                perm,
+                PageSize::Size2M,
+            )
+            .unwrap();
+        let usage = memory_set.usage();
+        assert_eq!(
+            usage.resident - usage.page_table,
+            2 * PageSize::Size2M.pages()
+        );
+        let first = memory_set.translate(VirtAddr(0x20_0000).floor()).unwrap();
+        let last = memory_set.translate(VirtAddr(0x3f_f000).floor()).unwrap();
+        assert_eq!(last.ppn().0, first.ppn().0 + PageSize::Size2M.pages() - 1);
+        // 不能只修改大页的一部分
+        let half = VPNRange::covering(VirtAddr(0x20_0000), VirtAddr(0x30_0000));
+        assert!(!memory_set.protect(half, MapPermission::R | MapPermission::U));
+        let whole = VPNRange::covering(VirtAddr(0x20_0000), VirtAddr(0x40_0000));
+        assert!(memory_set.protect(whole, MapPermission::R | MapPermission::U));
+        assert!(!memory_set
+            .translate(VirtAddr(0x3f_f000).floor())
+            .unwrap()
+            .writable());
+        assert!(memory_set
+            .translate(VirtAddr(0x40_0000).floor())
+            .unwrap()
+            .writable());
+        drop(memory_set);
+        assert_eq!(frame_remaining(), before);
+    }
+
+    #[test_case]
+    fn audit_catches_wx_and_tampered_ptes() {
+        let mut memory_set = MemorySet::new_bare();
+        memory_set.map_trampoline();
+        let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+        let rwx = rw | MapPermission::X;
+        memory_set
+            .insert_zeroed_area(VirtAddr(0x1000), VirtAddr(0x3000), rw)
+            .unwrap();
+        if cmdline::wx() {
+            assert!(matches!(
+                memory_set.insert_framed_area(VirtAddr(0x4000), VirtAddr(0x5000), rwx),
+                Err(MapError::WriteExecute)
+            ));
+        }
+        // 零页去掉 W 是预期的
+        assert_eq!(memory_set.audit(), Ok(()));
+        // 绕过逻辑段直接把零页改成可写
+        let vpn = VirtAddr(0x2000).floor();
+        memory_set
+            .page_table
+            .set_flags(vpn, PTEFlags::R | PTEFlags::W | PTEFlags::U);
+        assert_eq!(memory_set.audit(), Err(MapError::BadPte));
+    }
+
+    #[test_case]
+    fn page_state_tells_lazy_from_resident() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        memory_set
+            .insert_zeroed_area(VirtAddr(0x1000), VirtAddr(0x3000), perm)
+            .unwrap();
+        let state = |memory_set: &MemorySet, va: usize| memory_set.page_state(VirtAddr(va).floor());
+        assert_eq!(state(&memory_set, 0x1000), Some(PageState::LAZY));
+        assert!(memory_set.fault_in_zero_page(VirtAddr(0x1000).floor()));
+        assert_eq!(state(&memory_set, 0x1000), Some(PageState::RESIDENT));
+        assert_eq!(state(&memory_set, 0x2000), Some(PageState::LAZY));
+        assert_eq!(state(&memory_set, 0x3000), None);
+    }
+
+    #[test_case]
+    fn sample_access_counts_and_clears_ad_bits() {
+        let mut memory_set = MemorySet::new_bare();
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        memory_set
+            .insert_zeroed_area(VirtAddr(0x1000), VirtAddr(0x4000), perm)
+            .unwrap();
+        for va in [0x1000, 0x2000] {
+            assert!(memory_set.fault_in_zero_page(VirtAddr(va).floor()));
+        }
+        // 内核经线性映射访问页帧，不会经过用户页表，所以手动置位 A 和 D
+        let ad = PTEFlags::A | PTEFlags::D;
+        let rw = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+        let set = |memory_set: &mut MemorySet, va: usize, flags| {
+            memory_set.page_table.set_flags(VirtAddr(va).floor(), flags)
+        };
+        set(&mut memory_set, 0x1000, rw | ad);
+        set(&mut memory_set, 0x2000, rw | PTEFlags::A);
+        // 零页上的访问不计入
+        set(
+            &mut memory_set,
+            0x3000,
+            PTEFlags::R | PTEFlags::U | PTEFlags::A,
+        );
+        memory_set.sample_access();
+        let ws = memory_set.working_set();
+        assert_eq!((ws.accessed, ws.dirty, ws.estimate), (2, 1, 2));
+        for va in [0x1000, 0x2000] {
+            let flags = memory_set.translate(VirtAddr(va).floor()).unwrap().flags();
+            assert!(!flags.intersects(ad));
+        }
+        memory_set.sample_access();
+        let ws = memory_set.working_set();
+        assert_eq!(
+            (ws.accessed, ws.dirty, ws.estimate, ws.samples),
+            (0, 0, 2, 2)
+        );
+    }
+
+    #[test_case]
+    fn file_area_writes_back_dirty_pages() {
+        let name = "ktest_file_area";
+        let inode = ROOT_INODE
+            .find(name)
+            .or_else(|| ROOT_INODE.create(name))
+            .unwrap();
+        inode.clear();
+        inode.write_at(0, b"hello");
+        let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+        let file = FileBacking {
+            inode: inode.clone(),
+            offset: 0,
+        };
+        let mut memory_set = MemorySet::new_bare();
+        memory_set
+            .insert_file_area(VirtAddr(0x1000), VirtAddr(0x3000), perm, file)
+            .unwrap();
+        let page = |memory_set: &MemorySet, va: usize| {
+            memory_set
+                .translate(VirtAddr(va).floor())
+                .unwrap()
+                .ppn()
+                .as_page_bytes_mut()
+        };
+        assert_eq!(&page(&memory_set, 0x1000)[..6], b"hello\0");
+        // 内核经线性映射写入，不会置位 D，所以手动置位
+        page(&memory_set, 0x1000)[..5].copy_from_slice(b"HELLO");
+        page(&memory_set, 0x2000)[0] = 1;
+        let dirty = PTEFlags::R | PTEFlags::W | PTEFlags::U | PTEFlags::D;
+        for va in [0x1000, 0x2000] {
+            memory_set.page_table.set_flags(VirtAddr(va).floor(), dirty);
+        }
+        let range = VPNRange::new(VirtPageNum(1), VirtPageNum(3));
+        assert_eq!(memory_set.write_back(range), 2);
+        assert_eq!(memory_set.write_back(range), 0);
+        // 文件末尾之后的部分不写回，文件不会变长
+        let mut buf = [0u8; 8];
+        assert_eq!(inode.read_at(0, &mut buf), 5);
+        assert_eq!(&buf[..5], b"HELLO");
+        drop(memory_set);
+        ROOT_INODE.unlink(name);
+    }
+
+    /// 只有 ELF 头的 64 位小端 ELF 文件，程序头表位于 `ph_offset` 处，共 `ph_count` 项
+    fn elf_header(ph_offset: u64, ph_count: u16) -> Vec<u8> {
+        let mut elf = vec![0u8; ELF_HEADER_SIZE];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        // ELFCLASS64、ELFDATA2LSB、EV_CURRENT
+        elf[4..7].copy_from_slice(&[2, 1, 1]);
+        elf[32..40].copy_from_slice(&ph_offset.to_le_bytes());
+        elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+        elf[56..58].copy_from_slice(&ph_count.to_le_bytes());
+        elf
+    }
+
+    #[test_case]
+    fn elf_headers_are_read_from_a_source() {
+        let data = b"abcdef".to_vec();
+        let mut buf = [0u8; 4];
+        assert_eq!(data.read_at(4, &mut buf), 2);
+        assert_eq!(&buf[..2], b"ef");
+        assert_eq!(data.read_at(8, &mut buf), 0);
+        assert_eq!(
+            read_elf_headers(&b"\x7fELF".to_vec()).err(),
+            Some(ElfError::NotElf)
+        );
+
+        // 程序头表紧跟在 ELF 头之后，需要再读一次
+        let mut elf = elf_header(ELF_HEADER_SIZE as u64, 2);
+        assert_eq!(
+            read_elf_headers(&elf).err(),
+            Some(ElfError::BadProgramHeader)
+        );
+        elf.resize(ELF_HEADER_SIZE + 2 * 56, 0xaa);
+        assert_eq!(read_elf_headers(&elf), Ok(elf.clone()));
+
+        // 程序头表超出 `ELF_HEADERS_MAX`，或者计算结束位置时溢出
+        for ph_offset in [ELF_HEADERS_MAX as u64, u64::MAX] {
+            let mut elf = elf_header(ph_offset, 1);
+            elf.resize(ELF_HEADERS_MAX + 56, 0);
+            assert_eq!(
+                read_elf_headers(&elf).err(),
+                Some(ElfError::BadProgramHeader)
+            );
+        }
+    }
 }
@@ -1,14 +1,81 @@
 pub mod address;
+pub mod asid;
 pub mod frame_allocator;
 pub mod heap_allocator;
 pub mod memory_set;
 pub mod page_table;
+pub mod user;
+pub mod wss;
 
-pub use self::memory_set::remap_test;
-use self::memory_set::KERNEL_SPACE;
+use alloc::vec;
 
+pub use self::frame_allocator::{reserve_region, ReserveError};
+pub use self::memory_set::{remap_test, trap_context_test};
+use self::{
+    address::{virt_to_phys, PhysPageNum},
+    memory_set::KERNEL_SPACE,
+};
+use crate::dtb;
+
+/// 登记设备树中的保留内存，初始化页帧分配器并启用内核地址空间。内核堆需要在解析设备树之前单独初始化
 pub fn init() {
-    heap_allocator::init_heap();
+    for region in dtb::reserved_regions() {
+        if let Err(err) = reserve_region(region.clone(), "device tree") {
+            log::warn!(
+                "[kernel] cannot reserve [{:#x}, {:#x}): {:?}",
+                region.start,
+                region.end,
+                err
+            );
+        }
+    }
     frame_allocator::init_frame_allocator();
-    KERNEL_SPACE.exclusive_access().activate();
+    print_memory_map();
+    KERNEL_SPACE.lock().activate();
+    asid::init();
+}
+
+/// 打印物理内存布局：内核映像、预留的区间，以及页帧分配器可以分配的区间
+fn print_memory_map() {
+    extern "C" {
+        fn skernel();
+        fn ekernel();
+    }
+    let pa = |ppn: PhysPageNum| ppn.page_start().0;
+    let mut regions = vec![(
+        virt_to_phys(skernel as usize),
+        virt_to_phys(ekernel as usize),
+        "kernel image",
+    )];
+    let managed = frame_allocator::managed_range();
+    let mut free_start = managed.start;
+    for reservation in frame_allocator::reservations() {
+        let range = reservation.range;
+        regions.push((pa(range.start), pa(range.end), reservation.reason));
+        let inside = range.intersection(&managed);
+        if inside.is_empty() {
+            continue;
+        }
+        if free_start < inside.start {
+            regions.push((pa(free_start), pa(inside.start), "available"));
+        }
+        free_start = free_start.max(inside.end);
+    }
+    if free_start < managed.end {
+        regions.push((pa(free_start), pa(managed.end), "available"));
+    }
+    regions.sort_unstable_by_key(|&(start, _, _)| start);
+    println!(
+        "[kernel] memory map of [{:#x}, {:#x}):",
+        dtb::memory_start(),
+        dtb::memory_end()
+    );
+    for (start, end, name) in regions {
+        println!("[kernel]   [{:#x}, {:#x}) {}", start, end, name);
+    }
+    println!(
+        "[kernel]   {} of {} frames available",
+        frame_allocator::frame_remaining(),
+        frame_allocator::frame_total()
+    );
 }
@@ -5,8 +5,9 @@ use riscv::register::satp;
 use super::{
     address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
     frame_allocator::{frame_alloc, FrameTracker},
+    user::{with_user_access, Access},
 };
-use crate::config::PAGE_SIZE;
+use crate::config::PTE_PER_PAGE;
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -21,6 +22,48 @@ bitflags! {
     }
 }
 
+/// 页的大小。Sv39 中第一、二级页表项也可以作为叶子，分别映射 1 GiB 和 2 MiB 的大页
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    /// 包含多少个 4 KiB 页
+    pub const fn pages(self) -> usize {
+        match self {
+            PageSize::Size4K => 1,
+            PageSize::Size2M => PTE_PER_PAGE,
+            PageSize::Size1G => PTE_PER_PAGE * PTE_PER_PAGE,
+        }
+    }
+    /// 叶子页表项所在的层级，根页表为第 0 级
+    const fn level(self) -> usize {
+        match self {
+            PageSize::Size4K => 2,
+            PageSize::Size2M => 1,
+            PageSize::Size1G => 0,
+        }
+    }
+    const fn from_level(level: usize) -> Self {
+        match level {
+            0 => PageSize::Size1G,
+            1 => PageSize::Size2M,
+            _ => PageSize::Size4K,
+        }
+    }
+    /// 从 `vpn` 开始映射且不超过 `end` 时，能使用的最大页。`vpn` 不能在 `end` 之后
+    pub fn largest_fit(vpn: VirtPageNum, end: VirtPageNum) -> Self {
+        [PageSize::Size1G, PageSize::Size2M]
+            .iter()
+            .copied()
+            .find(|&size| vpn.is_aligned(size) && end - vpn >= size.pages())
+            .unwrap_or(PageSize::Size4K)
+    }
+}
+
 #[derive(Clone)]
 pub struct PageTableEntry {
     pub bits: usize,
@@ -54,6 +97,22 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         self.flags() & PTEFlags::X != PTEFlags::empty()
     }
+    /// R/W/X 不全为 0 的有效页表项是叶子，否则指向下一级页表
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid()
+            && self.flags() & (PTEFlags::R | PTEFlags::W | PTEFlags::X) != PTEFlags::empty()
+    }
+}
+
+/// 按页表翻译地址失败的原因
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TranslateError {
+    /// 地址所在的页没有映射、不允许以所需的方式访问，或者地址没有对齐
+    BadAddress(usize),
+    /// 字符串在限定的长度内没有结束
+    TooLong,
+    /// 字符串不是有效的 UTF-8
+    InvalidUtf8,
 }
 
 /// 注意 `PageTable` 所拥有的的物理页仅用于存放页表节点数据。
@@ -72,9 +131,17 @@ impl PageTable {
             frames: vec![frame],
         }
     }
+    /// 页表节点占用的页帧数。手动查询用的页表不持有页帧，返回 0
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+    /// 释放页表节点占用的所有页帧，之后这个页表不能再使用
+    pub fn release_frames(&mut self) {
+        self.frames.clear();
+    }
     /// 创造一个专门用于手动查询的页表。
     ///
-    /// 在内核看来，ekernel 之后的所有地址都是 Identical 映射的
+    /// 内核经线性映射访问页表节点，不需要把它们映射到内核地址空间中
     pub fn from_satp(satp: usize) -> Self {
         const LOW_44_MASK: usize = (1 << 44) - 1;
         // RV64 中 `satp` 低 44 位是根页表的 PPN
@@ -90,28 +157,81 @@ impl PageTable {
 
     /// 将 vpn 映射到 ppn，且其标志位设为 flags | V
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        log::trace!("map vpn: {:#x} to ppn: {:#x}", vpn.0, ppn.0);
-        let pte = self.find_pte_create(vpn);
+        self.map_huge(vpn, ppn, PageSize::Size4K, flags);
+    }
+    /// 以大小为 `size` 的页将 vpn 映射到 ppn，二者都需要按 `size` 对齐
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        size: PageSize,
+        flags: PTEFlags,
+    ) {
+        log::trace!("map vpn: {:#x} to ppn: {:#x}, {:?}", vpn.0, ppn.0, size);
+        assert!(
+            vpn.is_aligned(size) && ppn.is_aligned(size),
+            "vpn {:#x} or ppn {:#x} is not aligned to {:?}",
+            vpn.0,
+            ppn.0,
+            size
+        );
+        let pte = self.find_pte_create(vpn, size.level());
+        // 这里曾经用小页映射过，而它们都已经解除映射时，回收空的下一级页表
+        if pte.is_valid() && !pte.is_leaf() {
+            let mut child = pte.ppn();
+            if child.as_page_ptes_mut().iter().all(|pte| !pte.is_valid()) {
+                *pte = PageTableEntry::empty();
+                self.frames.retain(|frame| frame.ppn != child);
+            }
+        }
         // 这个 pte 之前不能被映射过。
         assert!(!pte.is_valid(), "vpn {} is mapped before mapping", vpn.0);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V)
     }
-    /// 解除 vpn 的映射
-    pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte_create(vpn);
+    /// 解除 vpn 的映射，返回被解除映射的页的大小。vpn 位于大页中时，它必须是大页的起始页
+    pub fn unmap(&mut self, vpn: VirtPageNum) -> PageSize {
+        let (pte, size) = self
+            .find_leaf(vpn)
+            .unwrap_or_else(|| panic!("vpn {} is invalid before unmapping", vpn.0));
         // 这个 pte 之前必须被映射过。
         assert!(pte.is_valid(), "vpn {} is invalid before unmapping", vpn.0);
+        assert!(
+            vpn.is_aligned(size),
+            "vpn {:#x} is in the middle of a {:?} page",
+            vpn.0,
+            size
+        );
         *pte = PageTableEntry::empty();
+        size
+    }
+    /// 修改 vpn 的映射的标志位为 flags | V，物理页号不变
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let (pte, _) = self
+            .find_leaf(vpn)
+            .filter(|(pte, _)| pte.is_valid())
+            .unwrap_or_else(|| panic!("vpn {} is invalid before setting flags", vpn.0));
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
     }
-    /// 尝试寻找 vpn 对应的 pte。如果遇到未分配的页帧就会返回 None。
-    pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+    /// 清除 vpn 的映射中 `mask` 里的标志位（一般是 A 和 D 位），返回清除前这些位中置位的部分。
+    /// vpn 未映射时返回 `None`。
+    ///
+    /// 调用者需要随后刷新 TLB，否则缓存了该表项的 TLB 不会再次设置这些位
+    pub fn take_flags(&mut self, vpn: VirtPageNum, mask: PTEFlags) -> Option<PTEFlags> {
+        let (pte, _) = self.find_leaf(vpn).filter(|(pte, _)| pte.is_valid())?;
+        let old = pte.flags();
+        *pte = PageTableEntry::new(pte.ppn(), old - mask);
+        Some(old & mask)
+    }
+    /// 尝试寻找映射了 vpn 的叶子 pte 及其页大小。如果遇到未分配的页帧就会返回 None。
+    ///
+    /// 遇到大页时返回大页的 pte，否则返回最后一级的 pte，它不一定有效
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&'static mut PageTableEntry, PageSize)> {
         let idx = vpn.indexes();
         let mut ppn = self.root_ppn;
-        for i in 0..idx.len() {
-            let pte = &mut ppn.as_page_ptes_mut()[idx[i]];
-            // 找到叶 PTE 后，不着急设置为有效，交给调用者处理
-            if i == idx.len() - 1 {
-                return Some(pte);
+        for (i, &index) in idx.iter().enumerate() {
+            let pte = &mut ppn.as_page_ptes_mut()[index];
+            if i == idx.len() - 1 || pte.is_leaf() {
+                return Some((pte, PageSize::from_level(i)));
             }
             if !pte.is_valid() {
                 return None;
@@ -120,20 +240,21 @@ impl PageTable {
         }
         unreachable!()
     }
-    /// 尝试寻找 vpn 对应的 pte。如果查询过程中遇到了未分配的页帧就会自动创建。
+    /// 尝试寻找 vpn 在第 `level` 级页表中的 pte。如果查询过程中遇到了未分配的页帧就会自动创建。
     ///
     /// # Panics
     ///
-    /// 物理内存不足时会 panic
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> &'static mut PageTableEntry {
+    /// 物理内存不足，或者 vpn 已经位于某个大页中时会 panic
+    fn find_pte_create(&mut self, vpn: VirtPageNum, level: usize) -> &'static mut PageTableEntry {
         let idx = vpn.indexes();
         let mut ppn = self.root_ppn;
-        for i in 0..idx.len() {
-            let pte = &mut ppn.as_page_ptes_mut()[idx[i]];
+        for (i, &index) in idx.iter().enumerate() {
+            let pte = &mut ppn.as_page_ptes_mut()[index];
             // 找到叶 PTE 后，不着急设置为有效，交给调用者处理
-            if i == idx.len() - 1 {
+            if i == level {
                 return pte;
             }
+            assert!(!pte.is_leaf(), "vpn {:#x} is mapped by a huge page", vpn.0);
             if !pte.is_valid() {
                 let frame = frame_alloc().expect("Physical Memory should be enough");
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
@@ -143,62 +264,56 @@ impl PageTable {
         }
         unreachable!()
     }
-    /// 采用 `find_pte` 的实现，查页表失败就会返回 None
+    /// 查页表失败就会返回 None。
+    ///
+    /// vpn 位于大页中时，返回的 pte 指向大页中与 vpn 对应的那个 4 KiB 物理页
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(Clone::clone)
+        self.find_leaf(vpn).map(|(pte, size)| {
+            PageTableEntry::new(pte.ppn() + (vpn - vpn.align_down(size)), pte.flags())
+        })
     }
     pub fn translate_va_to_pa(&mut self, va: VirtAddr) -> PhysAddr {
-        PhysAddr(self.find_pte(va.vpn()).unwrap().ppn().page_start().0 + va.page_offset())
-    }
-    pub fn translate_va_as<T>(&mut self, va: VirtAddr) -> &'static mut T {
-        self.find_pte(va.vpn())
-            .unwrap()
-            .ppn()
-            .as_mut_at(va.page_offset())
-    }
-    pub fn translated_mut<T>(satp: usize, ptr: *mut T) -> &'static mut T {
-        let mut page_table = PageTable::from_satp(satp);
-        let va = VirtAddr(ptr as usize);
-        page_table.translate_va_as(va)
-    }
-    pub fn translated_str(satp: usize, ptr: *const u8) -> String {
-        let mut page_table = PageTable::from_satp(satp);
-        let mut bytes = Vec::new();
-        let mut va = ptr as usize;
-        // 内核不知道用户地址空间中字符串的长度，而且字符串可能跨页，所以逐字节查页表，直到为 `\0`
-        loop {
-            let byte: u8 = *(page_table.translate_va_as(VirtAddr(va)));
-            if byte == 0 {
-                break;
-            } else {
-                bytes.push(byte);
-                va += 1;
-            }
-        }
-        String::from_utf8(bytes).unwrap()
-    }
-}
-
-pub fn translated_byte_buffer(satp: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_satp(satp);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::with_capacity(len / PAGE_SIZE + 2);
-    while start < end {
-        let start_va = VirtAddr(start);
-        let mut vpn = start_va.floor();
-        let mut ppn = page_table.translate(vpn).unwrap().ppn();
-        vpn.0 += 1;
-        let mut end_va = vpn.page_start();
-        end_va = end_va.min(VirtAddr(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.as_page_bytes_mut()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.as_page_bytes_mut()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.0;
+        PhysAddr(self.translate(va.vpn()).unwrap().ppn().page_start().0 + va.page_offset())
+    }
+    pub fn translate_va_as<T>(&mut self, va: VirtAddr) -> Result<&'static mut T, TranslateError> {
+        let pte = self
+            .translate(va.vpn())
+            .filter(|pte| pte.is_valid())
+            .ok_or(TranslateError::BadAddress(va.0))?;
+        Ok(pte.ppn().as_mut_at(va.page_offset()))
+    }
+    /// 取得用户地址 `ptr` 处的 `T` 以便写入，它所在的页必须用户可写
+    pub fn translated_mut<T>(satp: usize, ptr: *mut T) -> Result<&'static mut T, TranslateError> {
+        with_user_access(satp, |user| user.as_mut(ptr, Access::Write))
+            .ok_or(TranslateError::BadAddress(ptr as usize))
+    }
+    /// 取得用户地址 `ptr` 处的 `T` 以便读取，它所在的页必须用户可读
+    pub fn translated_ref<T>(satp: usize, ptr: *const T) -> Result<&'static T, TranslateError> {
+        with_user_access(satp, |user| user.as_mut(ptr as *mut T, Access::Read))
+            .map(|value| value as &T)
+            .ok_or(TranslateError::BadAddress(ptr as usize))
+    }
+    /// 读取用户地址 `ptr` 处以 `\0` 结尾的字符串，包括 `\0` 在内最多 `max_len` 字节
+    pub fn translated_str(
+        satp: usize,
+        ptr: *const u8,
+        max_len: usize,
+    ) -> Result<String, TranslateError> {
+        with_user_access(satp, |user| user.read_str(ptr, max_len))
     }
-    v
+}
+
+/// 把用户缓冲区翻译为若干段内核可以直接访问的切片，见 [`UserAccess::buffer`]
+///
+/// [`UserAccess::buffer`]: super::user::UserAccess::buffer
+pub fn translated_byte_buffer(
+    satp: usize,
+    ptr: *const u8,
+    len: usize,
+    access: Access,
+) -> Result<Vec<&'static mut [u8]>, TranslateError> {
+    with_user_access(satp, |user| user.buffer(ptr as usize, len, access))
+        .ok_or(TranslateError::BadAddress(ptr as usize))
 }
 
 pub struct UserBuffer {
@@ -214,6 +329,21 @@ impl UserBuffer {
     }
 }
 
+/// 测试用：把 `bytes` 复制到泄漏的内存中，当作用户缓冲区
+#[cfg(test)]
+pub fn buffer(bytes: &[u8]) -> UserBuffer {
+    let bytes = alloc::boxed::Box::leak(bytes.to_vec().into_boxed_slice());
+    UserBuffer::new(vec![bytes])
+}
+
+/// 测试用：`len` 字节的空缓冲区，以及读入之后查看内容用的起始地址
+#[cfg(test)]
+pub fn output_buffer(len: usize) -> (UserBuffer, *const u8) {
+    let bytes = alloc::boxed::Box::leak(vec![0u8; len].into_boxed_slice());
+    let ptr = bytes.as_ptr();
+    (UserBuffer::new(vec![bytes]), ptr)
+}
+
 impl IntoIterator for UserBuffer {
     type IntoIter = IntoIter;
     type Item = *mut u8;
@@ -249,3 +379,70 @@ impl Iterator for IntoIter {
         Some(ret as *mut u8)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::frame_allocator::frame_remaining;
+
+    fn is_mapped(page_table: &PageTable, vpn: VirtPageNum) -> bool {
+        page_table
+            .translate(vpn)
+            .map_or(false, |pte| pte.is_valid())
+    }
+
+    #[test_case]
+    fn map_translate_unmap() {
+        let mut page_table = PageTable::new();
+        let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+        page_table.map(VirtPageNum(0x10), PhysPageNum(0x80400), flags);
+        let pte = page_table.translate(VirtPageNum(0x10)).unwrap();
+        assert_eq!(pte.ppn(), PhysPageNum(0x80400));
+        assert_eq!(pte.flags(), flags | PTEFlags::V);
+        assert!(!is_mapped(&page_table, VirtPageNum(0x11)));
+        page_table.set_flags(VirtPageNum(0x10), PTEFlags::R | PTEFlags::U);
+        assert!(!page_table.translate(VirtPageNum(0x10)).unwrap().writable());
+        assert_eq!(page_table.unmap(VirtPageNum(0x10)), PageSize::Size4K);
+        assert!(!is_mapped(&page_table, VirtPageNum(0x10)));
+    }
+
+    #[test_case]
+    fn huge_page_translates_every_4k_page() {
+        let mut page_table = PageTable::new();
+        let pages = PageSize::Size2M.pages();
+        page_table.map_huge(
+            VirtPageNum(pages),
+            PhysPageNum(0x80400),
+            PageSize::Size2M,
+            PTEFlags::R | PTEFlags::W,
+        );
+        for offset in [0, 1, pages - 1] {
+            let pte = page_table.translate(VirtPageNum(pages + offset)).unwrap();
+            assert_eq!(pte.ppn(), PhysPageNum(0x80400 + offset));
+        }
+        assert!(!is_mapped(&page_table, VirtPageNum(2 * pages)));
+        assert_eq!(page_table.unmap(VirtPageNum(pages)), PageSize::Size2M);
+        assert!(!is_mapped(&page_table, VirtPageNum(pages + 1)));
+    }
+
+    #[test_case]
+    fn translating_an_unmapped_address_is_an_error() {
+        let mut page_table = PageTable::new();
+        page_table.map(VirtPageNum(0x10), PhysPageNum(0x80400), PTEFlags::R);
+        assert!(page_table.translate_va_as::<u8>(VirtAddr(0x10008)).is_ok());
+        assert_eq!(
+            page_table.translate_va_as::<u8>(VirtAddr(0x11008)).err(),
+            Some(TranslateError::BadAddress(0x11008))
+        );
+    }
+
+    #[test_case]
+    fn page_table_frames_are_released() {
+        let before = frame_remaining();
+        let mut page_table = PageTable::new();
+        page_table.map(VirtPageNum(0x12345), PhysPageNum(0x80400), PTEFlags::R);
+        assert!(frame_remaining() < before);
+        drop(page_table);
+        assert_eq!(frame_remaining(), before);
+    }
+}
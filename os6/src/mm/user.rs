@@ -0,0 +1,214 @@
+//! 内核访问用户内存的窗口。
+//!
+//! 内核不通过用户虚拟地址访问用户内存，而是查用户页表找到页帧，再经线性映射访问物理页帧。
+//! 所以一个错误的用户指针有可能被翻译到内核自己的数据上，比如没有 U 标志的 `TRAP_CONTEXT`。
+//! 这里的访问器都会先检查页表项：页必须有效、用户可访问，并具有所需的读写权限，否则拒绝访问。
+//!
+//! 所有访问都在 [`with_user_access`] 划定的范围内进行。范围内打开 `sstatus.SUM`，
+//! 范围外内核始终保持它关闭，这样日后改为直接用用户虚拟地址访问时，范围外的访问会立即出错，
+//! 而不是悄悄成功。
+
+use alloc::{string::String, vec::Vec};
+use core::mem::{size_of, MaybeUninit};
+use riscv::register::sstatus;
+
+use super::{
+    address::{PhysPageNum, VirtAddr},
+    memory_set,
+    page_table::{PTEFlags, PageTable, TranslateError},
+};
+use crate::config::PAGE_SIZE;
+
+/// 内核访问用户内存的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// 只读取用户内存，要求页可读
+    Read,
+    /// 会写入用户内存，要求页可读可写
+    Write,
+}
+
+impl Access {
+    fn required_flags(self) -> PTEFlags {
+        match self {
+            Access::Read => PTEFlags::V | PTEFlags::U | PTEFlags::R,
+            Access::Write => PTEFlags::V | PTEFlags::U | PTEFlags::R | PTEFlags::W,
+        }
+    }
+}
+
+/// 在 [`with_user_access`] 范围内访问某个用户地址空间的句柄
+pub struct UserAccess {
+    page_table: PageTable,
+}
+
+/// 打开访问 `satp` 所指用户地址空间的窗口并执行 `f`。窗口可以嵌套，退出时恢复原来的 `sstatus.SUM`
+pub fn with_user_access<R>(satp: usize, f: impl FnOnce(&UserAccess) -> R) -> R {
+    let was_open = sstatus::read().sum();
+    unsafe { sstatus::set_sum() };
+    let ret = f(&UserAccess {
+        page_table: PageTable::from_satp(satp),
+    });
+    if !was_open {
+        unsafe { sstatus::clear_sum() };
+    }
+    ret
+}
+
+/// 当前是否处于用户内存访问窗口中
+pub fn in_user_access() -> bool {
+    sstatus::read().sum()
+}
+
+impl UserAccess {
+    /// 检查 `va` 所在的页能否以 `access` 方式访问，返回其页帧。
+    ///
+    /// 写入映射到共享零页的页时，先为当前任务分配私有的页帧
+    pub fn translate(&self, va: VirtAddr, access: Access) -> Option<PhysPageNum> {
+        let vpn = va.floor();
+        let mut pte = self.page_table.translate(vpn)?;
+        if access == Access::Write && pte.ppn() == memory_set::zero_ppn() {
+            crate::task::fault_in_zero_page(va.0);
+            pte = self.page_table.translate(vpn)?;
+        }
+        if !pte.flags().contains(access.required_flags()) {
+            return None;
+        }
+        Some(pte.ppn())
+    }
+    /// 把 `[ptr, ptr + len)` 翻译为若干段内核可以直接访问的切片，每段不跨页。
+    /// 其中任何一页不能以 `access` 方式访问时返回 `None`
+    pub fn buffer(&self, ptr: usize, len: usize, access: Access) -> Option<Vec<&'static mut [u8]>> {
+        let end = ptr.checked_add(len)?;
+        let mut start = ptr;
+        let mut buffers = Vec::with_capacity(len / PAGE_SIZE + 2);
+        while start < end {
+            let start_va = VirtAddr(start);
+            let mut ppn = self.translate(start_va, access)?;
+            let page_end = start_va.floor().page_start().0 + PAGE_SIZE;
+            let chunk_end = page_end.min(end);
+            let offset = start_va.page_offset();
+            buffers.push(&mut ppn.as_page_bytes_mut()[offset..offset + (chunk_end - start)]);
+            start = chunk_end;
+        }
+        Some(buffers)
+    }
+    /// 取得用户地址 `ptr` 处的 `T` 的引用。`ptr` 必须对齐，且 `T` 不能跨页
+    pub fn as_mut<T>(&self, ptr: *mut T, access: Access) -> Option<&'static mut T> {
+        let va = VirtAddr(ptr as usize);
+        if va.0 % core::mem::align_of::<T>() != 0 || va.page_offset() + size_of::<T>() > PAGE_SIZE {
+            return None;
+        }
+        let mut ppn = self.translate(va, access)?;
+        Some(ppn.as_mut_at(va.page_offset()))
+    }
+    /// 复制用户地址 `ptr` 处的 `T`，可以跨页
+    pub fn read<T: Copy>(&self, ptr: *const T) -> Option<T> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let mut dst = value.as_mut_ptr() as *mut u8;
+        for buf in self.buffer(ptr as usize, size_of::<T>(), Access::Read)? {
+            unsafe {
+                core::ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len());
+                dst = dst.add(buf.len());
+            }
+        }
+        Some(unsafe { value.assume_init() })
+    }
+    /// 把 `value` 写到用户地址 `ptr` 处，可以跨页
+    pub fn write<T: Copy>(&self, ptr: *mut T, value: T) -> Option<()> {
+        let mut src = &value as *const T as *const u8;
+        for buf in self.buffer(ptr as usize, size_of::<T>(), Access::Write)? {
+            unsafe {
+                core::ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len());
+                src = src.add(buf.len());
+            }
+        }
+        Some(())
+    }
+    /// 读取用户地址 `ptr` 处以 `\0` 结尾的字符串，包括 `\0` 在内最多 `max_len` 字节。
+    /// 遇到不可读的页、`max_len` 字节内没有 `\0`，或者内容不是 UTF-8 时返回错误
+    pub fn read_str(&self, ptr: *const u8, max_len: usize) -> Result<String, TranslateError> {
+        let mut bytes = Vec::new();
+        let mut va = ptr as usize;
+        // 内核不知道字符串的长度，而且字符串可能跨页，所以逐页查页表，直到遇到 `\0`
+        loop {
+            let ppn = self
+                .translate(VirtAddr(va), Access::Read)
+                .ok_or(TranslateError::BadAddress(va))?;
+            let page = &ppn.as_page_bytes()[VirtAddr(va).page_offset()..];
+            let page = &page[..page.len().min(max_len - bytes.len())];
+            match page.iter().position(|&byte| byte == 0) {
+                Some(len) => {
+                    bytes.extend_from_slice(&page[..len]);
+                    break;
+                }
+                None => {
+                    bytes.extend_from_slice(page);
+                    if bytes.len() == max_len {
+                        return Err(TranslateError::TooLong);
+                    }
+                    va += page.len();
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| TranslateError::InvalidUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::{address::VirtPageNum, frame_allocator::frame_alloc};
+
+    #[test_case]
+    fn rejects_pages_without_user_permission() {
+        let mut page_table = PageTable::new();
+        let user_frame = frame_alloc().unwrap();
+        let kernel_frame = frame_alloc().unwrap();
+        let ro_frame = frame_alloc().unwrap();
+        page_table.map(
+            VirtPageNum(0x10),
+            user_frame.ppn,
+            PTEFlags::R | PTEFlags::W | PTEFlags::U,
+        );
+        page_table.map(
+            VirtPageNum(0x11),
+            kernel_frame.ppn,
+            PTEFlags::R | PTEFlags::W,
+        );
+        page_table.map(VirtPageNum(0x12), ro_frame.ppn, PTEFlags::R | PTEFlags::U);
+        with_user_access(page_table.satp(), |user| {
+            assert!(in_user_access());
+            let base = VirtPageNum(0x10).page_start().0;
+            assert!(user.write(base as *mut u64, 42).is_some());
+            assert_eq!(user.read(base as *const u64), Some(42));
+            // 跨入没有 U 标志的页
+            assert!(user.read((base + PAGE_SIZE - 4) as *const u64).is_none());
+            assert!(user.buffer(base, PAGE_SIZE + 1, Access::Read).is_none());
+            // 只读页可以读，不能写
+            let ro = base + 2 * PAGE_SIZE;
+            assert!(user.read(ro as *const u64).is_some());
+            assert!(user.write(ro as *mut u64, 1).is_none());
+            // 未映射的页
+            let unmapped = base + 3 * PAGE_SIZE;
+            assert_eq!(
+                user.read_str(unmapped as *const u8, 16),
+                Err(TranslateError::BadAddress(unmapped))
+            );
+            // 字符串的长度包括结尾的 `\0`
+            let s = (base + 8) as *mut [u8; 4];
+            assert!(user.write(s, *b"abc\0").is_some());
+            assert_eq!(user.read_str(s as *const u8, 4).as_deref(), Ok("abc"));
+            assert_eq!(
+                user.read_str(s as *const u8, 3),
+                Err(TranslateError::TooLong)
+            );
+            assert!(user.write(s, [b'a', 0xff, 0, 0]).is_some());
+            assert_eq!(
+                user.read_str(s as *const u8, 4),
+                Err(TranslateError::InvalidUtf8)
+            );
+        });
+        assert!(!in_user_access());
+    }
+}
@@ -0,0 +1,60 @@
+//! 工作集估计
+//!
+//! 处理器访问一页时置位其页表项的 A 位，写入时还会置位 D 位。后台的 wss 内核线程
+//! 每隔 `WSS_INTERVAL_US` 微秒扫描一遍所有进程的用户页，统计上一个间隔内被访问和被写入的页数，
+//! 然后清除这两位并刷新 TLB，开始下一个间隔。工作集大小取各次访问页数的指数加权平均，
+//! 结果可以从 `/proc/<pid>/wss` 读出。
+//!
+//! 映射到共享零页的页不占用页帧，不计入统计。内核目前没有交换区，也不会回收用户页，
+//! 日后实现换出时应优先换出工作集之外、最近没有被访问的页。
+
+use crate::{task, timer::MICRO_PER_SEC};
+
+/// 两次采样之间的时间，即 1 秒
+pub const WSS_INTERVAL_US: usize = MICRO_PER_SEC;
+
+/// 一个地址空间的工作集统计，单位均为页
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkingSet {
+    /// 工作集大小的估计，即各次采样的访问页数的指数加权平均，新样本的权重为 1/4
+    pub estimate: usize,
+    /// 最近一个间隔内被访问的页数
+    pub accessed: usize,
+    /// 最近一个间隔内被写入的页数，即每个间隔的脏页速率
+    pub dirty: usize,
+    /// 已经采样的次数
+    pub samples: usize,
+}
+
+impl WorkingSet {
+    /// 记录一次采样的结果
+    pub fn record(&mut self, accessed: usize, dirty: usize) {
+        self.estimate = if self.samples == 0 {
+            accessed
+        } else {
+            (self.estimate * 3 + accessed + 2) / 4
+        };
+        self.accessed = accessed;
+        self.dirty = dirty;
+        self.samples += 1;
+    }
+}
+
+/// 启动 wss 线程
+pub fn init() {
+    task::kthread_spawn(wss_main);
+}
+
+/// 对所有进程的地址空间采样一次
+pub fn sample_all() {
+    for task in task::all_tasks() {
+        task.inner_exclusive_access().memory_set.sample_access();
+    }
+}
+
+fn wss_main() {
+    loop {
+        task::sleep_us(WSS_INTERVAL_US);
+        sample_all();
+    }
+}
@@ -0,0 +1,214 @@
+//! 网络协议栈
+//!
+//! 只实现了 UDP 通信所需的最小部分：ARP 地址解析、不分片的 IPv4、ICMP 回显（可以被 ping）和 UDP。
+//! TCP 只能在本机的进程之间使用，不经过协议栈，见 [`tcp`]；UNIX 域套接字见 [`unix`]。
+//! 本机地址和网关固定为 QEMU 用户模式网络的默认配置，见 `config` 中的 `NET_*`。
+//! 发往本机地址或 127.0.0.0/8 的报文不经过网卡，直接交给接收的一方，没有网卡时也可以使用。
+//!
+//! 网卡收到帧时触发外部中断，由中断处理函数 [`poll`] 取走收到的帧，逐层解析后交给套接字。
+//! 用户态的时钟中断和 idle 控制流也会调用 `poll`，中断丢失时收到的帧也不会一直滞留在网卡中。
+//!
+//! 系统调用执行期间只响应时钟中断，它的处理函数不访问协议栈（见 [`crate::task::preempt`]），
+//! 外部中断只在用户态进入 trap 或者由 idle 控制流处理。所以协议栈的状态只会在系统调用和 `poll`
+//! 中被访问，不会重入。
+
+pub mod packet;
+pub mod socket;
+pub mod stream;
+pub mod tcp;
+pub mod udp;
+pub mod unix;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use lazy_static::*;
+
+use self::{
+    packet::{
+        ArpPacket, EthernetFrame, Ipv4Addr, Ipv4Packet, MacAddr, UdpDatagram, ARP_REPLY,
+        ARP_REQUEST, BROADCAST_MAC, ETHERTYPE_ARP, ETHERTYPE_IPV4, IP_PROTO_ICMP, IP_PROTO_UDP,
+    },
+    socket::{SocketAddr, SocketError},
+};
+use crate::{
+    config::{NET_GATEWAY, NET_LOCAL_IP, NET_PREFIX_LEN},
+    drivers::{net::NetDevice, net_device},
+    sync::UPSafeCell,
+};
+
+/// 以太网的 MTU，即一个帧最多承载的 IPv4 报文长度
+pub const MTU: usize = 1500;
+/// 网卡一次收到的最大帧：MTU 加上以太网首部
+const MAX_FRAME_LEN: usize = MTU + 14;
+/// 等待 ARP 应答的报文最多保留多少个，再多时丢弃最早的
+const MAX_UNRESOLVED: usize = 16;
+/// 本机回环地址
+const LOOPBACK_IP: Ipv4Addr = [127, 0, 0, 1];
+
+struct Interface {
+    /// IPv4 地址到 MAC 地址的映射，从收到的 ARP 报文中学习
+    arp_cache: BTreeMap<Ipv4Addr, MacAddr>,
+    /// 等待 ARP 应答的 IPv4 报文及其下一跳的地址
+    unresolved: Vec<(Ipv4Addr, Vec<u8>)>,
+}
+
+lazy_static! {
+    static ref INTERFACE: UPSafeCell<Interface> = unsafe {
+        UPSafeCell::new(Interface {
+            arp_cache: BTreeMap::new(),
+            unresolved: Vec::new(),
+        })
+    };
+}
+
+/// `ip` 是否是本机的地址
+pub fn is_local(ip: Ipv4Addr) -> bool {
+    ip == NET_LOCAL_IP || ip[0] == 127
+}
+
+/// 发往 `dst` 的报文使用的源地址
+pub fn source_ip(dst: Ipv4Addr) -> Ipv4Addr {
+    if dst[0] == 127 {
+        LOOPBACK_IP
+    } else {
+        NET_LOCAL_IP
+    }
+}
+
+/// 下一跳：同一网段的地址直接发送，其它地址经网关转发
+fn next_hop(dst: Ipv4Addr) -> Ipv4Addr {
+    let mask = u32::MAX << (32 - NET_PREFIX_LEN);
+    let same_subnet = u32::from_be_bytes(dst) & mask == u32::from_be_bytes(NET_LOCAL_IP) & mask;
+    if same_subnet {
+        dst
+    } else {
+        NET_GATEWAY
+    }
+}
+
+/// 处理网卡收到的所有帧
+pub fn poll() {
+    let device = match net_device() {
+        Some(device) => device,
+        None => return,
+    };
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    while let Some(len) = device.recv(&mut frame) {
+        handle_frame(device.as_ref(), &frame[..len]);
+    }
+}
+
+fn handle_frame(device: &dyn NetDevice, frame: &[u8]) {
+    let frame = match EthernetFrame::parse(frame) {
+        Some(frame) => frame,
+        None => return,
+    };
+    match frame.ethertype {
+        ETHERTYPE_ARP => handle_arp(device, frame.payload),
+        ETHERTYPE_IPV4 => handle_ipv4(frame.payload),
+        _ => {}
+    }
+}
+
+/// 记录发送方的地址并发出等待它的报文，再回答询问本机地址的请求
+fn handle_arp(device: &dyn NetDevice, packet: &[u8]) {
+    let arp = match ArpPacket::parse(packet) {
+        Some(arp) => arp,
+        None => return,
+    };
+    let mac = device.mac();
+    let ready: Vec<_> = {
+        let mut interface = INTERFACE.exclusive_access();
+        interface.arp_cache.insert(arp.sender_ip, arp.sender_mac);
+        let (ready, waiting) = core::mem::take(&mut interface.unresolved)
+            .into_iter()
+            .partition(|(hop, _)| *hop == arp.sender_ip);
+        interface.unresolved = waiting;
+        ready
+    };
+    for (_, packet) in ready {
+        device.send(&EthernetFrame::build(
+            arp.sender_mac,
+            mac,
+            ETHERTYPE_IPV4,
+            &packet,
+        ));
+    }
+    if arp.op == ARP_REQUEST && arp.target_ip == NET_LOCAL_IP {
+        let reply = ArpPacket {
+            op: ARP_REPLY,
+            sender_mac: mac,
+            sender_ip: NET_LOCAL_IP,
+            target_mac: arp.sender_mac,
+            target_ip: arp.sender_ip,
+        };
+        device.send(&EthernetFrame::build(
+            arp.sender_mac,
+            mac,
+            ETHERTYPE_ARP,
+            &reply.build(),
+        ));
+    }
+}
+
+fn handle_ipv4(packet: &[u8]) {
+    let ip = match Ipv4Packet::parse(packet) {
+        Some(ip) if is_local(ip.dst) => ip,
+        _ => return,
+    };
+    match ip.protocol {
+        IP_PROTO_ICMP => {
+            if let Some(reply) = packet::icmp_echo_reply(ip.payload) {
+                // 应答失败时对方会重试，不需要处理
+                let _ = send_ipv4(ip.src, IP_PROTO_ICMP, &reply);
+            }
+        }
+        IP_PROTO_UDP => {
+            if let Some(datagram) = UdpDatagram::parse(ip.src, ip.dst, ip.payload) {
+                let src = SocketAddr::new(ip.src, datagram.src_port);
+                udp::deliver(src, datagram.dst_port, datagram.payload);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 向 `dst` 发送协议为 `protocol` 的 IPv4 报文。
+///
+/// 发往本机的报文直接交给接收方。下一跳的 MAC 地址未知时先广播 ARP 请求，报文等到应答后再发出；
+/// 一直没有应答的报文最终会被丢弃，和网络上的丢包一样
+pub fn send_ipv4(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), SocketError> {
+    let packet = Ipv4Packet::build(source_ip(dst), dst, protocol, payload);
+    if is_local(dst) {
+        handle_ipv4(&packet);
+        return Ok(());
+    }
+    let device = net_device().ok_or(SocketError::NetworkDown)?;
+    let mac = device.mac();
+    let hop = next_hop(dst);
+    let hop_mac = {
+        let mut interface = INTERFACE.exclusive_access();
+        let hop_mac = interface.arp_cache.get(&hop).copied();
+        if hop_mac.is_none() {
+            if interface.unresolved.len() >= MAX_UNRESOLVED {
+                interface.unresolved.remove(0);
+            }
+            interface.unresolved.push((hop, packet.clone()));
+        }
+        hop_mac
+    };
+    let frame = match hop_mac {
+        Some(hop_mac) => EthernetFrame::build(hop_mac, mac, ETHERTYPE_IPV4, &packet),
+        None => {
+            let request = ArpPacket {
+                op: ARP_REQUEST,
+                sender_mac: mac,
+                sender_ip: NET_LOCAL_IP,
+                target_mac: [0; 6],
+                target_ip: hop,
+            };
+            EthernetFrame::build(BROADCAST_MAC, mac, ETHERTYPE_ARP, &request.build())
+        }
+    };
+    device.send(&frame);
+    Ok(())
+}
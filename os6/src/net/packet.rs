@@ -0,0 +1,282 @@
+//! 以太网、ARP、IPv4、ICMP 和 UDP 报文的解析与构造
+//!
+//! 报文中的多字节字段都是网络字节序，即大端序。解析函数遇到长度不足、校验和错误
+//! 或者不支持的格式时返回 `None`，调用者直接丢弃这样的报文。
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+pub type MacAddr = [u8; 6];
+pub type Ipv4Addr = [u8; 4];
+
+pub const BROADCAST_MAC: MacAddr = [0xff; 6];
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+const ETH_HEADER_LEN: usize = 14;
+
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_UDP: u8 = 17;
+const IPV4_HEADER_LEN: usize = 20;
+/// 发出的 IPv4 报文的生存时间
+const IPV4_TTL: u8 = 64;
+/// IPv4 首部中的 DF 标志：不允许分片。协议栈不支持分片，收到的分片也会被丢弃
+const IPV4_DONT_FRAGMENT: u16 = 0x4000;
+
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+const ARP_LEN: usize = 28;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+pub const UDP_HEADER_LEN: usize = 8;
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn ipv4_addr(bytes: &[u8]) -> Ipv4Addr {
+    [bytes[0], bytes[1], bytes[2], bytes[3]]
+}
+
+/// 按 16 位大端整数累加，奇数长度时末尾补零
+fn sum16(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|chunk| match *chunk {
+            [hi, lo] => u16::from_be_bytes([hi, lo]) as u32,
+            [hi] => (hi as u32) << 8,
+            _ => unreachable!(),
+        })
+        .sum()
+}
+
+/// 由累加和得到互联网校验和：折叠进位后取反
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// `data` 的互联网校验和。包含校验和字段的数据校验正确时结果为 0
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(sum16(data))
+}
+
+/// UDP 校验和，覆盖伪首部、UDP 首部和数据
+fn udp_checksum(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) -> u16 {
+    let pseudo = sum16(&src) + sum16(&dst) + IP_PROTO_UDP as u32 + segment.len() as u32;
+    fold(pseudo + sum16(segment))
+}
+
+pub struct EthernetFrame<'a> {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        if frame.len() < ETH_HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            dst: frame[0..6].try_into().unwrap(),
+            src: frame[6..12].try_into().unwrap(),
+            ethertype: be16(&frame[12..14]),
+            payload: &frame[ETH_HEADER_LEN..],
+        })
+    }
+    pub fn build(dst: MacAddr, src: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(ETH_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+}
+
+/// 以太网上用于 IPv4 的 ARP 报文
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        // 硬件类型为以太网（1），协议类型为 IPv4，地址长度分别为 6 和 4
+        if packet.len() < ARP_LEN || packet[0..6] != [0, 1, 0x08, 0x00, 6, 4] {
+            return None;
+        }
+        Some(Self {
+            op: be16(&packet[6..8]),
+            sender_mac: packet[8..14].try_into().unwrap(),
+            sender_ip: ipv4_addr(&packet[14..18]),
+            target_mac: packet[18..24].try_into().unwrap(),
+            target_ip: ipv4_addr(&packet[24..28]),
+        })
+    }
+    pub fn build(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(ARP_LEN);
+        packet.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4]);
+        packet.extend_from_slice(&self.op.to_be_bytes());
+        packet.extend_from_slice(&self.sender_mac);
+        packet.extend_from_slice(&self.sender_ip);
+        packet.extend_from_slice(&self.target_mac);
+        packet.extend_from_slice(&self.target_ip);
+        packet
+    }
+}
+
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// 只接受首部校验正确、没有分片的报文，忽略首部选项
+    pub fn parse(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < IPV4_HEADER_LEN || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (packet[0] & 0xf) as usize * 4;
+        let total_len = be16(&packet[2..4]) as usize;
+        let fragment = be16(&packet[6..8]) & !IPV4_DONT_FRAGMENT;
+        if header_len < IPV4_HEADER_LEN
+            || total_len < header_len
+            || total_len > packet.len()
+            || fragment != 0
+            || checksum(&packet[..header_len]) != 0
+        {
+            return None;
+        }
+        Some(Self {
+            src: ipv4_addr(&packet[12..16]),
+            dst: ipv4_addr(&packet[16..20]),
+            protocol: packet[9],
+            payload: &packet[header_len..total_len],
+        })
+    }
+    pub fn build(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let total_len = (IPV4_HEADER_LEN + payload.len()) as u16;
+        let mut packet = Vec::with_capacity(total_len as usize);
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        // 不分片，标识字段没有意义
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&IPV4_DONT_FRAGMENT.to_be_bytes());
+        packet.extend_from_slice(&[IPV4_TTL, protocol, 0, 0]);
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        let sum = checksum(&packet);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+pub struct UdpDatagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    /// `src` 和 `dst` 是 IPv4 首部中的地址，用于检查校验和。校验和为 0 表示发送方没有计算
+    pub fn parse(src: Ipv4Addr, dst: Ipv4Addr, segment: &'a [u8]) -> Option<Self> {
+        if segment.len() < UDP_HEADER_LEN {
+            return None;
+        }
+        let len = be16(&segment[4..6]) as usize;
+        if len < UDP_HEADER_LEN || len > segment.len() {
+            return None;
+        }
+        let segment = &segment[..len];
+        if be16(&segment[6..8]) != 0 && udp_checksum(src, dst, segment) != 0 {
+            return None;
+        }
+        Some(Self {
+            src_port: be16(&segment[0..2]),
+            dst_port: be16(&segment[2..4]),
+            payload: &segment[UDP_HEADER_LEN..],
+        })
+    }
+    pub fn build(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), payload: &[u8]) -> Vec<u8> {
+        let len = (UDP_HEADER_LEN + payload.len()) as u16;
+        let mut segment = Vec::with_capacity(len as usize);
+        segment.extend_from_slice(&src.1.to_be_bytes());
+        segment.extend_from_slice(&dst.1.to_be_bytes());
+        segment.extend_from_slice(&len.to_be_bytes());
+        segment.extend_from_slice(&[0, 0]);
+        segment.extend_from_slice(payload);
+        // 计算结果为 0 时发送全 1，因为 0 表示没有校验和
+        let sum = match udp_checksum(src.0, dst.0, &segment) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+        segment
+    }
+}
+
+/// 对 ICMP 回显请求（ping）构造回显应答，其它 ICMP 报文返回 `None`
+pub fn icmp_echo_reply(message: &[u8]) -> Option<Vec<u8>> {
+    if message.len() < 8 || message[0] != ICMP_ECHO_REQUEST || checksum(message) != 0 {
+        return None;
+    }
+    let mut reply = message.to_vec();
+    reply[0] = ICMP_ECHO_REPLY;
+    reply[2..4].copy_from_slice(&[0, 0]);
+    let sum = checksum(&reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn udp_in_ipv4_round_trip() {
+        let src = [10, 0, 2, 15];
+        let dst = [10, 0, 2, 2];
+        let udp = UdpDatagram::build((src, 2000), (dst, 53), b"hello");
+        let ip = Ipv4Packet::build(src, dst, IP_PROTO_UDP, &udp);
+        let parsed = Ipv4Packet::parse(&ip).unwrap();
+        assert_eq!(
+            (parsed.src, parsed.dst, parsed.protocol),
+            (src, dst, IP_PROTO_UDP)
+        );
+        let datagram = UdpDatagram::parse(parsed.src, parsed.dst, parsed.payload).unwrap();
+        assert_eq!((datagram.src_port, datagram.dst_port), (2000, 53));
+        assert_eq!(datagram.payload, b"hello");
+        // 改动一个字节后校验和不再正确
+        let mut corrupted = ip.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let parsed = Ipv4Packet::parse(&corrupted).unwrap();
+        assert!(UdpDatagram::parse(parsed.src, parsed.dst, parsed.payload).is_none());
+        corrupted[8] ^= 1;
+        assert!(Ipv4Packet::parse(&corrupted).is_none());
+    }
+
+    #[test_case]
+    fn answers_echo_requests_only() {
+        let mut request = [8, 0, 0, 0, 0, 1, 0, 2, b'p', b'i', b'n', b'g'];
+        let sum = checksum(&request);
+        request[2..4].copy_from_slice(&sum.to_be_bytes());
+        let reply = icmp_echo_reply(&request).unwrap();
+        assert_eq!(reply[0], ICMP_ECHO_REPLY);
+        assert_eq!(checksum(&reply), 0);
+        assert_eq!(reply[4..], request[4..]);
+        assert!(icmp_echo_reply(&reply).is_none());
+    }
+}
@@ -0,0 +1,148 @@
+//! 套接字的公共接口
+//!
+//! 套接字和管道一样是文件描述符表中的 [`File`](crate::fs::File)，`read`/`write` 照常可用；
+//! `bind`、`sendto` 这样只对套接字有意义的操作通过 [`File::socket`](crate::fs::File::socket)
+//! 取得 [`Socket`] 后调用。
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use super::packet::Ipv4Addr;
+use crate::{
+    fs::{File, IoError},
+    mm::page_table::UserBuffer,
+};
+
+/// 临时端口的范围，与 IANA 的建议一致
+pub const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// IPv4 套接字地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SocketAddr {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+}
+
+impl SocketAddr {
+    pub const fn new(ip: Ipv4Addr, port: u16) -> Self {
+        Self { ip, port }
+    }
+}
+
+/// UNIX 域套接字地址
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixAddr {
+    /// 没有绑定的套接字
+    Unnamed,
+    /// 文件系统中的路径
+    Path(String),
+    /// 抽象名字空间中的名字，不对应任何文件，套接字关闭后即可重用
+    Abstract(Vec<u8>),
+}
+
+/// 任意地址族的套接字地址，对应 C 的 `struct sockaddr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SockAddr {
+    Inet(SocketAddr),
+    Unix(UnixAddr),
+}
+
+impl SockAddr {
+    /// 取出 IPv4 地址，其它地址族返回 [`SocketError::AddrFamily`]
+    pub fn inet(&self) -> Result<SocketAddr, SocketError> {
+        match self {
+            Self::Inet(addr) => Ok(*addr),
+            _ => Err(SocketError::AddrFamily),
+        }
+    }
+    /// 取出 UNIX 域地址，其它地址族返回 [`SocketError::AddrFamily`]
+    pub fn unix(&self) -> Result<&UnixAddr, SocketError> {
+        match self {
+            Self::Unix(addr) => Ok(addr),
+            _ => Err(SocketError::AddrFamily),
+        }
+    }
+}
+
+/// 套接字操作失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketError {
+    /// 地址族与套接字不符
+    AddrFamily,
+    /// 端口或名字已被其它套接字绑定
+    AddrInUse,
+    /// 地址不合法，例如连接到未命名的 UNIX 域地址
+    InvalidAddr,
+    /// 地址不是本机的地址
+    AddrNotAvailable,
+    /// 套接字已经绑定过
+    AlreadyBound,
+    /// 没有指定目的地址
+    DestAddrRequired,
+    /// 数据报超过了一个以太网帧能容纳的长度
+    MessageTooLong,
+    /// 没有网卡
+    NetworkDown,
+    /// 目的地址不可达
+    NetworkUnreachable,
+    /// 对方没有在监听这个地址
+    ConnectionRefused,
+    /// UNIX 域套接字的路径不存在
+    NotFound,
+    /// 套接字还没有连接
+    NotConnected,
+    /// 套接字已经连接
+    AlreadyConnected,
+    /// 对方已经关闭了连接
+    BrokenPipe,
+    /// 套接字当前的状态不允许该操作，例如对已连接的套接字 `listen`
+    InvalidState,
+    /// 非阻塞的操作本会阻塞
+    WouldBlock,
+    /// 阻塞时收到了信号
+    Interrupted,
+    /// 这种套接字不支持该操作
+    Unsupported,
+}
+
+/// 通过 `read`/`write` 读写套接字时，本会阻塞和被信号打断与其它文件一样处理
+impl From<SocketError> for IoError {
+    fn from(err: SocketError) -> Self {
+        match err {
+            SocketError::WouldBlock => IoError::WouldBlock,
+            SocketError::Interrupted => IoError::Interrupted,
+            err => IoError::Socket(err),
+        }
+    }
+}
+
+/// 套接字特有的操作。非阻塞时本会阻塞的操作返回 [`SocketError::WouldBlock`]，
+/// 这种套接字不支持的操作返回 [`SocketError::Unsupported`]
+pub trait Socket {
+    /// 绑定到本机地址 `addr`，IPv4 的端口为 0 时由内核选择端口
+    fn bind(&self, addr: &SockAddr) -> Result<(), SocketError>;
+    /// 发送 `buf` 中的数据，返回发送的字节数。`addr` 为目的地址，已连接的套接字忽略它
+    fn send_to(
+        &self,
+        buf: UserBuffer,
+        addr: Option<&SockAddr>,
+        nonblock: bool,
+    ) -> Result<usize, SocketError>;
+    /// 接收数据，返回读到的字节数和发送方的地址。数据报套接字每次接收一个数据报，
+    /// 放不下的部分被丢弃
+    fn recv_from(&self, buf: UserBuffer, nonblock: bool) -> Result<(usize, SockAddr), SocketError>;
+    /// 开始监听连接请求，最多 `backlog` 个连接等待 `accept`
+    fn listen(&self, _backlog: usize) -> Result<(), SocketError> {
+        Err(SocketError::Unsupported)
+    }
+    /// 取出一个已经建立的连接，返回连接的套接字和对方的地址
+    fn accept(
+        &self,
+        _nonblock: bool,
+    ) -> Result<(Arc<dyn File + Send + Sync>, SockAddr), SocketError> {
+        Err(SocketError::Unsupported)
+    }
+    /// 连接到 `addr`
+    fn connect(&self, _addr: &SockAddr, _nonblock: bool) -> Result<(), SocketError> {
+        Err(SocketError::Unsupported)
+    }
+}
@@ -0,0 +1,256 @@
+//! 本机内的字节流连接，由本机 TCP 和 UNIX 域套接字共用
+//!
+//! 连接的两端都在本机，不需要真正的报文：`connect` 找到监听目的地址的套接字后，
+//! 直接创建一对字节流缓冲区，把服务端的一端放入监听者的队列等待 `accept`，相当于瞬间完成了三次握手。
+//! 之后两端的读写和管道一样，缓冲区空时读者阻塞，满时写者阻塞。一端关闭后另一端读到 EOF，
+//! 再写入时失败。
+//!
+//! 这里只有连接和监听队列本身，地址的绑定和查找由各地址族的套接字负责。
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use super::socket::{SockAddr, SocketError};
+use crate::{
+    config::STREAM_BUFFER_SIZE,
+    fs::{File, PollEvents},
+    mm::page_table::UserBuffer,
+    sync::{
+        wait_queue::{self, WaitQueue},
+        UPSafeCell,
+    },
+    task,
+};
+
+/// `listen` 的 backlog 的上限，与 Linux 的 `SOMAXCONN` 一致
+const SOMAXCONN: usize = 4096;
+
+/// 阻塞当前任务，直到 `cell` 中的等待队列被唤醒。有待处理的信号时不等待，返回错误
+fn wait<T>(
+    cell: &UPSafeCell<T>,
+    wakers: impl Fn(&mut T) -> &mut WaitQueue,
+) -> Result<(), SocketError> {
+    if task::signal_pending() {
+        return Err(SocketError::Interrupted);
+    }
+    wait_queue::wait_on(cell, wakers);
+    Ok(())
+}
+
+/// 一个连接的两端共享的缓冲区。两端分别为第 0 端（客户端）和第 1 端（服务端）
+struct Connection {
+    /// `streams[i]` 是发给第 i 端、等待它读取的数据
+    streams: [VecDeque<u8>; 2],
+    /// 第 i 端是否已经关闭
+    closed: [bool; 2],
+    /// 等待这个连接状态变化的任务，包括两端阻塞的读写者和 `poll` 的等待者
+    wakers: WaitQueue,
+}
+
+/// 连接的一端
+#[derive(Clone)]
+pub struct StreamEnd {
+    conn: Arc<UPSafeCell<Connection>>,
+    side: usize,
+}
+
+/// 新建一个连接，返回客户端和服务端的两端
+pub fn connection() -> (StreamEnd, StreamEnd) {
+    let conn = Arc::new(unsafe {
+        UPSafeCell::new(Connection {
+            streams: [VecDeque::new(), VecDeque::new()],
+            closed: [false; 2],
+            wakers: WaitQueue::new(),
+        })
+    });
+    let client = StreamEnd {
+        conn: Arc::clone(&conn),
+        side: 0,
+    };
+    (client, StreamEnd { conn, side: 1 })
+}
+
+impl StreamEnd {
+    fn peer_side(&self) -> usize {
+        1 - self.side
+    }
+    /// 缓冲区满时等待，直到全部写入。非阻塞或被信号打断时返回已写入的字节数，
+    /// 一个字节也没有写入时返回错误；对方已经关闭时同样处理
+    pub fn send(&self, buf: UserBuffer, nonblock: bool) -> Result<usize, SocketError> {
+        let peer_side = self.peer_side();
+        let want = buf.len();
+        let mut bytes = buf.into_iter();
+        let mut written = 0;
+        let partial = |written: usize, err: SocketError| {
+            if written == 0 {
+                Err(err)
+            } else {
+                Ok(written)
+            }
+        };
+        loop {
+            let mut conn = self.conn.exclusive_access();
+            if conn.closed[peer_side] {
+                return partial(written, SocketError::BrokenPipe);
+            }
+            while conn.streams[peer_side].len() < STREAM_BUFFER_SIZE && written < want {
+                let byte = unsafe { *bytes.next().unwrap() };
+                conn.streams[peer_side].push_back(byte);
+                written += 1;
+            }
+            conn.wakers.wake_all();
+            if written == want {
+                return Ok(written);
+            }
+            drop(conn);
+            if nonblock {
+                return partial(written, SocketError::WouldBlock);
+            }
+            if let Err(err) = wait(&self.conn, |conn| &mut conn.wakers) {
+                return partial(written, err);
+            }
+        }
+    }
+    /// 缓冲区为空时等待，读到至少一个字节后立即返回。对方关闭后返回 0
+    pub fn recv(&self, buf: UserBuffer, nonblock: bool) -> Result<usize, SocketError> {
+        let want = buf.len();
+        if want == 0 {
+            return Ok(0);
+        }
+        let mut conn = loop {
+            let conn = self.conn.exclusive_access();
+            if !conn.streams[self.side].is_empty() {
+                break conn;
+            }
+            if conn.closed[self.peer_side()] {
+                return Ok(0);
+            }
+            drop(conn);
+            if nonblock {
+                return Err(SocketError::WouldBlock);
+            }
+            wait(&self.conn, |conn| &mut conn.wakers)?;
+        };
+        let len = want.min(conn.streams[self.side].len());
+        for (dst, byte) in buf.into_iter().zip(conn.streams[self.side].drain(..len)) {
+            unsafe { *dst = byte };
+        }
+        conn.wakers.wake_all();
+        Ok(len)
+    }
+    /// 关闭这一端，对方随后读到 EOF
+    pub fn close(&self) {
+        let mut conn = self.conn.exclusive_access();
+        conn.closed[self.side] = true;
+        conn.wakers.wake_all();
+    }
+    pub fn poll(&self) -> PollEvents {
+        let conn = self.conn.exclusive_access();
+        let peer_side = self.peer_side();
+        let mut events = PollEvents::empty();
+        if !conn.streams[self.side].is_empty() {
+            events |= PollEvents::POLLIN;
+        }
+        if conn.closed[peer_side] {
+            events |= PollEvents::POLLHUP | PollEvents::POLLERR;
+        } else if conn.streams[peer_side].len() < STREAM_BUFFER_SIZE {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+    pub fn wakers<R>(&self, f: impl FnOnce(&mut WaitQueue) -> R) -> R {
+        f(&mut self.conn.exclusive_access().wakers)
+    }
+}
+
+struct ListenerInner {
+    backlog: usize,
+    /// 已经建立的连接的服务端一端及客户端的地址
+    pending: VecDeque<(Arc<dyn File + Send + Sync>, SockAddr)>,
+    /// 等待连接到来的 `accept` 和等待队列腾出空位的 `connect`
+    wakers: WaitQueue,
+}
+
+/// 监听套接字上等待 `accept` 的连接
+#[derive(Clone)]
+pub struct Listener(Arc<UPSafeCell<ListenerInner>>);
+
+impl Listener {
+    /// `backlog` 会被限制在 1 到 [`SOMAXCONN`] 之间
+    pub fn new(backlog: usize) -> Self {
+        Self(Arc::new(unsafe {
+            UPSafeCell::new(ListenerInner {
+                backlog: backlog.clamp(1, SOMAXCONN),
+                pending: VecDeque::new(),
+                wakers: WaitQueue::new(),
+            })
+        }))
+    }
+    pub fn set_backlog(&self, backlog: usize) {
+        self.0.exclusive_access().backlog = backlog.clamp(1, SOMAXCONN);
+    }
+    /// 放入一个已经建立的连接的服务端一端
+    pub fn push(&self, socket: Arc<dyn File + Send + Sync>, peer: SockAddr) {
+        let mut inner = self.0.exclusive_access();
+        inner.pending.push_back((socket, peer));
+        inner.wakers.wake_all();
+    }
+    /// 取出一个连接，没有连接时等待
+    pub fn accept(
+        &self,
+        nonblock: bool,
+    ) -> Result<(Arc<dyn File + Send + Sync>, SockAddr), SocketError> {
+        loop {
+            let mut inner = self.0.exclusive_access();
+            if let Some(pending) = inner.pending.pop_front() {
+                // 队列腾出了空位
+                inner.wakers.wake_all();
+                return Ok(pending);
+            }
+            drop(inner);
+            if nonblock {
+                return Err(SocketError::WouldBlock);
+            }
+            wait(&self.0, |inner| &mut inner.wakers)?;
+        }
+    }
+    /// 监听套接字关闭：还没有被 accept 的连接随之关闭，客户端读到 EOF
+    pub fn close(&self) {
+        let mut inner = self.0.exclusive_access();
+        let pending = core::mem::take(&mut inner.pending);
+        inner.wakers.wake_all();
+        drop(inner);
+        drop(pending);
+    }
+    pub fn poll(&self) -> PollEvents {
+        if self.0.exclusive_access().pending.is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::POLLIN
+        }
+    }
+    pub fn wakers<R>(&self, f: impl FnOnce(&mut WaitQueue) -> R) -> R {
+        f(&mut self.0.exclusive_access().wakers)
+    }
+}
+
+/// 等待 `lookup` 找到的监听者的队列腾出空位后返回它。
+///
+/// 监听套接字可能在等待期间关闭，所以每次醒来都重新查找，找不到时返回 `lookup` 的错误
+pub fn wait_for_room(
+    lookup: impl Fn() -> Result<Listener, SocketError>,
+    nonblock: bool,
+) -> Result<Listener, SocketError> {
+    loop {
+        let listener = lookup()?;
+        let inner = listener.0.exclusive_access();
+        if inner.pending.len() < inner.backlog {
+            drop(inner);
+            return Ok(listener);
+        }
+        drop(inner);
+        if nonblock {
+            return Err(SocketError::WouldBlock);
+        }
+        wait(&listener.0, |inner| &mut inner.wakers)?;
+    }
+}
@@ -0,0 +1,346 @@
+//! 只在本机内通信的 TCP
+//!
+//! 连接的两端都在本机，不产生真正的 TCP 报文，连接本身见 [`super::stream`]。
+//! 连接目的地址不是本机时返回 [`SocketError::NetworkUnreachable`]。
+//! TCP 的端口与 UDP 的端口互不相干，各有一张端口表。
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+use lazy_static::*;
+
+use super::{
+    socket::{SockAddr, Socket, SocketAddr, SocketError, EPHEMERAL_PORTS},
+    stream::{self, Listener, StreamEnd},
+};
+use crate::{
+    fs::{File, IoError, PollEvents, Stat, StatMode},
+    mm::page_table::UserBuffer,
+    sync::{wait_queue::WaitQueue, UPSafeCell},
+    task::TaskControlBlock,
+};
+
+/// 已连接的套接字
+struct Stream {
+    local: SocketAddr,
+    peer: SocketAddr,
+    end: StreamEnd,
+    /// 本地端口是否由这个套接字占用。`accept` 得到的套接字与监听套接字共用端口
+    owns_port: bool,
+}
+
+enum State {
+    /// 新建的、可能已经绑定了地址的套接字
+    Idle {
+        local: Option<SocketAddr>,
+    },
+    Listening {
+        local: SocketAddr,
+        listener: Listener,
+    },
+    Connected(Stream),
+}
+
+lazy_static! {
+    /// 已被占用的端口
+    static ref PORTS: UPSafeCell<BTreeSet<u16>> = unsafe { UPSafeCell::new(BTreeSet::new()) };
+    /// 正在监听的端口
+    static ref LISTENERS: UPSafeCell<BTreeMap<u16, Listener>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// 占用端口 `port`，为 0 时选择一个空闲的临时端口
+fn alloc_port(port: u16) -> Result<u16, SocketError> {
+    let mut ports = PORTS.exclusive_access();
+    let port = if port == 0 {
+        EPHEMERAL_PORTS
+            .find(|port| !ports.contains(port))
+            .ok_or(SocketError::AddrInUse)?
+    } else if ports.contains(&port) {
+        return Err(SocketError::AddrInUse);
+    } else {
+        port
+    };
+    ports.insert(port);
+    Ok(port)
+}
+
+fn release_port(port: u16) {
+    PORTS.exclusive_access().remove(&port);
+}
+
+pub struct TcpSocket {
+    state: UPSafeCell<State>,
+}
+
+impl TcpSocket {
+    pub fn new() -> Self {
+        Self::with_state(State::Idle { local: None })
+    }
+    fn with_state(state: State) -> Self {
+        Self {
+            state: unsafe { UPSafeCell::new(state) },
+        }
+    }
+    fn stream(&self) -> Result<(StreamEnd, SocketAddr), SocketError> {
+        match &*self.state.exclusive_access() {
+            State::Connected(stream) => Ok((stream.end.clone(), stream.peer)),
+            _ => Err(SocketError::NotConnected),
+        }
+    }
+    /// 在监听 `addr` 的套接字的队列中有空位时建立连接，返回客户端一端
+    fn establish(&self, addr: SocketAddr, nonblock: bool) -> Result<Stream, SocketError> {
+        let lookup = || {
+            LISTENERS
+                .exclusive_access()
+                .get(&addr.port)
+                .cloned()
+                .ok_or(SocketError::ConnectionRefused)
+        };
+        let listener = stream::wait_for_room(lookup, nonblock)?;
+        // 等待期间套接字可能已经被其它进程连接或者绑定，重新检查状态
+        let bound = match &*self.state.exclusive_access() {
+            State::Idle { local } => *local,
+            State::Listening { .. } => return Err(SocketError::InvalidState),
+            State::Connected(_) => return Err(SocketError::AlreadyConnected),
+        };
+        let port = match bound {
+            Some(local) => local.port,
+            None => alloc_port(0)?,
+        };
+        let local = SocketAddr::new(super::source_ip(addr.ip), port);
+        let (client, server) = stream::connection();
+        let server = TcpSocket::with_state(State::Connected(Stream {
+            local: addr,
+            peer: local,
+            end: server,
+            owns_port: false,
+        }));
+        listener.push(Arc::new(server), SockAddr::Inet(local));
+        Ok(Stream {
+            local,
+            peer: addr,
+            end: client,
+            owns_port: true,
+        })
+    }
+    /// 用 `f` 操作这个套接字当前的等待队列，未连接也未监听的套接字没有等待队列
+    fn with_wakers<R>(&self, f: impl FnOnce(&mut WaitQueue) -> R) -> Option<R> {
+        match &*self.state.exclusive_access() {
+            State::Idle { .. } => None,
+            State::Listening { listener, .. } => Some(listener.wakers(f)),
+            State::Connected(stream) => Some(stream.end.wakers(f)),
+        }
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        match &*self.state.exclusive_access() {
+            State::Idle { local: None } => {}
+            State::Idle { local: Some(local) } => release_port(local.port),
+            State::Listening { local, listener } => {
+                LISTENERS.exclusive_access().remove(&local.port);
+                release_port(local.port);
+                listener.close();
+            }
+            State::Connected(stream) => {
+                stream.end.close();
+                if stream.owns_port {
+                    release_port(stream.local.port);
+                }
+            }
+        }
+    }
+}
+
+impl Socket for TcpSocket {
+    fn bind(&self, addr: &SockAddr) -> Result<(), SocketError> {
+        let addr = addr.inet()?;
+        if addr.ip != [0; 4] && !super::is_local(addr.ip) {
+            return Err(SocketError::AddrNotAvailable);
+        }
+        let mut state = self.state.exclusive_access();
+        if !matches!(*state, State::Idle { local: None }) {
+            return Err(SocketError::AlreadyBound);
+        }
+        let port = alloc_port(addr.port)?;
+        *state = State::Idle {
+            local: Some(SocketAddr::new(addr.ip, port)),
+        };
+        Ok(())
+    }
+    fn send_to(
+        &self,
+        buf: UserBuffer,
+        _addr: Option<&SockAddr>,
+        nonblock: bool,
+    ) -> Result<usize, SocketError> {
+        self.stream()?.0.send(buf, nonblock)
+    }
+    fn recv_from(&self, buf: UserBuffer, nonblock: bool) -> Result<(usize, SockAddr), SocketError> {
+        let (end, peer) = self.stream()?;
+        let len = end.recv(buf, nonblock)?;
+        Ok((len, SockAddr::Inet(peer)))
+    }
+    fn listen(&self, backlog: usize) -> Result<(), SocketError> {
+        let mut state = self.state.exclusive_access();
+        let local = match *state {
+            State::Idle { local } => local,
+            State::Listening { ref listener, .. } => {
+                listener.set_backlog(backlog);
+                return Ok(());
+            }
+            State::Connected(_) => return Err(SocketError::InvalidState),
+        };
+        let local = match local {
+            Some(local) => local,
+            None => SocketAddr::new([0; 4], alloc_port(0)?),
+        };
+        let listener = Listener::new(backlog);
+        LISTENERS
+            .exclusive_access()
+            .insert(local.port, listener.clone());
+        *state = State::Listening { local, listener };
+        Ok(())
+    }
+    fn accept(
+        &self,
+        nonblock: bool,
+    ) -> Result<(Arc<dyn File + Send + Sync>, SockAddr), SocketError> {
+        let listener = match &*self.state.exclusive_access() {
+            State::Listening { listener, .. } => listener.clone(),
+            _ => return Err(SocketError::InvalidState),
+        };
+        listener.accept(nonblock)
+    }
+    /// 监听者的队列已满时等待
+    fn connect(&self, addr: &SockAddr, nonblock: bool) -> Result<(), SocketError> {
+        let addr = addr.inet()?;
+        match &*self.state.exclusive_access() {
+            State::Idle { .. } => {}
+            State::Listening { .. } => return Err(SocketError::InvalidState),
+            State::Connected(_) => return Err(SocketError::AlreadyConnected),
+        }
+        if !super::is_local(addr.ip) {
+            return Err(SocketError::NetworkUnreachable);
+        }
+        let stream = self.establish(addr, nonblock)?;
+        *self.state.exclusive_access() = State::Connected(stream);
+        Ok(())
+    }
+}
+
+impl File for TcpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// 对方关闭后读到 0；未连接时返回 [`SocketError::NotConnected`]
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.recv_from(buf, false)?.0)
+    }
+    /// 对方已关闭时返回 [`SocketError::BrokenPipe`]
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.send_to(buf, None, false)?)
+    }
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.recv_from(buf, true)?.0)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.send_to(buf, None, true)?)
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::SOCK,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+    fn poll(&self) -> PollEvents {
+        match &*self.state.exclusive_access() {
+            State::Idle { .. } => PollEvents::POLLHUP,
+            State::Listening { listener, .. } => listener.poll(),
+            State::Connected(stream) => stream.end.poll(),
+        }
+    }
+    fn register_waker(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.with_wakers(|wakers| wakers.add_waiter(Arc::clone(task)))
+            .is_some()
+    }
+    fn unregister_waker(&self, task: &Arc<TaskControlBlock>) {
+        self.with_wakers(|wakers| wakers.remove_waiter(task));
+    }
+    fn socket(&self) -> Option<&dyn Socket> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::page_table::{buffer, output_buffer};
+
+    #[test_case]
+    fn loopback_connection_carries_bytes_until_closed() {
+        let server = TcpSocket::new();
+        server
+            .bind(&SockAddr::Inet(SocketAddr::new([0; 4], 7001)))
+            .unwrap();
+        let dst = SockAddr::Inet(SocketAddr::new([127, 0, 0, 1], 7001));
+        assert_eq!(
+            TcpSocket::new().connect(&dst, true),
+            Err(SocketError::ConnectionRefused)
+        );
+        server.listen(1).unwrap();
+        assert!(matches!(server.accept(true), Err(SocketError::WouldBlock)));
+        let client = TcpSocket::new();
+        client.connect(&dst, true).unwrap();
+        // backlog 为 1，队列已满
+        assert_eq!(
+            TcpSocket::new().connect(&dst, true),
+            Err(SocketError::WouldBlock)
+        );
+        let (accepted, peer) = server.accept(true).unwrap();
+        let accepted = accepted.socket().unwrap();
+        let peer_addr = peer.inet().unwrap();
+        assert_eq!(peer_addr.ip, [127, 0, 0, 1]);
+        assert!(EPHEMERAL_PORTS.contains(&peer_addr.port));
+
+        assert_eq!(client.send_to(buffer(b"hello"), None, true), Ok(5));
+        let (out, ptr) = output_buffer(8);
+        let (len, src) = accepted.recv_from(out, true).unwrap();
+        assert_eq!(unsafe { core::slice::from_raw_parts(ptr, len) }, b"hello");
+        assert_eq!(src, peer);
+        assert_eq!(
+            accepted.recv_from(buffer(&[0; 8]), true),
+            Err(SocketError::WouldBlock)
+        );
+
+        drop(client);
+        assert_eq!(
+            accepted.recv_from(buffer(&[0; 8]), true),
+            Ok((0, peer)),
+            "EOF, not EAGAIN"
+        );
+        assert_eq!(
+            accepted.send_to(buffer(b"late"), None, true),
+            Err(SocketError::BrokenPipe)
+        );
+        // 通过 `read`/`write` 同样报告错误，而不是返回 0
+        assert_eq!(
+            TcpSocket::new().read(buffer(&[0; 8])),
+            Err(IoError::Socket(SocketError::NotConnected))
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! UDP 套接字
+//!
+//! 绑定的端口登记在全局的端口表中，收到的数据报按目的端口放入对应套接字的接收队列，
+//! 队列满时丢弃新到的数据报。没有绑定就发送时自动绑定一个临时端口。
+//! 套接字只记录端口，不区分绑定的是本机的哪个地址。
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use lazy_static::*;
+
+use super::{
+    packet::{UdpDatagram, IP_PROTO_UDP, UDP_HEADER_LEN},
+    socket::{SockAddr, Socket, SocketAddr, SocketError, EPHEMERAL_PORTS},
+    MTU,
+};
+use crate::{
+    config::UDP_RECV_QUEUE_LEN,
+    fs::{File, IoError, PollEvents, Stat, StatMode},
+    mm::page_table::UserBuffer,
+    sync::{
+        wait_queue::{self, WaitQueue},
+        UPSafeCell,
+    },
+    task::{self, TaskControlBlock},
+};
+
+/// 一个 UDP 数据报最多承载的数据，即 MTU 减去 IPv4 和 UDP 首部
+const MAX_PAYLOAD: usize = MTU - 20 - UDP_HEADER_LEN;
+
+/// 端口上收到的数据报，与套接字分开以便登记在端口表中
+#[derive(Default)]
+struct Endpoint {
+    queue: VecDeque<(SocketAddr, Vec<u8>)>,
+    /// 等待数据报的任务，包括阻塞的接收者和 `poll` 的等待者
+    wakers: WaitQueue,
+}
+
+lazy_static! {
+    /// 已绑定的端口
+    static ref PORTS: UPSafeCell<BTreeMap<u16, Arc<UPSafeCell<Endpoint>>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// 把收到的数据报放入绑定了 `port` 的套接字的接收队列。端口没有绑定或者队列已满时丢弃
+pub fn deliver(src: SocketAddr, port: u16, payload: &[u8]) {
+    let endpoint = match PORTS.exclusive_access().get(&port) {
+        Some(endpoint) => Arc::clone(endpoint),
+        None => return,
+    };
+    let mut endpoint = endpoint.exclusive_access();
+    if endpoint.queue.len() < UDP_RECV_QUEUE_LEN {
+        endpoint.queue.push_back((src, payload.to_vec()));
+        endpoint.wakers.wake_all();
+    }
+}
+
+pub struct UdpSocket {
+    /// 绑定的端口，尚未绑定时为 `None`
+    port: UPSafeCell<Option<u16>>,
+    endpoint: Arc<UPSafeCell<Endpoint>>,
+}
+
+impl UdpSocket {
+    pub fn new() -> Self {
+        Self {
+            port: unsafe { UPSafeCell::new(None) },
+            endpoint: Arc::new(unsafe { UPSafeCell::new(Endpoint::default()) }),
+        }
+    }
+    /// 把套接字登记到端口 `port` 上，为 0 时选择一个空闲的临时端口
+    fn bind_port(&self, port: u16) -> Result<u16, SocketError> {
+        let mut bound = self.port.exclusive_access();
+        if bound.is_some() {
+            return Err(SocketError::AlreadyBound);
+        }
+        let mut ports = PORTS.exclusive_access();
+        let port = if port == 0 {
+            EPHEMERAL_PORTS
+                .find(|port| !ports.contains_key(port))
+                .ok_or(SocketError::AddrInUse)?
+        } else if ports.contains_key(&port) {
+            return Err(SocketError::AddrInUse);
+        } else {
+            port
+        };
+        ports.insert(port, Arc::clone(&self.endpoint));
+        *bound = Some(port);
+        Ok(port)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(port) = *self.port.exclusive_access() {
+            PORTS.exclusive_access().remove(&port);
+        }
+    }
+}
+
+impl Socket for UdpSocket {
+    fn bind(&self, addr: &SockAddr) -> Result<(), SocketError> {
+        let addr = addr.inet()?;
+        if addr.ip != [0; 4] && !super::is_local(addr.ip) {
+            return Err(SocketError::AddrNotAvailable);
+        }
+        self.bind_port(addr.port).map(|_| ())
+    }
+    fn send_to(
+        &self,
+        buf: UserBuffer,
+        addr: Option<&SockAddr>,
+        _nonblock: bool,
+    ) -> Result<usize, SocketError> {
+        let addr = addr.ok_or(SocketError::DestAddrRequired)?.inet()?;
+        if buf.len() > MAX_PAYLOAD {
+            return Err(SocketError::MessageTooLong);
+        }
+        let bound = *self.port.exclusive_access();
+        let port = match bound {
+            Some(port) => port,
+            None => self.bind_port(0)?,
+        };
+        let payload: Vec<u8> = buf.into_iter().map(|byte| unsafe { *byte }).collect();
+        let src = (super::source_ip(addr.ip), port);
+        let datagram = UdpDatagram::build(src, (addr.ip, addr.port), &payload);
+        super::send_ipv4(addr.ip, IP_PROTO_UDP, &datagram)?;
+        Ok(payload.len())
+    }
+    fn recv_from(&self, buf: UserBuffer, nonblock: bool) -> Result<(usize, SockAddr), SocketError> {
+        let (src, data) = loop {
+            super::poll();
+            if let Some(datagram) = self.endpoint.exclusive_access().queue.pop_front() {
+                break datagram;
+            }
+            if nonblock {
+                return Err(SocketError::WouldBlock);
+            }
+            if task::signal_pending() {
+                return Err(SocketError::Interrupted);
+            }
+            wait_queue::wait_on(&self.endpoint, |endpoint| &mut endpoint.wakers);
+        };
+        let mut len = 0;
+        for (dst, &byte) in buf.into_iter().zip(data.iter()) {
+            unsafe { *dst = byte };
+            len += 1;
+        }
+        Ok((len, SockAddr::Inet(src)))
+    }
+}
+
+impl File for UdpSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// 接收一个数据报，丢弃发送方的地址
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.recv_from(buf, false)?.0)
+    }
+    /// 套接字没有连接到对端，不知道发给谁，返回 [`SocketError::DestAddrRequired`]
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.send_to(buf, None, false)?)
+    }
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.recv_from(buf, true)?.0)
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::SOCK,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+    fn poll(&self) -> PollEvents {
+        super::poll();
+        if self.endpoint.exclusive_access().queue.is_empty() {
+            PollEvents::POLLOUT
+        } else {
+            PollEvents::POLLIN | PollEvents::POLLOUT
+        }
+    }
+    fn register_waker(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.endpoint
+            .exclusive_access()
+            .wakers
+            .add_waiter(Arc::clone(task));
+        true
+    }
+    fn unregister_waker(&self, task: &Arc<TaskControlBlock>) {
+        self.endpoint.exclusive_access().wakers.remove_waiter(task);
+    }
+    fn socket(&self) -> Option<&dyn Socket> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::page_table::{buffer, output_buffer};
+
+    #[test_case]
+    fn loopback_datagrams_reach_the_bound_port() {
+        let receiver = UdpSocket::new();
+        let sender = UdpSocket::new();
+        receiver
+            .bind(&SockAddr::Inet(SocketAddr::new([0; 4], 7000)))
+            .unwrap();
+        assert_eq!(
+            UdpSocket::new().bind(&SockAddr::Inet(SocketAddr::new([0; 4], 7000))),
+            Err(SocketError::AddrInUse)
+        );
+        let dst = SockAddr::Inet(SocketAddr::new([127, 0, 0, 1], 7000));
+        assert_eq!(sender.send_to(buffer(b"ping"), Some(&dst), false), Ok(4));
+        // 没有连接，`write` 不知道发给谁
+        assert_eq!(
+            sender.write(buffer(b"ping")),
+            Err(IoError::Socket(SocketError::DestAddrRequired))
+        );
+        let (out, ptr) = output_buffer(8);
+        let (len, src) = receiver.recv_from(out, true).unwrap();
+        assert_eq!(unsafe { core::slice::from_raw_parts(ptr, len) }, b"ping");
+        let src = src.inet().unwrap();
+        assert_eq!(src.ip, [127, 0, 0, 1]);
+        assert!(EPHEMERAL_PORTS.contains(&src.port));
+        assert_eq!(
+            receiver.recv_from(buffer(&[0; 8]), true),
+            Err(SocketError::WouldBlock)
+        );
+        drop(receiver);
+        // 端口在套接字关闭后释放
+        UdpSocket::new()
+            .bind(&SockAddr::Inet(SocketAddr::new([0; 4], 7000)))
+            .unwrap();
+    }
+}
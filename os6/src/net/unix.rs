@@ -0,0 +1,359 @@
+//! UNIX 域流套接字
+//!
+//! 地址可以是文件系统中的路径，也可以是抽象名字空间中的名字（`sun_path` 以 0 字节开头）。
+//! 绑定到路径时在文件系统中创建一个同名的空文件，套接字按文件的 inode 编号登记，
+//! 所以改名或者另建硬链接后仍然能连接到它。easy-fs 没有套接字类型的 inode，这个文件只是占位：
+//! 与 Linux 一样，套接字关闭后文件仍然存在，需要 `unlink` 后才能再次绑定。
+//! 抽象名字不对应任何文件，套接字关闭后即可重用。
+//!
+//! 连接的建立和读写见 [`super::stream`]。
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use lazy_static::*;
+
+use super::{
+    socket::{SockAddr, Socket, SocketError, UnixAddr},
+    stream::{self, Listener, StreamEnd},
+};
+use crate::{
+    fs::{
+        inode::{self, OpenError},
+        File, IoError, PollEvents, Stat, StatMode,
+    },
+    mm::page_table::UserBuffer,
+    sync::{wait_queue::WaitQueue, UPSafeCell},
+    task::{Processor, TaskControlBlock},
+};
+
+/// 套接字文件的权限位，再去掉 umask 中的位
+const SOCKET_FILE_MODE: u16 = 0o777;
+
+/// 登记绑定的套接字时使用的名字
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Name {
+    /// 路径对应的文件的 inode 编号
+    Inode(usize),
+    Abstract(Vec<u8>),
+}
+
+/// 套接字绑定的地址
+struct Bound {
+    name: Name,
+    addr: UnixAddr,
+}
+
+enum State {
+    /// 新建的、可能已经绑定了地址的套接字
+    Idle {
+        bound: Option<Bound>,
+    },
+    Listening {
+        bound: Bound,
+        listener: Listener,
+    },
+    Connected {
+        /// `accept` 得到的套接字没有自己的地址
+        bound: Option<Bound>,
+        peer: UnixAddr,
+        end: StreamEnd,
+    },
+}
+
+lazy_static! {
+    /// 已绑定的名字，正在监听时带有监听队列
+    static ref NAMES: UPSafeCell<BTreeMap<Name, Option<Listener>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// `addr` 当前指向的套接字的名字
+fn resolve(addr: &UnixAddr) -> Result<Name, SocketError> {
+    match addr {
+        UnixAddr::Unnamed => Err(SocketError::InvalidAddr),
+        UnixAddr::Path(path) => inode::inode_id(path)
+            .map(Name::Inode)
+            .ok_or(SocketError::NotFound),
+        UnixAddr::Abstract(name) => Ok(Name::Abstract(name.clone())),
+    }
+}
+
+/// 占用地址 `addr`。路径在文件系统中已经存在时，即使没有套接字绑定也视为被占用
+fn claim(addr: &UnixAddr) -> Result<Name, SocketError> {
+    let name = match addr {
+        UnixAddr::Unnamed => return Err(SocketError::InvalidAddr),
+        UnixAddr::Path(path) => {
+            let umask = Processor::current_task()
+                .unwrap()
+                .inner_exclusive_access()
+                .umask;
+            match inode::create_socket_file(path, SOCKET_FILE_MODE & !umask) {
+                Ok(ino) => Name::Inode(ino),
+                Err(OpenError::Exists) => return Err(SocketError::AddrInUse),
+                Err(_) => return Err(SocketError::NotFound),
+            }
+        }
+        UnixAddr::Abstract(name) => Name::Abstract(name.clone()),
+    };
+    let mut names = NAMES.exclusive_access();
+    if names.contains_key(&name) {
+        return Err(SocketError::AddrInUse);
+    }
+    names.insert(name.clone(), None);
+    Ok(name)
+}
+
+pub struct UnixSocket {
+    state: UPSafeCell<State>,
+}
+
+impl UnixSocket {
+    pub fn new() -> Self {
+        Self::with_state(State::Idle { bound: None })
+    }
+    fn with_state(state: State) -> Self {
+        Self {
+            state: unsafe { UPSafeCell::new(state) },
+        }
+    }
+    fn stream(&self) -> Result<(StreamEnd, UnixAddr), SocketError> {
+        match &*self.state.exclusive_access() {
+            State::Connected { end, peer, .. } => Ok((end.clone(), peer.clone())),
+            _ => Err(SocketError::NotConnected),
+        }
+    }
+    /// 用 `f` 操作这个套接字当前的等待队列，未连接也未监听的套接字没有等待队列
+    fn with_wakers<R>(&self, f: impl FnOnce(&mut WaitQueue) -> R) -> Option<R> {
+        match &*self.state.exclusive_access() {
+            State::Idle { .. } => None,
+            State::Listening { listener, .. } => Some(listener.wakers(f)),
+            State::Connected { end, .. } => Some(end.wakers(f)),
+        }
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let state = self.state.exclusive_access();
+        let bound = match &*state {
+            State::Idle { bound } => bound.as_ref(),
+            State::Listening { bound, listener } => {
+                listener.close();
+                Some(bound)
+            }
+            State::Connected { bound, end, .. } => {
+                end.close();
+                bound.as_ref()
+            }
+        };
+        if let Some(bound) = bound {
+            NAMES.exclusive_access().remove(&bound.name);
+        }
+    }
+}
+
+impl Socket for UnixSocket {
+    fn bind(&self, addr: &SockAddr) -> Result<(), SocketError> {
+        let addr = addr.unix()?;
+        let mut state = self.state.exclusive_access();
+        if !matches!(*state, State::Idle { bound: None }) {
+            return Err(SocketError::AlreadyBound);
+        }
+        let name = claim(addr)?;
+        *state = State::Idle {
+            bound: Some(Bound {
+                name,
+                addr: addr.clone(),
+            }),
+        };
+        Ok(())
+    }
+    fn send_to(
+        &self,
+        buf: UserBuffer,
+        _addr: Option<&SockAddr>,
+        nonblock: bool,
+    ) -> Result<usize, SocketError> {
+        self.stream()?.0.send(buf, nonblock)
+    }
+    fn recv_from(&self, buf: UserBuffer, nonblock: bool) -> Result<(usize, SockAddr), SocketError> {
+        let (end, peer) = self.stream()?;
+        let len = end.recv(buf, nonblock)?;
+        Ok((len, SockAddr::Unix(peer)))
+    }
+    /// 只有绑定了地址的套接字可以监听
+    fn listen(&self, backlog: usize) -> Result<(), SocketError> {
+        let mut state = self.state.exclusive_access();
+        let bound = match &mut *state {
+            State::Idle { bound } => bound.take().ok_or(SocketError::InvalidState)?,
+            State::Listening { listener, .. } => {
+                listener.set_backlog(backlog);
+                return Ok(());
+            }
+            State::Connected { .. } => return Err(SocketError::InvalidState),
+        };
+        let listener = Listener::new(backlog);
+        NAMES
+            .exclusive_access()
+            .insert(bound.name.clone(), Some(listener.clone()));
+        *state = State::Listening { bound, listener };
+        Ok(())
+    }
+    fn accept(
+        &self,
+        nonblock: bool,
+    ) -> Result<(Arc<dyn File + Send + Sync>, SockAddr), SocketError> {
+        let listener = match &*self.state.exclusive_access() {
+            State::Listening { listener, .. } => listener.clone(),
+            _ => return Err(SocketError::InvalidState),
+        };
+        listener.accept(nonblock)
+    }
+    /// 监听者的队列已满时等待
+    fn connect(&self, addr: &SockAddr, nonblock: bool) -> Result<(), SocketError> {
+        let addr = addr.unix()?;
+        match &*self.state.exclusive_access() {
+            State::Idle { .. } => {}
+            State::Listening { .. } => return Err(SocketError::InvalidState),
+            State::Connected { .. } => return Err(SocketError::AlreadyConnected),
+        }
+        let name = resolve(addr)?;
+        let lookup = || match NAMES.exclusive_access().get(&name) {
+            Some(Some(listener)) => Ok(listener.clone()),
+            _ => Err(SocketError::ConnectionRefused),
+        };
+        let listener = stream::wait_for_room(lookup, nonblock)?;
+        // 等待期间套接字可能已经被其它进程连接或者绑定，重新检查状态
+        let mut state = self.state.exclusive_access();
+        let bound = match &mut *state {
+            State::Idle { bound } => bound.take(),
+            State::Listening { .. } => return Err(SocketError::InvalidState),
+            State::Connected { .. } => return Err(SocketError::AlreadyConnected),
+        };
+        let local = bound
+            .as_ref()
+            .map_or(UnixAddr::Unnamed, |bound| bound.addr.clone());
+        let (client, server) = stream::connection();
+        let server = UnixSocket::with_state(State::Connected {
+            bound: None,
+            peer: local.clone(),
+            end: server,
+        });
+        listener.push(Arc::new(server), SockAddr::Unix(local));
+        *state = State::Connected {
+            bound,
+            peer: addr.clone(),
+            end: client,
+        };
+        Ok(())
+    }
+}
+
+impl File for UnixSocket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// 对方关闭后读到 0；未连接时返回 [`SocketError::NotConnected`]
+    fn read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.recv_from(buf, false)?.0)
+    }
+    /// 对方已关闭时返回 [`SocketError::BrokenPipe`]
+    fn write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.send_to(buf, None, false)?)
+    }
+    fn try_read(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.recv_from(buf, true)?.0)
+    }
+    fn try_write(&self, buf: UserBuffer) -> Result<usize, IoError> {
+        Ok(self.send_to(buf, None, true)?)
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::SOCK,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            pad: [0; 3],
+        }
+    }
+    fn poll(&self) -> PollEvents {
+        match &*self.state.exclusive_access() {
+            State::Idle { .. } => PollEvents::POLLHUP,
+            State::Listening { listener, .. } => listener.poll(),
+            State::Connected { end, .. } => end.poll(),
+        }
+    }
+    fn register_waker(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.with_wakers(|wakers| wakers.add_waiter(Arc::clone(task)))
+            .is_some()
+    }
+    fn unregister_waker(&self, task: &Arc<TaskControlBlock>) {
+        self.with_wakers(|wakers| wakers.remove_waiter(task));
+    }
+    fn socket(&self) -> Option<&dyn Socket> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mm::page_table::{buffer, output_buffer};
+
+    #[test_case]
+    fn abstract_names_serve_several_clients() {
+        let addr = SockAddr::Unix(UnixAddr::Abstract(b"display".to_vec()));
+        let server = UnixSocket::new();
+        assert_eq!(
+            UnixSocket::new().connect(&addr, true),
+            Err(SocketError::ConnectionRefused)
+        );
+        // 未绑定的套接字不能监听
+        assert_eq!(server.listen(4), Err(SocketError::InvalidState));
+        server.bind(&addr).unwrap();
+        assert_eq!(UnixSocket::new().bind(&addr), Err(SocketError::AddrInUse));
+        server.listen(4).unwrap();
+
+        let first = UnixSocket::new();
+        let second = UnixSocket::new();
+        let second_addr = UnixAddr::Abstract(b"client".to_vec());
+        second.bind(&SockAddr::Unix(second_addr.clone())).unwrap();
+        first.connect(&addr, true).unwrap();
+        second.connect(&addr, true).unwrap();
+        let (a, peer_a) = server.accept(true).unwrap();
+        let (b, peer_b) = server.accept(true).unwrap();
+        assert_eq!(peer_a, SockAddr::Unix(UnixAddr::Unnamed));
+        assert_eq!(peer_b, SockAddr::Unix(second_addr));
+        let (a, b) = (a.socket().unwrap(), b.socket().unwrap());
+
+        // 两个连接互不干扰
+        assert_eq!(first.send_to(buffer(b"one"), None, true), Ok(3));
+        assert_eq!(
+            b.recv_from(buffer(&[0; 8]), true),
+            Err(SocketError::WouldBlock)
+        );
+        let (out, ptr) = output_buffer(8);
+        let (len, _) = a.recv_from(out, true).unwrap();
+        assert_eq!(unsafe { core::slice::from_raw_parts(ptr, len) }, b"one");
+
+        // 服务端关闭后还没有 accept 的连接被关闭，名字可以重用
+        let third = UnixSocket::new();
+        third.connect(&addr, true).unwrap();
+        drop(server);
+        assert_eq!(
+            third.recv_from(buffer(&[0; 8]), true).map(|(len, _)| len),
+            Ok(0)
+        );
+        assert_eq!(
+            third.write(buffer(b"late")),
+            Err(IoError::Socket(SocketError::BrokenPipe))
+        );
+        UnixSocket::new().bind(&addr).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+//! Kernel oops
+//!
+//! 内核替某个用户任务工作时发现的错误，例如系统调用深处才发现的无效用户指针，只与这个任务有关，
+//! 不必让整个内核 panic。命令行为 `oops=kill`（默认）时，[`oops!`] 打印诊断信息后杀死当前任务，
+//! 系统继续运行；为 `oops=panic` 时与 `panic!` 相同，便于调试。
+//!
+//! 杀死任务时直接切换走，不会回到出错的位置，内核栈上的局部变量都不会析构。因此只有在
+//! 没有任何锁被持有、没有任何 `UPSafeCell` 被借用时才能恢复，否则仍然 panic。局部变量持有的
+//! 引用计数等资源仍会泄漏，所以能向上返回错误的地方应当返回错误，oops 只是最后的手段。
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{backtrace, cmdline, sync, task, task::Processor};
+
+/// 因 oops 被杀死的任务的退出码，取 SIGSEGV 的编号
+pub const OOPS_EXIT_CODE: i32 = -11;
+
+/// 启动以来发生的 oops 次数
+static OOPS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// 发生 oops 时的处理方式
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OopsPolicy {
+    /// 杀死当前任务，系统继续运行
+    Kill,
+    /// 与 panic 相同
+    Panic,
+}
+
+/// 当前任务出了只与它有关的错误。能够安全恢复时杀死它，否则 panic
+#[cold]
+pub fn oops(args: fmt::Arguments) -> ! {
+    let policy = cmdline::oops_policy();
+    let user_task = Processor::current_task().map_or(false, |task| !task.is_kernel_thread());
+    if policy == OopsPolicy::Panic || !user_task || sync::held_count() != 0 {
+        panic!("{}", args);
+    }
+    let count = OOPS_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    log::error!("[kernel] oops #{}: {}, task killed", count, args);
+    backtrace::backtrace();
+    task::exit_current_and_run_next(OOPS_EXIT_CODE);
+    unreachable!()
+}
+
+/// 以 `format!` 的格式报告一个 oops，见 [`oops`](crate::oops)
+#[macro_export]
+macro_rules! oops {
+    ($($arg: tt)+) => {
+        $crate::oops::oops(format_args!($($arg)+))
+    };
+}
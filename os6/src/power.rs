@@ -0,0 +1,24 @@
+//! Shutdown
+//!
+//! 所有关机路径（`sys_shutdown`、initproc 退出、panic）都经过这里：先把块缓存中被修改的块写回磁盘，
+//! 再关闭 QEMU，这样一次运行结束后磁盘镜像中不会缺少只留在缓存中的元数据。
+//!
+//! 正常关机时还把文件系统标记为干净地卸载。panic 时不能保证所有块都已写回，文件系统保持未卸载的状态，
+//! 下次挂载时会给出警告。
+
+use crate::{fs, sbi::exit::exit_qemu};
+
+/// 写回所有块缓存并卸载文件系统后关闭系统，QEMU 的退出码为 `code`
+pub fn poweroff(code: u16) -> ! {
+    println!("[kernel] unmounting filesystems");
+    fs::inode::unmount();
+    exit_qemu(code)
+}
+
+/// panic 时尽量写回块缓存。panic 时可能正持有某个块缓存的锁，持有者不会再释放它，
+/// 因此跳过被锁住的块而不是等待
+pub fn sync_after_panic() {
+    if !easy_fs::block_cache_try_sync_all() {
+        println!("[kernel] some block caches are locked, the disk image may be inconsistent");
+    }
+}
@@ -0,0 +1,177 @@
+//! Sampling profiler
+//!
+//! 命令行带有 `profile` 选项时，每个时钟中断周期登记一次定时器，到期时对被打断的位置采样：
+//! 系统调用期间被时钟中断打断时按内核的 sepc 计数，见 [`preempt::interrupted_pc`]；
+//! 用户态按 (pid, sepc) 计数；idle 控制流按 [`run_tasks`](crate::task::run_tasks) 的地址计数。
+//!
+//! 结果从 `/proc/profile` 读出，按采样次数从多到少排列。内核的采样用 [`ksyms`] 中的符号表
+//! 按函数汇总，没有符号表时（例如 `cargo test` 构建的内核）按地址列出：
+//!
+//! ```text
+//! kernel <function 或 pc> <count>
+//! user <pid> <pc> <count>
+//! ```
+//!
+//! 用户程序的符号不在内核中，地址在宿主机上用 `addr2line -f -e <elf>` 换成符号。
+
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::{
+    cmdline, ksyms,
+    sync::UPSafeCell,
+    task::{self, preempt, Processor},
+    timer::{self, TimerAction},
+};
+use lazy_static::lazy_static;
+
+/// 最多记录的不同地址数，之后新地址的采样只计入 `dropped`
+const MAX_ENTRIES: usize = 4096;
+
+#[derive(Default)]
+struct Profiler {
+    /// 内核态的采样，按地址计数
+    kernel: BTreeMap<usize, usize>,
+    /// 用户态的采样，按 (pid, pc) 计数
+    user: BTreeMap<(usize, usize), usize>,
+    /// 因为地址太多而没有记录的采样数
+    dropped: usize,
+}
+
+impl Profiler {
+    fn len(&self) -> usize {
+        self.kernel.len() + self.user.len()
+    }
+    fn record_kernel(&mut self, pc: usize) {
+        if self.kernel.contains_key(&pc) || self.len() < MAX_ENTRIES {
+            *self.kernel.entry(pc).or_insert(0) += 1;
+        } else {
+            self.dropped += 1;
+        }
+    }
+    fn record_user(&mut self, pid: usize, pc: usize) {
+        if self.user.contains_key(&(pid, pc)) || self.len() < MAX_ENTRIES {
+            *self.user.entry((pid, pc)).or_insert(0) += 1;
+        } else {
+            self.dropped += 1;
+        }
+    }
+    /// 把内核的采样按所在的函数汇总。`symbols` 按地址从小到大排列，和同样按地址排列的采样一起扫描一遍；
+    /// 第一个符号之前的地址原样列出
+    fn kernel_by_symbol(
+        &self,
+        symbols: impl Iterator<Item = (usize, &'static str)>,
+    ) -> BTreeMap<String, usize> {
+        let mut symbols = symbols.peekable();
+        let mut current = None;
+        let mut counts = BTreeMap::new();
+        for (&pc, &count) in self.kernel.iter() {
+            while let Some(&(addr, name)) = symbols.peek() {
+                if addr > pc {
+                    break;
+                }
+                current = Some(name);
+                symbols.next();
+            }
+            let label = match current {
+                Some(name) => String::from(name),
+                None => format!("{:#x}", pc),
+            };
+            *counts.entry(label).or_insert(0) += count;
+        }
+        counts
+    }
+    fn report(&self, symbols: impl Iterator<Item = (usize, &'static str)>) -> String {
+        let mut lines: Vec<(usize, String)> =
+            self.kernel_by_symbol(symbols)
+                .into_iter()
+                .map(|(label, count)| (count, format!("kernel {} {}", label, count)))
+                .chain(self.user.iter().map(|((pid, pc), &count)| {
+                    (count, format!("user {} {:#x} {}", pid, pc, count))
+                }))
+                .collect();
+        lines.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut report = String::new();
+        for (_, line) in lines {
+            writeln!(report, "{}", line).unwrap();
+        }
+        if self.dropped != 0 {
+            writeln!(report, "dropped {}", self.dropped).unwrap();
+        }
+        report
+    }
+}
+
+lazy_static! {
+    static ref PROFILER: UPSafeCell<Profiler> = unsafe { UPSafeCell::new(Profiler::default()) };
+}
+
+/// 命令行带有 `profile` 选项时开始采样，需要在 `cmdline::init` 之后调用
+pub fn init() {
+    if cmdline::profile() {
+        schedule_sample();
+    }
+}
+
+fn schedule_sample() {
+    let next = timer::get_time_us() + timer::ticks_to_us(1);
+    timer::add_timer(next, TimerAction::Call(sample));
+}
+
+/// 采样定时器到期，记录被打断的位置并登记下一次采样
+fn sample() {
+    if let Some(pc) = preempt::interrupted_pc() {
+        PROFILER.exclusive_access().record_kernel(pc);
+        schedule_sample();
+        return;
+    }
+    match Processor::current_task() {
+        Some(task) => {
+            let pc = task.inner_exclusive_access().trap_ctx().sepc;
+            PROFILER.exclusive_access().record_user(task.pid(), pc);
+        }
+        None => PROFILER
+            .exclusive_access()
+            .record_kernel(task::run_tasks as usize),
+    }
+    schedule_sample();
+}
+
+/// `/proc/profile` 的内容
+pub fn report() -> String {
+    PROFILER.exclusive_access().report(ksyms::symbols())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn report_sorts_by_count_and_caps_entries() {
+        let mut profiler = Profiler::default();
+        profiler.record_user(2, 0x1000);
+        profiler.record_kernel(0x8020_0000);
+        profiler.record_kernel(0x8020_0000);
+        assert_eq!(
+            profiler.report(core::iter::empty()),
+            "kernel 0x80200000 2\nuser 2 0x1000 1\n"
+        );
+        for pc in 0..MAX_ENTRIES {
+            profiler.record_user(3, pc);
+        }
+        assert_eq!(profiler.len(), MAX_ENTRIES);
+        assert_eq!(profiler.dropped, 2);
+    }
+    #[test_case]
+    fn kernel_samples_are_grouped_by_function() {
+        let mut profiler = Profiler::default();
+        for pc in [0x100, 0x1000, 0x1010, 0x1010, 0x2000, 0x2ff0] {
+            profiler.record_kernel(pc);
+        }
+        let symbols = [(0x1000, "os::a"), (0x1800, "os::b"), (0x2000, "os::c")];
+        assert_eq!(
+            profiler.report(symbols.iter().copied()),
+            "kernel os::a 3\nkernel os::c 2\nkernel 0x100 1\n"
+        );
+    }
+}
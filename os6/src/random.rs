@@ -0,0 +1,202 @@
+//! 内核随机数发生器
+//!
+//! 基于 ChaCha20 的 CSPRNG，启动时用周期计数器和时钟抖动播种，有 virtio 熵源设备时再混入设备
+//! 给出的随机字节。每输出 [`RESEED_INTERVAL`] 字节后重新播种一次，没有熵源设备时只能混入新的抖动样本。
+//! 每次输出结束后都会用新生成的密钥替换旧密钥（快速密钥擦除），即使之后内核状态泄露也无法倒推出
+//! 已经输出的随机数。
+
+use riscv::register::{cycle, time};
+
+use crate::{drivers::rng_device, sync::UPSafeCell};
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+/// ChaCha20 每块输出的字节数
+const BLOCK_SIZE: usize = 64;
+/// 启动时采集的抖动样本数
+const SEED_SAMPLES: usize = 64;
+/// 每次从熵源设备取得的字节数，与密钥一样长
+const SEED_BYTES: usize = 32;
+/// 两次重新播种之间最多输出的字节数
+const RESEED_INTERVAL: usize = 1 << 16;
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// 计算一个 ChaCha20 块。计数器为 64 位，nonce 为 64 位（原始的 ChaCha 布局）
+fn chacha20_block(key: &[u32; 8], counter: u64, nonce: [u32; 2]) -> [u32; 16] {
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    input[14..].copy_from_slice(&nonce);
+    let mut s = input;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+    for (s, input) in s.iter_mut().zip(input) {
+        *s = s.wrapping_add(input);
+    }
+    s
+}
+
+pub struct ChaChaRng {
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl ChaChaRng {
+    pub const fn new(key: [u32; 8]) -> Self {
+        Self { key, counter: 0 }
+    }
+    fn next_block(&mut self) -> [u32; 16] {
+        let block = chacha20_block(&self.key, self.counter, [0; 2]);
+        self.counter = self.counter.wrapping_add(1);
+        block
+    }
+    /// 用新生成的一块的前 32 字节替换密钥
+    fn rekey(&mut self) {
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..8]);
+    }
+    /// 把一个熵样本混入密钥
+    pub fn mix(&mut self, sample: u64) {
+        self.key[0] ^= sample as u32;
+        self.key[1] ^= (sample >> 32) as u32;
+        self.rekey();
+    }
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            let block = self.next_block();
+            for (dst, src) in chunk
+                .iter_mut()
+                .zip(block.iter().flat_map(|w| w.to_le_bytes()))
+            {
+                *dst = src;
+            }
+        }
+        self.rekey();
+    }
+}
+
+struct KernelRng {
+    chacha: ChaChaRng,
+    /// 上次重新播种之后输出的字节数
+    output: usize,
+}
+
+static RNG: UPSafeCell<KernelRng> = unsafe {
+    UPSafeCell::new(KernelRng {
+        chacha: ChaChaRng::new([0; 8]),
+        output: 0,
+    })
+};
+
+/// 采集一个抖动样本：空转固定次数，记录这段时间的周期数和时钟数
+fn jitter_sample(spins: usize) -> u64 {
+    let (cycle_start, time_start) = (cycle::read(), time::read());
+    for _ in 0..spins {
+        core::hint::spin_loop();
+    }
+    let cycles = cycle::read().wrapping_sub(cycle_start) as u64;
+    let ticks = time::read().wrapping_sub(time_start) as u64;
+    cycles.rotate_left(32) ^ ticks ^ cycle_start as u64
+}
+
+/// 混入 [`SEED_BYTES`] 字节的设备熵，没有熵源设备或者设备出错时返回 false
+fn mix_device_entropy(rng: &mut ChaChaRng) -> bool {
+    let device = match rng_device() {
+        Some(device) => device,
+        None => return false,
+    };
+    let mut seed = [0u8; SEED_BYTES];
+    let len = device.fill(&mut seed);
+    for chunk in seed[..len].chunks(8) {
+        let mut sample = [0u8; 8];
+        sample[..chunk.len()].copy_from_slice(chunk);
+        rng.mix(u64::from_le_bytes(sample));
+    }
+    len == SEED_BYTES
+}
+
+/// 用周期计数器和时钟抖动为随机数发生器播种，有熵源设备时再混入设备给出的随机字节
+pub fn init() {
+    let mut rng = RNG.exclusive_access();
+    let mut spins = 16;
+    for _ in 0..SEED_SAMPLES {
+        let sample = jitter_sample(spins);
+        rng.chacha.mix(sample);
+        // 让下一次空转的长度也依赖于本次的结果
+        spins = 16 + (sample as usize & 0xff);
+    }
+    if mix_device_entropy(&mut rng.chacha) {
+        log::info!("[kernel] random: seeded from virtio-rng");
+    } else {
+        log::info!("[kernel] random: no entropy device, seeded from timer jitter only");
+    }
+}
+
+/// 用随机字节填满 buf
+pub fn fill(buf: &mut [u8]) {
+    let mut rng = RNG.exclusive_access();
+    if rng.output >= RESEED_INTERVAL {
+        if !mix_device_entropy(&mut rng.chacha) {
+            rng.chacha.mix(jitter_sample(16));
+        }
+        rng.output = 0;
+    }
+    rng.chacha.mix(cycle::read() as u64);
+    rng.chacha.fill(buf);
+    rng.output = rng.output.saturating_add(buf.len());
+}
+
+/// 一个随机的 usize
+pub fn next_usize() -> usize {
+    let mut bytes = [0; core::mem::size_of::<usize>()];
+    fill(&mut bytes);
+    usize::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn chacha20_block_matches_rfc7539() {
+        // RFC 7539 2.3.2，96 位 nonce 的第一个字作为 64 位计数器的高 32 位
+        let key = core::array::from_fn(|i| {
+            let i = i as u32 * 4;
+            u32::from_le_bytes([i as u8, i as u8 + 1, i as u8 + 2, i as u8 + 3])
+        });
+        let block = chacha20_block(&key, 0x0900_0000_0000_0001, [0x4a00_0000, 0]);
+        assert_eq!(block[0], 0xe4e7_f110);
+        assert_eq!(block[1], 0x1559_3bd1);
+        assert_eq!(block[15], 0x4e3c_50a2);
+    }
+
+    #[test_case]
+    fn rng_erases_key_after_output() {
+        let mut rng = ChaChaRng::new([0; 8]);
+        let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+        rng.fill(&mut a);
+        assert_ne!(rng.key, [0; 8]);
+        rng.fill(&mut b);
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,61 @@
+//! QEMU exit device
+//!
+//! QEMU virt 机器上的 SiFive test 设备（`sifive,test0`）：向它写入 `FINISHER_PASS` 时 QEMU 以退出码 0 退出，
+//! 写入 `code << 16 | FINISHER_FAIL` 时以退出码 `code` 退出。这样脚本和 CI 可以从 QEMU 的退出码得知运行结果。
+//! 设备不存在时（例如在真实硬件上）退回到 SBI 关机，此时没有退出码。
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{dtb, mm::address::phys_to_virt};
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+
+/// 设备寄存器在线性映射区中的地址，为 0 表示设备不存在
+static TEST_DEVICE: AtomicUsize = AtomicUsize::new(0);
+
+/// 从设备树中找到 SiFive test 设备，需要在 `dtb::init` 之后调用。
+/// 先记下基址，panic 时关闭 QEMU 就不需要再访问设备树和分配内存
+pub fn init() {
+    if let Some(device) = dtb::devices()
+        .into_iter()
+        .find(|device| device.kind == dtb::DeviceKind::Test)
+    {
+        TEST_DEVICE.store(phys_to_virt(device.base), Ordering::Relaxed);
+    }
+}
+
+/// 关闭 QEMU，QEMU 的退出码为 `code`
+pub fn exit_qemu(code: u16) -> ! {
+    let base = TEST_DEVICE.load(Ordering::Relaxed);
+    if base != 0 {
+        let value = match code {
+            0 => FINISHER_PASS,
+            code => (code as u32) << 16 | FINISHER_FAIL,
+        };
+        // 启动页表和内核地址空间都线性映射了设备树中的所有 MMIO 设备
+        unsafe { (base as *mut u32).write_volatile(value) };
+    }
+    super::shutdown()
+}
+
+/// 进程的退出码转换为 QEMU 的退出码：非零的退出码不会变成 0
+pub fn exit_code(code: i32) -> u16 {
+    match code as u16 {
+        0 if code != 0 => 1,
+        code => code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn nonzero_exit_codes_stay_nonzero() {
+        assert_eq!(exit_code(0), 0);
+        assert_eq!(exit_code(3), 3);
+        assert_eq!(exit_code(-1), 0xffff);
+        assert_eq!(exit_code(0x10000), 1);
+    }
+}
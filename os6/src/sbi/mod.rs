@@ -0,0 +1,93 @@
+//! SBI call wrappers
+
+#![allow(unused)]
+
+pub mod exit;
+
+const SBI_SET_TIMER: usize = 0;
+const SBI_CONSOLE_PUTCHAR: usize = 1;
+const SBI_CONSOLE_GETCHAR: usize = 2;
+const SBI_SHUTDOWN: usize = 8;
+/// IPI Extension
+const SBI_IPI: usize = 0x735049;
+const IPI_SEND_IPI: usize = 0;
+/// System Reset Extension
+const SBI_SRST: usize = 0x53525354;
+const SRST_SYSTEM_RESET: usize = 0;
+const SRST_COLD_REBOOT: usize = 1;
+
+#[inline(always)]
+/// general sbi call
+fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+    let mut ret;
+    unsafe {
+        core::arch::asm!(
+            "li x16, 0",
+            "ecall",
+            inlateout("x10") arg0 => ret,
+            inlateout("x11") arg1 => _,
+            in("x12") arg2,
+            in("x17") which,
+        );
+    }
+    ret
+}
+
+#[inline(always)]
+/// SBI v0.2 call of function `fid` in extension `eid`, returning the (error, value) pair
+/// in a0 and a1
+fn sbi_call_v2(eid: usize, fid: usize, args: [usize; 3]) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => error,
+            inlateout("x11") args[1] => value,
+            in("x12") args[2],
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
+/// use sbi call to set timer
+pub fn set_timer(timer: usize) {
+    sbi_call(SBI_SET_TIMER, timer, 0, 0);
+}
+
+/// use sbi call to putchar in console (qemu uart handler)
+pub fn console_putchar(c: usize) {
+    sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
+}
+
+/// use sbi call to getchar from console (qemu uart handler)
+pub fn console_getchar() -> usize {
+    sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
+}
+
+/// use sbi call to send a supervisor software interrupt to the harts in `hart_mask`,
+/// whose bit 0 stands for hart `hart_mask_base`; returns the SBI error code if the call failed
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), isize> {
+    match sbi_call_v2(SBI_IPI, IPI_SEND_IPI, [hart_mask, hart_mask_base, 0]) {
+        (0, _) => Ok(()),
+        (error, _) => Err(error),
+    }
+}
+
+/// use sbi call to shutdown the kernel
+pub fn shutdown() -> ! {
+    sbi_call(SBI_SHUTDOWN, 0, 0, 0);
+    panic!("It should shutdown!");
+}
+
+/// use sbi call to reboot the machine; falls back to shutdown if SRST is not supported
+pub fn reboot() -> ! {
+    // system_reset 成功时不会返回
+    let (error, _) = sbi_call_v2(SBI_SRST, SRST_SYSTEM_RESET, [SRST_COLD_REBOOT, 0, 0]);
+    log::warn!(
+        "[kernel] SBI system reset failed with error {}, shutting down",
+        error
+    );
+    shutdown()
+}
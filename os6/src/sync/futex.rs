@@ -0,0 +1,64 @@
+//! Futex wait queues
+//!
+//! 等待同一个 futex 的任务按 futex 所在的物理地址分组，放在一个全局的散列表中。
+//! 用物理地址而不是虚拟地址作为键，这样映射到同一物理页的不同地址空间也能互相唤醒。
+//! 散列表只负责保存等待者，阻塞和唤醒任务由调用者完成。
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+
+use super::UPSafeCell;
+use crate::task::TaskControlBlock;
+
+/// 散列表的桶数
+const FUTEX_BUCKETS: usize = 64;
+
+type Bucket = VecDeque<(usize, Arc<TaskControlBlock>)>;
+
+lazy_static! {
+    static ref FUTEX_TABLE: UPSafeCell<[Bucket; FUTEX_BUCKETS]> =
+        unsafe { UPSafeCell::new(core::array::from_fn(|_| VecDeque::new())) };
+}
+
+fn bucket_of(pa: usize) -> usize {
+    // futex 至少按 4 字节对齐，低两位没有区分度
+    (pa >> 2) % FUTEX_BUCKETS
+}
+
+/// 将 `task` 加入物理地址 `pa` 处 futex 的等待队列
+pub fn enqueue(pa: usize, task: Arc<TaskControlBlock>) {
+    FUTEX_TABLE.exclusive_access()[bucket_of(pa)].push_back((pa, task));
+}
+
+/// 按等待的先后顺序从 `pa` 处 futex 的等待队列中取出至多 `count` 个任务
+pub fn dequeue(pa: usize, count: usize) -> Vec<Arc<TaskControlBlock>> {
+    let bucket = &mut FUTEX_TABLE.exclusive_access()[bucket_of(pa)];
+    let mut woken = Vec::new();
+    let mut i = 0;
+    while i < bucket.len() && woken.len() < count {
+        if bucket[i].0 == pa {
+            woken.push(bucket.remove(i).unwrap().1);
+        } else {
+            i += 1;
+        }
+    }
+    woken
+}
+
+/// 从所有等待队列中取出满足 `pred` 的任务，用于向等待中的任务发送信号
+pub fn dequeue_if(
+    mut pred: impl FnMut(&Arc<TaskControlBlock>) -> bool,
+) -> Vec<Arc<TaskControlBlock>> {
+    let mut woken = Vec::new();
+    for bucket in FUTEX_TABLE.exclusive_access().iter_mut() {
+        bucket.retain(|(_, task)| {
+            if pred(task) {
+                woken.push(Arc::clone(task));
+                false
+            } else {
+                true
+            }
+        });
+    }
+    woken
+}
@@ -1,4 +1,23 @@
-use core::cell::{RefCell, RefMut};
+pub mod futex;
+mod spin;
+pub mod wait_queue;
+
+pub use spin::{KSpinLock, KSpinLockGuard};
+
+use core::{
+    cell::{RefCell, RefMut},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 当前被持有的 [`KSpinLock`] 和被借用的 [`UPSafeCell`] 的总数
+static HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// 当前被持有的锁和被借用的 `UPSafeCell` 的总数。为 0 时放弃当前的控制流不会留下永远无法释放的锁，
+/// 见 [`oops`](crate::oops)
+pub fn held_count() -> usize {
+    HELD.load(Ordering::Relaxed)
+}
 
 /// Wrap a static data structure inside it so that we are
 /// able to access it without any `unsafe`.
@@ -23,7 +42,42 @@ impl<T> UPSafeCell<T> {
         }
     }
     /// Panic if the data has been borrowed.
-    pub fn exclusive_access(&self) -> RefMut<'_, T> {
-        self.inner.borrow_mut()
+    pub fn exclusive_access(&self) -> UPRefMut<'_, T> {
+        UPRefMut::new(self.inner.borrow_mut())
+    }
+    /// Return `None` instead of panicking if the data has been borrowed.
+    pub fn try_exclusive_access(&self) -> Option<UPRefMut<'_, T>> {
+        self.inner.try_borrow_mut().ok().map(UPRefMut::new)
+    }
+}
+
+/// [`UPSafeCell`] 的借用，借用期间计入 [`held_count`]
+pub struct UPRefMut<'a, T> {
+    inner: RefMut<'a, T>,
+}
+
+impl<'a, T> UPRefMut<'a, T> {
+    fn new(inner: RefMut<'a, T>) -> Self {
+        HELD.fetch_add(1, Ordering::Relaxed);
+        Self { inner }
+    }
+}
+
+impl<T> Deref for UPRefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for UPRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for UPRefMut<'_, T> {
+    fn drop(&mut self) {
+        HELD.fetch_sub(1, Ordering::Relaxed);
     }
 }
@@ -0,0 +1,124 @@
+//! Kernel spin lock
+//!
+//! 与 [`UPSafeCell`](super::UPSafeCell) 一样只适用于单处理器。内核态只响应不访问共享数据的时钟中断，
+//! 也只在不持有锁的抢占点切换任务，持有锁时不会被抢占，
+//! 所以获取锁失败只可能是同一控制流重入，自旋也等不到锁被释放。这时直接 panic，报告锁的名字和
+//! 再次获取锁的位置；打开 `lock-debug` feature 时还会记录并报告当前持有者获取锁的位置。
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::HELD;
+
+#[cfg(feature = "lock-debug")]
+use core::cell::Cell;
+
+pub struct KSpinLock<T> {
+    /// 锁的名字，用于诊断信息
+    name: &'static str,
+    locked: AtomicBool,
+    /// 当前持有者获取锁的位置
+    #[cfg(feature = "lock-debug")]
+    holder: Cell<Option<&'static Location<'static>>>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for KSpinLock<T> {}
+
+impl<T> KSpinLock<T> {
+    /// 调用者需要保证只在单处理器上使用
+    pub const unsafe fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            locked: AtomicBool::new(false),
+            #[cfg(feature = "lock-debug")]
+            holder: Cell::new(None),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// 获取锁，已被持有时 panic
+    #[track_caller]
+    pub fn lock(&self) -> KSpinLockGuard<'_, T> {
+        match self.try_lock() {
+            Some(guard) => guard,
+            None => self.relock_panic(Location::caller()),
+        }
+    }
+
+    /// 获取锁，已被持有时返回 `None`
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<KSpinLockGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        #[cfg(feature = "lock-debug")]
+        self.holder.set(Some(Location::caller()));
+        HELD.fetch_add(1, Ordering::Relaxed);
+        Some(KSpinLockGuard { lock: self })
+    }
+
+    #[cold]
+    fn relock_panic(&self, at: &Location) -> ! {
+        #[cfg(feature = "lock-debug")]
+        if let Some(holder) = self.holder.get() {
+            panic!(
+                "{} locked again at {}, already held since {}",
+                self.name, at, holder
+            );
+        }
+        panic!(
+            "{} locked again at {} while already held (enable the lock-debug feature to see where)",
+            self.name, at
+        );
+    }
+}
+
+pub struct KSpinLockGuard<'a, T> {
+    lock: &'a KSpinLock<T>,
+}
+
+impl<T> Deref for KSpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for KSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for KSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lock-debug")]
+        self.lock.holder.set(None);
+        self.lock.locked.store(false, Ordering::Release);
+        HELD.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn lock_is_released_when_guard_drops() {
+        let lock = unsafe { KSpinLock::new("test", 1) };
+        let held = crate::sync::held_count();
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            assert!(lock.try_lock().is_none());
+            assert_eq!(crate::sync::held_count(), held + 1);
+        }
+        assert_eq!(crate::sync::held_count(), held);
+        assert_eq!(*lock.try_lock().unwrap(), 2);
+    }
+}
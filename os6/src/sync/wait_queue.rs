@@ -0,0 +1,109 @@
+//! Wait queues
+//!
+//! 等待队列保存阻塞在某个条件上的任务，条件满足时由另一方唤醒。它只负责等待者的记录，
+//! 阻塞和唤醒任务通过 [`task::block_current_and_run_next`] 和 [`task::wakeup_task`] 完成。
+//!
+//! 任务也可能在条件满足之前被唤醒，例如收到了信号，因此等待者醒来后总是需要重新检查条件。
+
+use alloc::{sync::Arc, vec::Vec};
+
+use super::UPSafeCell;
+use crate::task::{self, Processor, TaskControlBlock};
+
+/// 按先来先服务的顺序排列的等待者
+#[derive(Default)]
+pub struct WaitQueue {
+    waiters: Vec<Arc<TaskControlBlock>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+
+    pub fn contains(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.waiters.iter().any(|waiter| Arc::ptr_eq(waiter, task))
+    }
+
+    /// 加入等待队列，已经在队列中时什么也不做
+    pub fn add_waiter(&mut self, task: Arc<TaskControlBlock>) {
+        if !self.contains(&task) {
+            self.waiters.push(task);
+        }
+    }
+
+    /// 从等待队列中移除 `task`，它不在队列中时什么也不做
+    pub fn remove_waiter(&mut self, task: &Arc<TaskControlBlock>) {
+        self.waiters.retain(|waiter| !Arc::ptr_eq(waiter, task));
+    }
+
+    /// 唤醒等待最久的任务，队列为空时返回 `false`
+    pub fn wake_one(&mut self) -> bool {
+        if self.waiters.is_empty() {
+            return false;
+        }
+        task::wakeup_task(self.waiters.remove(0));
+        true
+    }
+
+    /// 唤醒所有等待者，返回唤醒的任务数
+    pub fn wake_all(&mut self) -> usize {
+        let waiters = core::mem::take(&mut self.waiters);
+        let count = waiters.len();
+        waiters.into_iter().for_each(task::wakeup_task);
+        count
+    }
+
+    /// 唤醒满足 `pred` 的等待者，返回唤醒的任务数
+    pub fn wake_if(&mut self, mut pred: impl FnMut(&Arc<TaskControlBlock>) -> bool) -> usize {
+        let mut woken = Vec::new();
+        self.waiters.retain(|waiter| {
+            if pred(waiter) {
+                woken.push(Arc::clone(waiter));
+                false
+            } else {
+                true
+            }
+        });
+        let count = woken.len();
+        woken.into_iter().for_each(task::wakeup_task);
+        count
+    }
+}
+
+/// 把当前任务加入 `cell` 中由 `queue` 选出的等待队列，然后阻塞直到被唤醒。
+///
+/// 调用者不能持有 `cell` 的借用。醒来后当前任务会把自己从队列中移除，以免因信号等原因
+/// 提前醒来时留在队列中，白白消耗之后的一次唤醒
+pub fn wait_on<T>(cell: &UPSafeCell<T>, queue: impl Fn(&mut T) -> &mut WaitQueue) {
+    let current = Processor::current_task().unwrap();
+    queue(&mut cell.exclusive_access()).add_waiter(Arc::clone(&current));
+    task::block_current_and_run_next();
+    queue(&mut cell.exclusive_access()).remove_waiter(&current);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn waiters_are_woken_in_fifo_order() {
+        let first = Arc::new(TaskControlBlock::new_kthread(|| {}));
+        let second = Arc::new(TaskControlBlock::new_kthread(|| {}));
+        let mut queue = WaitQueue::new();
+        queue.add_waiter(Arc::clone(&first));
+        queue.add_waiter(Arc::clone(&second));
+        queue.add_waiter(Arc::clone(&first));
+        assert!(queue.wake_one());
+        assert!(!queue.contains(&first));
+        assert!(queue.contains(&second));
+        assert_eq!(queue.wake_all(), 1);
+        assert!(!queue.wake_one());
+    }
+}
@@ -1,26 +1,78 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::convert::TryFrom;
+
 use crate::{
+    config::PATH_MAX,
     fs::{
-        self,
-        inode::{OpenFlags, ROOT_INODE},
-        Stat,
+        eventfd::EventFd,
+        flock::{FlockError, LockKind},
+        flusher,
+        inode::{self, OpenError, OpenFlags, ROOT_INODE},
+        pipe::make_pipe,
+        proc, stdio, FdFlags, File, FileDescriptor, IoError, PollEvents, Stat,
+    },
+    mm::{
+        page_table::{self, PageTable, TranslateError, UserBuffer},
+        user::Access,
     },
-    mm::page_table::{self, PageTable, UserBuffer},
-    task::Processor,
+    task::{self, Processor},
+    timer::{self, MICRO_PER_SEC},
 };
 
+use super::Errno;
+
+/// `sys_open` 新建文件时使用的权限位，再去掉 umask 中的位
+const CREATE_MODE: u16 = 0o666;
+
+impl From<OpenError> for Errno {
+    fn from(err: OpenError) -> Self {
+        match err {
+            OpenError::NotFound => Errno::ENOENT,
+            OpenError::Exists => Errno::EEXIST,
+            OpenError::NotDir => Errno::ENOTDIR,
+            OpenError::PermissionDenied => Errno::EACCES,
+            OpenError::InvalidFlags => Errno::EINVAL,
+        }
+    }
+}
+
+impl From<IoError> for Errno {
+    fn from(err: IoError) -> Self {
+        match err {
+            IoError::WouldBlock => Errno::EAGAIN,
+            IoError::Interrupted => Errno::EINTR,
+            IoError::Socket(err) => Errno::from(err),
+        }
+    }
+}
+
+impl From<FlockError> for Errno {
+    fn from(err: FlockError) -> Self {
+        match err {
+            FlockError::Unsupported => Errno::EINVAL,
+            FlockError::WouldBlock => Errno::EAGAIN,
+        }
+    }
+}
+
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let task = Processor::current_task().unwrap();
     let inner = task.inner_exclusive_access();
-    if let Some(Some(file)) = inner.fd_table.get(fd) {
-        let file = file.clone();
-        assert!(file.writable());
-        drop(inner);
-        let satp = Processor::current_user_satp();
-        file.write(UserBuffer::new(page_table::translated_byte_buffer(
-            satp, buf, len,
-        ))) as isize
-    } else {
-        -1
+    match inner.fd_table.get(fd) {
+        Some(Some(desc)) if desc.file.writable() => {
+            let desc = desc.clone();
+            drop(inner);
+            let satp = Processor::current_user_satp();
+            let buf = match page_table::translated_byte_buffer(satp, buf, len, Access::Read) {
+                Ok(buffers) => UserBuffer::new(buffers),
+                Err(err) => return Errno::from(err).into(),
+            };
+            match desc.write(buf) {
+                Ok(write_size) => write_size as isize,
+                Err(err) => Errno::from(err).into(),
+            }
+        }
+        _ => Errno::EBADF.into(),
     }
 }
 
@@ -28,22 +80,165 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
 ///
 /// 参数：fd 是待读取文件的文件描述符，切片 buffer 则给出缓冲区。
 ///
-/// 返回值：如果出现了错误则返回 -1，否则返回实际读到的字节数。
+/// 返回值：成功返回实际读到的字节数；fd 无效或不可读时返回 -EBADF，
+/// fd 设置了 O_NONBLOCK 而暂时没有数据可读时返回 -EAGAIN，等待时被信号打断而没有读到数据时返回 -EINTR。
 ///
 /// syscall ID：63
 pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     let task = Processor::current_task().unwrap();
     let inner = task.inner_exclusive_access();
-    if let Some(Some(file)) = inner.fd_table.get(fd) {
-        let file = file.clone();
-        assert!(file.readable());
-        drop(inner);
-        let satp = Processor::current_user_satp();
-        file.read(UserBuffer::new(page_table::translated_byte_buffer(
-            satp, buf, len,
-        ))) as isize
-    } else {
-        -1
+    match inner.fd_table.get(fd) {
+        Some(Some(desc)) if desc.file.readable() => {
+            let desc = desc.clone();
+            drop(inner);
+            let satp = Processor::current_user_satp();
+            let buf = match page_table::translated_byte_buffer(satp, buf, len, Access::Write) {
+                Ok(buffers) => UserBuffer::new(buffers),
+                Err(err) => return Errno::from(err).into(),
+            };
+            match desc.read(buf) {
+                Ok(read_size) => read_size as isize,
+                Err(err) => Errno::from(err).into(),
+            }
+        }
+        _ => Errno::EBADF.into(),
+    }
+}
+
+/// 一次 `readv`/`writev` 最多的缓冲区数，与 Linux 的 `IOV_MAX` 一致
+const IOV_MAX: usize = 1024;
+
+#[repr(C)]
+pub struct IoVec {
+    pub base: *mut u8,
+    pub len: usize,
+}
+
+/// 把用户的 iovec 数组翻译为一个由多段组成的 `UserBuffer`
+///
+/// `access` 为对缓冲区的访问方式，`readv` 要写入缓冲区，`writev` 只读取缓冲区
+fn translated_iovecs(
+    satp: usize,
+    iov: *const IoVec,
+    iovcnt: usize,
+    access: Access,
+) -> Result<UserBuffer, TranslateError> {
+    let mut buffers = Vec::new();
+    for i in 0..iovcnt {
+        let iov = PageTable::translated_ref(satp, iov.wrapping_add(i))?;
+        buffers.extend(page_table::translated_byte_buffer(
+            satp, iov.base, iov.len, access,
+        )?);
+    }
+    Ok(UserBuffer::new(buffers))
+}
+
+/// 功能：把 iov 描述的 iovcnt 个缓冲区中的内容依次写入文件，如同一次 write。
+///
+/// 返回值：成功返回写入的总字节数；fd 无效或不可写返回 -EBADF，iovcnt 超过 IOV_MAX 返回 -EINVAL。
+///
+/// syscall ID：66
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    if iovcnt > IOV_MAX {
+        return Errno::EINVAL.into();
+    }
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(desc)) if desc.file.writable() => {
+            let desc = desc.clone();
+            let satp = inner.user_satp();
+            drop(inner);
+            match translated_iovecs(satp, iov, iovcnt, Access::Read) {
+                Ok(buf) => match desc.write(buf) {
+                    Ok(write_size) => write_size as isize,
+                    Err(err) => Errno::from(err).into(),
+                },
+                Err(err) => Errno::from(err).into(),
+            }
+        }
+        _ => Errno::EBADF.into(),
+    }
+}
+
+/// 功能：从文件中读取内容，依次填入 iov 描述的 iovcnt 个缓冲区，如同一次 read。
+///
+/// 返回值：成功返回读到的总字节数；fd 无效或不可读返回 -EBADF，iovcnt 超过 IOV_MAX 返回 -EINVAL。
+///
+/// syscall ID：65
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    if iovcnt > IOV_MAX {
+        return Errno::EINVAL.into();
+    }
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(desc)) if desc.file.readable() => {
+            let desc = desc.clone();
+            let satp = inner.user_satp();
+            drop(inner);
+            match translated_iovecs(satp, iov, iovcnt, Access::Write) {
+                Ok(buf) => match desc.read(buf) {
+                    Ok(read_size) => read_size as isize,
+                    Err(err) => Errno::from(err).into(),
+                },
+                Err(err) => Errno::from(err).into(),
+            }
+        }
+        _ => Errno::EBADF.into(),
+    }
+}
+
+/// 功能：从文件的 offset 处读取一段内容到缓冲区，不改变文件的读写位置。
+/// 多个任务共享同一个打开的文件时可以各自读取，不会争用读写位置。
+///
+/// 返回值：成功返回实际读到的字节数；fd 无效或不可读返回 -EBADF，文件不支持定位读写（如管道）返回 -ESPIPE。
+///
+/// syscall ID：67
+pub fn sys_pread64(fd: usize, buf: *mut u8, len: usize, offset: usize) -> isize {
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(desc)) if desc.file.readable() => {
+            let file = desc.file.clone();
+            let satp = inner.user_satp();
+            drop(inner);
+            let buf = match page_table::translated_byte_buffer(satp, buf, len, Access::Write) {
+                Ok(buffers) => UserBuffer::new(buffers),
+                Err(err) => return Errno::from(err).into(),
+            };
+            match file.read_at(offset, buf) {
+                Some(read_size) => read_size as isize,
+                None => Errno::ESPIPE.into(),
+            }
+        }
+        _ => Errno::EBADF.into(),
+    }
+}
+
+/// 功能：把缓冲区中的内容写入文件的 offset 处，不改变文件的读写位置。
+///
+/// 返回值：成功返回写入的字节数；fd 无效或不可写返回 -EBADF，文件不支持定位读写（如管道）返回 -ESPIPE。
+///
+/// syscall ID：68
+pub fn sys_pwrite64(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(desc)) if desc.file.writable() => {
+            let file = desc.file.clone();
+            let satp = inner.user_satp();
+            drop(inner);
+            let buf = match page_table::translated_byte_buffer(satp, buf, len, Access::Read) {
+                Ok(buffers) => UserBuffer::new(buffers),
+                Err(err) => return Errno::from(err).into(),
+            };
+            match file.write_at(offset, buf) {
+                Some(write_size) => write_size as isize,
+                None => Errno::ESPIPE.into(),
+            }
+        }
+        _ => Errno::EBADF.into(),
     }
 }
 
@@ -58,29 +253,64 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 /// - flags\[2\]=1 即 flags=0x002，表示可读可写，即 RDRW
 /// - flags\[9\]=1 即 flags=0x200，表示创建文件，即 CREATE
 /// - flags\[10\]=1 即 flags=0x400，表示打开文件时应该清空文件内容并将文件大小归零，即 TRUNC
+/// - flags\[7\]=1 即 flags=0x080，与 CREATE 一起使用时文件已存在则失败，即 EXCL
+/// - flags\[16\]=1 即 flags=0x10000，目标不是目录则失败，即 DIRECTORY
+/// - flags\[11\]=1 即 flags=0x800，通过返回的 fd 读写时不阻塞，即 NONBLOCK
+///
+/// 新建文件的权限位为 0o666 去掉当前进程 umask 中的位。
+///
+/// `/proc/<pid>/statm` 是只读的虚拟文件，内容为该进程地址空间的
+/// “size resident shared text data lib dt”七个页数，其中 resident 包括页表占用的页帧，
+/// shared 为与其它逻辑段共享的页帧数。
 ///
-/// 返回值：如果出现了错误则返回 -1，否则返回打开常规文件的文件描述符。可能的错误原因是：文件不存在。
+/// 返回值：成功返回打开常规文件的文件描述符，出错返回错误码的相反数：
+///
+/// - -ENOENT：文件不存在，且没有指定 CREATE
+/// - -EEXIST：指定了 CREATE | EXCL，但文件已存在
+/// - -ENOTDIR：指定了 DIRECTORY，但文件不是目录
+/// - -EACCES：文件的权限位不允许所要求的读写
+/// - -EINVAL：flags 不合法，如同时指定了 CREATE 和 DIRECTORY
+/// - -EMFILE：当前进程打开的文件数已达上限
 ///
 /// syscall ID：56
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let flags = match OpenFlags::from_bits(flags) {
         Some(flags) => flags,
-        None => return -1,
+        None => return Errno::EINVAL.into(),
     };
     let user_satp = Processor::current_user_satp();
-    let path = PageTable::translated_str(user_satp, path);
-    let os_inode = match fs::open_file(&path, flags) {
-        Some(os_inode) => os_inode,
-        None => return -1,
+    let path = match PageTable::translated_str(user_satp, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let umask = Processor::current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .umask;
+    let opened: Result<Arc<dyn File + Send + Sync>, OpenError> =
+        match proc::open(&path, flags.read_write().1) {
+            Some(opened) => opened.map(|file| file as _),
+            None => {
+                inode::open_file_with_mode(&path, flags, CREATE_MODE & !umask).map(|file| file as _)
+            }
+        };
+    let file = match opened {
+        Ok(file) => file,
+        Err(err) => return Errno::from(err).into(),
     };
     let task = Processor::current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
-    let fd = inner.alloc_fd();
-    inner.fd_table[fd] = Some(os_inode);
-    fd as isize
+    match inner.alloc_fd() {
+        Some(fd) => {
+            let fd_flags = FdFlags::from_bits_truncate(flags.bits());
+            inner.fd_table[fd] = Some(FileDescriptor::new(file, fd_flags));
+            fd as isize
+        }
+        None => Errno::EMFILE.into(),
+    }
 }
 
-/// 关闭文件。出错返回 -1，如传入的文件描述符并不对应一个打开的文件
+/// 关闭文件。传入的文件描述符并不对应一个打开的文件时返回 -EBADF
 ///
 /// syscall ID：57
 pub fn sys_close(fd: usize) -> isize {
@@ -88,10 +318,326 @@ pub fn sys_close(fd: usize) -> isize {
     let mut inner = task.inner_exclusive_access();
     match inner.fd_table.get_mut(fd) {
         Some(file) if file.is_some() => {
-            file.take();
+            // 关闭时可能释放文件锁并唤醒其他任务，因此先释放借用
+            let file = file.take();
+            drop(inner);
+            drop(file);
             0
         }
-        _ => -1,
+        _ => Errno::EBADF.into(),
+    }
+}
+
+const LOCK_SH: u32 = 1;
+const LOCK_EX: u32 = 2;
+const LOCK_NB: u32 = 4;
+const LOCK_UN: u32 = 8;
+
+/// 功能：给打开的文件整体加劝告锁或解锁。锁属于打开的文件本身，fork 得到的描述符共享同一把锁，
+/// 所有引用都关闭后锁自动释放。
+///
+/// 参数：op 为 LOCK_SH(1) 加共享锁，LOCK_EX(2) 加排他锁，LOCK_UN(8) 解锁；
+/// 可以与 LOCK_NB(4) 组合，此时锁被他人持有也不会阻塞。已持有锁时再加另一种锁会先释放原来的锁。
+///
+/// 返回值：成功返回 0；fd 无效返回 -EBADF，op 不合法或文件不支持加锁返回 -EINVAL，
+/// 指定了 LOCK_NB 而锁被他人持有返回 -EAGAIN。
+///
+/// syscall ID：32
+pub fn sys_flock(fd: usize, op: u32) -> isize {
+    let kind = match op & !LOCK_NB {
+        LOCK_SH => Some(LockKind::Shared),
+        LOCK_EX => Some(LockKind::Exclusive),
+        LOCK_UN => None,
+        _ => return Errno::EINVAL.into(),
+    };
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(desc)) => desc.file.clone(),
+        _ => return Errno::EBADF.into(),
+    };
+    drop(inner);
+    match file.flock(kind, op & LOCK_NB != 0) {
+        Ok(()) => 0,
+        Err(err) => Errno::from(err).into(),
+    }
+}
+
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+
+/// 功能：查询或修改文件描述符的标志。
+///
+/// 参数：cmd 为 F_GETFL(3) 时返回 fd 的访问模式和标志，忽略 arg；
+/// cmd 为 F_SETFL(4) 时把 fd 的标志设为 arg，只支持 O_NONBLOCK，其它位被忽略。
+///
+/// 返回值：F_GETFL 返回访问模式（O_RDONLY、O_WRONLY 或 O_RDWR）与标志的按位或，
+/// F_SETFL 成功返回 0；fd 无效返回 -EBADF，cmd 不支持返回 -EINVAL。
+///
+/// syscall ID：25
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let desc = match inner.fd_table.get_mut(fd) {
+        Some(Some(desc)) => desc,
+        _ => return Errno::EBADF.into(),
+    };
+    match cmd {
+        F_GETFL => {
+            let access_mode = match (desc.file.readable(), desc.file.writable()) {
+                (true, false) => OpenFlags::RDONLY,
+                (false, true) => OpenFlags::WRONLY,
+                _ => OpenFlags::RDWR,
+            };
+            (access_mode.bits() | desc.flags.bits()) as isize
+        }
+        F_SETFL => {
+            desc.flags = FdFlags::from_bits_truncate(arg as u32);
+            0
+        }
+        _ => Errno::EINVAL.into(),
+    }
+}
+
+const TIOCGPGRP: usize = 0x540f;
+const TIOCSPGRP: usize = 0x5410;
+
+/// 功能：控制设备。目前只支持控制台的 TIOCGPGRP(0x540f) 和 TIOCSPGRP(0x5410)，
+/// 即 tcgetpgrp 和 tcsetpgrp。
+///
+/// 参数：fd 为控制台的文件描述符，request 为请求，argp 指向一个 i32 的进程组号：
+/// TIOCGPGRP 时写入前台进程组，TIOCSPGRP 时把它设为前台进程组。
+///
+/// 返回值：成功返回 0；fd 无效返回 -EBADF，fd 不是控制台返回 -ENOTTY，request 不支持返回 -EINVAL，
+/// 要设为前台的进程组不在当前进程的会话中返回 -EPERM。
+///
+/// syscall ID：29
+pub fn sys_ioctl(fd: usize, request: usize, argp: *mut i32) -> isize {
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(desc)) => desc.file.clone(),
+        _ => return Errno::EBADF.into(),
+    };
+    let (satp, sid) = (inner.user_satp(), inner.sid);
+    drop(inner);
+    if !file.is_tty() {
+        return Errno::ENOTTY.into();
+    }
+    match request {
+        TIOCGPGRP => match PageTable::translated_mut(satp, argp) {
+            Ok(pgid) => {
+                *pgid = stdio::foreground_pgrp() as i32;
+                0
+            }
+            Err(err) => Errno::from(err).into(),
+        },
+        TIOCSPGRP => {
+            let pgid = match PageTable::translated_ref(satp, argp) {
+                Ok(&pgid) => pgid,
+                Err(err) => return Errno::from(err).into(),
+            };
+            match usize::try_from(pgid) {
+                Ok(pgid) if task::pgrp_in_session(pgid, sid) => {
+                    stdio::set_foreground_pgrp(pgid);
+                    0
+                }
+                Ok(_) => Errno::EPERM.into(),
+                Err(_) => Errno::EINVAL.into(),
+            }
+        }
+        _ => Errno::EINVAL.into(),
+    }
+}
+
+/// 功能：为当前进程打开一个管道。
+///
+/// 参数：pipe 表示应用地址空间中的一个长度为 2 的 usize 数组的起始地址，
+/// 内核需要按顺序将管道读端和写端的文件描述符写入到数组中。
+///
+/// flags 只支持 O_NONBLOCK，同时作用于两端。
+///
+/// 返回值：成功返回 0，flags 不合法返回 -EINVAL，当前进程打开的文件数已达上限时返回 -EMFILE。
+///
+/// syscall ID：59
+pub fn sys_pipe(pipe: *mut usize, flags: u32) -> isize {
+    let fd_flags = match FdFlags::from_bits(flags) {
+        Some(fd_flags) => fd_flags,
+        None => return Errno::EINVAL.into(),
+    };
+    // 先取得写回文件描述符的位置，免得分配了文件描述符之后才发现 pipe 无效
+    let satp = Processor::current_user_satp();
+    let read_end = match PageTable::translated_mut(satp, pipe) {
+        Ok(read_end) => read_end,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let write_end = match PageTable::translated_mut(satp, pipe.wrapping_add(1)) {
+        Ok(write_end) => write_end,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let (pipe_read, pipe_write) = make_pipe();
+    let read_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return Errno::EMFILE.into(),
+    };
+    inner.fd_table[read_fd] = Some(FileDescriptor::new(pipe_read, fd_flags));
+    let write_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            inner.fd_table[read_fd] = None;
+            return Errno::EMFILE.into();
+        }
+    };
+    inner.fd_table[write_fd] = Some(FileDescriptor::new(pipe_write, fd_flags));
+    drop(inner);
+    *read_end = read_fd;
+    *write_end = write_fd;
+    0
+}
+
+const EFD_SEMAPHORE: u32 = 1;
+const EFD_NONBLOCK: u32 = FdFlags::NONBLOCK.bits();
+/// 没有 exec 时关闭文件的机制，接受但忽略
+const EFD_CLOEXEC: u32 = 1 << 19;
+
+/// 功能：创建一个事件通知对象，返回访问它的文件描述符。
+///
+/// 对象内部有一个 64 位计数器，初值为 initval。read 需要至少 8 字节的缓冲区，取走计数器的值并清零，
+/// 计数器为零时阻塞；write 把缓冲区中的 8 字节整数加到计数器上，计数器会超过 2^64-2 时阻塞。
+/// 缓冲区不足 8 字节或写入 2^64-1 时读写返回 0。计数器不为零时 ppoll 报告可读。
+///
+/// 参数：flags 可以是以下值的组合：
+/// - EFD_SEMAPHORE(1)：每次 read 只取走 1
+/// - EFD_NONBLOCK(0x800)：读写不阻塞，本会阻塞时返回 -EAGAIN
+/// - EFD_CLOEXEC(0x80000)：忽略
+///
+/// 返回值：成功返回文件描述符；flags 不合法返回 -EINVAL，当前进程打开的文件数已达上限时返回 -EMFILE。
+///
+/// syscall ID：19
+pub fn sys_eventfd2(initval: u32, flags: u32) -> isize {
+    if flags & !(EFD_SEMAPHORE | EFD_NONBLOCK | EFD_CLOEXEC) != 0 {
+        return Errno::EINVAL.into();
+    }
+    let eventfd = Arc::new(EventFd::new(initval as u64, flags & EFD_SEMAPHORE != 0));
+    let fd_flags = FdFlags::from_bits_truncate(flags);
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    match inner.alloc_fd() {
+        Some(fd) => {
+            inner.fd_table[fd] = Some(FileDescriptor::new(eventfd, fd_flags));
+            fd as isize
+        }
+        None => Errno::EMFILE.into(),
+    }
+}
+
+/// 一次 `ppoll` 最多等待的文件数
+const POLL_MAX_FDS: usize = 1024;
+
+#[repr(C)]
+pub struct PollFd {
+    /// 为负数时忽略这一项
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+#[repr(C)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn from_us(us: usize) -> Self {
+        Self {
+            sec: us / MICRO_PER_SEC,
+            nsec: us % MICRO_PER_SEC * 1000,
+        }
+    }
+    pub fn as_us(&self) -> usize {
+        self.sec * MICRO_PER_SEC + self.nsec / 1000
+    }
+}
+
+/// 功能：等待一组文件中的任意一个就绪。
+///
+/// 参数：fds 为长度为 nfds 的 PollFd 数组，events 为关心的事件（POLLIN、POLLOUT），
+/// 内核将就绪的事件写入 revents，POLLERR、POLLHUP 和 POLLNVAL 总是会报告；
+/// timeout 为等待的最长时间，为空指针时表示一直等待。
+///
+/// 返回值：返回 revents 不为 0 的项数，超时返回 0，nfds 过大或 timeout 不合法返回 -EINVAL。
+///
+/// syscall ID：73
+pub fn sys_ppoll(fds: *mut PollFd, nfds: usize, timeout: *const TimeSpec) -> isize {
+    if nfds > POLL_MAX_FDS {
+        return Errno::EINVAL.into();
+    }
+    let satp = Processor::current_user_satp();
+    let deadline = if timeout.is_null() {
+        None
+    } else {
+        let timeout = match PageTable::translated_ref(satp, timeout) {
+            Ok(timeout) => timeout,
+            Err(err) => return Errno::from(err).into(),
+        };
+        if timeout.nsec >= 1_000_000_000 {
+            return Errno::EINVAL.into();
+        }
+        Some(timer::get_time_us() + timeout.sec * MICRO_PER_SEC + timeout.nsec / 1000)
+    };
+    loop {
+        let task = Processor::current_task().unwrap();
+        let files: Result<Vec<_>, TranslateError> = (0..nfds)
+            .map(|i| {
+                let poll_fd = PageTable::translated_mut(satp, fds.wrapping_add(i))?;
+                let file = usize::try_from(poll_fd.fd)
+                    .ok()
+                    .and_then(|fd| task.inner_exclusive_access().fd_table.get(fd).cloned())
+                    .flatten()
+                    .map(|desc| desc.file);
+                Ok((poll_fd, file))
+            })
+            .collect();
+        let mut files = match files {
+            Ok(files) => files,
+            Err(err) => return Errno::from(err).into(),
+        };
+        let mut ready = 0;
+        for (poll_fd, file) in files.iter_mut() {
+            let revents = match file {
+                Some(file) => {
+                    let interest = PollEvents::from_bits_truncate(poll_fd.events as u16)
+                        | PollEvents::POLLERR
+                        | PollEvents::POLLHUP;
+                    file.poll() & interest
+                }
+                None if poll_fd.fd >= 0 => PollEvents::POLLNVAL,
+                None => PollEvents::empty(),
+            };
+            poll_fd.revents = revents.bits() as i16;
+            if !revents.is_empty() {
+                ready += 1;
+            }
+        }
+        if ready > 0 {
+            return ready;
+        }
+        if matches!(deadline, Some(deadline) if timer::get_time_us() >= deadline) {
+            return 0;
+        }
+        let files: Vec<_> = files.into_iter().filter_map(|(_, file)| file).collect();
+        // 所有文件都能唤醒我们且没有超时时间时才阻塞，否则只能轮询
+        let can_block = deadline.is_none() && files.iter().all(|file| file.register_waker(&task));
+        if can_block {
+            task::block_current_and_run_next();
+        }
+        files.iter().for_each(|file| file.unregister_waker(&task));
+        if !can_block {
+            task::suspend_current_and_run_next();
+        }
     }
 }
 
@@ -103,7 +649,7 @@ pub fn sys_close(fd: usize) -> isize {
 /// - oldpath：原有文件路径
 /// - newpath: 新的链接文件路径。
 ///
-/// 返回值：如果出现了错误则返回 -1，否则返回 0。
+/// 返回值：成功返回 0；oldpath 不存在返回 -ENOENT，newpath 已存在返回 -EEXIST。
 ///
 /// syscall ID: 37
 pub fn sys_linkat(
@@ -114,15 +660,54 @@ pub fn sys_linkat(
     _flags: u32,
 ) -> isize {
     let satp = Processor::current_user_satp();
-    let old_path = PageTable::translated_str(satp, oldpath);
-    let new_path = PageTable::translated_str(satp, newpath);
-    if old_path == new_path {
-        return -1;
-    }
+    let old_path = match PageTable::translated_str(satp, oldpath, PATH_MAX) {
+        Ok(old_path) => old_path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let new_path = match PageTable::translated_str(satp, newpath, PATH_MAX) {
+        Ok(new_path) => new_path,
+        Err(err) => return Errno::from(err).into(),
+    };
     if ROOT_INODE.link(&old_path, &new_path) {
         0
+    } else if ROOT_INODE.find(&old_path).is_none() {
+        Errno::ENOENT.into()
+    } else {
+        Errno::EEXIST.into()
+    }
+}
+
+/// 功能：把文件从 oldpath 改名为 newpath。newpath 已存在时原子地替换它。
+///
+/// 参数：
+/// - olddirfd，newdirfd: 仅为了兼容性考虑，始终为 AT_FDCWD (-100)，可以忽略
+/// - oldpath：原有文件路径
+/// - newpath: 新的文件路径
+///
+/// 返回值：成功返回 0；oldpath 不存在返回 -ENOENT，newpath 过长返回 -ENAMETOOLONG。
+///
+/// syscall ID：38
+pub fn sys_renameat(
+    _olddirfd: i32,
+    oldpath: *const u8,
+    _newdirfd: i32,
+    newpath: *const u8,
+) -> isize {
+    let satp = Processor::current_user_satp();
+    let old_path = match PageTable::translated_str(satp, oldpath, PATH_MAX) {
+        Ok(old_path) => old_path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let new_path = match PageTable::translated_str(satp, newpath, PATH_MAX) {
+        Ok(new_path) => new_path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    if ROOT_INODE.rename(&old_path, &new_path) {
+        0
+    } else if ROOT_INODE.find(&old_path).is_none() {
+        Errno::ENOENT.into()
     } else {
-        -1
+        Errno::ENAMETOOLONG.into()
     }
 }
 
@@ -132,26 +717,163 @@ pub fn sys_linkat(
 /// - dirfd: 仅为了兼容性考虑，本次实验中始终为 AT_FDCWD (-100)，可以忽略
 /// - path：文件路径
 /// - flags: 仅为了兼容性考虑，本次实验中始终为 0，可以忽略
+///
+/// 返回值：成功返回 0，文件不存在返回 -ENOENT。
+///
+/// syscall ID：35
 pub fn sys_unlinkat(_dirfd: i32, path: *const u8, _flags: u32) -> isize {
     let satp = Processor::current_user_satp();
-    let path = PageTable::translated_str(satp, path);
+    let path = match PageTable::translated_str(satp, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return Errno::from(err).into(),
+    };
     if ROOT_INODE.unlink(&path) {
         0
     } else {
-        -1
+        Errno::ENOENT.into()
+    }
+}
+
+/// 功能：修改文件的权限位。
+///
+/// 参数：
+/// - dirfd: 仅为了兼容性考虑，始终为 AT_FDCWD (-100)，可以忽略
+/// - path：文件路径
+/// - mode：新的权限位，只保留低 12 位
+///
+/// 返回值：成功返回 0，文件不存在返回 -ENOENT。
+///
+/// syscall ID：53
+pub fn sys_fchmodat(_dirfd: i32, path: *const u8, mode: u32) -> isize {
+    let satp = Processor::current_user_satp();
+    let path = match PageTable::translated_str(satp, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    if inode::chmod(&path, mode as u16) {
+        0
+    } else {
+        Errno::ENOENT.into()
     }
 }
 
+/// `TimeSpec::nsec` 取此值时使用当前时间
+const UTIME_NOW: usize = (1 << 30) - 1;
+/// `TimeSpec::nsec` 取此值时保持原来的时间不变
+const UTIME_OMIT: usize = (1 << 30) - 2;
+
+impl TimeSpec {
+    /// 翻译为要设置的时间（秒），`None` 表示不变。nsec 不合法时返回 `Err`
+    fn to_time(&self) -> Result<Option<u32>, Errno> {
+        match self.nsec {
+            UTIME_NOW => Ok(Some(inode::now())),
+            UTIME_OMIT => Ok(None),
+            nsec if nsec < 1_000_000_000 => Ok(Some(self.sec as u32)),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+/// 功能：设置文件的最后访问时间和最后修改时间，最后修改元数据的时间随之变为当前时间。
+/// 时间只精确到秒。
+///
+/// 参数：
+/// - dirfd: 仅为了兼容性考虑，始终为 AT_FDCWD (-100)，可以忽略
+/// - path：文件路径
+/// - times：依次为访问时间和修改时间的 TimeSpec 数组，为 0 时都设为当前时间。
+///   nsec 为 UTIME_NOW 时设为当前时间，为 UTIME_OMIT 时保持不变
+/// - flags：忽略
+///
+/// 返回值：成功返回 0，文件不存在返回 -ENOENT，nsec 不合法返回 -EINVAL。
+///
+/// syscall ID：88
+pub fn sys_utimensat(_dirfd: i32, path: *const u8, times: *const TimeSpec, _flags: u32) -> isize {
+    let satp = Processor::current_user_satp();
+    let path = match PageTable::translated_str(satp, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let (atime, mtime) = if times.is_null() {
+        (Some(inode::now()), Some(inode::now()))
+    } else {
+        let time = |i| -> Result<Option<u32>, Errno> {
+            PageTable::translated_ref(satp, times.wrapping_add(i))?.to_time()
+        };
+        match (time(0), time(1)) {
+            (Ok(atime), Ok(mtime)) => (atime, mtime),
+            (Err(err), _) | (_, Err(err)) => return err.into(),
+        }
+    };
+    if inode::utimes(&path, atime, mtime) {
+        0
+    } else {
+        Errno::ENOENT.into()
+    }
+}
+
+/// 功能：设置当前进程的文件创建掩码。
+///
+/// 参数：mask 为新的掩码，只保留低 9 位。
+///
+/// 返回值：原来的掩码。
+///
+/// syscall ID：166
+pub fn sys_umask(mask: u32) -> isize {
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let old = inner.umask;
+    inner.umask = mask as u16 & 0o777;
+    old as isize
+}
+
+/// 功能：把所有块缓存写回磁盘。
+///
+/// 返回值：总是返回 0。
+///
+/// syscall ID：81
+pub fn sys_sync() -> isize {
+    flusher::sync_all();
+    0
+}
+
+/// 功能：把一个文件在块缓存中的修改写回磁盘，不影响其他文件。
+///
+/// 返回值：成功返回 0；fd 无效返回 -EBADF，文件不在磁盘上（如管道）返回 -EINVAL。
+///
+/// syscall ID：82
+pub fn sys_fsync(fd: usize) -> isize {
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(desc)) => desc.file.clone(),
+        _ => return Errno::EBADF.into(),
+    };
+    drop(inner);
+    if file.fsync() {
+        0
+    } else {
+        Errno::EINVAL.into()
+    }
+}
+
+/// 功能：获取文件的状态。
+///
+/// 返回值：成功返回 0，fd 无效返回 -EBADF。
+///
+/// syscall ID：80
 pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
     let satp = Processor::current_user_satp();
-    let st = PageTable::translated_mut(satp, st);
+    let st = match PageTable::translated_mut(satp, st) {
+        Ok(st) => st,
+        Err(err) => return Errno::from(err).into(),
+    };
     st.dev = 0;
     let task = Processor::current_task().unwrap();
     let inner = task.inner_exclusive_access();
-    if let Some(Some(inode)) = inner.fd_table.get(fd as usize) {
-        *st = inode.stat();
+    if let Some(Some(desc)) = inner.fd_table.get(fd as usize) {
+        *st = desc.file.stat();
         0
     } else {
-        -1
+        Errno::EBADF.into()
     }
 }
@@ -0,0 +1,327 @@
+use core::{convert::TryFrom, ops::AddAssign};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    config::MAX_SYSCALL_NUM,
+    console::{self, ConsoleMode},
+    ktrace::{self, TraceEvent},
+    logging,
+    mm::{
+        frame_allocator,
+        heap_allocator::{self, HeapStats},
+        page_table::{self, PageTable, UserBuffer},
+        user::Access,
+    },
+    random,
+    sync::UPSafeCell,
+    task::{self, manager, Processor},
+    timer,
+};
+
+use super::{syscall_index, Errno, SUPPORTED_SYSCALL_NUM};
+
+/// 每个支持的系统调用的计数，按系统调用在 [`SUPPORTED_SYSCALLS`](super::SUPPORTED_SYSCALLS) 中的下标存放
+#[derive(Clone, Copy)]
+pub struct SyscallCounts<T = u32>([T; SUPPORTED_SYSCALL_NUM]);
+
+impl<T: Copy + Default + AddAssign + From<u8>> SyscallCounts<T> {
+    pub fn new() -> Self {
+        Self([T::default(); SUPPORTED_SYSCALL_NUM])
+    }
+
+    /// 计数加一，不支持的系统调用不计数
+    pub fn incr(&mut self, syscall_id: usize) {
+        if let Some(i) = syscall_index(syscall_id) {
+            self.0[i] += T::from(1);
+        }
+    }
+
+    pub fn get(&self, syscall_id: usize) -> T {
+        syscall_index(syscall_id).map_or_else(T::default, |i| self.0[i])
+    }
+
+    /// 所有计数清零
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// 按 syscall ID 展开到 `dst`，`dst[i]` 为 ID 为 i 的系统调用的计数
+    pub fn expand_into(&self, dst: &mut [T]) {
+        for (id, count) in dst.iter_mut().enumerate() {
+            *count = self.get(id);
+        }
+    }
+}
+
+impl<T: Copy + Default + AddAssign + From<u8>> Default for SyscallCounts<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 全局的系统调用计数，与 TCB 中每个进程的 `syscall_count` 互为补充
+struct SyscallStats {
+    /// 每个系统调用被调用的次数
+    calls: SyscallCounts<u64>,
+    /// 每个系统调用返回负数（出错）的次数
+    errors: SyscallCounts<u64>,
+}
+
+lazy_static! {
+    static ref SYSCALL_STATS: UPSafeCell<SyscallStats> = unsafe {
+        UPSafeCell::new(SyscallStats {
+            calls: SyscallCounts::new(),
+            errors: SyscallCounts::new(),
+        })
+    };
+}
+
+/// 记录一次系统调用。需在执行前调用，因为 exit 等系统调用不会返回
+pub fn record_call(syscall_id: usize) {
+    SYSCALL_STATS.exclusive_access().calls.incr(syscall_id);
+}
+
+/// 记录系统调用的返回值，负数计为一次出错
+pub fn record_ret(syscall_id: usize, ret: isize) {
+    if ret < 0 {
+        SYSCALL_STATS.exclusive_access().errors.incr(syscall_id);
+    }
+}
+
+/// 功能：读取最近的内核日志。
+///
+/// 参数：buf 为用户缓冲区，len 为缓冲区长度。日志较多时只返回最近的 len 字节。
+///
+/// 返回值：实际读取的字节数。
+///
+/// syscall ID：116
+pub fn sys_syslog(buf: *mut u8, len: usize) -> isize {
+    let satp = Processor::current_user_satp();
+    match page_table::translated_byte_buffer(satp, buf, len, Access::Write) {
+        Ok(buffers) => logging::read_log(UserBuffer::new(buffers)) as isize,
+        Err(err) => Errno::from(err).into(),
+    }
+}
+
+/// 功能：设置内核日志的默认等级，命令行中按模块设置的等级保持不变。
+///
+/// 参数：level 取 0~5，依次为 Off、Error、Warn、Info、Debug、Trace。
+///
+/// 返回值：成功返回 0，level 不合法返回 -EINVAL。
+///
+/// syscall ID：411
+pub fn sys_set_log_level(level: usize) -> isize {
+    if logging::set_default_level(level) {
+        0
+    } else {
+        Errno::EINVAL.into()
+    }
+}
+
+/// `sys_kernel_meminfo` 返回给用户的内存使用情况
+#[repr(C)]
+pub struct KernelMemInfo {
+    heap: HeapStats,
+    /// 物理页帧总数
+    frame_total: usize,
+    /// 剩余的物理页帧数
+    frame_remaining: usize,
+}
+
+/// 功能：查询内核堆和物理页帧的使用情况。
+///
+/// 参数：info 指向用户空间的 `KernelMemInfo`。
+///
+/// 返回值：总是返回 0。
+///
+/// syscall ID：412
+pub fn sys_kernel_meminfo(info: *mut KernelMemInfo) -> isize {
+    let info = match PageTable::translated_mut(Processor::current_user_satp(), info) {
+        Ok(info) => info,
+        Err(err) => return Errno::from(err).into(),
+    };
+    *info = KernelMemInfo {
+        heap: heap_allocator::stats(),
+        frame_total: frame_allocator::frame_total(),
+        frame_remaining: frame_allocator::frame_remaining(),
+    };
+    0
+}
+
+/// 功能：设置用户程序写标准输出时的处理方式。
+///
+/// 参数：mode 为 1（按行缓存每个进程的输出）和 2（每行前加上 `[pid] `）的组合；为 -1 时只查询不修改。
+///
+/// 返回值：原先的设置，mode 不合法返回 -EINVAL。
+///
+/// syscall ID：418
+pub fn sys_console_mode(mode: isize) -> isize {
+    if mode == -1 {
+        return console::mode().bits() as isize;
+    }
+    match usize::try_from(mode).ok().and_then(ConsoleMode::from_bits) {
+        Some(mode) => console::set_mode(mode).bits() as isize,
+        None => Errno::EINVAL.into(),
+    }
+}
+
+/// `sys_sysinfo` 返回给用户的系统概况
+#[repr(C)]
+pub struct SysInfo {
+    /// 启动以来经过的时间，单位为毫秒
+    uptime_ms: usize,
+    /// idle 控制流在 `wfi` 中等待的时间，单位为毫秒
+    idle_ms: usize,
+    /// 就绪、运行、阻塞的任务数和等待父进程回收的僵尸进程数
+    ready: usize,
+    running: usize,
+    blocked: usize,
+    zombie: usize,
+    /// 物理页帧总数和剩余的页帧数
+    frame_total: usize,
+    frame_free: usize,
+    /// 内核堆的总字节数和已分配的字节数
+    heap_total: usize,
+    heap_used: usize,
+    /// 启动以来切换到任务的次数
+    context_switches: usize,
+}
+
+/// 功能：查询系统运行时间、各状态的任务数、内存使用情况和任务切换次数。
+///
+/// 参数：info 指向用户空间的 `SysInfo`。
+///
+/// 返回值：总是返回 0。
+///
+/// syscall ID：417
+pub fn sys_sysinfo(info: *mut SysInfo) -> isize {
+    let info = match PageTable::translated_mut(Processor::current_user_satp(), info) {
+        Ok(info) => info,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let to_ms = |cycles: usize| (cycles as u128 * 1000 / timer::clock_freq() as u128) as usize;
+    let tasks = manager::task_counts();
+    let heap = heap_allocator::stats();
+    *info = SysInfo {
+        uptime_ms: timer::get_time_ms(),
+        idle_ms: to_ms(Processor::idle_stats().cycles),
+        ready: tasks.ready,
+        running: tasks.running,
+        blocked: tasks.blocked,
+        zombie: tasks.zombie,
+        frame_total: frame_allocator::frame_total(),
+        frame_free: frame_allocator::frame_remaining(),
+        heap_total: heap.total,
+        heap_used: heap.actual,
+        context_switches: Processor::switch_count(),
+    };
+    0
+}
+
+/// 功能：打开或关闭某个进程的系统调用跟踪。被跟踪进程的每个系统调用及其参数和返回值
+/// 都会以 Info 等级记录到内核日志中，每个进程每秒最多记录 64 条。
+///
+/// 参数：pid 为目标进程的 id，为 -1 时表示当前进程；on 不为 0 时打开跟踪，否则关闭。
+///
+/// 返回值：成功返回 0，进程不存在返回 -ESRCH。
+///
+/// syscall ID：413
+pub fn sys_trace(pid: isize, on: usize) -> isize {
+    let task = if pid == -1 {
+        Processor::current_task()
+    } else {
+        usize::try_from(pid).ok().and_then(task::pid2task)
+    };
+    match task {
+        Some(task) => {
+            task.inner_exclusive_access().trace.enabled = on != 0;
+            0
+        }
+        None => Errno::ESRCH.into(),
+    }
+}
+
+/// 功能：查询内核启动以来所有进程的系统调用次数和出错次数。
+///
+/// 参数：calls 和 errors 各指向用户空间中长度为 len 的 `u64` 数组，第 i 项对应
+/// syscall ID 为 i 的系统调用；返回负数的调用计为出错。任一指针为空时跳过对应的数组。
+///
+/// 返回值：实际写入的项数，即 len 与内核支持的最大 syscall ID 加一中的较小值。
+///
+/// syscall ID：414
+pub fn sys_kernel_stats(calls: *mut u64, errors: *mut u64, len: usize) -> isize {
+    let satp = Processor::current_user_satp();
+    let len = len.min(MAX_SYSCALL_NUM);
+    // 先拷贝一份，避免写用户内存时持有借用
+    let (call_counts, error_counts) = {
+        let stats = SYSCALL_STATS.exclusive_access();
+        (stats.calls, stats.errors)
+    };
+    for (dst, src) in [(calls, &call_counts), (errors, &error_counts)] {
+        if dst.is_null() {
+            continue;
+        }
+        for id in 0..len {
+            match PageTable::translated_mut(satp, dst.wrapping_add(id)) {
+                Ok(dst) => *dst = src.get(id),
+                Err(err) => return Errno::from(err).into(),
+            }
+        }
+    }
+    len as isize
+}
+
+/// 功能：读出调度跟踪缓冲区中的事件，需要在内核命令行中加上 `ktrace` 选项才会记录事件。
+///
+/// 参数：cursor 指向用户空间的游标，第一次读取时置 0，之后原样传回即可接着读取；
+/// buf 指向用户空间中长度为 len 的 `TraceEvent` 数组。未读的事件已被覆盖时从最旧的事件开始。
+///
+/// 返回值：写入 buf 的事件数，没有新事件时为 0。
+///
+/// syscall ID：416
+pub fn sys_ktrace_read(cursor: *mut usize, buf: *mut TraceEvent, len: usize) -> isize {
+    let satp = Processor::current_user_satp();
+    let cursor = match PageTable::translated_mut(satp, cursor) {
+        Ok(cursor) => cursor,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let (events, next) = ktrace::read(*cursor, len);
+    for (i, event) in events.iter().enumerate() {
+        // 出错时不更新游标，这些事件下次还能读到
+        match PageTable::translated_mut(satp, buf.wrapping_add(i)) {
+            Ok(dst) => *dst = *event,
+            Err(err) => return Errno::from(err).into(),
+        }
+    }
+    *cursor = next;
+    events.len() as isize
+}
+
+/// `sys_getrandom` 的 flags。内核的随机数发生器在启动时就已播种，因此这些标志都不影响行为
+const GRND_NONBLOCK: u32 = 1;
+const GRND_RANDOM: u32 = 2;
+const GRND_INSECURE: u32 = 4;
+
+/// 功能：用内核 CSPRNG 生成的随机字节填满用户缓冲区。
+///
+/// 参数：buf 为用户缓冲区，len 为缓冲区长度；flags 可以是 GRND_NONBLOCK、GRND_RANDOM
+/// 和 GRND_INSECURE 的组合。
+///
+/// 返回值：成功返回 len，flags 不合法返回 -EINVAL。
+///
+/// syscall ID：278
+pub fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> isize {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0 {
+        return Errno::EINVAL.into();
+    }
+    let satp = Processor::current_user_satp();
+    let buffers = match page_table::translated_byte_buffer(satp, buf, len, Access::Write) {
+        Ok(buffers) => buffers,
+        Err(err) => return Errno::from(err).into(),
+    };
+    for buf in buffers {
+        random::fill(buf);
+    }
+    len as isize
+}
@@ -1,33 +1,236 @@
-use crate::task::incr_syscall_times;
+use crate::{
+    mm::page_table::TranslateError,
+    task::{self, Processor},
+};
+
+use self::trace::Arg::{self, Hex, Int, Str, Uint};
 
 mod fs;
+mod info;
+mod net;
 mod process;
+mod sync;
+pub mod trace;
+
+pub use info::SyscallCounts;
+
+/// 系统调用的错误码，取值与 Linux 一致，用户程序可以按 Linux 的 errno 表解释。
+/// 系统调用出错时返回错误码的相反数，用 `Errno::EBADF.into()` 得到。
+///
+/// 参数中的用户指针无法按所需的方式访问时一律返回 -EFAULT；路径参数包括结尾的 `\0` 超过
+/// `PATH_MAX` 字节时返回 -ENAMETOOLONG，不是 UTF-8 时返回 -EINVAL。各系统调用的文档中不再一一列出
+#[repr(isize)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    /// 操作不允许
+    EPERM = 1,
+    /// 文件不存在
+    ENOENT = 2,
+    /// 进程不存在
+    ESRCH = 3,
+    /// 阻塞时被信号打断
+    EINTR = 4,
+    /// exec 的参数和环境变量过长
+    E2BIG = 7,
+    /// 不是有效的可执行文件
+    ENOEXEC = 8,
+    /// 文件描述符无效，或不支持所要求的读写
+    EBADF = 9,
+    /// 没有符合条件的子进程
+    ECHILD = 10,
+    /// 暂时无法完成，需要重试
+    EAGAIN = 11,
+    /// 内存不足
+    ENOMEM = 12,
+    /// 权限不足
+    EACCES = 13,
+    /// 用户地址无效
+    EFAULT = 14,
+    /// 文件已存在
+    EEXIST = 17,
+    /// 文件不支持映射到内存
+    ENODEV = 19,
+    /// 不是目录
+    ENOTDIR = 20,
+    /// 参数不合法
+    EINVAL = 22,
+    /// 进程打开的文件数已达上限
+    EMFILE = 24,
+    /// 文件不是终端
+    ENOTTY = 25,
+    /// 文件不支持定位读写
+    ESPIPE = 29,
+    /// 连接的对方已经关闭
+    EPIPE = 32,
+    /// 文件名过长
+    ENAMETOOLONG = 36,
+    /// 不支持的操作
+    ENOSYS = 38,
+    /// 文件不是套接字
+    ENOTSOCK = 88,
+    /// 没有指定目的地址
+    EDESTADDRREQ = 89,
+    /// 消息过长
+    EMSGSIZE = 90,
+    /// 不支持的协议
+    EPROTONOSUPPORT = 93,
+    /// 套接字不支持该操作
+    EOPNOTSUPP = 95,
+    /// 不支持的地址族
+    EAFNOSUPPORT = 97,
+    /// 地址已被使用
+    EADDRINUSE = 98,
+    /// 地址不是本机的地址
+    EADDRNOTAVAIL = 99,
+    /// 网络不可用
+    ENETDOWN = 100,
+    /// 目的地址不可达
+    ENETUNREACH = 101,
+    /// 套接字已经连接
+    EISCONN = 106,
+    /// 套接字还没有连接
+    ENOTCONN = 107,
+    /// 对方拒绝连接
+    ECONNREFUSED = 111,
+}
+
+impl Errno {
+    const ALL: [Errno; 35] = [
+        Errno::EPERM,
+        Errno::ENOENT,
+        Errno::ESRCH,
+        Errno::EINTR,
+        Errno::E2BIG,
+        Errno::ENOEXEC,
+        Errno::EBADF,
+        Errno::ECHILD,
+        Errno::EAGAIN,
+        Errno::ENOMEM,
+        Errno::EACCES,
+        Errno::EFAULT,
+        Errno::EEXIST,
+        Errno::ENODEV,
+        Errno::ENOTDIR,
+        Errno::EINVAL,
+        Errno::EMFILE,
+        Errno::ENOTTY,
+        Errno::ESPIPE,
+        Errno::EPIPE,
+        Errno::ENAMETOOLONG,
+        Errno::ENOSYS,
+        Errno::ENOTSOCK,
+        Errno::EDESTADDRREQ,
+        Errno::EMSGSIZE,
+        Errno::EPROTONOSUPPORT,
+        Errno::EOPNOTSUPP,
+        Errno::EAFNOSUPPORT,
+        Errno::EADDRINUSE,
+        Errno::EADDRNOTAVAIL,
+        Errno::ENETDOWN,
+        Errno::ENETUNREACH,
+        Errno::EISCONN,
+        Errno::ENOTCONN,
+        Errno::ECONNREFUSED,
+    ];
+    /// 由系统调用的返回值得到错误码，不是已知的错误码时返回 `None`
+    pub fn from_ret(ret: isize) -> Option<Self> {
+        Self::ALL.iter().copied().find(|&errno| ret == errno.into())
+    }
+}
+
+impl From<Errno> for isize {
+    fn from(errno: Errno) -> Self {
+        -(errno as isize)
+    }
+}
+
+impl From<TranslateError> for Errno {
+    fn from(err: TranslateError) -> Self {
+        match err {
+            TranslateError::BadAddress(_) => Errno::EFAULT,
+            TranslateError::TooLong => Errno::ENAMETOOLONG,
+            TranslateError::InvalidUtf8 => Errno::EINVAL,
+        }
+    }
+}
 
 pub const SYSCALL_OPEN: usize = 56;
 pub const SYSCALL_CLOSE: usize = 57;
+pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_READ: usize = 63;
 pub const SYSCALL_WRITE: usize = 64;
+pub const SYSCALL_READV: usize = 65;
+pub const SYSCALL_WRITEV: usize = 66;
+pub const SYSCALL_PREAD64: usize = 67;
+pub const SYSCALL_PWRITE64: usize = 68;
+pub const SYSCALL_PPOLL: usize = 73;
+pub const SYSCALL_EVENTFD2: usize = 19;
+pub const SYSCALL_FCNTL: usize = 25;
+pub const SYSCALL_IOCTL: usize = 29;
+pub const SYSCALL_FLOCK: usize = 32;
 pub const SYSCALL_UNLINKAT: usize = 35;
 pub const SYSCALL_LINKAT: usize = 37;
+pub const SYSCALL_RENAMEAT: usize = 38;
 pub const SYSCALL_FSTAT: usize = 80;
+pub const SYSCALL_SYNC: usize = 81;
+pub const SYSCALL_FSYNC: usize = 82;
+pub const SYSCALL_UTIMENSAT: usize = 88;
+pub const SYSCALL_FCHMODAT: usize = 53;
+pub const SYSCALL_UMASK: usize = 166;
+pub const SYSCALL_SOCKET: usize = 198;
+pub const SYSCALL_BIND: usize = 200;
+pub const SYSCALL_LISTEN: usize = 201;
+pub const SYSCALL_ACCEPT: usize = 202;
+pub const SYSCALL_CONNECT: usize = 203;
+pub const SYSCALL_SENDTO: usize = 206;
+pub const SYSCALL_RECVFROM: usize = 207;
 pub const SYSCALL_EXIT: usize = 93;
+pub const SYSCALL_EXIT_GROUP: usize = 94;
 // pub const SYSCALL_SLEEP: usize = 101;
+pub const SYSCALL_SETITIMER: usize = 103;
+pub const SYSCALL_TIMER_CREATE: usize = 107;
+pub const SYSCALL_TIMER_GETTIME: usize = 108;
+pub const SYSCALL_TIMER_SETTIME: usize = 110;
+pub const SYSCALL_TIMER_DELETE: usize = 111;
+pub const SYSCALL_FUTEX: usize = 98;
+pub const SYSCALL_SYSLOG: usize = 116;
+pub const SYSCALL_PTRACE: usize = 117;
 pub const SYSCALL_YIELD: usize = 124;
+pub const SYSCALL_KILL: usize = 129;
+pub const SYSCALL_SETPGID: usize = 154;
+pub const SYSCALL_GETPGID: usize = 155;
+pub const SYSCALL_GETSID: usize = 156;
+pub const SYSCALL_SETSID: usize = 157;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
 pub const SYSCALL_GETPID: usize = 172;
-// pub const SYSCALL_GETTID: usize = 178;
+pub const SYSCALL_GETUID: usize = 174;
+pub const SYSCALL_GETGID: usize = 176;
+pub const SYSCALL_GETTID: usize = 178;
 pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 pub const SYSCALL_WAITPID: usize = 260;
+pub const SYSCALL_PRLIMIT64: usize = 261;
+pub const SYSCALL_GETRANDOM: usize = 278;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_MPROTECT: usize = 226;
+pub const SYSCALL_MSYNC: usize = 227;
+pub const SYSCALL_MINCORE: usize = 232;
 pub const SYSCALL_SPAWN: usize = 400;
 // pub const SYSCALL_MAIL_READ: usize = 401;
 // pub const SYSCALL_MAIL_WRITE: usize = 402;
 // pub const SYSCALL_DUP: usize = 24;
-// pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_SET_LOG_LEVEL: usize = 411;
+pub const SYSCALL_KERNEL_MEMINFO: usize = 412;
+pub const SYSCALL_TRACE: usize = 413;
+pub const SYSCALL_KERNEL_STATS: usize = 414;
+pub const SYSCALL_SHUTDOWN: usize = 415;
+pub const SYSCALL_KTRACE_READ: usize = 416;
+pub const SYSCALL_SYSINFO: usize = 417;
+pub const SYSCALL_CONSOLE_MODE: usize = 418;
 // pub const SYSCALL_THREAD_CREATE: usize = 460;
 // pub const SYSCALL_WAITTID: usize = 462;
 // pub const SYSCALL_MUTEX_CREATE: usize = 463;
@@ -41,31 +244,339 @@ pub const SYSCALL_TASK_INFO: usize = 410;
 // pub const SYSCALL_CONDVAR_SIGNAL: usize = 472;
 // pub const SYSCALL_CONDVAR_WAIT: usize = 473;
 
-pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
-    incr_syscall_times(syscall_id);
+/// 内核支持的所有系统调用，按 ID 升序排列。新增系统调用时需要加到这里，它才会被计数
+pub const SUPPORTED_SYSCALLS: &[usize] = &[
+    SYSCALL_EVENTFD2,
+    SYSCALL_FCNTL,
+    SYSCALL_IOCTL,
+    SYSCALL_FLOCK,
+    SYSCALL_UNLINKAT,
+    SYSCALL_LINKAT,
+    SYSCALL_RENAMEAT,
+    SYSCALL_FCHMODAT,
+    SYSCALL_OPEN,
+    SYSCALL_CLOSE,
+    SYSCALL_PIPE,
+    SYSCALL_READ,
+    SYSCALL_WRITE,
+    SYSCALL_READV,
+    SYSCALL_WRITEV,
+    SYSCALL_PREAD64,
+    SYSCALL_PWRITE64,
+    SYSCALL_PPOLL,
+    SYSCALL_FSTAT,
+    SYSCALL_SYNC,
+    SYSCALL_FSYNC,
+    SYSCALL_UTIMENSAT,
+    SYSCALL_EXIT,
+    SYSCALL_EXIT_GROUP,
+    SYSCALL_FUTEX,
+    SYSCALL_SETITIMER,
+    SYSCALL_TIMER_CREATE,
+    SYSCALL_TIMER_GETTIME,
+    SYSCALL_TIMER_SETTIME,
+    SYSCALL_TIMER_DELETE,
+    SYSCALL_SYSLOG,
+    SYSCALL_PTRACE,
+    SYSCALL_YIELD,
+    SYSCALL_KILL,
+    SYSCALL_SET_PRIORITY,
+    SYSCALL_SETPGID,
+    SYSCALL_GETPGID,
+    SYSCALL_GETSID,
+    SYSCALL_SETSID,
+    SYSCALL_UMASK,
+    SYSCALL_GETTIMEOFDAY,
+    SYSCALL_GETPID,
+    SYSCALL_GETUID,
+    SYSCALL_GETGID,
+    SYSCALL_GETTID,
+    SYSCALL_SOCKET,
+    SYSCALL_BIND,
+    SYSCALL_LISTEN,
+    SYSCALL_ACCEPT,
+    SYSCALL_CONNECT,
+    SYSCALL_SENDTO,
+    SYSCALL_RECVFROM,
+    SYSCALL_MUNMAP,
+    SYSCALL_FORK,
+    SYSCALL_EXEC,
+    SYSCALL_MMAP,
+    SYSCALL_MPROTECT,
+    SYSCALL_MSYNC,
+    SYSCALL_MINCORE,
+    SYSCALL_WAITPID,
+    SYSCALL_PRLIMIT64,
+    SYSCALL_GETRANDOM,
+    SYSCALL_SPAWN,
+    SYSCALL_TASK_INFO,
+    SYSCALL_SET_LOG_LEVEL,
+    SYSCALL_KERNEL_MEMINFO,
+    SYSCALL_TRACE,
+    SYSCALL_KERNEL_STATS,
+    SYSCALL_SHUTDOWN,
+    SYSCALL_KTRACE_READ,
+    SYSCALL_SYSINFO,
+    SYSCALL_CONSOLE_MODE,
+];
+
+/// 支持的系统调用数，系统调用计数数组的长度
+pub const SUPPORTED_SYSCALL_NUM: usize = SUPPORTED_SYSCALLS.len();
+
+const _: () = {
+    let mut i = 1;
+    while i < SUPPORTED_SYSCALL_NUM {
+        assert!(
+            SUPPORTED_SYSCALLS[i - 1] < SUPPORTED_SYSCALLS[i],
+            "SUPPORTED_SYSCALLS must be sorted"
+        );
+        i += 1;
+    }
+};
+
+/// 系统调用在 [`SUPPORTED_SYSCALLS`] 中的下标，不支持的系统调用返回 `None`
+pub fn syscall_index(syscall_id: usize) -> Option<usize> {
+    SUPPORTED_SYSCALLS.binary_search(&syscall_id).ok()
+}
+
+/// 系统调用的名字和各参数的显示方式，用于跟踪系统调用
+fn signature(syscall_id: usize) -> (Option<&'static str>, &'static [Arg]) {
+    let (name, args): (_, &[Arg]) = match syscall_id {
+        SYSCALL_READ => ("read", &[Int, Hex, Uint]),
+        SYSCALL_WRITE => ("write", &[Int, Hex, Uint]),
+        SYSCALL_READV => ("readv", &[Int, Hex, Uint]),
+        SYSCALL_WRITEV => ("writev", &[Int, Hex, Uint]),
+        SYSCALL_PREAD64 => ("pread64", &[Int, Hex, Uint, Uint]),
+        SYSCALL_PWRITE64 => ("pwrite64", &[Int, Hex, Uint, Uint]),
+        SYSCALL_OPEN => ("open", &[Int, Str, Hex]),
+        SYSCALL_LINKAT => ("linkat", &[Int, Str, Int, Str]),
+        SYSCALL_RENAMEAT => ("renameat", &[Int, Str, Int, Str]),
+        SYSCALL_UNLINKAT => ("unlinkat", &[Int, Str]),
+        SYSCALL_FSTAT => ("fstat", &[Int, Hex]),
+        SYSCALL_SYNC => ("sync", &[]),
+        SYSCALL_FSYNC => ("fsync", &[Int]),
+        SYSCALL_UTIMENSAT => ("utimensat", &[Int, Str, Hex, Hex]),
+        SYSCALL_FCHMODAT => ("fchmodat", &[Int, Str, Hex]),
+        SYSCALL_UMASK => ("umask", &[Hex]),
+        SYSCALL_CLOSE => ("close", &[Int]),
+        SYSCALL_FLOCK => ("flock", &[Int, Hex]),
+        SYSCALL_FCNTL => ("fcntl", &[Int, Int, Hex]),
+        SYSCALL_IOCTL => ("ioctl", &[Int, Hex, Hex]),
+        SYSCALL_PIPE => ("pipe", &[Hex, Hex]),
+        SYSCALL_PPOLL => ("ppoll", &[Hex, Uint, Hex]),
+        SYSCALL_EVENTFD2 => ("eventfd2", &[Uint, Hex]),
+        SYSCALL_SOCKET => ("socket", &[Int, Hex, Int]),
+        SYSCALL_BIND => ("bind", &[Int, Hex, Uint]),
+        SYSCALL_LISTEN => ("listen", &[Int, Int]),
+        SYSCALL_ACCEPT => ("accept", &[Int, Hex, Hex]),
+        SYSCALL_CONNECT => ("connect", &[Int, Hex, Uint]),
+        SYSCALL_SENDTO => ("sendto", &[Int, Hex, Uint, Hex, Hex, Uint]),
+        SYSCALL_RECVFROM => ("recvfrom", &[Int, Hex, Uint, Hex, Hex, Hex]),
+        SYSCALL_EXIT => ("exit", &[Int]),
+        SYSCALL_EXIT_GROUP => ("exit_group", &[Int]),
+        SYSCALL_YIELD => ("yield", &[Int]),
+        SYSCALL_KILL => ("kill", &[Int, Int]),
+        SYSCALL_GETPID => ("getpid", &[]),
+        SYSCALL_GETTID => ("gettid", &[]),
+        SYSCALL_SETPGID => ("setpgid", &[Int, Int]),
+        SYSCALL_GETPGID => ("getpgid", &[Int]),
+        SYSCALL_GETSID => ("getsid", &[Int]),
+        SYSCALL_SETSID => ("setsid", &[]),
+        SYSCALL_GETUID => ("getuid", &[]),
+        SYSCALL_GETGID => ("getgid", &[]),
+        SYSCALL_SET_PRIORITY => ("set_priority", &[Int]),
+        SYSCALL_GETTIMEOFDAY => ("gettimeofday", &[Hex, Hex]),
+        SYSCALL_SETITIMER => ("setitimer", &[Int, Hex, Hex]),
+        SYSCALL_TIMER_CREATE => ("timer_create", &[Int, Hex, Hex]),
+        SYSCALL_TIMER_GETTIME => ("timer_gettime", &[Int, Hex]),
+        SYSCALL_TIMER_SETTIME => ("timer_settime", &[Int, Hex, Hex, Hex]),
+        SYSCALL_TIMER_DELETE => ("timer_delete", &[Int]),
+        SYSCALL_TASK_INFO => ("task_info", &[Hex, Hex]),
+        SYSCALL_MMAP => ("mmap", &[Hex, Uint, Hex]),
+        SYSCALL_MUNMAP => ("munmap", &[Hex, Uint]),
+        SYSCALL_MPROTECT => ("mprotect", &[Hex, Uint, Hex]),
+        SYSCALL_MSYNC => ("msync", &[Hex, Uint, Hex]),
+        SYSCALL_MINCORE => ("mincore", &[Hex, Uint, Hex]),
+        SYSCALL_FORK => ("fork", &[]),
+        SYSCALL_EXEC => ("exec", &[Str, Hex, Hex]),
+        SYSCALL_SPAWN => ("spawn", &[Str]),
+        SYSCALL_WAITPID => ("waitpid", &[Int, Hex, Hex]),
+        SYSCALL_PRLIMIT64 => ("prlimit64", &[Int, Int, Hex, Hex]),
+        SYSCALL_PTRACE => ("ptrace", &[Int, Int, Hex, Hex]),
+        SYSCALL_FUTEX => ("futex", &[Hex, Int, Uint]),
+        SYSCALL_SYSLOG => ("syslog", &[Hex, Uint]),
+        SYSCALL_SET_LOG_LEVEL => ("set_log_level", &[Uint]),
+        SYSCALL_KERNEL_MEMINFO => ("kernel_meminfo", &[Hex]),
+        SYSCALL_TRACE => ("trace", &[Int, Uint]),
+        SYSCALL_KERNEL_STATS => ("kernel_stats", &[Hex, Hex, Uint]),
+        SYSCALL_SHUTDOWN => ("shutdown", &[Uint]),
+        SYSCALL_KTRACE_READ => ("ktrace_read", &[Hex, Hex, Uint]),
+        SYSCALL_SYSINFO => ("sysinfo", &[Hex]),
+        SYSCALL_CONSOLE_MODE => ("console_mode", &[Int]),
+        SYSCALL_GETRANDOM => ("getrandom", &[Hex, Uint, Hex]),
+        _ => return (None, &[Hex, Hex, Hex, Hex]),
+    };
+    (Some(name), args)
+}
+
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
+    let current = Processor::current_task().unwrap();
+    task::incr_syscall_times(&mut current.inner_exclusive_access(), syscall_id);
+    drop(current);
+    info::record_call(syscall_id);
+    let (name, arg_kinds) = signature(syscall_id);
+    let traced = trace::begin(name, syscall_id, arg_kinds, args);
+    if let Some(call) = &traced {
+        // exit 不会返回，也不能等到返回再记录
+        if matches!(syscall_id, SYSCALL_EXIT | SYSCALL_EXIT_GROUP) {
+            trace::finish(call, None);
+        }
+    }
+    let ret = dispatch(syscall_id, args);
+    info::record_ret(syscall_id, ret);
+    if let Some(call) = &traced {
+        trace::finish(call, Some(ret));
+    }
+    ret
+}
+
+fn dispatch(syscall_id: usize, args: [usize; 6]) -> isize {
     match syscall_id {
         SYSCALL_READ => fs::sys_read(args[0], args[1] as _, args[2]),
         SYSCALL_WRITE => fs::sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_READV => fs::sys_readv(args[0], args[1] as _, args[2]),
+        SYSCALL_WRITEV => fs::sys_writev(args[0], args[1] as _, args[2]),
+        SYSCALL_PREAD64 => fs::sys_pread64(args[0], args[1] as _, args[2], args[3]),
+        SYSCALL_PWRITE64 => fs::sys_pwrite64(args[0], args[1] as _, args[2], args[3]),
         SYSCALL_OPEN => fs::sys_open(args[1] as _, args[2] as u32),
         SYSCALL_LINKAT => fs::sys_linkat(-100, args[1] as _, -100, args[3] as _, 0),
+        SYSCALL_RENAMEAT => fs::sys_renameat(-100, args[1] as _, -100, args[3] as _),
         SYSCALL_UNLINKAT => fs::sys_unlinkat(-100, args[1] as _, 0),
         SYSCALL_FSTAT => fs::sys_fstat(args[0], args[1] as _),
+        SYSCALL_SYNC => fs::sys_sync(),
+        SYSCALL_FSYNC => fs::sys_fsync(args[0]),
+        SYSCALL_UTIMENSAT => {
+            fs::sys_utimensat(args[0] as i32, args[1] as _, args[2] as _, args[3] as u32)
+        }
+        SYSCALL_FCHMODAT => fs::sys_fchmodat(args[0] as i32, args[1] as _, args[2] as u32),
+        SYSCALL_UMASK => fs::sys_umask(args[0] as u32),
         SYSCALL_CLOSE => fs::sys_close(args[0]),
+        SYSCALL_FLOCK => fs::sys_flock(args[0], args[1] as u32),
+        SYSCALL_FCNTL => fs::sys_fcntl(args[0], args[1], args[2]),
+        SYSCALL_IOCTL => fs::sys_ioctl(args[0], args[1], args[2] as _),
+        SYSCALL_PIPE => fs::sys_pipe(args[0] as _, args[1] as u32),
+        SYSCALL_EVENTFD2 => fs::sys_eventfd2(args[0] as u32, args[1] as u32),
+        SYSCALL_PPOLL => fs::sys_ppoll(args[0] as _, args[1], args[2] as _),
+        SYSCALL_SOCKET => net::sys_socket(args[0], args[1] as u32, args[2]),
+        SYSCALL_BIND => net::sys_bind(args[0], args[1] as _, args[2]),
+        SYSCALL_LISTEN => net::sys_listen(args[0], args[1] as i32),
+        SYSCALL_ACCEPT => net::sys_accept(args[0], args[1] as _, args[2] as _),
+        SYSCALL_CONNECT => net::sys_connect(args[0], args[1] as _, args[2]),
+        SYSCALL_SENDTO => net::sys_sendto(
+            args[0],
+            args[1] as _,
+            args[2],
+            args[3] as u32,
+            args[4] as _,
+            args[5],
+        ),
+        SYSCALL_RECVFROM => net::sys_recvfrom(
+            args[0],
+            args[1] as _,
+            args[2],
+            args[3] as u32,
+            args[4] as _,
+            args[5] as _,
+        ),
         SYSCALL_EXIT => process::sys_exit(args[0] as i32),
-        SYSCALL_YIELD => process::sys_yield(),
+        SYSCALL_EXIT_GROUP => process::sys_exit_group(args[0] as i32),
+        SYSCALL_YIELD => process::sys_yield(args[0] as isize),
+        SYSCALL_KILL => process::sys_kill(args[0] as isize, args[1]),
         SYSCALL_GETPID => process::sys_getpid(),
+        SYSCALL_GETTID => process::sys_gettid(),
+        SYSCALL_SETPGID => process::sys_setpgid(args[0], args[1] as isize),
+        SYSCALL_GETPGID => process::sys_getpgid(args[0]),
+        SYSCALL_GETSID => process::sys_getsid(args[0]),
+        SYSCALL_SETSID => process::sys_setsid(),
+        SYSCALL_GETUID => process::sys_getuid(),
+        SYSCALL_GETGID => process::sys_getgid(),
         SYSCALL_SET_PRIORITY => process::sys_set_priority(args[0] as isize),
         SYSCALL_GETTIMEOFDAY => process::sys_get_time(args[0] as _, args[1]),
-        SYSCALL_TASK_INFO => process::sys_task_info(args[0] as _),
-        SYSCALL_MMAP => process::sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_SETITIMER => process::sys_setitimer(args[0], args[1] as _, args[2] as _),
+        SYSCALL_TIMER_CREATE => process::sys_timer_create(args[0], args[1] as _, args[2] as _),
+        SYSCALL_TIMER_GETTIME => process::sys_timer_gettime(args[0], args[1] as _),
+        SYSCALL_TIMER_SETTIME => {
+            process::sys_timer_settime(args[0], args[1], args[2] as _, args[3] as _)
+        }
+        SYSCALL_TIMER_DELETE => process::sys_timer_delete(args[0]),
+        SYSCALL_TASK_INFO => process::sys_task_info(args[0] as _, args[1]),
+        SYSCALL_MMAP => process::sys_mmap(args[0], args[1], args[2], args[3], args[4]),
         SYSCALL_MUNMAP => process::sys_munmap(args[0], args[1]),
+        SYSCALL_MPROTECT => process::sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MSYNC => process::sys_msync(args[0], args[1], args[2]),
+        SYSCALL_MINCORE => process::sys_mincore(args[0], args[1], args[2] as _),
         SYSCALL_FORK => process::sys_fork(),
-        SYSCALL_EXEC => process::sys_exec(args[0] as _),
+        SYSCALL_EXEC => process::sys_exec(args[0] as _, args[1] as _, args[2] as _),
         SYSCALL_SPAWN => process::sys_spawn(args[0] as _),
-        SYSCALL_WAITPID => process::sys_waitpid(args[0] as isize, args[1] as _),
+        SYSCALL_WAITPID => process::sys_waitpid(args[0] as isize, args[1] as _, args[2]),
+        SYSCALL_PRLIMIT64 => process::sys_prlimit64(args[0], args[1], args[2] as _, args[3] as _),
+        SYSCALL_PTRACE => process::sys_ptrace(args[0], args[1], args[2], args[3]),
+        SYSCALL_FUTEX => sync::sys_futex(args[0], args[1], args[2]),
+        SYSCALL_SYSLOG => info::sys_syslog(args[0] as _, args[1]),
+        SYSCALL_SET_LOG_LEVEL => info::sys_set_log_level(args[0]),
+        SYSCALL_KERNEL_MEMINFO => info::sys_kernel_meminfo(args[0] as _),
+        SYSCALL_TRACE => info::sys_trace(args[0] as isize, args[1]),
+        SYSCALL_KERNEL_STATS => info::sys_kernel_stats(args[0] as _, args[1] as _, args[2]),
+        SYSCALL_SHUTDOWN => process::sys_shutdown(args[0] != 0),
+        SYSCALL_KTRACE_READ => info::sys_ktrace_read(args[0] as _, args[1] as _, args[2]),
+        SYSCALL_SYSINFO => info::sys_sysinfo(args[0] as _),
+        SYSCALL_CONSOLE_MODE => info::sys_console_mode(args[0] as isize),
+        SYSCALL_GETRANDOM => info::sys_getrandom(args[0] as _, args[1], args[2] as u32),
         _ => {
             log::error!("Unsupported syscall_id: {}", syscall_id);
             process::sys_exit(-1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MAX_SYSCALL_NUM;
+
+    #[test_case]
+    fn errno_round_trips_through_return_value() {
+        assert_eq!(isize::from(Errno::EINVAL), -22);
+        assert_eq!(Errno::from_ret(-2), Some(Errno::ENOENT));
+        assert_eq!(Errno::from_ret(0), None);
+        assert_eq!(Errno::from_ret(-1000), None);
+    }
+
+    #[test_case]
+    fn every_traced_syscall_is_counted() {
+        for id in 0..MAX_SYSCALL_NUM {
+            assert_eq!(signature(id).0.is_some(), syscall_index(id).is_some());
+        }
+    }
+
+    #[test_case]
+    fn timespec_converts_from_microseconds() {
+        let ts = fs::TimeSpec::from_us(2_500_001);
+        assert_eq!((ts.sec, ts.nsec), (2, 500_001_000));
+        assert_eq!(ts.as_us(), 2_500_001);
+    }
+
+    #[test_case]
+    fn syscall_counts_expand_by_id() {
+        let mut counts = SyscallCounts::<u32>::new();
+        counts.incr(SYSCALL_WRITE);
+        counts.incr(SYSCALL_WRITE);
+        counts.incr(MAX_SYSCALL_NUM + 1);
+        let mut times = [0; MAX_SYSCALL_NUM];
+        counts.expand_into(&mut times);
+        assert_eq!(times[SYSCALL_WRITE], 2);
+        assert_eq!(times.iter().sum::<u32>(), 2);
+        counts.reset();
+        assert_eq!(counts.get(SYSCALL_WRITE), 0);
+    }
+}
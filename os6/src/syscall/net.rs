@@ -0,0 +1,416 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::convert::TryFrom;
+
+use crate::{
+    fs::{FdFlags, File, FileDescriptor},
+    mm::{
+        page_table::{self, PageTable, UserBuffer},
+        user::Access,
+    },
+    net::{
+        socket::{SockAddr, SocketAddr, SocketError, UnixAddr},
+        tcp::TcpSocket,
+        udp::UdpSocket,
+        unix::UnixSocket,
+    },
+    task::Processor,
+};
+
+use super::Errno;
+
+const AF_UNIX: u16 = 1;
+const AF_INET: u16 = 2;
+const SOCK_STREAM: usize = 1;
+const SOCK_DGRAM: usize = 2;
+const SOCK_NONBLOCK: u32 = FdFlags::NONBLOCK.bits();
+/// 没有 exec 时关闭文件的机制，接受但忽略
+const SOCK_CLOEXEC: u32 = 1 << 19;
+const IPPROTO_TCP: usize = 6;
+const IPPROTO_UDP: usize = 17;
+/// 本次收发不阻塞，与描述符上的 O_NONBLOCK 效果相同
+const MSG_DONTWAIT: u32 = 0x40;
+/// `struct sockaddr_in` 的长度
+const SOCKADDR_IN_LEN: usize = 16;
+/// `struct sockaddr_un` 的长度：2 字节的地址族和 108 字节的路径
+const SOCKADDR_UN_LEN: usize = 110;
+
+impl From<SocketError> for Errno {
+    fn from(err: SocketError) -> Self {
+        match err {
+            SocketError::AddrFamily => Errno::EAFNOSUPPORT,
+            SocketError::AddrInUse => Errno::EADDRINUSE,
+            SocketError::InvalidAddr => Errno::EINVAL,
+            SocketError::AddrNotAvailable => Errno::EADDRNOTAVAIL,
+            SocketError::AlreadyBound => Errno::EINVAL,
+            SocketError::DestAddrRequired => Errno::EDESTADDRREQ,
+            SocketError::MessageTooLong => Errno::EMSGSIZE,
+            SocketError::NetworkDown => Errno::ENETDOWN,
+            SocketError::NetworkUnreachable => Errno::ENETUNREACH,
+            SocketError::ConnectionRefused => Errno::ECONNREFUSED,
+            SocketError::NotFound => Errno::ENOENT,
+            SocketError::NotConnected => Errno::ENOTCONN,
+            SocketError::AlreadyConnected => Errno::EISCONN,
+            SocketError::BrokenPipe => Errno::EPIPE,
+            SocketError::InvalidState => Errno::EINVAL,
+            SocketError::WouldBlock => Errno::EAGAIN,
+            SocketError::Interrupted => Errno::EINTR,
+            SocketError::Unsupported => Errno::EOPNOTSUPP,
+        }
+    }
+}
+
+/// 由用户态的 `struct sockaddr` 的字节得到套接字地址，开头的 2 字节是本机字节序的地址族。
+///
+/// - `sockaddr_in`：之后是网络字节序的端口和 IPv4 地址，再填充 8 个字节
+/// - `sockaddr_un`：之后是路径，以 0 结尾或者占满全部字节；以 0 开头时其余的字节都是抽象名字；
+///   只有地址族时是未命名的地址
+fn decode_sockaddr(bytes: &[u8]) -> Result<SockAddr, Errno> {
+    if bytes.len() < 2 {
+        return Err(Errno::EINVAL);
+    }
+    let body = &bytes[2..];
+    match u16::from_ne_bytes([bytes[0], bytes[1]]) {
+        AF_INET => {
+            if bytes.len() < SOCKADDR_IN_LEN {
+                return Err(Errno::EINVAL);
+            }
+            let port = u16::from_be_bytes([body[0], body[1]]);
+            let ip = [body[2], body[3], body[4], body[5]];
+            Ok(SockAddr::Inet(SocketAddr::new(ip, port)))
+        }
+        AF_UNIX => {
+            let addr = match body.split_first() {
+                None => UnixAddr::Unnamed,
+                Some((0, name)) => UnixAddr::Abstract(name.to_vec()),
+                Some(_) => {
+                    let path = body.split(|&byte| byte == 0).next().unwrap();
+                    let path = core::str::from_utf8(path).map_err(|_| Errno::EINVAL)?;
+                    UnixAddr::Path(String::from(path))
+                }
+            };
+            Ok(SockAddr::Unix(addr))
+        }
+        _ => Err(Errno::EAFNOSUPPORT),
+    }
+}
+
+/// [`decode_sockaddr`] 的逆过程
+fn encode_sockaddr(addr: &SockAddr) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match addr {
+        SockAddr::Inet(addr) => {
+            bytes.extend_from_slice(&AF_INET.to_ne_bytes());
+            bytes.extend_from_slice(&addr.port.to_be_bytes());
+            bytes.extend_from_slice(&addr.ip);
+            bytes.extend_from_slice(&[0; 8]);
+        }
+        SockAddr::Unix(addr) => {
+            bytes.extend_from_slice(&AF_UNIX.to_ne_bytes());
+            match addr {
+                UnixAddr::Unnamed => {}
+                UnixAddr::Path(path) => {
+                    bytes.extend_from_slice(path.as_bytes());
+                    bytes.push(0);
+                }
+                UnixAddr::Abstract(name) => {
+                    bytes.push(0);
+                    bytes.extend_from_slice(name);
+                }
+            }
+        }
+    }
+    bytes
+}
+
+/// 读取用户给出的长度为 addrlen 的套接字地址，超过 `sockaddr_un` 长度的部分被忽略
+fn read_sockaddr(satp: usize, addr: *const u8, addrlen: usize) -> Result<SockAddr, Errno> {
+    let len = addrlen.min(SOCKADDR_UN_LEN);
+    let bytes: Vec<u8> = UserBuffer::new(page_table::translated_byte_buffer(
+        satp,
+        addr,
+        len,
+        Access::Read,
+    )?)
+    .into_iter()
+    .map(|byte| unsafe { *byte })
+    .collect();
+    decode_sockaddr(&bytes)
+}
+
+/// 把套接字地址写回用户的 addr，并把地址的实际长度写入 addrlen。
+/// addr 为空时什么也不做，addrlen 指向的长度不足时地址被截断
+fn write_sockaddr(
+    satp: usize,
+    addr: *mut u8,
+    addrlen: *mut u32,
+    src: &SockAddr,
+) -> Result<(), Errno> {
+    if addr.is_null() {
+        return Ok(());
+    }
+    let bytes = encode_sockaddr(src);
+    let addrlen = PageTable::translated_mut(satp, addrlen)?;
+    let len = bytes.len().min(*addrlen as usize);
+    let buf = UserBuffer::new(page_table::translated_byte_buffer(
+        satp,
+        addr,
+        len,
+        Access::Write,
+    )?);
+    for (dst, &byte) in buf.into_iter().zip(bytes.iter()) {
+        unsafe { *dst = byte };
+    }
+    *addrlen = bytes.len() as u32;
+    Ok(())
+}
+
+/// 取得 fd 对应的描述符，fd 无效时返回 -EBADF，不是套接字时返回 -ENOTSOCK
+fn socket_fd(fd: usize) -> Result<FileDescriptor, Errno> {
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let desc = match inner.fd_table.get(fd) {
+        Some(Some(desc)) => desc.clone(),
+        _ => return Err(Errno::EBADF),
+    };
+    match desc.file.socket() {
+        Some(_) => Ok(desc),
+        None => Err(Errno::ENOTSOCK),
+    }
+}
+
+/// 功能：创建一个套接字，返回访问它的文件描述符。
+///
+/// 参数：domain 为 AF_UNIX(1) 或 AF_INET(2)；type 为 SOCK_STREAM(1) 或 SOCK_DGRAM(2)，可以或上
+/// SOCK_NONBLOCK(0x800) 和 SOCK_CLOEXEC(0x80000)，后者被忽略；protocol 为 0，
+/// 或者与 type 对应的 IPPROTO_TCP(6)、IPPROTO_UDP(17)。AF_INET 的 SOCK_STREAM 只能连接本机的地址，
+/// AF_UNIX 只支持 SOCK_STREAM。
+///
+/// 返回值：成功返回文件描述符；domain 不支持返回 -EAFNOSUPPORT，type 或 protocol 不支持返回
+/// -EPROTONOSUPPORT，type 中有未知的标志返回 -EINVAL，当前进程打开的文件数已达上限时返回 -EMFILE。
+///
+/// syscall ID：198
+pub fn sys_socket(domain: usize, ty: u32, protocol: usize) -> isize {
+    let domain = match u16::try_from(domain) {
+        Ok(domain @ (AF_UNIX | AF_INET)) => domain,
+        _ => return Errno::EAFNOSUPPORT.into(),
+    };
+    let flags = ty & (SOCK_NONBLOCK | SOCK_CLOEXEC);
+    let kind = (ty & !flags) as usize;
+    if kind & !0xf != 0 {
+        return Errno::EINVAL.into();
+    }
+    let socket: Arc<dyn File + Send + Sync> = match (domain, kind, protocol) {
+        (AF_UNIX, SOCK_STREAM, 0) => Arc::new(UnixSocket::new()),
+        (AF_INET, SOCK_STREAM, 0 | IPPROTO_TCP) => Arc::new(TcpSocket::new()),
+        (AF_INET, SOCK_DGRAM, 0 | IPPROTO_UDP) => Arc::new(UdpSocket::new()),
+        _ => return Errno::EPROTONOSUPPORT.into(),
+    };
+    let fd_flags = FdFlags::from_bits_truncate(flags);
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    match inner.alloc_fd() {
+        Some(fd) => {
+            inner.fd_table[fd] = Some(FileDescriptor::new(socket, fd_flags));
+            fd as isize
+        }
+        None => Errno::EMFILE.into(),
+    }
+}
+
+/// 功能：把套接字绑定到本机地址 addr 上。
+///
+/// 参数：addr 指向长度为 addrlen 的 `struct sockaddr_in` 或 `struct sockaddr_un`。
+/// IPv4 地址为 0.0.0.0 表示本机的任意地址，端口为 0 时由内核选择一个临时端口。
+/// UNIX 域的路径地址会在文件系统中创建一个套接字文件，套接字关闭后文件仍然保留；
+/// 以 0 开头的抽象地址不出现在文件系统中。
+///
+/// 返回值：成功返回 0；fd 无效返回 -EBADF，不是套接字返回 -ENOTSOCK，addrlen 过短、路径为空或者
+/// 已经绑定过返回 -EINVAL，地址族与套接字不符返回 -EAFNOSUPPORT，地址不是本机的地址返回
+/// -EADDRNOTAVAIL，端口、路径或抽象名字已被占用返回 -EADDRINUSE。
+///
+/// syscall ID：200
+pub fn sys_bind(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    let result = socket_fd(fd).and_then(|desc| {
+        let addr = read_sockaddr(Processor::current_user_satp(), addr, addrlen)?;
+        desc.file.socket().unwrap().bind(&addr)?;
+        Ok(0)
+    });
+    result.unwrap_or_else(Errno::into)
+}
+
+/// 功能：发送 buf 开始的 len 个字节。
+///
+/// 数据报套接字向 addr 发送一个数据报，没有绑定时先绑定一个临时端口。发送不会阻塞，
+/// 目的地址的 MAC 地址还不知道时数据报等到 ARP 应答后才发出。
+/// 流套接字忽略 addr，发给连接的对方，缓冲区满时阻塞。
+///
+/// 参数：flags 只支持 MSG_DONTWAIT(0x40)。
+///
+/// 返回值：成功返回发送的字节数；fd 无效返回 -EBADF，不是套接字返回 -ENOTSOCK，数据报套接字的 addr
+/// 为空返回 -EDESTADDRREQ，flags 不合法或 addrlen 过短返回 -EINVAL，地址族与套接字不符返回
+/// -EAFNOSUPPORT，数据超过一个以太网帧能容纳的长度返回 -EMSGSIZE，发往其它主机而没有网卡时返回
+/// -ENETDOWN；流套接字未连接返回 -ENOTCONN，对方已关闭返回 -EPIPE，非阻塞而缓冲区已满时返回 -EAGAIN。
+///
+/// syscall ID：206
+pub fn sys_sendto(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    flags: u32,
+    addr: *const u8,
+    addrlen: usize,
+) -> isize {
+    if flags & !MSG_DONTWAIT != 0 {
+        return Errno::EINVAL.into();
+    }
+    let result = socket_fd(fd).and_then(|desc| {
+        let satp = Processor::current_user_satp();
+        let addr = if addr.is_null() {
+            None
+        } else {
+            Some(read_sockaddr(satp, addr, addrlen)?)
+        };
+        let nonblock = desc.flags.contains(FdFlags::NONBLOCK) || flags & MSG_DONTWAIT != 0;
+        let buf = UserBuffer::new(page_table::translated_byte_buffer(
+            satp,
+            buf,
+            len,
+            Access::Read,
+        )?);
+        let len = desc
+            .file
+            .socket()
+            .unwrap()
+            .send_to(buf, addr.as_ref(), nonblock)?;
+        Ok(len as isize)
+    });
+    result.unwrap_or_else(Errno::into)
+}
+
+/// 功能：接收数据，放入 buf 开始的 len 个字节，没有数据时阻塞。
+///
+/// 数据报套接字每次接收一个数据报，放不下的部分被丢弃。流套接字读到至少一个字节后返回，
+/// 对方关闭连接后返回 0。
+///
+/// 参数：flags 只支持 MSG_DONTWAIT(0x40)。addr 不为空时，把发送方的地址写入 addr，
+/// 并把地址的实际长度写入 addrlen；addrlen 原本指向的长度不足时地址被截断。
+///
+/// 返回值：成功返回读到的字节数；fd 无效返回 -EBADF，不是套接字返回 -ENOTSOCK，flags 不合法返回 -EINVAL，
+/// 流套接字未连接返回 -ENOTCONN，非阻塞而没有数据时返回 -EAGAIN，阻塞时收到信号返回 -EINTR。
+///
+/// syscall ID：207
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    flags: u32,
+    addr: *mut u8,
+    addrlen: *mut u32,
+) -> isize {
+    if flags & !MSG_DONTWAIT != 0 {
+        return Errno::EINVAL.into();
+    }
+    let result = socket_fd(fd).and_then(|desc| {
+        let satp = Processor::current_user_satp();
+        let nonblock = desc.flags.contains(FdFlags::NONBLOCK) || flags & MSG_DONTWAIT != 0;
+        let buf = UserBuffer::new(page_table::translated_byte_buffer(
+            satp,
+            buf,
+            len,
+            Access::Write,
+        )?);
+        let (len, src) = desc.file.socket().unwrap().recv_from(buf, nonblock)?;
+        write_sockaddr(satp, addr, addrlen, &src)?;
+        Ok(len as isize)
+    });
+    result.unwrap_or_else(Errno::into)
+}
+
+/// 功能：让流套接字开始监听连接请求。没有绑定的套接字先绑定一个临时端口。
+///
+/// 参数：backlog 为最多等待 accept 的连接数，会被限制在 1 到 4096 之间。
+/// 已经在监听的套接字只更新 backlog。
+///
+/// 返回值：成功返回 0；fd 无效返回 -EBADF，不是套接字返回 -ENOTSOCK，已经连接返回 -EINVAL，
+/// 数据报套接字返回 -EOPNOTSUPP。
+///
+/// syscall ID：201
+pub fn sys_listen(fd: usize, backlog: i32) -> isize {
+    let result = socket_fd(fd).and_then(|desc| {
+        let backlog = backlog.max(0) as usize;
+        desc.file.socket().unwrap().listen(backlog)?;
+        Ok(0)
+    });
+    result.unwrap_or_else(Errno::into)
+}
+
+/// 功能：从监听的套接字上取出一个已经建立的连接，返回新的文件描述符。没有连接时阻塞。
+///
+/// 参数：addr 不为空时，把对方的地址写入 addr，并把地址的长度写入 addrlen，同 recvfrom。
+///
+/// 返回值：成功返回文件描述符；fd 无效返回 -EBADF，不是套接字返回 -ENOTSOCK，没有在监听返回 -EINVAL，
+/// 数据报套接字返回 -EOPNOTSUPP，非阻塞而没有连接时返回 -EAGAIN，阻塞时收到信号返回 -EINTR，
+/// 当前进程打开的文件数已达上限时返回 -EMFILE。
+///
+/// syscall ID：202
+pub fn sys_accept(fd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    let result = socket_fd(fd).and_then(|desc| {
+        let nonblock = desc.flags.contains(FdFlags::NONBLOCK);
+        let (socket, peer) = desc.file.socket().unwrap().accept(nonblock)?;
+        // 与 Linux 相同，地址写不进去时这个连接被丢弃
+        write_sockaddr(Processor::current_user_satp(), addr, addrlen, &peer)?;
+        let task = Processor::current_task().unwrap();
+        let mut inner = task.inner_exclusive_access();
+        let new_fd = inner.alloc_fd().ok_or(Errno::EMFILE)?;
+        inner.fd_table[new_fd] = Some(FileDescriptor::new(socket, FdFlags::empty()));
+        Ok(new_fd as isize)
+    });
+    result.unwrap_or_else(Errno::into)
+}
+
+/// 功能：把流套接字连接到 addr。对方的等待队列已满时阻塞。没有绑定的套接字先绑定一个临时端口。
+///
+/// 返回值：成功返回 0；fd 无效返回 -EBADF，不是套接字返回 -ENOTSOCK，addrlen 过短或者套接字正在监听
+/// 返回 -EINVAL，地址族与套接字不符返回 -EAFNOSUPPORT，已经连接返回 -EISCONN，addr 不是本机的地址
+/// 返回 -ENETUNREACH，UNIX 域的路径不存在返回 -ENOENT，没有套接字在监听 addr 返回 -ECONNREFUSED，非阻塞而对方的等待队列已满时返回
+/// -EAGAIN，阻塞时收到信号返回 -EINTR，数据报套接字返回 -EOPNOTSUPP。
+///
+/// syscall ID：203
+pub fn sys_connect(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    let result = socket_fd(fd).and_then(|desc| {
+        let addr = read_sockaddr(Processor::current_user_satp(), addr, addrlen)?;
+        let nonblock = desc.flags.contains(FdFlags::NONBLOCK);
+        desc.file.socket().unwrap().connect(&addr, nonblock)?;
+        Ok(0)
+    });
+    result.unwrap_or_else(Errno::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn sockaddr_round_trips_through_bytes() {
+        let addrs = [
+            SockAddr::Inet(SocketAddr::new([10, 0, 2, 15], 2000)),
+            SockAddr::Unix(UnixAddr::Unnamed),
+            SockAddr::Unix(UnixAddr::Path(String::from("/tmp/sock"))),
+            SockAddr::Unix(UnixAddr::Abstract(b"name".to_vec())),
+        ];
+        // 端口是网络字节序
+        assert_eq!(&encode_sockaddr(&addrs[0])[2..4], &[0x07, 0xd0]);
+        for addr in addrs {
+            assert_eq!(decode_sockaddr(&encode_sockaddr(&addr)), Ok(addr));
+        }
+        // 路径之后的填充被忽略
+        let mut padded = encode_sockaddr(&SockAddr::Unix(UnixAddr::Path(String::from("a"))));
+        padded.resize(SOCKADDR_UN_LEN, 0);
+        assert_eq!(
+            decode_sockaddr(&padded),
+            Ok(SockAddr::Unix(UnixAddr::Path(String::from("a"))))
+        );
+        assert_eq!(decode_sockaddr(&[10, 0]), Err(Errno::EAFNOSUPPORT));
+        assert_eq!(decode_sockaddr(&[2, 0, 0, 1]), Err(Errno::EINVAL));
+    }
+}
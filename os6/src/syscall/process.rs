@@ -1,25 +1,91 @@
-use alloc::sync::Arc;
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::mem;
 
 use crate::{
-    config::{MAX_SYSCALL_NUM, PAGE_SIZE},
-    fs::inode::{self, OpenFlags},
-    mm::{address::VirtAddr, memory_set::MapPermission, page_table::PageTable},
-    task::{self, manager::TaskManager, Processor, TaskStatus},
-    timer::{self, MICRO_PER_SEC},
+    cmdline,
+    config::{ARG_MAX, MAX_POSIX_TIMERS, MAX_SYSCALL_NUM, PAGE_SIZE, PATH_MAX},
+    fs::inode,
+    mm::{
+        address::{VPNRange, VirtAddr},
+        memory_set::{self, ElfError, FileBacking, MapError, MapPermission},
+        page_table::{self, PageTable, TranslateError},
+        user::Access,
+    },
+    power,
+    task::{
+        self,
+        manager::{self, TaskManager},
+        ptrace::{self, Ptrace, TraceState},
+        signal::SignalFlags,
+        Processor, RLimit, TaskControlBlock, TaskControlBlockInner, TaskStatus,
+    },
+    timer::{self, IntervalTimer, PosixTimer, MICRO_PER_SEC},
 };
 
+use super::{fs::TimeSpec, Errno};
+
 pub fn sys_exit(exit_code: i32) -> ! {
     log::info!("[kernel] Application exited with code {}", exit_code);
     task::exit_current_and_run_next(exit_code);
     unreachable!();
 }
 
+/// 功能：结束进程中的所有线程。目前每个进程只有一个线程，因此与 `sys_exit` 相同。
+///
+/// 参数：`exit_code` 表示进程的退出码。
+///
+/// syscall ID：94
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    sys_exit(exit_code)
+}
+
+/// 功能：返回当前进程的用户 id。所有进程都以 root 身份运行，总是返回 0。
+///
+/// syscall ID：174
+pub fn sys_getuid() -> isize {
+    inode::ROOT_UID as isize
+}
+
+/// 功能：返回当前进程的组 id。所有进程都属于 root 组，总是返回 0。
+///
+/// syscall ID：176
+pub fn sys_getgid() -> isize {
+    inode::ROOT_GID as isize
+}
+
 /// APP 将 CPU 控制权交给 OS，由 OS 决定下一步。
 ///
-/// 总是返回 0.
+/// 参数：`target` 大于 0 时把剩余的时间片让给 pid 为 `target` 的子进程或兄弟进程，
+/// 它处于就绪状态时会被下一个调度，例如让刚 fork 出的子进程尽快 exec。
+/// 为 0 时（用户库不传参数时即为 0）交给调度器决定。
+///
+/// 返回值：成功返回 0；`target` 不存在返回 -ESRCH，不是子进程或兄弟进程返回 -EPERM。
 ///
 /// syscall ID: 124
-pub fn sys_yield() -> isize {
+pub fn sys_yield(target: isize) -> isize {
+    if target > 0 {
+        let target = match task::pid2task(target as usize) {
+            Some(target) => target,
+            None => return Errno::ESRCH.into(),
+        };
+        let current = Processor::current_task().unwrap();
+        let parent = |task: &Arc<task::TaskControlBlock>| {
+            task.inner_exclusive_access()
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.upgrade())
+        };
+        let target_parent = parent(&target);
+        let is_child = matches!(&target_parent, Some(p) if Arc::ptr_eq(p, &current));
+        let is_sibling = match (&target_parent, parent(&current)) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, &b) && !Arc::ptr_eq(&target, &current),
+            _ => false,
+        };
+        if !is_child && !is_sibling {
+            return Errno::EPERM.into();
+        }
+        TaskManager::donate(&target);
+    }
     task::suspend_current_and_run_next();
     0
 }
@@ -34,82 +100,507 @@ pub struct TimeVal {
 ///
 /// syscall ID: 169
 pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
-    let ts_mut = PageTable::translated_mut(Processor::current_user_satp(), ts);
+    let ts_mut = match PageTable::translated_mut(Processor::current_user_satp(), ts) {
+        Ok(ts_mut) => ts_mut,
+        Err(err) => return Errno::from(err).into(),
+    };
     let us = timer::get_time_us();
     ts_mut.sec = us / MICRO_PER_SEC;
     ts_mut.usec = us % MICRO_PER_SEC;
     0
 }
 
+/// 目前只支持 `ITIMER_REAL`
+const ITIMER_REAL: usize = 0;
+
+#[repr(C)]
+pub struct ITimerVal {
+    pub interval: TimeVal,
+    pub value: TimeVal,
+}
+
+impl TimeVal {
+    fn from_us(us: usize) -> Self {
+        Self {
+            sec: us / MICRO_PER_SEC,
+            usec: us % MICRO_PER_SEC,
+        }
+    }
+    fn as_us(&self) -> usize {
+        self.sec * MICRO_PER_SEC + self.usec
+    }
+}
+
+/// 功能：设置当前进程的间隔定时器。定时器到期时向进程发送 SIGALRM，
+/// 之后若 interval 不为 0 则按 interval 重新计时。
+///
+/// 参数：which 只支持 ITIMER_REAL(0)；new_value 为新的设置，其中 value 为 0 表示关闭定时器；
+/// old_value 不为 0 时保存原先的设置。
+///
+/// 返回值：成功返回 0，参数错误返回 -EINVAL。
+///
+/// syscall ID：103
+pub fn sys_setitimer(
+    which: usize,
+    new_value: *const ITimerVal,
+    old_value: *mut ITimerVal,
+) -> isize {
+    if which != ITIMER_REAL || new_value.is_null() {
+        return Errno::EINVAL.into();
+    }
+    let user_satp = Processor::current_user_satp();
+    let new_value = match PageTable::translated_ref(user_satp, new_value) {
+        Ok(new_value) => new_value,
+        Err(err) => return Errno::from(err).into(),
+    };
+    if new_value.interval.usec >= MICRO_PER_SEC || new_value.value.usec >= MICRO_PER_SEC {
+        return Errno::EINVAL.into();
+    }
+    let old = task::set_itimer_real(new_value.value.as_us(), new_value.interval.as_us());
+    if !old_value.is_null() {
+        match PageTable::translated_mut(user_satp, old_value) {
+            Ok(old_value) => {
+                *old_value = ITimerVal {
+                    interval: TimeVal::from_us(old.interval),
+                    value: TimeVal::from_us(old.remaining(timer::get_time_us())),
+                }
+            }
+            Err(err) => return Errno::from(err).into(),
+        }
+    }
+    0
+}
+
+/// `timer_create` 支持的时钟。内核只有一个从启动开始计时的时钟，两者相同
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+
+/// 定时器到期时发送信号
+const SIGEV_SIGNAL: i32 = 0;
+/// 定时器到期时不通知
+const SIGEV_NONE: i32 = 1;
+
+/// `timer_settime` 的 flags：value 是绝对时间而不是相对于现在的时间
+const TIMER_ABSTIME: usize = 1;
+
+/// `struct sigevent` 的开头部分，其余字段目前用不到
+#[repr(C)]
+pub struct SigEvent {
+    pub value: usize,
+    pub signo: i32,
+    pub notify: i32,
+}
+
+#[repr(C)]
+pub struct ITimerSpec {
+    pub interval: TimeSpec,
+    pub value: TimeSpec,
+}
+
+/// 功能：创建一个 POSIX 定时器，创建后处于停止状态，需要用 `sys_timer_settime` 启动。
+///
+/// 参数：clockid 为 CLOCK_REALTIME(0) 或 CLOCK_MONOTONIC(1)；sevp 指定到期时的通知方式，
+/// notify 为 SIGEV_SIGNAL(0) 时发送 signo 号信号，为 SIGEV_NONE(1) 时不通知，为空指针时发送 SIGALRM；
+/// signo 不能是 SIGSTOP、SIGTSTP 和 SIGCONT；
+/// timerid 用于保存新定时器的 id。
+///
+/// 返回值：成功返回 0；参数不合法返回 -EINVAL，定时器数达到上限返回 -EAGAIN。
+///
+/// syscall ID：107
+pub fn sys_timer_create(clockid: usize, sevp: *const SigEvent, timerid: *mut i32) -> isize {
+    if (clockid != CLOCK_REALTIME && clockid != CLOCK_MONOTONIC) || timerid.is_null() {
+        return Errno::EINVAL.into();
+    }
+    let satp = Processor::current_user_satp();
+    // 先检查 timerid 可写，免得创建了定时器之后才发现它无效
+    let timerid = match PageTable::translated_mut(satp, timerid) {
+        Ok(timerid) => timerid,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let signal = if sevp.is_null() {
+        SignalFlags::SIGALRM
+    } else {
+        let sev = match PageTable::translated_ref(satp, sevp) {
+            Ok(sev) => sev,
+            Err(err) => return Errno::from(err).into(),
+        };
+        match sev.notify {
+            SIGEV_NONE => SignalFlags::empty(),
+            SIGEV_SIGNAL if (1..32).contains(&sev.signo) => {
+                match SignalFlags::from_bits(1 << sev.signo) {
+                    // 暂停和继续运行要经过 `signal_pgrp` 的处理，不能像普通信号那样直接挂起。
+                    // 不支持的信号（包括 SIGSTOP）不在 `SignalFlags` 中
+                    Some(signal)
+                        if !signal.intersects(SignalFlags::SIGTSTP | SignalFlags::SIGCONT) =>
+                    {
+                        signal
+                    }
+                    _ => return Errno::EINVAL.into(),
+                }
+            }
+            _ => return Errno::EINVAL.into(),
+        }
+    };
+    let posix_timer = PosixTimer {
+        timer: IntervalTimer::default(),
+        signal,
+    };
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let timers = &mut inner.posix_timers;
+    let id = match timers.iter().position(Option::is_none) {
+        Some(id) => id,
+        None if timers.len() < MAX_POSIX_TIMERS => {
+            timers.push(None);
+            timers.len() - 1
+        }
+        None => return Errno::EAGAIN.into(),
+    };
+    timers[id] = Some(posix_timer);
+    drop(inner);
+    *timerid = id as i32;
+    0
+}
+
+/// 当前进程中 id 为 `timerid` 的定时器
+fn posix_timer_mut(inner: &mut TaskControlBlockInner, timerid: usize) -> Option<&mut PosixTimer> {
+    inner.posix_timers.get_mut(timerid)?.as_mut()
+}
+
+/// 功能：启动或停止 POSIX 定时器。
+///
+/// 参数：timerid 为定时器 id；flags 为 0 或 TIMER_ABSTIME(1)，后者表示 new_value 的 value 是
+/// 启动以来的绝对时间；new_value 中 value 为 0 表示停止定时器，interval 不为 0 时到期后按它重新计时；
+/// old_value 不为空指针时保存原先的设置。
+///
+/// 返回值：成功返回 0；定时器不存在或参数不合法返回 -EINVAL。
+///
+/// syscall ID：110
+pub fn sys_timer_settime(
+    timerid: usize,
+    flags: usize,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    if flags & !TIMER_ABSTIME != 0 || new_value.is_null() {
+        return Errno::EINVAL.into();
+    }
+    let satp = Processor::current_user_satp();
+    let new_value = match PageTable::translated_ref(satp, new_value) {
+        Ok(new_value) => new_value,
+        Err(err) => return Errno::from(err).into(),
+    };
+    if new_value.interval.nsec >= 1_000_000_000 || new_value.value.nsec >= 1_000_000_000 {
+        return Errno::EINVAL.into();
+    }
+    let now = timer::get_time_us();
+    let mut value = new_value.value.as_us();
+    if flags & TIMER_ABSTIME != 0 && value != 0 {
+        // 已经过去的时间在下一次时钟中断时到期
+        value = value.saturating_sub(now).max(1);
+    }
+    let interval = new_value.interval.as_us();
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let posix_timer = match posix_timer_mut(&mut inner, timerid) {
+        Some(posix_timer) => posix_timer,
+        None => return Errno::EINVAL.into(),
+    };
+    let old = posix_timer.timer;
+    posix_timer.timer.set(now, value, interval);
+    task::schedule_itimer_check(posix_timer.timer.expire);
+    drop(inner);
+    if !old_value.is_null() {
+        match PageTable::translated_mut(satp, old_value) {
+            Ok(old_value) => {
+                *old_value = ITimerSpec {
+                    interval: TimeSpec::from_us(old.interval),
+                    value: TimeSpec::from_us(old.remaining(now)),
+                }
+            }
+            Err(err) => return Errno::from(err).into(),
+        }
+    }
+    0
+}
+
+/// 功能：查询 POSIX 定时器距离下次到期的时间和重新计时的间隔。
+///
+/// 参数：timerid 为定时器 id；curr_value 用于保存结果，value 为 0 表示定时器已停止。
+///
+/// 返回值：成功返回 0；定时器不存在返回 -EINVAL。
+///
+/// syscall ID：108
+pub fn sys_timer_gettime(timerid: usize, curr_value: *mut ITimerSpec) -> isize {
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let timer = match posix_timer_mut(&mut inner, timerid) {
+        Some(posix_timer) => posix_timer.timer,
+        None => return Errno::EINVAL.into(),
+    };
+    let satp = inner.user_satp();
+    drop(inner);
+    let curr_value = match PageTable::translated_mut(satp, curr_value) {
+        Ok(curr_value) => curr_value,
+        Err(err) => return Errno::from(err).into(),
+    };
+    *curr_value = ITimerSpec {
+        interval: TimeSpec::from_us(timer.interval),
+        value: TimeSpec::from_us(timer.remaining(timer::get_time_us())),
+    };
+    0
+}
+
+/// 功能：删除 POSIX 定时器，尚未到期的通知不再发送。
+///
+/// 参数：timerid 为定时器 id。
+///
+/// 返回值：成功返回 0；定时器不存在返回 -EINVAL。
+///
+/// syscall ID：111
+pub fn sys_timer_delete(timerid: usize) -> isize {
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    match inner.posix_timers.get_mut(timerid) {
+        Some(slot @ Some(_)) => {
+            *slot = None;
+            0
+        }
+        _ => Errno::EINVAL.into(),
+    }
+}
+
 pub struct TaskInfo {
     status: TaskStatus,
     syscall_times: [u32; MAX_SYSCALL_NUM],
     time: usize,
 }
 
+/// `sys_task_info` 的 flags：读取后把当前进程的系统调用计数清零
+const TASK_INFO_RESET: usize = 1;
+
 /// 查询任务信息。syscall_id = 410
 ///
-/// 成功返回 0，错误返回 -1
-pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
-    let page_table = PageTable::from_satp(Processor::current_user_satp());
-    let ti_va = VirtAddr(ti as usize);
-    let ti_mut = page_table
-        .translate(ti_va.floor())
-        .unwrap()
-        .ppn()
-        .as_mut_at::<TaskInfo>(ti_va.page_offset());
+/// flags 为 0 或 `TASK_INFO_RESET`，后者在读取后把系统调用计数清零，以便统计一段代码的系统调用。
+/// 成功返回 0，flags 不合法返回 -EINVAL
+pub fn sys_task_info(ti: *mut TaskInfo, flags: usize) -> isize {
+    if flags & !TASK_INFO_RESET != 0 {
+        return Errno::EINVAL.into();
+    }
+    // 写入零页时要为当前任务分配页帧，所以先翻译地址再借用当前任务
+    let ti_mut = match PageTable::translated_mut(Processor::current_user_satp(), ti) {
+        Ok(ti_mut) => ti_mut,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
     ti_mut.status = TaskStatus::Running;
-    task::set_syscall_times(&mut ti_mut.syscall_times);
-    let start_time = task::start_time();
-    let now = timer::get_time_ms();
-    ti_mut.time = now - start_time;
+    task::set_syscall_times(&mut inner, &mut ti_mut.syscall_times);
+    ti_mut.time = timer::get_time_ms() - inner.start_time;
+    if flags & TASK_INFO_RESET != 0 {
+        task::reset_syscall_times(&mut inner);
+    }
     0
 }
 
-/// 本实验仅用于申请内存。syscall id = 222。成功返回 0，错误返回 -1。
+/// `sys_mmap` 的 `port[3]`：共享的文件映射
+const MAP_FILE: usize = 1 << 3;
+
+/// 申请内存或者映射文件。syscall id = 222。成功返回 0，参数错误返回 -EINVAL，
+/// 与已有映射重叠返回 -EEXIST，物理内存或者 mmap 区域的空间不足返回 -ENOMEM。
+/// 启用 W^X 时同时可写可执行的映射返回 -EACCES；启用 `ptecheck` 且页表项检查失败时返回 -EFAULT。
 ///
-/// `start` 要求按页对齐。port 低三位分别表示以下属性，其它位无效且必须为 0
+/// `start` 要求按页对齐。`start` 为 0 时由内核选择地址，成功时返回选择的地址。port 低四位分别表示以下属性，其它位无效且必须为 0
 ///
+/// - `port[3]`: 共享的文件映射，映射 `fd` 中从 `offset` 开始的内容，`offset` 要求按页对齐。
+///   映射时读入文件的内容，被修改的页由 `sys_msync`、`sys_munmap` 或者进程退出时写回文件。
+///   `fd` 无效返回 -EBADF，不是磁盘上的文件返回 -ENODEV，文件不可读或者可写的映射对应的文件不可写返回 -EACCES。
+///   未置位时忽略 `fd` 和 `offset`，只传前三个参数的调用者不受影响
 /// - `port[2]`: read.
 /// - `port[1]`: write.
 /// - `port[0]`: exec.
-pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
+pub fn sys_mmap(start: usize, len: usize, port: usize, fd: usize, offset: usize) -> isize {
+    if len == 0 {
+        return 0;
+    }
+    if start % PAGE_SIZE != 0 || port & !(MAP_FILE | 0x7) != 0 || port & 0x7 == 0 {
+        return Errno::EINVAL.into();
+    }
+    let map_perm = MapPermission::from_bits_truncate((port as u8 & 0x7) << 1) | MapPermission::U;
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let result = if port & MAP_FILE != 0 {
+        if offset % PAGE_SIZE != 0 {
+            return Errno::EINVAL.into();
+        }
+        let file = match inner.fd_table.get(fd) {
+            Some(Some(desc)) => desc.file.clone(),
+            _ => return Errno::EBADF.into(),
+        };
+        let inode = match file.inode() {
+            Some(inode) => inode,
+            None => return Errno::ENODEV.into(),
+        };
+        if !file.readable() || map_perm.contains(MapPermission::W) && !file.writable() {
+            return Errno::EACCES.into();
+        }
+        let file = FileBacking { inode, offset };
+        task::map_file(&mut inner, start, len, map_perm, file)
+    } else {
+        task::map_range(&mut inner, start, len, map_perm)
+    };
+    match result {
+        Ok(addr) if start == 0 => addr as isize,
+        Ok(_) => 0,
+        Err(MapError::Overlap) => Errno::EEXIST.into(),
+        Err(MapError::OutOfMemory) => Errno::ENOMEM.into(),
+        Err(MapError::WriteExecute) => Errno::EACCES.into(),
+        Err(MapError::BadPte) => Errno::EFAULT.into(),
+    }
+}
+
+/// 修改已映射内存的访问权限。syscall id = 226。成功返回 0，参数错误返回 -EINVAL，
+/// 范围内有未映射的内存返回 -ENOMEM，启用 W^X 时改为同时可写可执行返回 -EACCES。
+///
+/// `start` 要求按页对齐，`prot` 的含义与 `sys_mmap` 的 `port` 相同，但不能为 0，也不能只写。
+/// `[start, start + len)` 必须完全位于已映射的用户内存中。
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
     if len == 0 {
         return 0;
     }
-    if start % PAGE_SIZE != 0 || port & !0x7 != 0 || port & 0x7 == 0 {
-        return -1;
+    if start % PAGE_SIZE != 0 || prot & !0x7 != 0 || prot & 0x3 == 0x2 || prot == 0 {
+        return Errno::EINVAL.into();
+    }
+    let map_perm = MapPermission::from_bits_truncate((prot as u8) << 1) | MapPermission::U;
+    if memory_set::check_wx(map_perm).is_err() {
+        return Errno::EACCES.into();
     }
-    let map_perm = MapPermission::from_bits_truncate((port as u8) << 1) | MapPermission::U;
-    if task::map_range(start, len, map_perm) {
+    let task = Processor::current_task().unwrap();
+    let protected = task::protect_range(&mut task.inner_exclusive_access(), start, len, map_perm);
+    if protected {
         0
     } else {
-        -1
+        Errno::ENOMEM.into()
+    }
+}
+
+/// 功能：查询 `[start, start + len)` 中各页的状态，每页一个字节写入 vec。
+///
+/// 每个字节是以下各位的组合，都为 0 表示页已映射但没有页帧：
+///
+/// - bit 0：页有自己的页帧，与 Linux 的 mincore 一致
+/// - bit 1：页还没有分配页帧，映射到共享的零页
+/// - bit 2：页帧写时复制地共享（保留，目前不会出现）
+/// - bit 3：页已换出（保留，目前不会出现）
+///
+/// 返回值：成功返回 0；start 未按页对齐返回 -EINVAL，范围内有未映射的页返回 -ENOMEM。
+///
+/// syscall ID：232
+pub fn sys_mincore(start: usize, len: usize, vec: *mut u8) -> isize {
+    if start % PAGE_SIZE != 0 {
+        return Errno::EINVAL.into();
+    }
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return Errno::ENOMEM.into(),
+    };
+    let states: Option<Vec<u8>> = {
+        let task = Processor::current_task().unwrap();
+        let inner = task.inner_exclusive_access();
+        VPNRange::covering(VirtAddr(start), VirtAddr(end))
+            .into_iter()
+            .map(|vpn| inner.memory_set.page_state(vpn).map(|state| state.bits()))
+            .collect()
+    };
+    let states = match states {
+        Some(states) => states,
+        None => return Errno::ENOMEM.into(),
+    };
+    let satp = Processor::current_user_satp();
+    let buffers = match page_table::translated_byte_buffer(satp, vec, states.len(), Access::Write) {
+        Ok(buffers) => buffers,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let mut states = states.as_slice();
+    for buf in buffers {
+        let (head, tail) = states.split_at(buf.len());
+        buf.copy_from_slice(head);
+        states = tail;
     }
+    0
 }
 
-/// 取消映射。syscall id = 215。成功返回 0，错误返回 -1。
+/// `msync` 的 flags：异步写回、使其它映射失效、同步写回
+const MS_ASYNC: usize = 1;
+const MS_INVALIDATE: usize = 2;
+const MS_SYNC: usize = 4;
+
+/// 功能：把 `[start, start + len)` 中被修改（D 位置位）的文件映射页写回文件，匿名映射的页不需要写回。
+///
+/// 写回的数据先进入块缓存，由 flusher 线程定期写回磁盘；MS_SYNC 等待块缓存全部写回磁盘后才返回。
+/// 写回的是页帧中的最新内容，没有其它缓存需要失效，因此忽略 MS_INVALIDATE。
+///
+/// 返回值：成功返回 0；start 未按页对齐、flags 含有未知的位或者同时含有 MS_ASYNC 和 MS_SYNC
+/// 返回 -EINVAL，范围内有未映射的页返回 -ENOMEM。
+///
+/// syscall ID：227
+pub fn sys_msync(start: usize, len: usize, flags: usize) -> isize {
+    if start % PAGE_SIZE != 0
+        || flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+        || flags & (MS_ASYNC | MS_SYNC) == MS_ASYNC | MS_SYNC
+    {
+        return Errno::EINVAL.into();
+    }
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return Errno::ENOMEM.into(),
+    };
+    let vpn_range = VPNRange::covering(VirtAddr(start), VirtAddr(end));
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let mapped = vpn_range
+        .into_iter()
+        .all(|vpn| inner.memory_set.page_state(vpn).is_some());
+    if !mapped {
+        return Errno::ENOMEM.into();
+    }
+    inner.memory_set.write_back(vpn_range);
+    drop(inner);
+    if flags & MS_SYNC != 0 {
+        easy_fs::block_cache_sync_all();
+    }
+    0
+}
+
+/// 取消映射。syscall id = 215。成功返回 0，错误返回 -EINVAL。
 ///
 /// `start` 要求按页对齐。
 ///
 /// FIXME: 注意，这里的实现是钻空子的。具体请看 `task::unmap_range` 的注释
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     if start % PAGE_SIZE != 0 {
-        return -1;
+        return Errno::EINVAL.into();
     }
-    if task::unmap_range(start, len) {
+    let task = Processor::current_task().unwrap();
+    let unmapped = task::unmap_range(&mut task.inner_exclusive_access(), start, len);
+    if unmapped {
         0
     } else {
-        -1
+        Errno::EINVAL.into()
     }
 }
 
 /// 功能：由当前进程 fork 出一个子进程。
-/// 返回值：对于子进程返回 0，对于当前进程则返回子进程的 PID。
+/// 返回值：对于子进程返回 0，对于当前进程则返回子进程的 PID；物理内存不足返回 -ENOMEM，
+/// 启用 `ptecheck` 时复制出的页表项不一致返回 -EFAULT。
 /// syscall ID：220
 pub fn sys_fork() -> isize {
     let current_task = Processor::current_task().unwrap();
-    let new_task = current_task.fork();
+    let new_task = match current_task.fork() {
+        Ok(task) => task,
+        Err(MapError::OutOfMemory) => return Errno::ENOMEM.into(),
+        Err(_) => return Errno::EFAULT.into(),
+    };
     let new_pid = new_task.pid.0;
     let trap_ctx = new_task.inner_exclusive_access().trap_ctx();
     // 父进程调用了 sys_fork() 创建子进程，接收 sys_fork() 的返回值
@@ -119,91 +610,605 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 
+/// 读取用户空间中以空指针结尾的字符串指针数组，字符串和指针总共超过 `ARG_MAX` 字节时返回 E2BIG
+fn translated_str_array(satp: usize, mut ptr: *const usize) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    let mut size = 0;
+    loop {
+        let str_ptr = *PageTable::translated_ref(satp, ptr)?;
+        if str_ptr == 0 {
+            return Ok(strings);
+        }
+        // 单个字符串过长时同样返回 E2BIG
+        let string = match PageTable::translated_str(satp, str_ptr as *const u8, ARG_MAX) {
+            Ok(string) => string,
+            Err(TranslateError::TooLong) => return Err(Errno::E2BIG),
+            Err(err) => return Err(err.into()),
+        };
+        size += string.len() + 1 + core::mem::size_of::<usize>();
+        if size > ARG_MAX {
+            return Err(Errno::E2BIG);
+        }
+        strings.push(string);
+        ptr = ptr.wrapping_add(1);
+    }
+}
+
+/// 查找可执行文件的搜索路径：当前进程的 `PATH` 环境变量，没有时为内核命令行给出的路径
+fn search_path(task: &TaskControlBlock) -> String {
+    let inner = task.inner_exclusive_access();
+    match inner.env.iter().find_map(|var| var.strip_prefix("PATH=")) {
+        Some(path) => String::from(path),
+        None => cmdline::exec_path(),
+    }
+}
+
+/// 加载 ELF 失败时返回给用户的错误码
+fn elf_errno(err: ElfError) -> Errno {
+    match err {
+        ElfError::ArgsTooLong => Errno::E2BIG,
+        ElfError::WriteExecute => Errno::EACCES,
+        ElfError::BadPte => Errno::EFAULT,
+        _ => Errno::ENOEXEC,
+    }
+}
+
 /// 功能：将当前进程的地址空间清空并加载一个特定的可执行文件，返回用户态后开始它的执行。
 ///
-/// 参数：字符串 path 给出了要加载的可执行文件的名字；
+/// 参数：字符串 path 给出了要加载的可执行文件的路径，如 `/user_shell`；不含 `/` 的名字在当前进程的
+/// `PATH` 环境变量给出的目录中依次查找，没有 `PATH` 时使用内核命令行 `path=` 选项给出的搜索路径；
+/// argv 和 envp 是以空指针结尾的字符串指针数组，按 SysV ABI 的布局放在新程序的初始用户栈上，
+/// 程序开始执行时 a0、a1、a2 分别为 argc、argv 和 envp。argv 为空指针时相当于只有 path 一项，
+/// envp 为空指针时沿用当前的环境变量；
 ///
-/// 返回值：如果出错的话（如找不到名字相符的可执行文件）则返回 -1，否则不应该返回。
+/// 返回值：成功时返回到新程序，返回值即 a0 中的 argc；找不到名字相符的可执行文件时返回 -ENOENT，
+/// 它不是有效的 ELF 时返回 -ENOEXEC，参数和环境变量总共超过 `ARG_MAX` 字节时返回 -E2BIG，
+/// 启用 W^X 而有同时可写可执行的段时返回 -EACCES。
 ///
 /// 注意：path 必须以 "\0" 结尾，否则内核将无法确定其长度
 ///
 /// syscall ID：221
-pub fn sys_exec(path: *const u8) -> isize {
+pub fn sys_exec(path: *const u8, argv: *const usize, envp: *const usize) -> isize {
     let user_satp = Processor::current_user_satp();
-    let path = PageTable::translated_str(user_satp, path);
-    if let Some(app_inode) = inode::open_file(&path, OpenFlags::RDONLY) {
-        let task = Processor::current_task().unwrap();
-        task.exec(&app_inode.read_all());
-        0
+    let path = match PageTable::translated_str(user_satp, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let argv = if argv.is_null() {
+        vec![path.clone()]
     } else {
-        -1
+        match translated_str_array(user_satp, argv) {
+            Ok(argv) => argv,
+            Err(errno) => return errno.into(),
+        }
+    };
+    let envp = if envp.is_null() {
+        None
+    } else {
+        match translated_str_array(user_satp, envp) {
+            Ok(envp) => Some(envp),
+            Err(errno) => return errno.into(),
+        }
+    };
+    let task = Processor::current_task().unwrap();
+    if let Some(app_inode) = inode::open_executable(&path, &search_path(&task)) {
+        match task.exec(&*app_inode, &argv, envp) {
+            // 系统调用的返回值写入 a0，必须与新程序初始栈上的 argc 一致
+            Ok(()) => argv.len() as isize,
+            Err(err) => {
+                log::info!("[kernel] exec {} failed: {:?}", path, err);
+                elf_errno(err).into()
+            }
+        }
+    } else {
+        Errno::ENOENT.into()
     }
 }
 
 /// 功能：新建子进程，使其执行目标程序。
 ///
-/// 参数：字符串 path 给出了要加载的可执行文件的名字，必须以 "\0" 结尾
+/// 参数：字符串 path 给出了要加载的可执行文件的路径，必须以 "\0" 结尾，查找方式与 `sys_exec` 相同。
+/// 子进程的 argv 只有 path 一项，环境变量与当前进程相同
 ///
-/// 返回值：成功返回子进程 id，否则返回 -1。
+/// 返回值：成功返回子进程 id；找不到可执行文件返回 -ENOENT，它不是有效的 ELF 返回 -ENOEXEC。
 ///
 /// syscall ID：400
 pub fn sys_spawn(path: *const u8) -> isize {
     let user_satp = Processor::current_user_satp();
-    let path = PageTable::translated_str(user_satp, path);
-    if let Some(app_inode) = inode::open_file(&path, OpenFlags::RDONLY) {
-        let task = Processor::current_task().unwrap();
-        task.spawn(&app_inode.read_all()) as isize
+    let path = match PageTable::translated_str(user_satp, path, PATH_MAX) {
+        Ok(path) => path,
+        Err(err) => return Errno::from(err).into(),
+    };
+    let task = Processor::current_task().unwrap();
+    if let Some(app_inode) = inode::open_executable(&path, &search_path(&task)) {
+        match task.spawn(&*app_inode, &[path.clone()]) {
+            Ok(pid) => pid as isize,
+            Err(err) => {
+                log::info!("[kernel] spawn {} failed: {:?}", path, err);
+                elf_errno(err).into()
+            }
+        }
     } else {
-        -1
+        Errno::ENOENT.into()
     }
 }
 
+/// 把子进程的状态写入用户地址 `ptr`，`ptr` 为空时什么也不做
+fn write_status(satp: usize, ptr: *mut i32, status: i32) -> Result<(), TranslateError> {
+    if !ptr.is_null() {
+        *PageTable::translated_mut(satp, ptr)? = status;
+    }
+    Ok(())
+}
+
+/// `waitpid` 的选项：同时报告因 SIGTSTP 暂停的子进程
+const WUNTRACED: usize = 2;
+
+/// 子进程因 SIGTSTP 暂停时 `sys_waitpid` 写入的状态，与 Linux 的 `WIFSTOPPED` 格式相同
+pub const JOB_STOP_STATUS: i32 = 0x147f;
+
 /// 功能：当前进程等待一个子进程变为僵尸进程，回收其全部资源并收集其返回值。
 /// 参数：pid 表示要等待的子进程的进程 ID，如果为 -1 的话表示等待任意一个子进程；
-/// exit_code 表示保存子进程返回值的地址，如果这个地址为 0 的话表示不必保存。
-/// 返回值：如果要等待的子进程不存在则返回 -1；否则如果要等待的子进程均未结束则返回 -2；
-/// 否则返回结束的子进程的进程 ID。
+/// exit_code 表示保存子进程返回值的地址，如果这个地址为 0 的话表示不必保存；
+/// options 只支持 WUNTRACED(2)。
+/// 要等待的子进程均未结束时阻塞，直到有子进程退出。
+/// 被跟踪的子进程暂停时也会返回它的进程 ID，此时写入的状态为 `PTRACE_STOP_STATUS`；
+/// 设置了 WUNTRACED 时，子进程因 SIGTSTP 暂停后也会返回一次它的进程 ID，此时写入的状态为 `JOB_STOP_STATUS`。
+/// 返回值：如果要等待的子进程不存在则返回 -ECHILD；options 不支持返回 -EINVAL；等待时收到信号则返回 -EINTR；
+/// 否则返回结束或暂停的子进程的进程 ID。
 /// syscall id = 260
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, options: usize) -> isize {
+    if options & !WUNTRACED != 0 {
+        return Errno::EINVAL.into();
+    }
     let task = Processor::current_task().unwrap();
+    loop {
+        let mut inner = task.inner_exclusive_access();
 
-    let mut inner = task.inner_exclusive_access();
+        // 不存在这样的子进程
+        if !inner
+            .children
+            .iter()
+            .any(|p| pid == -1 || pid as usize == p.pid())
+        {
+            log::debug!("not such child: {}", pid);
+            return Errno::ECHILD.into();
+        }
+
+        if let Some((idx, _)) = inner.children.iter().enumerate().find(|(_, p)| {
+            p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.pid())
+        }) {
+            let child = inner.children.swap_remove(idx);
+            assert_eq!(Arc::strong_count(&child), 1);
+            let found_pid = child.pid();
+            let exit_code = child.inner_exclusive_access().exit_code;
+            // 写入用户内存时可能要处理零页，不能持有当前任务的借用
+            let satp = inner.user_satp();
+            drop(inner);
+            // 与 Linux 相同，地址无效时子进程仍被回收
+            return match write_status(satp, exit_code_ptr, exit_code) {
+                Ok(()) => found_pid as isize,
+                Err(err) => Errno::from(err).into(),
+            };
+        }
+        if let Some(child) = inner.children.iter().find(|p| {
+            (pid == -1 || pid as usize == p.pid())
+                && p.inner_exclusive_access()
+                    .ptrace
+                    .as_mut()
+                    .map_or(false, Ptrace::take_unreported_stop)
+        }) {
+            let found_pid = child.pid();
+            let satp = inner.user_satp();
+            drop(inner);
+            return match write_status(satp, exit_code_ptr, PTRACE_STOP_STATUS) {
+                Ok(()) => found_pid as isize,
+                Err(err) => Errno::from(err).into(),
+            };
+        }
+        if let Some(child) = inner.children.iter().find(|p| {
+            (pid == -1 || pid as usize == p.pid())
+                && options & WUNTRACED != 0
+                && mem::take(&mut p.inner_exclusive_access().stop_unreported)
+        }) {
+            let found_pid = child.pid();
+            let satp = inner.user_satp();
+            drop(inner);
+            return match write_status(satp, exit_code_ptr, JOB_STOP_STATUS) {
+                Ok(()) => found_pid as isize,
+                Err(err) => Errno::from(err).into(),
+            };
+        }
+        drop(inner);
+        // 返回用户态处理信号，用户库会重新调用 waitpid
+        if task::signal_pending() {
+            return Errno::EINTR.into();
+        }
+        task.wait_child_exit();
+    }
+}
+
+/// `prlimit64` 支持的资源：处理器时间
+const RLIMIT_CPU: usize = 0;
+
+/// 功能：查询并设置进程的资源限制。目前只支持 RLIMIT_CPU：进程消耗的处理器时间达到软限制后被杀死，
+/// 退出码为 `CPU_LIMIT_EXIT_CODE`(-24)。限制在 fork 和 spawn 时继承，exec 时保留。
+///
+/// 参数：pid 为目标进程的 id，为 0 时表示当前进程；resource 只支持 RLIMIT_CPU(0)；
+/// new_limit 不为 0 时设置新的限制，单位为秒，`u64::MAX` 表示不限制；old_limit 不为 0 时保存原先的限制。
+///
+/// 返回值：成功返回 0，进程不存在返回 -ESRCH，resource 不支持或软限制超过硬限制返回 -EINVAL。
+///
+/// syscall ID：261
+pub fn sys_prlimit64(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    if resource != RLIMIT_CPU {
+        return Errno::EINVAL.into();
+    }
+    let user_satp = Processor::current_user_satp();
+    let new_limit = if new_limit.is_null() {
+        None
+    } else {
+        match PageTable::translated_ref(user_satp, new_limit) {
+            Ok(&limit) => Some(limit),
+            Err(err) => return Errno::from(err).into(),
+        }
+    };
+    if matches!(new_limit, Some(limit) if limit.cur > limit.max) {
+        return Errno::EINVAL.into();
+    }
+    let task = if pid == 0 {
+        Processor::current_task()
+    } else {
+        task::pid2task(pid)
+    };
+    let task = match task {
+        Some(task) => task,
+        None => return Errno::ESRCH.into(),
+    };
+    let old = {
+        let mut inner = task.inner_exclusive_access();
+        let old = inner.cpu_limit;
+        if let Some(limit) = new_limit {
+            inner.cpu_limit = limit;
+        }
+        old
+    };
+    if !old_limit.is_null() {
+        match PageTable::translated_mut(user_satp, old_limit) {
+            Ok(old_limit) => *old_limit = old,
+            Err(err) => return Errno::from(err).into(),
+        }
+    }
+    0
+}
+
+/// `sys_ptrace` 的请求，取值与 Linux 相同
+const PTRACE_PEEKDATA: usize = 2;
+const PTRACE_POKEDATA: usize = 5;
+const PTRACE_CONT: usize = 7;
+const PTRACE_SINGLESTEP: usize = 9;
+const PTRACE_GETREGS: usize = 12;
+const PTRACE_SETREGS: usize = 13;
+const PTRACE_ATTACH: usize = 16;
+const PTRACE_DETACH: usize = 17;
+
+/// 被跟踪的子进程暂停时 `sys_waitpid` 写入的状态，与 Linux 中因 SIGTRAP 暂停的状态相同
+pub const PTRACE_STOP_STATUS: i32 = 0x57f;
 
-    // 不存在这样的子进程
-    if !inner
+/// 功能：跟踪一个子进程，读写它的寄存器和内存，让它继续运行或者单步执行，见 [`task::ptrace`]。
+///
+/// 参数：request 为请求，pid 为子进程的 id，addr 和 data 的含义取决于请求：
+/// - `PTRACE_ATTACH`：开始跟踪，子进程在下一次返回用户态之前暂停，用 `sys_waitpid` 等待它暂停；
+/// - `PTRACE_PEEKDATA`：读取子进程 addr 处的 8 字节，写入当前进程 data 指向的 `u64`；
+/// - `PTRACE_POKEDATA`：把 data 作为 8 字节写入子进程的 addr 处，代码段也可以写入；
+/// - `PTRACE_GETREGS`、`PTRACE_SETREGS`：data 指向 32 个 `usize`，依次为 pc 和 x1~x31；
+/// - `PTRACE_CONT`：继续运行；
+/// - `PTRACE_SINGLESTEP`：执行一条指令后再次暂停；
+/// - `PTRACE_DETACH`：停止跟踪，暂停的子进程继续运行。
+///
+/// 除了 `PTRACE_ATTACH` 和 `PTRACE_DETACH`，其余请求要求子进程已经暂停。
+/// 跟踪者退出时，被跟踪的子进程随之停止跟踪。
+///
+/// 返回值：成功返回 0；pid 不是当前进程的子进程，或者子进程没有被跟踪或没有暂停返回 -ESRCH；
+/// 子进程已经被跟踪返回 -EPERM；子进程的地址无法访问返回 -EFAULT；request 不合法返回 -EINVAL。
+///
+/// syscall ID：117
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    let current = Processor::current_task().unwrap();
+    let satp = current.inner_exclusive_access().user_satp();
+    let tracee = match current
+        .inner_exclusive_access()
         .children
         .iter()
-        .any(|p| pid == -1 || pid as usize == p.pid())
+        .find(|child| child.pid() == pid)
     {
-        log::debug!("not such child: {}", pid);
-        return -1;
-    }
-
-    if let Some((idx, _)) = inner.children.iter().enumerate().find(|(_, p)| {
-        p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.pid())
-    }) {
-        let child = inner.children.swap_remove(idx);
-        assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.pid();
-        let exit_code = child.inner_exclusive_access().exit_code;
-        *(PageTable::translated_mut(inner.user_satp(), exit_code_ptr)) = exit_code;
-        found_pid as isize
+        Some(child) => Arc::clone(child),
+        None => return Errno::ESRCH.into(),
+    };
+    drop(current);
+    match request {
+        PTRACE_ATTACH => {
+            let mut inner = tracee.inner_exclusive_access();
+            if inner.ptrace.is_some() {
+                return Errno::EPERM.into();
+            }
+            inner.ptrace = Some(Ptrace::new());
+            return 0;
+        }
+        PTRACE_DETACH => {
+            if tracee.inner_exclusive_access().ptrace.is_none() {
+                return Errno::ESRCH.into();
+            }
+            ptrace::detach(&tracee);
+            return 0;
+        }
+        _ => {}
+    }
+    // 写入的寄存器要在借用子进程之前从当前进程读出
+    let regs = if request == PTRACE_SETREGS {
+        let regs = data as *const usize;
+        let mut values = [0; 32];
+        for (i, value) in values.iter_mut().enumerate() {
+            match PageTable::translated_ref(satp, regs.wrapping_add(i)) {
+                Ok(&reg) => *value = reg,
+                Err(err) => return Errno::from(err).into(),
+            }
+        }
+        values
     } else {
-        -2
+        [0; 32]
+    };
+    let mut guard = tracee.inner_exclusive_access();
+    let trap_ctx = guard.trap_ctx();
+    let inner = &mut *guard;
+    let trace = match inner.ptrace.as_mut() {
+        Some(trace) if trace.is_stopped() => trace,
+        _ => return Errno::ESRCH.into(),
+    };
+    match request {
+        PTRACE_PEEKDATA => {
+            let mut bytes = [0; 8];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                match ptrace::read_byte(&inner.memory_set, addr.wrapping_add(i)) {
+                    Some(value) => *byte = value,
+                    None => return Errno::EFAULT.into(),
+                }
+            }
+            drop(guard);
+            match PageTable::translated_mut(satp, data as *mut u64) {
+                Ok(data) => *data = u64::from_le_bytes(bytes),
+                Err(err) => return Errno::from(err).into(),
+            }
+        }
+        PTRACE_POKEDATA => {
+            for (i, &byte) in data.to_le_bytes().iter().enumerate() {
+                if !ptrace::write_byte(&mut inner.memory_set, addr.wrapping_add(i), byte) {
+                    return Errno::EFAULT.into();
+                }
+            }
+        }
+        PTRACE_GETREGS => {
+            let mut values = trap_ctx.x;
+            values[0] = trap_ctx.sepc;
+            drop(guard);
+            let regs = data as *mut usize;
+            for (i, &value) in values.iter().enumerate() {
+                match PageTable::translated_mut(satp, regs.wrapping_add(i)) {
+                    Ok(reg) => *reg = value,
+                    Err(err) => return Errno::from(err).into(),
+                }
+            }
+        }
+        PTRACE_SETREGS => {
+            trap_ctx.sepc = regs[0];
+            trap_ctx.x[1..].copy_from_slice(&regs[1..]);
+        }
+        PTRACE_CONT => {
+            trace.state = TraceState::Running;
+            drop(guard);
+            ptrace::resume(&tracee);
+        }
+        PTRACE_SINGLESTEP => {
+            let regs = trap_ctx.x;
+            if !trace.single_step(&mut inner.memory_set, trap_ctx.sepc, |i| regs[i]) {
+                return Errno::EFAULT.into();
+            }
+            drop(guard);
+            ptrace::resume(&tracee);
+        }
+        _ => return Errno::EINVAL.into(),
+    }
+    0
+}
+
+/// 功能：关闭系统。在 QEMU 中运行时 QEMU 随之退出，用于脚本和 CI 结束一次运行。
+///
+/// 参数：failure 为 true 时 QEMU 的退出码为 1，否则为 0。
+///
+/// 返回值：成功时不返回；调用者不是 root 返回 -EPERM。
+///
+/// syscall ID：415
+pub fn sys_shutdown(failure: bool) -> isize {
+    if sys_getuid() != inode::ROOT_UID as isize {
+        return Errno::EPERM.into();
     }
+    log::info!(
+        "[kernel] Shutdown requested by pid {}, failure = {}",
+        sys_getpid(),
+        failure
+    );
+    power::poweroff(failure as u16)
 }
 
+/// 功能：获取当前进程的进程号，同一进程的所有线程得到相同的值。
+///
+/// 返回值：当前进程的 pid。
+///
+/// syscall ID：172
 pub fn sys_getpid() -> isize {
-    Processor::current_task().unwrap().pid.0 as isize
+    Processor::current_task().unwrap().pid() as isize
+}
+
+/// 功能：获取当前线程的线程号。
+///
+/// 返回值：当前线程的 tid。目前每个进程只有一个线程，它与 pid 相同。
+///
+/// syscall ID：178
+pub fn sys_gettid() -> isize {
+    Processor::current_task().unwrap().tid() as isize
+}
+
+/// pid 为 0 时是当前进程，否则是 pid 对应的尚未退出的进程
+fn task_or_current(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    if pid == 0 {
+        Processor::current_task()
+    } else {
+        task::pid2task(pid)
+    }
+}
+
+/// 功能：把进程 pid 移入进程组 pgid。
+///
+/// 参数：pid 为 0 表示当前进程，否则必须是当前进程或其子进程；
+/// pgid 为 0 表示使用 pid 作为进程组号，即新建以 pid 为组长的进程组。
+///
+/// 返回值：成功返回 0；pgid 为负返回 -EINVAL，pid 既不是当前进程也不是其子进程返回 -ESRCH，
+/// pid 是会话首进程、与当前进程不在同一会话，或者会话中没有进程组 pgid 时返回 -EPERM。
+///
+/// syscall ID：154
+pub fn sys_setpgid(pid: usize, pgid: isize) -> isize {
+    if pgid < 0 {
+        return Errno::EINVAL.into();
+    }
+    let current = Processor::current_task().unwrap();
+    let target = if pid == 0 || pid == current.pid() {
+        Arc::clone(&current)
+    } else {
+        let inner = current.inner_exclusive_access();
+        match inner.children.iter().find(|child| child.pid() == pid) {
+            Some(child) => Arc::clone(child),
+            None => return Errno::ESRCH.into(),
+        }
+    };
+    let pgid = if pgid == 0 {
+        target.pid()
+    } else {
+        pgid as usize
+    };
+    let sid = current.inner_exclusive_access().sid;
+    let target_sid = target.inner_exclusive_access().sid;
+    if target_sid == target.pid() || target_sid != sid {
+        return Errno::EPERM.into();
+    }
+    if pgid != target.pid() && !task::pgrp_in_session(pgid, sid) {
+        return Errno::EPERM.into();
+    }
+    target.inner_exclusive_access().pgid = pgid;
+    0
+}
+
+/// 功能：获取进程 pid 所在的进程组号，pid 为 0 表示当前进程。
+///
+/// 返回值：成功返回进程组号，进程不存在返回 -ESRCH。
+///
+/// syscall ID：155
+pub fn sys_getpgid(pid: usize) -> isize {
+    match task_or_current(pid) {
+        Some(task) => task.inner_exclusive_access().pgid as isize,
+        None => Errno::ESRCH.into(),
+    }
+}
+
+/// 功能：获取进程 pid 所在的会话号，pid 为 0 表示当前进程。
+///
+/// 返回值：成功返回会话号，进程不存在返回 -ESRCH。
+///
+/// syscall ID：156
+pub fn sys_getsid(pid: usize) -> isize {
+    match task_or_current(pid) {
+        Some(task) => task.inner_exclusive_access().sid as isize,
+        None => Errno::ESRCH.into(),
+    }
+}
+
+/// 功能：新建一个会话，当前进程成为会话首进程和新进程组的组长。
+///
+/// 返回值：成功返回新的会话号，即当前进程的 pid；已经存在以当前进程的 pid 为号的进程组时返回 -EPERM。
+///
+/// syscall ID：157
+pub fn sys_setsid() -> isize {
+    let current = Processor::current_task().unwrap();
+    let pid = current.pid();
+    if !manager::pgrp_members(pid).is_empty() {
+        return Errno::EPERM.into();
+    }
+    let mut inner = current.inner_exclusive_access();
+    inner.pgid = pid;
+    inner.sid = pid;
+    pid as isize
+}
+
+/// 功能：向进程 pid 发送 sig 号信号，例如发送 SIGCONT 让暂停的作业继续运行。
+///
+/// 参数：pid 大于 0 时发给该进程，为 0 时发给当前进程组中的所有进程，小于 -1 时发给进程组 -pid 中的所有进程；
+/// sig 为 0 时只检查目标是否存在。initproc 不接收信号。
+///
+/// 返回值：成功返回 0；sig 不支持或 pid 为 -1 返回 -EINVAL，目标进程或进程组不存在返回 -ESRCH，
+/// 目标与当前进程不在同一会话返回 -EPERM。
+///
+/// syscall ID：129
+pub fn sys_kill(pid: isize, sig: usize) -> isize {
+    let signal = match sig {
+        0 => SignalFlags::empty(),
+        1..=31 => match SignalFlags::from_bits(1 << sig) {
+            Some(signal) => signal,
+            None => return Errno::EINVAL.into(),
+        },
+        _ => return Errno::EINVAL.into(),
+    };
+    let current = Processor::current_task().unwrap();
+    let (pgid, sid) = {
+        let inner = current.inner_exclusive_access();
+        (inner.pgid, inner.sid)
+    };
+    if pid > 0 {
+        let target = match task::pid2task(pid as usize) {
+            Some(target) => target,
+            None => return Errno::ESRCH.into(),
+        };
+        if target.inner_exclusive_access().sid != sid {
+            return Errno::EPERM.into();
+        }
+        task::signal_task(&target, signal);
+        return 0;
+    }
+    let pgid = match pid {
+        0 => pgid,
+        -1 => return Errno::EINVAL.into(),
+        _ => pid.unsigned_abs(),
+    };
+    if manager::pgrp_members(pgid).is_empty() {
+        return Errno::ESRCH.into();
+    }
+    if !task::pgrp_in_session(pgid, sid) {
+        return Errno::EPERM.into();
+    }
+    task::signal_pgrp(pgid, signal);
+    0
 }
 
 // syscall ID：140
 // 设置当前进程优先级为 prio
 // 参数：prio 进程优先级，要求 prio >= 2
-// 返回值：如果输入合法则返回 prio，否则返回 -1
+// 返回值：如果输入合法则返回 prio，否则返回 -EINVAL
 pub fn sys_set_priority(priority: isize) -> isize {
     if priority <= 1 {
-        return -1;
+        return Errno::EINVAL.into();
     }
     Processor::current_task()
         .unwrap()
@@ -0,0 +1,62 @@
+use crate::{
+    mm::{
+        address::{phys_to_virt, VirtAddr},
+        user::{with_user_access, Access},
+    },
+    sync::futex,
+    task::{self, Processor},
+};
+
+use super::Errno;
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+
+/// 将用户地址 `addr` 处的 futex 翻译为物理地址，用户不可访问时返回 `None`
+fn futex_pa(addr: usize) -> Option<usize> {
+    let va = VirtAddr(addr);
+    let ppn = with_user_access(Processor::current_user_satp(), |user| {
+        user.translate(va, Access::Read)
+    })?;
+    Some(ppn.page_start().0 + va.page_offset())
+}
+
+/// 功能：在用户地址 addr 处的 32 位整数（futex）上等待或唤醒。
+/// 等待者以 futex 所在的物理地址区分，用户态可以据此实现互斥锁和条件变量。
+///
+/// 参数：op 为 FUTEX_WAIT(0) 时，若 addr 处的值等于 val 则阻塞，直到被 FUTEX_WAKE 唤醒；
+/// op 为 FUTEX_WAKE(1) 时，按等待的先后顺序唤醒至多 val 个等待者。
+///
+/// 返回值：FUTEX_WAIT 被唤醒后返回 0，值不等于 val 时返回 -EAGAIN；
+/// FUTEX_WAKE 返回唤醒的任务数。addr 未对齐返回 -EINVAL，用户不可访问返回 -EFAULT，
+/// op 不支持返回 -ENOSYS。
+///
+/// syscall ID：98
+pub fn sys_futex(addr: usize, op: usize, val: usize) -> isize {
+    if addr % core::mem::size_of::<u32>() != 0 {
+        return Errno::EINVAL.into();
+    }
+    let pa = match futex_pa(addr) {
+        Some(pa) => pa,
+        None => return Errno::EFAULT.into(),
+    };
+    match op {
+        FUTEX_WAIT => {
+            // 单处理器上读取和入队之间不会被打断，因此不会错过唤醒
+            let value = unsafe { *(phys_to_virt(pa) as *const u32) };
+            if value != val as u32 {
+                return Errno::EAGAIN.into();
+            }
+            futex::enqueue(pa, Processor::current_task().unwrap());
+            task::block_current_and_run_next();
+            0
+        }
+        FUTEX_WAKE => {
+            let woken = futex::dequeue(pa, val);
+            let count = woken.len();
+            woken.into_iter().for_each(task::wakeup_task);
+            count as isize
+        }
+        _ => Errno::ENOSYS.into(),
+    }
+}
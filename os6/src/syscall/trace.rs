@@ -0,0 +1,148 @@
+//! Syscall tracing
+//!
+//! 打开某个进程的跟踪开关后，它的每个系统调用都会以类似 strace 的格式记录到内核日志中，
+//! 如 `[strace] pid 2: write(1, "hello", 5) = 5`。为了避免频繁调用系统调用的进程刷屏，
+//! 每个进程每秒最多记录 `TRACE_RATE_LIMIT` 条，其余的只计数，在下一秒开始时汇总报告。
+
+use alloc::string::String;
+use core::fmt::Write;
+
+use log::Level;
+
+use crate::{mm::user::with_user_access, task::Processor, timer};
+
+use super::Errno;
+
+/// 每个进程每个时间窗口内最多记录的系统调用数
+const TRACE_RATE_LIMIT: usize = 64;
+const TRACE_WINDOW_MS: usize = 1000;
+/// 字符串参数最多显示的字节数
+const TRACE_STR_MAX: usize = 32;
+/// 跟踪记录使用的日志等级，需要用 `sys_set_log_level` 打开
+const TRACE_LEVEL: Level = Level::Info;
+
+/// 系统调用参数的显示方式
+#[derive(Copy, Clone)]
+pub enum Arg {
+    /// 有符号整数，如 fd、pid 和退出码
+    Int,
+    /// 无符号整数，如长度
+    Uint,
+    /// 地址或标志位，以十六进制显示
+    Hex,
+    /// 以 `\0` 结尾的用户字符串
+    Str,
+}
+
+/// 一个进程的跟踪状态
+#[derive(Default)]
+pub struct SyscallTrace {
+    pub enabled: bool,
+    /// 当前时间窗口的起始时间（毫秒）
+    window_start: usize,
+    /// 当前时间窗口内已经记录的条数
+    logged: usize,
+    /// 当前时间窗口内因限流而略去的条数
+    suppressed: usize,
+}
+
+impl SyscallTrace {
+    /// 判断这次系统调用是否应当记录。进入新的时间窗口时返回上一个窗口略去的条数
+    fn admit(&mut self, now_ms: usize) -> (bool, usize) {
+        let mut suppressed = 0;
+        if now_ms >= self.window_start + TRACE_WINDOW_MS {
+            suppressed = core::mem::take(&mut self.suppressed);
+            self.window_start = now_ms;
+            self.logged = 0;
+        }
+        if self.logged < TRACE_RATE_LIMIT {
+            self.logged += 1;
+            (true, suppressed)
+        } else {
+            self.suppressed += 1;
+            (false, suppressed)
+        }
+    }
+}
+
+/// 读取用户字符串用于显示。遇到未映射或用户不可访问的页时停止，而不是 panic
+fn user_str(satp: usize, ptr: usize, out: &mut String) {
+    with_user_access(satp, |user| {
+        out.push('"');
+        for i in 0..=TRACE_STR_MAX {
+            let byte = match user.read(ptr.wrapping_add(i) as *const u8) {
+                Some(byte) => byte,
+                None => {
+                    out.push_str("\"<fault>");
+                    return;
+                }
+            };
+            if byte == 0 {
+                break;
+            }
+            if i == TRACE_STR_MAX {
+                out.push_str("\"...");
+                return;
+            }
+            for c in core::ascii::escape_default(byte) {
+                out.push(c as char);
+            }
+        }
+        out.push('"');
+    })
+}
+
+/// 在系统调用执行之前调用。当前进程需要跟踪时返回格式化好的调用，执行完毕后交给 [`finish`]
+pub fn begin(name: Option<&str>, id: usize, arg_kinds: &[Arg], args: [usize; 6]) -> Option<String> {
+    let task = Processor::current_task()?;
+    let mut inner = task.inner_exclusive_access();
+    if !inner.trace.enabled || !log::log_enabled!(TRACE_LEVEL) {
+        return None;
+    }
+    // 限流只需要时钟中断的精度
+    let (admitted, suppressed) = inner.trace.admit(timer::ticks_to_ms(timer::jiffies()));
+    if suppressed > 0 {
+        log::log!(
+            TRACE_LEVEL,
+            "[strace] pid {}: {} syscalls not traced (rate limited)",
+            task.pid(),
+            suppressed
+        );
+    }
+    if !admitted {
+        return None;
+    }
+    let satp = inner.user_satp();
+    drop(inner);
+    let mut call = String::new();
+    write!(call, "pid {}: ", task.pid()).unwrap();
+    match name {
+        Some(name) => call.push_str(name),
+        None => write!(call, "syscall_{}", id).unwrap(),
+    }
+    call.push('(');
+    for (i, (kind, &arg)) in arg_kinds.iter().zip(args.iter()).enumerate() {
+        if i > 0 {
+            call.push_str(", ");
+        }
+        match kind {
+            Arg::Int => write!(call, "{}", arg as isize).unwrap(),
+            Arg::Uint => write!(call, "{}", arg).unwrap(),
+            Arg::Hex => write!(call, "{:#x}", arg).unwrap(),
+            Arg::Str => user_str(satp, arg, &mut call),
+        }
+    }
+    call.push(')');
+    Some(call)
+}
+
+/// 记录系统调用的结果。`ret` 为 `None` 表示系统调用不会返回，如 `exit`
+pub fn finish(call: &str, ret: Option<isize>) {
+    match ret {
+        Some(ret) => match Errno::from_ret(ret) {
+            Some(errno) => log::log!(TRACE_LEVEL, "[strace] {} = {} {:?}", call, ret, errno),
+            None => log::log!(TRACE_LEVEL, "[strace] {} = {}", call, ret),
+        },
+        None => log::log!(TRACE_LEVEL, "[strace] {} = ?", call),
+    }
+}
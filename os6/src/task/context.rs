@@ -1,5 +1,7 @@
 use crate::trap::trap_return;
 
+use super::kthread_start;
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct TaskContext {
@@ -30,4 +32,12 @@ impl TaskContext {
             s: [0; 12],
         }
     }
+    /// 内核线程第一次被调度时从 `kthread_start` 开始执行
+    pub fn goto_kthread_start(kernel_stack_ptr: usize) -> Self {
+        Self {
+            ra: kthread_start as usize,
+            sp: kernel_stack_ptr,
+            s: [0; 12],
+        }
+    }
 }
@@ -0,0 +1,158 @@
+//! 浮点寄存器的延迟保存与恢复
+//!
+//! 用户程序开始运行时 `sstatus.FS` 为 Off，第一次执行浮点指令时引发非法指令异常，内核这时才为它打开
+//! 浮点单元，之后重新执行这条指令。内核自身不使用浮点寄存器，进入内核时关闭 FS，内核中误用浮点指令会
+//! 引发来自内核的 trap。
+//!
+//! 浮点寄存器堆属于最近一个在其上运行的进程 `FP_OWNER`。只有另一个使用浮点的进程返回用户态时才需要
+//! 交换：原拥有者的 FS 为 Dirty 时才保存它的寄存器，再恢复新进程的寄存器。FS 随 Trap 上下文中的
+//! `sstatus` 保存和恢复，硬件在写浮点寄存器时把它置为 Dirty。
+
+use alloc::sync::{Arc, Weak};
+
+use riscv::register::sstatus::{self, FS};
+
+use super::{Processor, TaskControlBlock, TaskControlBlockInner};
+use crate::sync::UPSafeCell;
+
+/// 进程的浮点寄存器，不是寄存器堆的拥有者时以这里保存的为准
+#[derive(Clone, Default)]
+pub struct FpState {
+    f: [u64; 32],
+    fcsr: usize,
+    /// 是否已经使用过浮点指令
+    pub enabled: bool,
+}
+
+/// 浮点寄存器堆当前的拥有者
+static FP_OWNER: UPSafeCell<Option<Weak<TaskControlBlock>>> = unsafe { UPSafeCell::new(None) };
+
+fn owner() -> Option<Arc<TaskControlBlock>> {
+    FP_OWNER.exclusive_access().as_ref()?.upgrade()
+}
+
+fn is_owner(task: &TaskControlBlock) -> bool {
+    owner().map_or(false, |owner| core::ptr::eq(Arc::as_ptr(&owner), task))
+}
+
+/// 临时打开浮点单元执行 `f`
+fn with_fp<T>(f: impl FnOnce() -> T) -> T {
+    let fs = sstatus::read().fs();
+    unsafe { sstatus::set_fs(FS::Clean) };
+    let ret = f();
+    unsafe { sstatus::set_fs(fs) };
+    ret
+}
+
+impl FpState {
+    /// 把寄存器堆的内容保存到这里
+    fn save(&mut self) {
+        let f = self.f.as_mut_ptr();
+        self.fcsr = with_fp(|| {
+            let fcsr;
+            unsafe {
+                core::arch::asm!(
+                    ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                    "fsd f\\i, \\i*8({f})",
+                    ".endr",
+                    "frcsr {fcsr}",
+                    f = in(reg) f,
+                    fcsr = out(reg) fcsr,
+                );
+            }
+            fcsr
+        });
+    }
+    /// 用这里保存的内容覆盖寄存器堆
+    fn restore(&self) {
+        let f = self.f.as_ptr();
+        with_fp(|| unsafe {
+            core::arch::asm!(
+                ".irp i, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "fld f\\i, \\i*8({f})",
+                ".endr",
+                "fscsr {fcsr}",
+                f = in(reg) f,
+                fcsr = in(reg) self.fcsr,
+            );
+        });
+    }
+}
+
+/// 进入内核时关闭浮点单元，用户的 FS 已经保存在 Trap 上下文中
+pub fn disable_in_kernel() {
+    unsafe { sstatus::set_fs(FS::Off) };
+}
+
+/// 寄存器堆的拥有者修改过寄存器时，保存到它的 TCB 中。之后它的 TCB 中的内容就是最新的，例如可以被 fork 复制
+pub fn flush_owner() {
+    if let Some(owner) = owner() {
+        let mut inner = owner.inner_exclusive_access();
+        if inner.trap_ctx_ppn.is_some() {
+            save_if_dirty(&mut inner);
+        }
+    }
+}
+
+fn save_if_dirty(inner: &mut TaskControlBlockInner) {
+    let trap_ctx = inner.trap_ctx();
+    if trap_ctx.sstatus.fs() == FS::Dirty {
+        inner.fp.save();
+        trap_ctx.sstatus.set_fs(FS::Clean);
+    }
+}
+
+/// 当前进程执行了非法指令。还没有打开浮点单元时为它打开，返回 true，返回用户态后重新执行这条指令；
+/// 否则这是真正的非法指令，返回 false
+pub fn handle_fp_disabled() -> bool {
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let trap_ctx = inner.trap_ctx();
+    if inner.fp.enabled || trap_ctx.sstatus.fs() != FS::Off {
+        return false;
+    }
+    inner.fp.enabled = true;
+    trap_ctx.sstatus.set_fs(FS::Initial);
+    true
+}
+
+/// 返回用户态之前调用：当前进程使用浮点而寄存器堆属于别的进程时，保存原拥有者的寄存器，
+/// 换上当前进程的寄存器
+pub fn prepare_return() {
+    let task = Processor::current_task().unwrap();
+    if !task.inner_exclusive_access().fp.enabled || is_owner(&task) {
+        return;
+    }
+    flush_owner();
+    let mut inner = task.inner_exclusive_access();
+    inner.fp.restore();
+    inner.trap_ctx().sstatus.set_fs(FS::Clean);
+    *FP_OWNER.exclusive_access() = Some(Arc::downgrade(&task));
+}
+
+/// exec 换掉了程序或者进程退出，寄存器堆中的内容不再属于 `task`
+pub fn release(task: &TaskControlBlock) {
+    if is_owner(task) {
+        *FP_OWNER.exclusive_access() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn fp_state_survives_save_and_restore() {
+        let mut state = FpState::default();
+        for (i, f) in state.f.iter_mut().enumerate() {
+            *f = 0x4000_0000_0000_0000 | i as u64;
+        }
+        // frm 为 RTZ，fflags 为 NX 和 OF
+        state.fcsr = 0b001 << 5 | 0b00101;
+        state.restore();
+        let mut saved = FpState::default();
+        saved.save();
+        assert_eq!(saved.f, state.f);
+        assert_eq!(saved.fcsr, state.fcsr);
+    }
+}
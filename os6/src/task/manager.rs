@@ -1,47 +1,166 @@
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
 use lazy_static::lazy_static;
 
+use super::tcb::Pass;
 pub use super::tcb::TaskStatus;
 use super::{tcb::TaskControlBlock, INITPROC};
-use crate::{config::BIG_STRIDE, sync::UPSafeCell};
+use crate::{
+    cmdline::{self, SchedPolicy},
+    fs::stdio,
+    ktrace::{self, EventKind},
+    sync::{KSpinLock, UPSafeCell},
+};
 
 lazy_static! {
-    static ref TASK_MANAGER: UPSafeCell<TaskManager> =
-        unsafe { UPSafeCell::new(TaskManager::new()) };
+    static ref TASK_MANAGER: KSpinLock<TaskManager> =
+        unsafe { KSpinLock::new("TASK_MANAGER", TaskManager::new()) };
+    /// 所有尚未退出的进程，无论它们处于就绪、运行还是阻塞状态
+    static ref PID2TASK: UPSafeCell<BTreeMap<usize, Arc<TaskControlBlock>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
 }
 
 pub struct TaskManager {
     pub ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// 最近一次调度出的任务的 pass，即当前所有就绪任务中最小的 pass
+    min_pass: Pass,
 }
 
 impl TaskManager {
     pub fn new() -> Self {
         Self {
             ready_queue: VecDeque::new(),
+            min_pass: Pass(0),
         }
     }
+    /// 新建或被唤醒的任务的 pass 至少为当前最小的 pass，
+    /// 否则长时间阻塞的任务醒来后会一直占用处理器
     pub fn add_task(task: Arc<TaskControlBlock>) {
-        TASK_MANAGER.exclusive_access().ready_queue.push_back(task)
+        ktrace::record(EventKind::Wakeup, task.pid(), task.tid(), 0);
+        let mut manager = TASK_MANAGER.lock();
+        {
+            let mut inner = task.inner_exclusive_access();
+            inner.pass = inner.pass.max(manager.min_pass);
+        }
+        manager.ready_queue.push_back(task)
     }
-    pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-        let ready_queue = &mut TASK_MANAGER.exclusive_access().ready_queue;
-        if let Some((index, _)) = ready_queue
+    /// 让就绪的 `task` 下一个被调度：暂时把它的 pass 降到所有就绪任务之下，
+    /// 原来的 pass 在它让出处理器时恢复。`task` 不在就绪队列中时返回 false
+    pub fn donate(task: &Arc<TaskControlBlock>) -> bool {
+        let manager = TASK_MANAGER.lock();
+        if !manager.ready_queue.iter().any(|t| Arc::ptr_eq(t, task)) {
+            return false;
+        }
+        let min_pass = manager
+            .ready_queue
             .iter()
-            .enumerate()
-            .min_by_key(|(_, task)| task.inner_exclusive_access().pass)
-        {
-            let ret = ready_queue.swap_remove_back(index).unwrap();
-            {
-                let mut inner = ret.inner_exclusive_access();
-                inner.pass.0 += BIG_STRIDE / inner.priority;
+            .filter(|t| !Arc::ptr_eq(t, task))
+            .map(|t| t.inner_exclusive_access().pass)
+            .min();
+        let mut inner = task.inner_exclusive_access();
+        if let Some(min_pass) = min_pass.filter(|&min_pass| min_pass <= inner.pass) {
+            if inner.lent_pass.is_none() {
+                inner.lent_pass = Some(inner.pass);
             }
-            Some(ret)
-        } else {
-            None
+            inner.pass = Pass(min_pass.0.saturating_sub(1));
+        }
+        true
+    }
+    /// 是否有就绪的任务
+    pub fn has_ready() -> bool {
+        !TASK_MANAGER.lock().ready_queue.is_empty()
+    }
+    /// 就绪队列中的任务数，包括内核线程
+    pub fn ready_count() -> usize {
+        TASK_MANAGER.lock().ready_queue.len()
+    }
+    pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
+        if cmdline::sched_policy() == SchedPolicy::Fifo {
+            return TASK_MANAGER.lock().ready_queue.pop_front();
         }
+        // pass 在任务让出处理器时按实际运行的时间增加，见 `processor::charge`
+        let mut manager = TASK_MANAGER.lock();
+        let (index, pass) = manager
+            .ready_queue
+            .iter()
+            .enumerate()
+            .map(|(index, task)| (index, task.inner_exclusive_access().pass))
+            .min_by_key(|&(_, pass)| pass)?;
+        manager.min_pass = manager.min_pass.max(pass);
+        manager.ready_queue.swap_remove_back(index)
     }
 }
 
 pub fn add_initproc() {
+    insert_into_pid2task(INITPROC.pid(), INITPROC.clone());
+    stdio::set_foreground_pgrp(INITPROC.pid());
     TaskManager::add_task(INITPROC.clone());
 }
+
+pub fn insert_into_pid2task(pid: usize, task: Arc<TaskControlBlock>) {
+    PID2TASK.exclusive_access().insert(pid, task);
+}
+
+/// 进程退出时调用
+pub fn remove_from_pid2task(pid: usize) {
+    if PID2TASK.exclusive_access().remove(&pid).is_none() {
+        panic!("cannot find pid {} in pid2task!", pid);
+    }
+}
+
+/// 进程组 `pgid` 中尚未退出的进程。调用者不能持有任何进程的借用
+pub fn pgrp_members(pgid: usize) -> Vec<Arc<TaskControlBlock>> {
+    PID2TASK
+        .exclusive_access()
+        .values()
+        .filter(|task| task.inner_exclusive_access().pgid == pgid)
+        .cloned()
+        .collect()
+}
+
+/// 所有尚未退出的进程
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    PID2TASK.exclusive_access().values().cloned().collect()
+}
+
+/// 处于各个状态的任务数
+#[derive(Copy, Clone, Default)]
+pub struct TaskCounts {
+    /// 就绪队列中的任务，包括内核线程
+    pub ready: usize,
+    pub running: usize,
+    pub blocked: usize,
+    /// 已经退出、等待父进程回收的进程
+    pub zombie: usize,
+}
+
+/// 统计各状态的任务数。调用者不能持有任何进程的借用
+pub fn task_counts() -> TaskCounts {
+    let mut counts = TaskCounts {
+        ready: TaskManager::ready_count(),
+        ..TaskCounts::default()
+    };
+    // 僵尸进程已经从 PID2TASK 中移除，只能从父进程的子进程列表中找到
+    for task in all_tasks() {
+        let inner = task.inner_exclusive_access();
+        match inner.task_status {
+            TaskStatus::Running => counts.running += 1,
+            TaskStatus::Blocked => counts.blocked += 1,
+            _ => {}
+        }
+        counts.zombie += inner
+            .children
+            .iter()
+            .filter(|child| child.inner_exclusive_access().is_zombie())
+            .count();
+    }
+    counts
+}
+
+/// 根据 pid 查找尚未退出的进程
+pub fn pid2task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    PID2TASK.exclusive_access().get(&pid).cloned()
+}
@@ -1,25 +1,55 @@
+//! 任务管理
+//!
+//! 进程的可变部分由 [`TaskControlBlock::inner_exclusive_access`] 借用，借用不能嵌套。
+//! 这里的函数分为两类：不带 `inner` 参数的函数自己借用当前进程，调用者不能持有当前进程的借用；
+//! 带 `inner: &mut TaskControlBlockInner` 参数的函数使用调用者已经持有的借用，
+//! 调用者可以在一次借用中连续调用多个这类函数。
+
 pub mod context;
+pub mod fp;
 pub mod manager;
 mod pid;
+pub mod preempt;
 mod processor;
+pub mod ptrace;
+pub mod signal;
 pub mod switch;
 mod tcb;
 
-use core::mem;
+use core::{
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use alloc::sync::Arc;
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
 use lazy_static::lazy_static;
 
-pub use self::tcb::TaskStatus;
-use self::{context::TaskContext, manager::TaskManager, tcb::TaskControlBlock};
-use crate::fs::inode::{self, OpenFlags};
-use crate::mm::{address::VirtAddr, memory_set::MapPermission};
+pub use self::tcb::{RLimit, TaskControlBlock, TaskControlBlockInner, TaskStatus};
+use self::{context::TaskContext, manager::TaskManager, signal::SignalFlags};
+use crate::cmdline;
+use crate::config::{INIT_ENV, PAGE_SIZE, PTE_PER_PAGE, USER_STACK_MAX_SIZE};
+use crate::console;
+use crate::fs::inode;
+use crate::mm::{
+    address::{VPNRange, VirtAddr},
+    frame_allocator,
+    memory_set::{self, FileBacking, MapError, MapPermission},
+    page_table::PageSize,
+};
+use crate::power;
+use crate::sbi::exit;
+use crate::sync::{futex, wait_queue::WaitQueue, UPSafeCell};
+use crate::timer::{self, IntervalTimer, TimerAction};
 pub use processor::Processor;
 
 lazy_static! {
     pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
-        let inode = inode::open_file("ch6b_initproc", OpenFlags::RDONLY).unwrap();
-        TaskControlBlock::new(&inode.read_all())
+        let init = cmdline::init_proc();
+        let inode = inode::open_executable(&init, &cmdline::exec_path())
+            .unwrap_or_else(|| panic!("initproc {} not found", init));
+        let mut env: Vec<String> = INIT_ENV.iter().map(|var| String::from(*var)).collect();
+        env.push(format!("PATH={}", cmdline::exec_path()));
+        TaskControlBlock::new(&*inode, &[init], env)
     });
 }
 
@@ -34,73 +64,488 @@ pub fn suspend_current_and_run_next() {
     Processor::schedule(task_ctx_ptr);
 }
 
+/// 创建执行 `entry` 的内核线程并加入就绪队列，返回它的 pid。
+///
+/// 内核线程运行在内核态，使用当前的地址空间，借助全局的内核映射访问内核数据。
+/// 内核态不响应时钟中断，因此它需要主动调用 [`suspend_current_and_run_next`] 或阻塞来让出处理器。
+/// 内核线程不属于任何进程，`entry` 返回后线程退出，其资源由 idle 控制流回收
+pub fn kthread_spawn(entry: fn()) -> usize {
+    let task = Arc::new(TaskControlBlock::new_kthread(entry));
+    let pid = task.pid();
+    TaskManager::add_task(task);
+    pid
+}
+
+/// 内核线程的起点，见 [`TaskContext::goto_kthread_start`]
+fn kthread_start() -> ! {
+    let entry = Processor::current_task().unwrap().kernel_entry.unwrap();
+    entry();
+    exit_current_and_run_next(0);
+    unreachable!()
+}
+
+/// 阻塞当前任务并切换到其他任务。调用者需要事先把当前任务放入某个等待队列，
+/// 之后由 [`wakeup_task`] 唤醒
+pub fn block_current_and_run_next() {
+    let task = Processor::take_current_task().unwrap();
+    let task_ctx_ptr = {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.task_status = TaskStatus::Blocked;
+        &mut task_inner.task_ctx as *mut TaskContext
+    };
+    drop(task);
+    Processor::schedule(task_ctx_ptr);
+}
+
+/// 唤醒一个被阻塞的任务，将其放回就绪队列。任务没有阻塞时什么也不做
+pub fn wakeup_task(task: Arc<TaskControlBlock>) {
+    let mut inner = task.inner_exclusive_access();
+    if inner.task_status != TaskStatus::Blocked {
+        return;
+    }
+    inner.task_status = TaskStatus::Ready;
+    drop(inner);
+    TaskManager::add_task(task);
+}
+
+/// 处理器时间达到 `RLIMIT_CPU` 而被杀死的任务的退出码。取 SIGXCPU 的编号，与其它异常退出区分开，
+/// 评测学生程序时可以据此判断程序是否陷入了死循环
+pub const CPU_LIMIT_EXIT_CODE: i32 = -24;
+
+/// 当前任务消耗的处理器时间达到 `RLIMIT_CPU` 的软限制时杀死它，由时钟中断调用。
+///
+/// Linux 达到软限制时先发送 SIGXCPU，这里直接结束任务
+pub fn check_cpu_limit() {
+    let task = Processor::current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let used = inner.cpu_time + Processor::slice_cycles();
+    if !inner.cpu_limit.exceeded_by(used, timer::clock_freq()) {
+        return;
+    }
+    log::error!(
+        "[kernel] CPU time limit ({}s) exceeded, pid = {}, killed.",
+        inner.cpu_limit.cur,
+        task.pid()
+    );
+    drop(inner);
+    drop(task);
+    exit_current_and_run_next(CPU_LIMIT_EXIT_CODE);
+}
+
 pub fn exit_current_and_run_next(exit_code: i32) {
     {
         let task = Processor::take_current_task().unwrap();
         log::info!("exit task {}", task.pid.0);
+        console::flush_task(task.pid());
+        // initproc 退出后不会再有新的进程，关闭系统，QEMU 的退出码即为 initproc 的退出码
+        if Arc::ptr_eq(&task, &INITPROC) {
+            println!(
+                "[kernel] initproc exited with code {}, shutting down",
+                exit_code
+            );
+            power::poweroff(exit::exit_code(exit_code));
+        }
+        if !task.is_kernel_thread() {
+            manager::remove_from_pid2task(task.pid());
+        }
+        // Trap 上下文所在的页即将释放，之后不能再向其中保存浮点寄存器
+        fp::release(&task);
         let mut inner = task.inner_exclusive_access();
         inner.task_status = TaskStatus::Zombie;
         inner.exit_code = exit_code;
 
-        // 子进程转交给 initproc 来处理
+        // 子进程转交给 initproc 来处理，其中可能已有僵尸进程，所以唤醒 initproc
         let children = mem::take(&mut inner.children);
         let mut initproc_inner = INITPROC.inner_exclusive_access();
-        for child in children {
+        if !children.is_empty() {
+            initproc_inner.child_exit.wake_all();
+        }
+        for child in &children {
             child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-            initproc_inner.children.push(Arc::clone(&child))
+            initproc_inner.children.push(Arc::clone(child))
         }
 
-        // 暂时只清空了存放数据的页，而存放页表项的页则未清空
-        // 这个进程真正被回收是在父进程 `wait` 它时，那时引用计数会归零，然后自动释放所有资源
+        // 这里只清空存放数据的页。我们还在使用这个地址空间的页表，存放页表项的页在切换到 idle
+        // 控制流后由 `reap_exited` 回收，剩下的资源在父进程 `wait` 它时随引用计数归零释放
         inner.memory_set.recycle_data_pages();
+
+        // 关闭所有文件，例如让管道的另一端读到 EOF。关闭时可能唤醒其他任务，因此先释放借用
+        let fd_table = mem::take(&mut inner.fd_table);
+        let parent = inner.parent.as_ref().and_then(|parent| parent.upgrade());
+        drop(initproc_inner);
+        drop(inner);
+        drop(fd_table);
+        // 被跟踪的子进程随之停止跟踪
+        children.iter().for_each(ptrace::detach);
+        if let Some(parent) = parent {
+            parent.inner_exclusive_access().child_exit.wake_all();
+        }
+        // 我们还运行在这个任务的内核栈上，由 idle 控制流释放对它的引用
+        Processor::retire(task);
     }
     // 注意，调用 `schedule` 后控制流中断了，因此上述变量被包裹起来以在离开作用域时自动释放
     let mut _unused = TaskContext::zero_init();
     Processor::schedule(&mut _unused as _);
 }
 
-/// 由调用者保证 `time` 是物理地址
-pub fn set_syscall_times(times: &mut [u32]) {
-    times.copy_from_slice(
-        &Processor::current_task()
-            .unwrap()
-            .inner_exclusive_access()
-            .syscall_count,
+/// 把进程的系统调用计数按 syscall ID 展开到 `times`
+pub fn set_syscall_times(inner: &mut TaskControlBlockInner, times: &mut [u32]) {
+    inner.syscall_count.expand_into(times);
+}
+
+/// 进程的系统调用计数清零
+pub fn reset_syscall_times(inner: &mut TaskControlBlockInner) {
+    inner.syscall_count.reset();
+}
+
+/// 进程的系统调用计数加一，不支持的系统调用不计数
+pub fn incr_syscall_times(inner: &mut TaskControlBlockInner, syscall_id: usize) {
+    inner.syscall_count.incr(syscall_id);
+}
+
+/// 设置当前进程的 `ITIMER_REAL` 定时器，返回原先的设置。时间单位均为微秒
+pub fn set_itimer_real(value: usize, interval: usize) -> IntervalTimer {
+    let now = timer::get_time_us();
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let old = inner.itimer_real;
+    inner.itimer_real.set(now, value, interval);
+    schedule_itimer_check(inner.itimer_real.expire);
+    old
+}
+
+/// 让当前任务睡眠 `us` 微秒。可能因为信号提前醒来
+pub fn sleep_us(us: usize) {
+    let task = Processor::current_task().unwrap();
+    timer::add_timer(
+        timer::get_time_us() + us,
+        TimerAction::Wake(Arc::downgrade(&task)),
     );
+    drop(task);
+    block_current_and_run_next();
 }
 
-/// 需满足 syscall_id < 500
-pub fn incr_syscall_times(syscall_id: usize) {
-    Processor::current_task()
+/// 已经登记的最早一次定时器检查的时间，为 0 表示没有登记
+static ITIMER_CHECK_AT: AtomicUsize = AtomicUsize::new(0);
+
+/// 在 `expire` 微秒时检查间隔定时器和 POSIX 定时器，`expire` 为 0 表示定时器未启用。
+/// 已经登记了更早的检查时不再登记，那次检查会登记之后的检查
+pub fn schedule_itimer_check(expire: usize) {
+    let scheduled = ITIMER_CHECK_AT.load(Ordering::Relaxed);
+    if expire == 0 || (scheduled != 0 && scheduled <= expire) {
+        return;
+    }
+    ITIMER_CHECK_AT.store(expire, Ordering::Relaxed);
+    timer::add_timer(expire, TimerAction::Call(check_itimers));
+}
+
+/// 检查所有进程的间隔定时器和 POSIX 定时器，向到期的进程发送 SIGALRM 或者定时器指定的信号。
+/// 由 [`schedule_itimer_check`] 登记的定时器调用
+///
+/// 阻塞的进程收到信号后会被唤醒，以便在返回用户态前处理信号
+pub fn check_itimers() {
+    ITIMER_CHECK_AT.store(0, Ordering::Relaxed);
+    let now = timer::get_time_us();
+    // 定时器到期时发送信号，发送了信号时返回 true
+    let alarm = |task: &Arc<TaskControlBlock>| {
+        let mut inner = task.inner_exclusive_access();
+        let mut signals = SignalFlags::empty();
+        if inner.itimer_real.poll(now) {
+            signals |= SignalFlags::SIGALRM;
+        }
+        for posix_timer in inner.posix_timers.iter_mut().flatten() {
+            if posix_timer.timer.poll(now) {
+                signals |= posix_timer.signal;
+            }
+        }
+        inner.signals |= signals;
+        !signals.is_empty()
+    };
+    let tasks = manager::all_tasks();
+    let alarmed: Vec<_> = tasks.iter().filter(|task| alarm(task)).cloned().collect();
+    interrupt_waits(&alarmed);
+    // 重新计时的定时器在下次到期时再检查
+    let next = tasks
+        .iter()
+        .filter_map(|task| {
+            let inner = task.inner_exclusive_access();
+            let posix = inner.posix_timers.iter().flatten().map(|t| t.timer.expire);
+            core::iter::once(inner.itimer_real.expire)
+                .chain(posix)
+                .filter(|&expire| expire != 0)
+                .min()
+        })
+        .min();
+    if let Some(next) = next {
+        schedule_itimer_check(next);
+    }
+}
+
+/// 唤醒阻塞在等待队列上的 `tasks`，让它们重新检查等待的条件，发现有待处理的信号时提前返回。
+/// 暂停的进程不会被唤醒
+fn interrupt_waits(tasks: &[Arc<TaskControlBlock>]) {
+    let is_target = |task: &Arc<TaskControlBlock>| tasks.iter().any(|t| Arc::ptr_eq(t, task));
+    futex::dequeue_if(is_target)
+        .into_iter()
+        .for_each(wakeup_task);
+    let stopped = STOPPED.exclusive_access();
+    tasks
+        .iter()
+        .filter(|task| !stopped.contains(task))
+        .cloned()
+        .for_each(wakeup_task);
+}
+
+/// 因 SIGTSTP 暂停的进程
+static STOPPED: UPSafeCell<WaitQueue> = unsafe { UPSafeCell::new(WaitQueue::new()) };
+
+/// 向进程组 `pgid` 中的所有进程发送信号，返回收到信号的进程数。initproc 不接收信号。
+///
+/// 与 [`check_itimers`] 一样，阻塞的进程会被唤醒，以便在返回用户态前处理信号。
+/// SIGCONT 不会挂起，它清除待处理的 SIGTSTP 并让暂停的进程继续运行；致命信号也会让暂停的进程继续运行，以便退出
+pub fn signal_pgrp(pgid: usize, signal: SignalFlags) -> usize {
+    send_signal(manager::pgrp_members(pgid), signal)
+}
+
+/// 向进程 `task` 发送信号，与 [`signal_pgrp`] 相同。`task` 是 initproc 时返回 false
+pub fn signal_task(task: &Arc<TaskControlBlock>, signal: SignalFlags) -> bool {
+    send_signal(vec![Arc::clone(task)], signal) == 1
+}
+
+/// 向 `tasks` 中除 initproc 以外的进程发送信号，返回收到信号的进程数
+fn send_signal(tasks: Vec<Arc<TaskControlBlock>>, signal: SignalFlags) -> usize {
+    let members: Vec<_> = tasks
+        .into_iter()
+        .filter(|task| !Arc::ptr_eq(task, &INITPROC))
+        .collect();
+    for task in &members {
+        let mut inner = task.inner_exclusive_access();
+        if signal.contains(SignalFlags::SIGCONT) {
+            inner.signals.remove(SignalFlags::SIGTSTP);
+        }
+        inner.signals |= signal - SignalFlags::SIGCONT;
+    }
+    interrupt_waits(&members);
+    if signal.contains(SignalFlags::SIGCONT) || signal.check_error().is_some() {
+        STOPPED
+            .exclusive_access()
+            .wake_if(|task| members.iter().any(|m| Arc::ptr_eq(m, task)));
+    }
+    members.len()
+}
+
+/// 如果当前进程有待处理的 SIGTSTP，暂停当前进程，直到 [`signal_pgrp`] 或 [`signal_task`] 发送 SIGCONT
+/// 或致命信号。暂停时唤醒等待的父进程，用 `WUNTRACED` 等待的 `waitpid` 会报告一次。在返回用户态前调用
+pub fn handle_stop_signal() {
+    let task = Processor::current_task().unwrap();
+    let parent = {
+        let mut inner = task.inner_exclusive_access();
+        if !inner.signals.contains(SignalFlags::SIGTSTP) {
+            return;
+        }
+        inner.signals.remove(SignalFlags::SIGTSTP);
+        inner.stop_unreported = true;
+        inner.parent.as_ref().and_then(|parent| parent.upgrade())
+    };
+    log::info!("stop task {}", task.pid());
+    STOPPED.exclusive_access().add_waiter(Arc::clone(&task));
+    if let Some(parent) = parent {
+        parent.inner_exclusive_access().child_exit.wake_all();
+    }
+    // 只有从 `STOPPED` 中移出才算继续运行，其他原因的唤醒都继续等待
+    while STOPPED.exclusive_access().contains(&task) {
+        block_current_and_run_next();
+    }
+    task.inner_exclusive_access().stop_unreported = false;
+}
+
+/// 当前进程是否有待处理的信号。阻塞在内核中的操作据此提前返回，以便进程及时处理信号
+pub fn signal_pending() -> bool {
+    !Processor::current_task()
         .unwrap()
         .inner_exclusive_access()
-        .syscall_count[syscall_id] += 1;
+        .signals
+        .is_empty()
 }
 
-pub fn start_time() -> usize {
+/// 会话 `sid` 中是否存在进程组 `pgid`
+pub fn pgrp_in_session(pgid: usize, sid: usize) -> bool {
+    manager::pgrp_members(pgid)
+        .iter()
+        .any(|task| task.inner_exclusive_access().sid == sid)
+}
+
+/// 当前进程待处理的致命信号，返回退出码和提示信息
+pub fn current_signal_error() -> Option<(i32, &'static str)> {
     Processor::current_task()
         .unwrap()
         .inner_exclusive_access()
-        .start_time
+        .signals
+        .check_error()
+}
+
+/// 将 start 开始 len 字节的虚拟地址映射，返回映射的起始地址。`start` 为 0 时由内核在 mmap 区域中选择地址。
+///
+/// 范围内按 2 MiB 对齐的部分至少有一个大页时，这部分尽量用 2 MiB 的大页映射，页帧在映射时全部分配；
+/// 找不到连续的页帧或者剩余的部分则使用 4 KiB 的页，第一次写入时才分配页帧。
+///
+/// 与已有的逻辑段相交时返回 `MapError::Overlap`，页帧或者 mmap 区域的空间不足时返回 `MapError::OutOfMemory`，
+/// 违反 W^X 时返回 `MapError::WriteExecute`
+pub fn map_range(
+    inner: &mut TaskControlBlockInner,
+    start: usize,
+    len: usize,
+    map_perm: MapPermission,
+) -> Result<usize, MapError> {
+    let start = if start == 0 {
+        inner
+            .memory_set
+            .find_free_range(len, 0)
+            .ok_or(MapError::OutOfMemory)?
+    } else {
+        start
+    };
+    let vpn_range = VPNRange::covering(VirtAddr(start), VirtAddr(start + len));
+    if inner.memory_set.overlaps(&vpn_range) || memory_set::overlaps_kernel_global(&vpn_range) {
+        return Err(MapError::Overlap);
+    }
+    // 各页先映射到零页，只有页表本身需要页帧：最坏情况下每 PTE_PER_PAGE 页需要一个叶子页表，
+    // 另有两级中间页表
+    let pages = vpn_range.len();
+    if frame_allocator::frame_remaining() < pages / PTE_PER_PAGE + 3 {
+        return Err(MapError::OutOfMemory);
+    }
+    let huge = PageSize::Size2M.pages() * PAGE_SIZE;
+    let huge_start = (start + huge - 1) / huge * huge;
+    let huge_end = (start + len) / huge * huge;
+    if huge_start < huge_end
+        && inner
+            .memory_set
+            .insert_huge_area(
+                VirtAddr(huge_start),
+                VirtAddr(huge_end),
+                map_perm,
+                PageSize::Size2M,
+            )
+            .is_ok()
+    {
+        // 两端剩余的部分只映射到零页，除了页表外不需要页帧。失败时撤销已经映射的大页和另一端，
+        // 范围内原先没有逻辑段，所以取消映射范围内的所有逻辑段即可
+        for (start, end) in [(start, huge_start), (huge_end, start + len)] {
+            if start < end {
+                if let Err(err) =
+                    inner
+                        .memory_set
+                        .insert_zeroed_area(VirtAddr(start), VirtAddr(end), map_perm)
+                {
+                    inner.memory_set.remove_areas_within(vpn_range);
+                    return Err(err);
+                }
+            }
+        }
+    } else {
+        inner
+            .memory_set
+            .insert_zeroed_area(VirtAddr(start), VirtAddr(start + len), map_perm)?;
+    }
+    if cmdline::pte_check() {
+        if let Err(err) = inner.memory_set.audit() {
+            inner.memory_set.remove_areas_within(vpn_range);
+            return Err(err);
+        }
+    }
+    Ok(start)
+}
+
+/// 将 start 开始 len 字节的虚拟地址映射为 `file` 的共享文件映射，返回映射的起始地址。
+/// `start` 为 0 时由内核在 mmap 区域中选择地址。
+///
+/// 页帧在映射时全部分配并读入文件的内容，错误与 [`map_range`] 相同
+pub fn map_file(
+    inner: &mut TaskControlBlockInner,
+    start: usize,
+    len: usize,
+    map_perm: MapPermission,
+    file: FileBacking,
+) -> Result<usize, MapError> {
+    let start = if start == 0 {
+        inner
+            .memory_set
+            .find_free_range(len, 0)
+            .ok_or(MapError::OutOfMemory)?
+    } else {
+        start
+    };
+    let vpn_range = VPNRange::covering(VirtAddr(start), VirtAddr(start + len));
+    if memory_set::overlaps_kernel_global(&vpn_range) {
+        return Err(MapError::Overlap);
+    }
+    inner
+        .memory_set
+        .insert_file_area(VirtAddr(start), VirtAddr(start + len), map_perm, file)?;
+    if cmdline::pte_check() {
+        if let Err(err) = inner.memory_set.audit() {
+            inner.memory_set.remove_areas_within(vpn_range);
+            return Err(err);
+        }
+    }
+    Ok(start)
 }
 
-/// 将 start 开始 len 字节的虚拟地址映射。失败返回 false。
-pub fn map_range(start: usize, len: usize, map_perm: MapPermission) -> bool {
+/// 将 start 开始 len 字节的虚拟地址的权限改为 map_perm。失败返回 false。
+pub fn protect_range(
+    inner: &mut TaskControlBlockInner,
+    start: usize,
+    len: usize,
+    map_perm: MapPermission,
+) -> bool {
+    let vpn_range = VPNRange::covering(VirtAddr(start), VirtAddr(start + len));
+    inner.memory_set.protect(vpn_range, map_perm)
+}
+
+/// 尝试将当前任务的用户栈向下扩展，使其覆盖 `va`。
+///
+/// 要求 `va` 位于用户栈下方 `USER_STACK_GROW_PAGES` 页以内，且扩展后用户栈不超过 `USER_STACK_MAX_SIZE`。
+/// 失败返回 false，此时应视为用户栈溢出。
+pub fn grow_user_stack(va: usize) -> bool {
     let tcb_arc = Processor::current_task().unwrap();
     let mut inner = tcb_arc.inner_exclusive_access();
-    let vpn_range = VirtAddr(start).floor()..VirtAddr(start + len).ceil();
+    if !inner.in_stack_guard(va) {
+        return false;
+    }
+    let new_bottom = VirtAddr(va).floor().page_start().0;
+    if inner.user_stack.end - new_bottom > USER_STACK_MAX_SIZE {
+        return false;
+    }
+    let old_bottom = VirtAddr(inner.user_stack.start).floor();
     if inner
         .memory_set
-        .areas
-        .iter()
-        .any(|area| !area.intersection(&vpn_range).is_empty())
+        .extend_area_down(old_bottom, VirtAddr(new_bottom).floor())
     {
-        return false;
+        log::debug!(
+            "grow user stack of task {} to [{:#x}, {:#x})",
+            tcb_arc.pid(),
+            new_bottom,
+            inner.user_stack.end
+        );
+        inner.user_stack.start = new_bottom;
+        true
+    } else {
+        false
+    }
+}
+
+/// 处理当前任务写入零页引发的缺页，见 [`MemorySet::fault_in_zero_page`]。
+///
+/// [`MemorySet::fault_in_zero_page`]: crate::mm::memory_set::MemorySet::fault_in_zero_page
+pub fn fault_in_zero_page(va: usize) -> bool {
+    match Processor::current_task() {
+        Some(task) => task
+            .inner_exclusive_access()
+            .memory_set
+            .fault_in_zero_page(VirtAddr(va).floor()),
+        None => false,
     }
-    inner
-        .memory_set
-        .insert_framed_area(VirtAddr(start), VirtAddr(start + len), map_perm);
-    true
 }
 
 /// 将一个范围内的虚拟地址取消映射。失败返回 false。
@@ -112,27 +557,21 @@ pub fn map_range(start: usize, len: usize, map_perm: MapPermission) -> bool {
 /// 部分相交的情况会很麻烦，可能涉及到 MapArea 的缩小，甚至是分裂。而 MapArea 内部包含的 BTree 也要分裂。
 ///
 /// 至少我暂时没想到什么优雅简单的实现。可能要费不少功夫，这里领会精神，过 CI 就行。
-pub fn unmap_range(start: usize, len: usize) -> bool {
-    let tcb_arc = Processor::current_task().unwrap();
-    let mut inner = tcb_arc.inner_exclusive_access();
-    let vpn_range = VirtAddr(start).floor()..VirtAddr(start + len).ceil();
+pub fn unmap_range(inner: &mut TaskControlBlockInner, start: usize, len: usize) -> bool {
+    let vpn_range = VPNRange::covering(VirtAddr(start), VirtAddr(start + len));
     let map_set = &mut inner.memory_set;
-    let mut unmaped_count = 0;
-    let areas = &mut map_set.areas;
-    let page_table = &mut map_set.page_table;
-    areas.retain_mut(|area| {
-        // 释放的地址完全将该内存段包含在内
-        if area.intersection(&vpn_range) == area.vpn_range {
-            unmaped_count += area.vpn_range.end.0 - area.vpn_range.start.0;
-            area.unmap(page_table);
-            false
-        } else {
-            true
-        }
-    });
-    unmaped_count == vpn_range.end.0 - vpn_range.start.0
+    // Trap 上下文这类用户不可访问的逻辑段不能由用户取消映射
+    if map_set
+        .areas_intersecting(&vpn_range)
+        .any(|area| !area.is_user())
+    {
+        return false;
+    }
+    // 释放的地址完全将该内存段包含在内
+    let unmaped_count = map_set.remove_areas_within(vpn_range);
+    unmaped_count == vpn_range.len()
 }
 
 pub use processor::run_tasks;
 
-pub use manager::add_initproc;
+pub use manager::{add_initproc, all_tasks, pid2task};
@@ -66,11 +66,14 @@ impl KernelStack {
     pub fn new(pid_handle: &PidHandle) -> Self {
         let pid = pid_handle.0;
         let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
-        KERNEL_SPACE.exclusive_access().insert_framed_area(
-            VirtAddr(kernel_stack_bottom),
-            VirtAddr(kernel_stack_top),
-            MapPermission::R | MapPermission::W,
-        );
+        KERNEL_SPACE
+            .lock()
+            .insert_framed_area(
+                VirtAddr(kernel_stack_bottom),
+                VirtAddr(kernel_stack_top),
+                MapPermission::R | MapPermission::W,
+            )
+            .expect("Should have enough memory for kernel stack");
         KernelStack { pid: pid_handle.0 }
     }
     pub const fn top(&self) -> usize {
@@ -84,7 +87,7 @@ impl Drop for KernelStack {
         let (kernel_stack_bottom, _) = kernel_stack_position(self.pid);
         let kernel_stack_bottom_va = VirtAddr(kernel_stack_bottom);
         KERNEL_SPACE
-            .exclusive_access()
+            .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.vpn());
     }
 }
@@ -0,0 +1,124 @@
+//! Kernel preemption points
+//!
+//! 内核态原本不响应中断，一个很长的系统调用（例如读写大文件）会一直占着处理器，
+//! 直到返回用户态时才发现时间片早已用完。现在系统调用执行期间打开 `sstatus.SIE`，但只响应时钟中断：
+//! 外部中断和软件中断在 `sie` 中暂时屏蔽，保持挂起，回到用户态后立即进入 trap 处理。
+//!
+//! 时钟中断可能打断任何内核代码，处理函数 [`timer_interrupt`] 不访问定时器队列等共享数据，
+//! 只推迟下一次时钟中断并设置 need-resched 标志。长时间运行的内核代码在适当的位置调用
+//! [`cond_resched`]，由它处理到期的定时器，时间片用完时让出处理器。系统调用返回时也会检查一次。
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use riscv::register::{sie, sstatus};
+
+use crate::{mm::user, sbi::set_timer, sync, timer};
+
+/// `sie` 中外部中断和软件中断的使能位
+const SEIE: usize = 1 << 9;
+const SSIE: usize = 1 << 1;
+
+/// 内核态到来过时钟中断，尚未在抢占点处理
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+/// 可抢占区间中被屏蔽的中断源
+static MASKED: AtomicUsize = AtomicUsize::new(0);
+/// 内核态最近一次被时钟中断打断的位置，0 表示没有。下一个抢占点处理完到期的定时器后清除
+static INTERRUPTED_PC: AtomicUsize = AtomicUsize::new(0);
+
+/// 进入可抢占的区间：在 `sie` 中屏蔽时钟中断以外的中断，再打开 `sstatus.SIE`
+pub fn enable() {
+    let sie = sie::read();
+    let mut masked = 0;
+    unsafe {
+        if sie.sext() {
+            sie::clear_sext();
+            masked |= SEIE;
+        }
+        if sie.ssoft() {
+            sie::clear_ssoft();
+            masked |= SSIE;
+        }
+    }
+    MASKED.fetch_or(masked, Ordering::Relaxed);
+    unsafe { sstatus::set_sie() };
+}
+
+/// 离开可抢占的区间：关闭 `sstatus.SIE`，恢复被屏蔽的中断源
+pub fn disable() {
+    unsafe { sstatus::clear_sie() };
+    let masked = MASKED.swap(0, Ordering::Relaxed);
+    unsafe {
+        if masked & SEIE != 0 {
+            sie::set_sext();
+        }
+        if masked & SSIE != 0 {
+            sie::set_ssoft();
+        }
+    }
+}
+
+/// 切换到 idle 控制流之前调用。切换回来时不一定还在同一个区间中，所以离开可抢占的区间；
+/// 丢弃 need-resched 标志，idle 控制流会处理到期的定时器并重新设置 `mtimecmp`
+pub fn reset() {
+    disable();
+    NEED_RESCHED.store(false, Ordering::Relaxed);
+    INTERRUPTED_PC.store(0, Ordering::Relaxed);
+}
+
+/// 内核态在 `pc` 处到来的时钟中断。被打断的代码可能正在访问定时器队列，这里只把 `mtimecmp`
+/// 推迟到无穷远，由下一个抢占点调用 [`timer::handle_expired`] 重新设置
+pub fn timer_interrupt(pc: usize) {
+    set_timer(usize::MAX);
+    NEED_RESCHED.store(true, Ordering::Relaxed);
+    INTERRUPTED_PC.store(pc, Ordering::Relaxed);
+}
+
+/// 抢占点处理到期的定时器时，返回内核态被时钟中断打断的位置，供采样使用。
+/// 定时器不是在抢占点处理的（例如在用户态到期），返回 `None`
+pub fn interrupted_pc() -> Option<usize> {
+    match INTERRUPTED_PC.load(Ordering::Relaxed) {
+        0 => None,
+        pc => Some(pc),
+    }
+}
+
+/// 抢占点。内核态到来过时钟中断时处理到期的定时器，当前任务的时间片用完时让出处理器。
+///
+/// 持有锁、借用了 `UPSafeCell` 或者处于用户内存访问窗口中时切换任务并不安全，此时什么也不做，
+/// 留给下一个抢占点
+pub fn cond_resched() {
+    if !NEED_RESCHED.load(Ordering::Relaxed) || sync::held_count() != 0 || user::in_user_access() {
+        return;
+    }
+    let preemptible = sstatus::read().sie();
+    disable();
+    NEED_RESCHED.store(false, Ordering::Relaxed);
+    let expired = timer::handle_expired();
+    INTERRUPTED_PC.store(0, Ordering::Relaxed);
+    if expired {
+        super::suspend_current_and_run_next();
+    }
+    if preemptible {
+        enable();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::UPSafeCell;
+
+    #[test_case]
+    fn need_resched_waits_until_nothing_is_held() {
+        let cell = unsafe { UPSafeCell::new(()) };
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+        {
+            let _borrow = cell.exclusive_access();
+            cond_resched();
+            assert!(NEED_RESCHED.load(Ordering::Relaxed));
+        }
+        cond_resched();
+        assert!(!NEED_RESCHED.load(Ordering::Relaxed));
+        assert!(!sstatus::read().sie());
+    }
+}
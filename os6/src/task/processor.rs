@@ -1,18 +1,46 @@
 use alloc::sync::Arc;
 
-use crate::{sync::UPSafeCell, timer, trap::TrapContext};
+use crate::{
+    config::IDLE_POLL_TICKS,
+    drivers::plic,
+    fs, ipi,
+    ktrace::{self, EventKind},
+    mm::memory_set::KERNEL_SPACE,
+    net,
+    sync::KSpinLock,
+    timer,
+    trap::TrapContext,
+};
 
 use super::{
     context::TaskContext, manager::TaskManager, switch::__switch, tcb::TaskControlBlock, TaskStatus,
 };
 
-pub static PROCESSOR: UPSafeCell<Processor> = unsafe { UPSafeCell::new(Processor::new()) };
+pub static PROCESSOR: KSpinLock<Processor> =
+    unsafe { KSpinLock::new("PROCESSOR", Processor::new()) };
 
 /// 负责管理处理器
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
     /// 每个 Processor 都有一个 idle 控制流，它尝试从 TaskManager 中选出一个任务来执行
     idle_task_ctx: TaskContext,
+    /// 刚刚退出的任务。它退出时还运行在自己的内核栈和地址空间上，不能释放它们，
+    /// 因此把引用留在这里，回到 idle 控制流后再释放
+    exited: Option<Arc<TaskControlBlock>>,
+    /// 当前任务开始运行时的 `time`
+    slice_start: usize,
+    /// 从 idle 控制流切换到任务的次数
+    switches: usize,
+    idle: IdleStats,
+}
+
+/// idle 控制流等待中断的统计
+#[derive(Copy, Clone, Default)]
+pub struct IdleStats {
+    /// 执行 `wfi` 的次数
+    pub waits: usize,
+    /// 在 `wfi` 中度过的 `time` 总数
+    pub cycles: usize,
 }
 
 impl Processor {
@@ -20,16 +48,31 @@ impl Processor {
         Self {
             current: None,
             idle_task_ctx: TaskContext::zero_init(),
+            exited: None,
+            slice_start: 0,
+            switches: 0,
+            idle: IdleStats {
+                waits: 0,
+                cycles: 0,
+            },
         }
     }
     fn idle_task_ctx_ptr(&self) -> *const TaskContext {
         &self.idle_task_ctx as *const _
     }
     pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-        PROCESSOR.exclusive_access().current.take()
+        PROCESSOR.lock().current.take()
     }
     pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-        PROCESSOR.exclusive_access().current.clone()
+        PROCESSOR.lock().current.clone()
+    }
+    /// 当前任务的进程号和线程号。供日志和 panic 等诊断路径使用，即使 `PROCESSOR` 已被借用也不会再次 panic
+    pub fn try_current_ids() -> Option<(usize, usize)> {
+        PROCESSOR
+            .try_lock()?
+            .current
+            .as_ref()
+            .map(|task| (task.pid(), task.tid()))
     }
     pub fn current_user_satp() -> usize {
         Self::current_task()
@@ -44,9 +87,27 @@ impl Processor {
             .trap_ctx()
     }
 
+    /// 当前任务这次开始运行以来经过的 `time` 周期数
+    pub fn slice_cycles() -> usize {
+        timer::get_time() - PROCESSOR.lock().slice_start
+    }
+
+    pub fn idle_stats() -> IdleStats {
+        PROCESSOR.lock().idle
+    }
+    /// 启动以来切换到任务的次数
+    pub fn switch_count() -> usize {
+        PROCESSOR.lock().switches
+    }
+
+    /// 当前任务已经退出，交出对它的引用，由 idle 控制流释放
+    pub fn retire(task: Arc<TaskControlBlock>) {
+        PROCESSOR.lock().exited = Some(task);
+    }
     /// 应用交出控制权，切入内核态后，将会调用 `schedule` 函数进入 idle 控制流进行任务调度
     pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-        let idle_task_cx_ptr = PROCESSOR.exclusive_access().idle_task_ctx_ptr();
+        super::preempt::reset();
+        let idle_task_cx_ptr = PROCESSOR.lock().idle_task_ctx_ptr();
         unsafe {
             __switch(switched_task_cx_ptr, idle_task_cx_ptr);
         }
@@ -56,6 +117,8 @@ impl Processor {
 /// idle 控制流不断运行该函数，从 TaskManager 拉取任务
 pub fn run_tasks() -> ! {
     loop {
+        // 内核态不响应时钟中断，没有任务运行时由 idle 控制流处理到期的定时器
+        timer::handle_expired();
         if let Some(task) = TaskManager::fetch_task() {
             let next_task_ctx_ptr = {
                 let mut task_inner = task.inner_exclusive_access();
@@ -63,17 +126,86 @@ pub fn run_tasks() -> ! {
                 if task_inner.start_time == 0 {
                     task_inner.start_time = timer::get_time_ms();
                 }
+                timer::set_preempt_after(task_inner.time_slice());
                 &task_inner.task_ctx as *const TaskContext
             };
             let idle_task_ctx_ptr = {
-                let mut processor = PROCESSOR.exclusive_access();
-                processor.current = Some(task);
+                let mut processor = PROCESSOR.lock();
+                processor.current = Some(Arc::clone(&task));
+                processor.slice_start = timer::get_time();
+                processor.switches += 1;
                 &mut processor.idle_task_ctx as *mut _
             };
 
+            ktrace::record(EventKind::SwitchIn, task.pid(), task.tid(), 0);
             unsafe {
                 __switch(idle_task_ctx_ptr, next_task_ctx_ptr);
             }
+            let status = match task.inner_exclusive_access().task_status {
+                TaskStatus::Ready => 0,
+                TaskStatus::Blocked => 1,
+                _ => 2,
+            };
+            ktrace::record(EventKind::SwitchOut, task.pid(), task.tid(), status);
+            timer::clear_preempt();
+            charge(&task);
+            drop(task);
+            reap_exited();
+        } else {
+            idle_wait();
         }
     }
 }
+
+/// 没有任务可运行，执行 `wfi` 等待下一个定时器到期。
+///
+/// 内核态关闭了中断（`sstatus.SIE` 为 0），但 `wfi` 在 `sie` 中打开的中断到来时仍然会返回，
+/// 中断保持挂起而不会进入 trap，回到 [`run_tasks`] 后由 [`timer::handle_expired`] 处理。
+/// 在检查就绪队列之后到来的中断也会保持挂起，此时 `wfi` 立即返回，不会错过唤醒
+fn idle_wait() {
+    // 控制台输入可能向阻塞的任务发送信号，网卡收到的数据报可能唤醒阻塞的接收者，使它们重新就绪。
+    // idle 控制流不响应中断，wfi 被外部中断或 IPI 唤醒后在这里处理。idle 控制流本来就会重新调度
+    plic::handle_interrupts();
+    ipi::handle();
+    fs::stdio::poll_console();
+    net::poll();
+    if TaskManager::has_ready() {
+        return;
+    }
+    timer::program_idle(IDLE_POLL_TICKS);
+    let start = timer::get_time();
+    unsafe { riscv::asm::wfi() };
+    let mut processor = PROCESSOR.lock();
+    processor.idle.waits += 1;
+    processor.idle.cycles += timer::get_time() - start;
+}
+
+/// 任务让出处理器后，按它这次实际运行的时间增加它的 pass
+fn charge(task: &Arc<TaskControlBlock>) {
+    let cycles = Processor::slice_cycles();
+    let mut inner = task.inner_exclusive_access();
+    inner.cpu_time += cycles;
+    if let Some(pass) = inner.lent_pass.take() {
+        inner.pass = pass;
+    }
+    let priority = inner.priority;
+    inner
+        .pass
+        .charge(priority, cycles, timer::cycles_per_tick());
+}
+
+/// 在 idle 控制流中回收刚刚退出的任务。
+///
+/// 僵尸进程只需保留 pid 和退出码等父进程 `wait` 时用到的信息，这里先切换到内核地址空间，
+/// 再回收它存放页表项的页，这样即使父进程一直不 `wait`，它也不会继续占用页表。
+/// 这里释放的可能是它的最后一个引用，此时还会回收它的内核栈
+fn reap_exited() {
+    let exited = PROCESSOR.lock().exited.take();
+    if let Some(task) = exited {
+        KERNEL_SPACE.lock().activate();
+        task.inner_exclusive_access()
+            .memory_set
+            .recycle_page_table();
+        drop(task);
+    }
+}
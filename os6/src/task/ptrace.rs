@@ -0,0 +1,340 @@
+//! 进程跟踪（ptrace 的最小子集）
+//!
+//! 父进程用 `PTRACE_ATTACH` 跟踪一个子进程，子进程在下一次返回用户态之前暂停，父进程用 `waitpid`
+//! 等到它暂停后，可以读写它的寄存器和内存，再让它继续运行或者单步执行。
+//!
+//! RISC-V 没有 S 态可用的单步执行，单步用断点模拟：解码被跟踪进程下一条指令，在它执行后可能到达的
+//! 每个地址（分支指令有两个）写入 `c.ebreak`。进程执行到其中一个断点时陷入内核，内核恢复原来的指令
+//! 并暂停进程，`sepc` 仍指向断点处，继续运行时执行的就是原来的指令。
+
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{block_current_and_run_next, wakeup_task, Processor, TaskControlBlock};
+use crate::mm::{
+    address::VirtAddr,
+    memory_set::{zero_ppn, MemorySet},
+    page_table::PTEFlags,
+};
+
+/// `c.ebreak` 的编码
+const C_EBREAK: u16 = 0x9002;
+
+/// 被跟踪进程的运行状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceState {
+    Running,
+    /// 在下一次返回用户态之前暂停
+    StopPending,
+    /// 已经暂停，`reported` 表示父进程是否已经用 `waitpid` 得知
+    Stopped {
+        reported: bool,
+    },
+}
+
+/// 被跟踪进程的跟踪状态，保存在它的 TCB 中
+pub struct Ptrace {
+    pub state: TraceState,
+    /// 单步执行插入的断点：地址和原来的两个字节
+    breakpoints: Vec<(usize, u16)>,
+}
+
+impl Ptrace {
+    /// 刚开始跟踪的进程在下一次返回用户态之前暂停
+    pub fn new() -> Self {
+        Self {
+            state: TraceState::StopPending,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.state, TraceState::Stopped { .. })
+    }
+
+    /// 暂停且父进程还没有得知时返回 true，并记为已得知
+    pub fn take_unreported_stop(&mut self) -> bool {
+        if self.state == (TraceState::Stopped { reported: false }) {
+            self.state = TraceState::Stopped { reported: true };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 在执行 `pc` 处的指令后可能到达的地址插入断点，然后继续运行。`reg(i)` 读取通用寄存器 xi。
+    /// 无法读写指令所在的内存时返回 false
+    pub fn single_step(
+        &mut self,
+        memory_set: &mut MemorySet,
+        pc: usize,
+        reg: impl Fn(usize) -> usize,
+    ) -> bool {
+        let inst = match read_inst(memory_set, pc) {
+            Some(inst) => inst,
+            None => return false,
+        };
+        for target in next_pcs(pc, inst, reg).iter().flatten().copied() {
+            if self.breakpoints.iter().any(|&(addr, _)| addr == target) {
+                continue;
+            }
+            let original = match read_u16(memory_set, target) {
+                Some(original) => original,
+                None => {
+                    self.remove_breakpoints(memory_set);
+                    return false;
+                }
+            };
+            if !write_u16(memory_set, target, C_EBREAK) {
+                self.remove_breakpoints(memory_set);
+                return false;
+            }
+            self.breakpoints.push((target, original));
+        }
+        self.state = TraceState::Running;
+        true
+    }
+
+    /// 恢复断点处原来的指令
+    pub fn remove_breakpoints(&mut self, memory_set: &mut MemorySet) {
+        for (addr, original) in self.breakpoints.drain(..) {
+            write_u16(memory_set, addr, original);
+        }
+    }
+
+    /// exec 换掉了地址空间，原来的断点随之消失
+    pub fn forget_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// 进程执行到 `pc` 处的 `ebreak`。是单步插入的断点时恢复原来的指令并暂停进程，返回 true
+    pub fn hit_breakpoint(&mut self, memory_set: &mut MemorySet, pc: usize) -> bool {
+        if !self.breakpoints.iter().any(|&(addr, _)| addr == pc) {
+            return false;
+        }
+        self.remove_breakpoints(memory_set);
+        self.state = TraceState::StopPending;
+        true
+    }
+}
+
+impl Default for Ptrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 如果当前进程被跟踪且需要暂停，暂停它并唤醒等待的父进程，直到父进程让它继续运行、
+/// 停止跟踪，或者收到致命信号。在返回用户态前调用
+pub fn handle_trace_stop() {
+    let task = Processor::current_task().unwrap();
+    let mut notified = false;
+    loop {
+        let parent = {
+            let mut inner = task.inner_exclusive_access();
+            if inner.signals.check_error().is_some() {
+                return;
+            }
+            let trace = match inner.ptrace.as_mut() {
+                Some(trace) => trace,
+                None => return,
+            };
+            match trace.state {
+                TraceState::Running => return,
+                TraceState::StopPending => {
+                    trace.state = TraceState::Stopped { reported: false };
+                    notified = false;
+                }
+                TraceState::Stopped { .. } => {}
+            }
+            inner.parent.as_ref().and_then(|parent| parent.upgrade())
+        };
+        if !notified {
+            log::info!("trace stop task {}", task.pid());
+            if let Some(parent) = parent {
+                parent.inner_exclusive_access().child_exit.wake_all();
+            }
+            notified = true;
+        }
+        block_current_and_run_next();
+    }
+}
+
+/// 当前进程执行了 `pc` 处的 `ebreak`。是单步插入的断点时返回 true，进程将在返回用户态前暂停
+pub fn handle_breakpoint(pc: usize) -> bool {
+    let task = Processor::current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let inner = &mut *inner;
+    match inner.ptrace.as_mut() {
+        Some(trace) => trace.hit_breakpoint(&mut inner.memory_set, pc),
+        None => false,
+    }
+}
+
+/// 停止跟踪 `task`，恢复单步插入的断点，暂停的进程继续运行
+pub fn detach(task: &Arc<TaskControlBlock>) {
+    let stopped = {
+        let mut inner = task.inner_exclusive_access();
+        let inner = &mut *inner;
+        match inner.ptrace.take() {
+            Some(mut trace) => {
+                trace.remove_breakpoints(&mut inner.memory_set);
+                trace.is_stopped()
+            }
+            None => false,
+        }
+    };
+    if stopped {
+        wakeup_task(Arc::clone(task));
+    }
+}
+
+/// 让暂停的 `task` 继续运行
+pub fn resume(task: &Arc<TaskControlBlock>) {
+    wakeup_task(Arc::clone(task));
+}
+
+/// 读取 `va` 处的一个字节，页面需要是用户可访问的
+pub fn read_byte(memory_set: &MemorySet, va: usize) -> Option<u8> {
+    let va = VirtAddr(va);
+    let pte = memory_set.translate(va.floor())?;
+    if !pte.is_valid() || !pte.flags().contains(PTEFlags::U) {
+        return None;
+    }
+    Some(*pte.ppn().as_mut_at::<u8>(va.page_offset()))
+}
+
+/// 写入 `va` 处的一个字节，页面需要是用户可访问的，不要求可写，这样才能在代码段中插入断点。
+/// 写入零页时先分配私有的页帧，不可写的零页不能写入
+pub fn write_byte(memory_set: &mut MemorySet, va: usize, value: u8) -> bool {
+    let va = VirtAddr(va);
+    let pte = match memory_set.translate(va.floor()) {
+        Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) => pte,
+        _ => return false,
+    };
+    let pte = if pte.ppn() == zero_ppn() {
+        if !memory_set.fault_in_zero_page(va.floor()) {
+            return false;
+        }
+        memory_set.translate(va.floor()).unwrap()
+    } else {
+        pte
+    };
+    *pte.ppn().as_mut_at::<u8>(va.page_offset()) = value;
+    true
+}
+
+fn read_u16(memory_set: &MemorySet, va: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([
+        read_byte(memory_set, va)?,
+        read_byte(memory_set, va + 1)?,
+    ]))
+}
+
+fn write_u16(memory_set: &mut MemorySet, va: usize, value: u16) -> bool {
+    let [low, high] = value.to_le_bytes();
+    write_byte(memory_set, va, low) && write_byte(memory_set, va + 1, high)
+}
+
+/// 读取 `pc` 处的指令，压缩指令只有低 16 位
+fn read_inst(memory_set: &MemorySet, pc: usize) -> Option<u32> {
+    let low = read_u16(memory_set, pc)? as u32;
+    if low & 0b11 != 0b11 {
+        return Some(low);
+    }
+    Some(low | (read_u16(memory_set, pc + 2)? as u32) << 16)
+}
+
+/// 把 `bits` 位的补码 `value` 符号扩展为 `usize`
+fn sign_extend(value: u32, bits: u32) -> usize {
+    let shift = 32 - bits;
+    (((value << shift) as i32) >> shift) as isize as usize
+}
+
+/// 执行 `pc` 处的指令 `inst` 之后可能到达的地址。`reg(i)` 读取通用寄存器 xi，用于间接跳转
+pub fn next_pcs(pc: usize, inst: u32, reg: impl Fn(usize) -> usize) -> [Option<usize>; 2] {
+    let bit = |i: u32| (inst >> i) & 1;
+    let bits = |hi: u32, lo: u32| (inst >> lo) & ((1 << (hi - lo + 1)) - 1);
+    let reg = |i: u32| if i == 0 { 0 } else { reg(i as usize) };
+    if inst & 0b11 == 0b11 {
+        let next = pc.wrapping_add(4);
+        match inst & 0x7f {
+            // JAL
+            0b110_1111 => {
+                let imm = bit(31) << 20 | bits(19, 12) << 12 | bit(20) << 11 | bits(30, 21) << 1;
+                [Some(pc.wrapping_add(sign_extend(imm, 21))), None]
+            }
+            // JALR
+            0b110_0111 => {
+                let imm = sign_extend(bits(31, 20), 12);
+                [Some(reg(bits(19, 15)).wrapping_add(imm) & !1), None]
+            }
+            // BRANCH
+            0b110_0011 => {
+                let imm = bit(31) << 12 | bit(7) << 11 | bits(30, 25) << 5 | bits(11, 8) << 1;
+                [Some(next), Some(pc.wrapping_add(sign_extend(imm, 13)))]
+            }
+            _ => [Some(next), None],
+        }
+    } else {
+        let next = pc.wrapping_add(2);
+        match (inst & 0b11, bits(15, 13)) {
+            // C.J
+            (0b01, 0b101) => {
+                let imm = bit(12) << 11
+                    | bit(8) << 10
+                    | bits(10, 9) << 8
+                    | bit(6) << 7
+                    | bit(7) << 6
+                    | bit(2) << 5
+                    | bit(11) << 4
+                    | bits(5, 3) << 1;
+                [Some(pc.wrapping_add(sign_extend(imm, 12))), None]
+            }
+            // C.BEQZ、C.BNEZ
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let imm = bit(12) << 8
+                    | bits(6, 5) << 6
+                    | bit(2) << 5
+                    | bits(11, 10) << 3
+                    | bits(4, 3) << 1;
+                [Some(next), Some(pc.wrapping_add(sign_extend(imm, 9)))]
+            }
+            // C.JR、C.JALR
+            (0b10, 0b100) if bits(11, 7) != 0 && bits(6, 2) == 0 => {
+                [Some(reg(bits(11, 7)) & !1), None]
+            }
+            _ => [Some(next), None],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn next_pcs_follow_jumps_and_branches() {
+        let pc = 0x1000;
+        let reg = |i: usize| 0x2000 + i;
+        let next = |inst: u32| next_pcs(pc, inst, reg);
+        // jal ra, 0x800; j -16; jalr ra, 8(a0); jr -4(a1)
+        assert_eq!(next(0x001000ef), [Some(0x1800), None]);
+        assert_eq!(next(0xff1ff06f), [Some(0xff0), None]);
+        assert_eq!(next(0x008500e7), [Some(0x2012), None]);
+        assert_eq!(next(0xffc58067), [Some(0x2006), None]);
+        // beq a0, a1, 0x100; bne a0, a1, -8; bltu t0, t1, -4096; addi a0, a0, 1
+        assert_eq!(next(0x10b50063), [Some(0x1004), Some(0x1100)]);
+        assert_eq!(next(0xfeb51ce3), [Some(0x1004), Some(0xff8)]);
+        assert_eq!(next(0x8062e063), [Some(0x1004), Some(0)]);
+        assert_eq!(next(0x00150513), [Some(0x1004), None]);
+        // c.j 64; c.j -32; c.beqz a0, 32; c.bnez a0, -6; c.jr a1; c.jalr a2; c.addi a0, 1; c.ebreak
+        assert_eq!(next(0xa081), [Some(0x1040), None]);
+        assert_eq!(next(0xb7c5), [Some(0xfe0), None]);
+        assert_eq!(next(0xc105), [Some(0x1002), Some(0x1020)]);
+        assert_eq!(next(0xfd6d), [Some(0x1002), Some(0xffa)]);
+        assert_eq!(next(0x8582), [Some(0x200a), None]);
+        assert_eq!(next(0x9602), [Some(0x200c), None]);
+        assert_eq!(next(0x0505), [Some(0x1002), None]);
+        assert_eq!(next(u32::from(C_EBREAK)), [Some(0x1002), None]);
+    }
+}
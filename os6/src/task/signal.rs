@@ -0,0 +1,44 @@
+//! 信号
+//!
+//! 目前还不支持用户注册信号处理函数，所有信号都按默认方式处理：进程在返回用户态之前
+//! 检查待处理的信号，收到致命信号时直接退出，收到 SIGTSTP 时暂停运行，直到收到 SIGCONT 或致命信号。
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// 待处理的信号集合，第 n 位对应 n 号信号
+    pub struct SignalFlags: u32 {
+        const SIGINT = 1 << 2;
+        const SIGILL = 1 << 4;
+        const SIGABRT = 1 << 6;
+        const SIGFPE = 1 << 8;
+        const SIGKILL = 1 << 9;
+        const SIGSEGV = 1 << 11;
+        const SIGALRM = 1 << 14;
+        const SIGCONT = 1 << 18;
+        const SIGTSTP = 1 << 20;
+    }
+}
+
+impl SignalFlags {
+    /// 如果有致命信号待处理，返回进程的退出码和提示信息
+    pub fn check_error(&self) -> Option<(i32, &'static str)> {
+        if self.contains(Self::SIGINT) {
+            Some((-2, "Killed, SIGINT=2"))
+        } else if self.contains(Self::SIGILL) {
+            Some((-4, "Illegal Instruction, SIGILL=4"))
+        } else if self.contains(Self::SIGABRT) {
+            Some((-6, "Aborted, SIGABRT=6"))
+        } else if self.contains(Self::SIGFPE) {
+            Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
+        } else if self.contains(Self::SIGKILL) {
+            Some((-9, "Killed, SIGKILL=9"))
+        } else if self.contains(Self::SIGSEGV) {
+            Some((-11, "Segmentation Fault, SIGSEGV=11"))
+        } else if self.contains(Self::SIGALRM) {
+            Some((-14, "Alarm clock, SIGALRM=14"))
+        } else {
+            None
+        }
+    }
+}
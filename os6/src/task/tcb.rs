@@ -1,49 +1,76 @@
-use core::cell::RefMut;
+use core::ops::{Deref, Range};
 
 use alloc::{
+    string::String,
     sync::{Arc, Weak},
-    vec,
     vec::Vec,
 };
 
 use crate::{
-    config::{BIG_STRIDE, MAX_SYSCALL_NUM, TRAP_CONTEXT},
-    fs::{
-        stdio::{Stdin, Stdout},
-        File,
+    config::{
+        BIG_STRIDE, DEFAULT_PRIORITY, DEFAULT_UMASK, MAX_FD, MAX_TIME_SLICE, PAGE_SIZE,
+        TRAP_CONTEXT, USER_STACK_GROW_PAGES, USER_STACK_SIZE,
     },
+    fs::{stdio, FileDescriptor},
     mm::{
         address::{PhysPageNum, VirtAddr},
-        memory_set::{MemorySet, KERNEL_SPACE},
+        memory_set::{ElfError, ElfImage, ElfSource, MapError, MemorySet, KERNEL_SPACE},
     },
-    sync::UPSafeCell,
+    sync::{wait_queue::WaitQueue, KSpinLock, KSpinLockGuard},
+    syscall::{trace::SyscallTrace, SyscallCounts},
+    timer::{IntervalTimer, PosixTimer},
     trap::{self, TrapContext},
 };
 
 use super::{
     context::TaskContext,
-    manager::TaskManager,
+    fp::{self, FpState},
+    manager::{self, TaskManager},
     pid::{KernelStack, PidAllocator, PidHandle},
+    ptrace::Ptrace,
+    signal::SignalFlags,
 };
 
 #[derive(Copy, Clone, PartialEq)]
-/// task status: UnInit, Ready, Running, Zombie
+/// task status: UnInit, Ready, Running, Blocked, Zombie
 pub enum TaskStatus {
     UnInit,
     Ready,
     Running,
+    /// 等待某个事件（如 futex），既不在就绪队列中也不在运行
+    Blocked,
     Zombie,
 }
 
 pub struct TaskControlBlock {
     pub pid: PidHandle,
     pub kernel_stack: KernelStack,
-    inner: UPSafeCell<TaskControlBlockInner>,
+    /// 内核线程执行的函数，用户进程为 `None`
+    pub kernel_entry: Option<fn()>,
+    inner: KSpinLock<TaskControlBlockInner>,
+}
+
+/// 锁住的任务控制块，只用来访问其中的地址空间，见 [`MemorySet::from_existed_user`]
+struct LockedMemorySet<'a>(KSpinLockGuard<'a, TaskControlBlockInner>);
+
+impl Deref for LockedMemorySet<'_> {
+    type Target = MemorySet;
+    fn deref(&self) -> &MemorySet {
+        &self.0.memory_set
+    }
 }
 
 impl TaskControlBlock {
-    pub fn new(elf_data: &[u8]) -> Self {
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+    /// 第一个用户进程，参数为 `argv`，环境变量为 `env`
+    pub fn new(elf: &dyn ElfSource, argv: &[String], env: Vec<String>) -> Self {
+        let ElfImage {
+            memory_set,
+            user_stack_top,
+            user_sp,
+            argc,
+            entry,
+            tp,
+        } = MemorySet::from_elf(elf, argv, &env).expect("invalid elf");
         // `from_elf` 中已经将为 TRAP_CONTEXT 分配好了地址，所以这里可以直接 `unwrap()`
         let trap_ctx_ppn = memory_set
             .translate(VirtAddr(TRAP_CONTEXT).vpn())
@@ -52,44 +79,117 @@ impl TaskControlBlock {
         let pid = PidAllocator::alloc();
         let kernel_stack = KernelStack::new(&pid);
         let kernel_stack_top = kernel_stack.top();
+        // 第一个进程自成一个进程组和会话
+        let pgid = pid.0;
         let tcb = Self {
             pid,
             kernel_stack,
+            kernel_entry: None,
             inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    task_ctx: TaskContext::goto_trap_return(kernel_stack_top),
-                    task_status: TaskStatus::Ready,
-                    memory_set,
-                    trap_ctx_ppn,
-                    base_size: user_sp,
-                    parent: None,
-                    children: Vec::new(),
-                    syscall_count: [0; MAX_SYSCALL_NUM],
-                    start_time: 0,
-                    exit_code: 0,
-                    priority: 16,
-                    pass: Pass(0),
-                    fd_table: vec![
-                        Some(Arc::new(Stdin)),
-                        Some(Arc::new(Stdout)),
-                        Some(Arc::new(Stdout)),
-                    ],
-                })
+                KSpinLock::new(
+                    "task inner",
+                    TaskControlBlockInner {
+                        task_ctx: TaskContext::goto_trap_return(kernel_stack_top),
+                        task_status: TaskStatus::Ready,
+                        memory_set,
+                        trap_ctx_ppn: Some(trap_ctx_ppn),
+                        base_size: user_stack_top,
+                        user_stack: user_stack_top - USER_STACK_SIZE..user_stack_top,
+                        parent: None,
+                        children: Vec::new(),
+                        syscall_count: SyscallCounts::new(),
+                        start_time: 0,
+                        exit_code: 0,
+                        priority: DEFAULT_PRIORITY,
+                        pass: Pass(0),
+                        lent_pass: None,
+                        signals: SignalFlags::empty(),
+                        itimer_real: IntervalTimer::default(),
+                        posix_timers: Vec::new(),
+                        trace: SyscallTrace::default(),
+                        umask: DEFAULT_UMASK,
+                        fd_table: stdio::initial_fd_table(),
+                        pgid,
+                        sid: pgid,
+                        stop_unreported: false,
+                        child_exit: WaitQueue::new(),
+                        env,
+                        ptrace: None,
+                        fp: FpState::default(),
+                        cpu_time: 0,
+                        cpu_limit: RLimit::INFINITY,
+                    },
+                )
             },
         };
         let trap_ctx = tcb.inner_exclusive_access().trap_ctx();
         *trap_ctx = TrapContext::app_init_context(
-            entry_point,
+            entry,
             user_sp,
-            KERNEL_SPACE.exclusive_access().satp(),
+            KERNEL_SPACE.lock().satp(),
             kernel_stack_top,
             trap::trap_handler as usize,
         );
+        trap_ctx.set_tp(tp);
+        trap_ctx.set_main_args(argc);
         tcb
     }
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// 创建执行 `entry` 的内核线程。它没有用户地址空间和 Trap 上下文，也不属于任何进程
+    pub fn new_kthread(entry: fn()) -> Self {
+        let pid = PidAllocator::alloc();
+        let kernel_stack = KernelStack::new(&pid);
+        let kernel_stack_top = kernel_stack.top();
+        let pgid = pid.0;
+        Self {
+            pid,
+            kernel_stack,
+            kernel_entry: Some(entry),
+            inner: unsafe {
+                KSpinLock::new(
+                    "task inner",
+                    TaskControlBlockInner {
+                        task_ctx: TaskContext::goto_kthread_start(kernel_stack_top),
+                        task_status: TaskStatus::Ready,
+                        memory_set: MemorySet::new_kernel_thread(),
+                        trap_ctx_ppn: None,
+                        base_size: 0,
+                        user_stack: 0..0,
+                        parent: None,
+                        children: Vec::new(),
+                        syscall_count: SyscallCounts::new(),
+                        start_time: 0,
+                        exit_code: 0,
+                        priority: DEFAULT_PRIORITY,
+                        pass: Pass(0),
+                        lent_pass: None,
+                        signals: SignalFlags::empty(),
+                        itimer_real: IntervalTimer::default(),
+                        posix_timers: Vec::new(),
+                        trace: SyscallTrace::default(),
+                        umask: DEFAULT_UMASK,
+                        fd_table: Vec::new(),
+                        pgid,
+                        sid: pgid,
+                        stop_unreported: false,
+                        child_exit: WaitQueue::new(),
+                        env: Vec::new(),
+                        ptrace: None,
+                        fp: FpState::default(),
+                        cpu_time: 0,
+                        cpu_limit: RLimit::INFINITY,
+                    },
+                )
+            },
+        }
+    }
+    /// 复制当前进程。复制地址空间失败时返回错误，见 [`MemorySet::from_existed_user`]
+    pub fn fork(self: &Arc<Self>) -> Result<Arc<Self>, MapError> {
+        // 复制地址空间期间可能被抢占，不能一直持有父进程的锁
+        let memory_set =
+            MemorySet::from_existed_user(|| LockedMemorySet(self.inner_exclusive_access()))?;
+        // 父进程可能是浮点寄存器堆的拥有者，先把寄存器保存到 TCB 中再复制
+        fp::flush_owner();
         let mut parent_inner = self.inner_exclusive_access();
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
         let trap_ctx_ppn = memory_set
             .translate(VirtAddr(TRAP_CONTEXT).vpn())
             .unwrap()
@@ -100,84 +200,163 @@ impl TaskControlBlock {
         let tcb = Arc::new(Self {
             pid,
             kernel_stack,
+            kernel_entry: None,
             inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    task_ctx: TaskContext::goto_trap_return(kernel_stack_top),
-                    task_status: TaskStatus::Ready,
-                    memory_set,
-                    trap_ctx_ppn,
-                    base_size: parent_inner.base_size,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    syscall_count: [0; 500],
-                    start_time: 0,
-                    exit_code: 0,
-                    priority: 16,
-                    pass: Pass(0),
-                    fd_table: vec![
-                        Some(Arc::new(Stdin)),
-                        Some(Arc::new(Stdout)),
-                        Some(Arc::new(Stdout)),
-                    ],
-                })
+                KSpinLock::new(
+                    "task inner",
+                    TaskControlBlockInner {
+                        task_ctx: TaskContext::goto_trap_return(kernel_stack_top),
+                        task_status: TaskStatus::Ready,
+                        memory_set,
+                        trap_ctx_ppn: Some(trap_ctx_ppn),
+                        base_size: parent_inner.base_size,
+                        user_stack: parent_inner.user_stack.clone(),
+                        parent: Some(Arc::downgrade(self)),
+                        children: Vec::new(),
+                        syscall_count: SyscallCounts::new(),
+                        start_time: 0,
+                        exit_code: 0,
+                        priority: DEFAULT_PRIORITY,
+                        pass: Pass(0),
+                        lent_pass: None,
+                        signals: SignalFlags::empty(),
+                        itimer_real: IntervalTimer::default(),
+                        posix_timers: Vec::new(),
+                        trace: SyscallTrace::default(),
+                        umask: parent_inner.umask,
+                        fd_table: parent_inner.fd_table.clone(),
+                        pgid: parent_inner.pgid,
+                        sid: parent_inner.sid,
+                        stop_unreported: false,
+                        child_exit: WaitQueue::new(),
+                        env: parent_inner.env.clone(),
+                        ptrace: None,
+                        fp: parent_inner.fp.clone(),
+                        cpu_time: 0,
+                        cpu_limit: parent_inner.cpu_limit,
+                    },
+                )
             },
         });
         parent_inner.children.push(Arc::clone(&tcb));
+        manager::insert_into_pid2task(tcb.pid(), Arc::clone(&tcb));
         let trap_ctx = tcb.inner_exclusive_access().trap_ctx();
         trap_ctx.kernel_sp = kernel_stack_top;
-        tcb
+        Ok(tcb)
     }
-    pub fn exec(&self, elf_data: &[u8]) {
-        let (memory_set, user_sp, entry) = MemorySet::from_elf(elf_data);
+    /// ELF 无效时返回错误，此时当前进程不受影响
+    ///
+    /// `env` 为 `None` 时沿用当前的环境变量
+    pub fn exec(
+        &self,
+        elf: &dyn ElfSource,
+        argv: &[String],
+        env: Option<Vec<String>>,
+    ) -> Result<(), ElfError> {
+        let env = env.unwrap_or_else(|| self.inner_exclusive_access().env.clone());
+        let ElfImage {
+            memory_set,
+            user_stack_top,
+            user_sp,
+            argc,
+            entry,
+            tp,
+        } = MemorySet::from_elf(elf, argv, &env)?;
         let trap_ctx_ppn = memory_set
             .translate(VirtAddr(TRAP_CONTEXT).vpn())
             .unwrap()
             .ppn();
         let mut inner = self.inner_exclusive_access();
+        // 旧地址空间的文件映射中被修改的页写回文件
+        inner.memory_set.recycle_data_pages();
         inner.memory_set = memory_set;
-        inner.trap_ctx_ppn = trap_ctx_ppn;
+        inner.trap_ctx_ppn = Some(trap_ctx_ppn);
+        inner.user_stack = user_stack_top - USER_STACK_SIZE..user_stack_top;
+        inner.posix_timers.clear();
+        inner.env = env;
+        if let Some(trace) = inner.ptrace.as_mut() {
+            trace.forget_breakpoints();
+        }
+        inner.fp = FpState::default();
+        fp::release(self);
         let trap_ctx = inner.trap_ctx();
         *trap_ctx = TrapContext::app_init_context(
             entry,
             user_sp,
-            KERNEL_SPACE.exclusive_access().satp(),
+            KERNEL_SPACE.lock().satp(),
             self.kernel_stack.top(),
             trap::trap_handler as usize,
         );
+        trap_ctx.set_tp(tp);
+        trap_ctx.set_main_args(argc);
+        Ok(())
     }
-    pub fn spawn(self: &Arc<Self>, elf_data: &[u8]) -> usize {
+    /// 成功返回子进程的 pid，ELF 无效时返回错误。子进程继承当前的环境变量
+    pub fn spawn(
+        self: &Arc<Self>,
+        elf: &dyn ElfSource,
+        argv: &[String],
+    ) -> Result<usize, ElfError> {
+        let env = self.inner_exclusive_access().env.clone();
         // 1. 创建子进程对应的 tcb
-        let (memory_set, user_sp, entry) = MemorySet::from_elf(elf_data);
+        let ElfImage {
+            memory_set,
+            user_stack_top,
+            user_sp,
+            argc,
+            entry,
+            tp,
+        } = MemorySet::from_elf(elf, argv, &env)?;
         let trap_ctx_ppn = memory_set
             .translate(VirtAddr(TRAP_CONTEXT).vpn())
             .unwrap()
             .ppn();
+        let (umask, pgid, sid, cpu_limit) = {
+            let inner = self.inner_exclusive_access();
+            (inner.umask, inner.pgid, inner.sid, inner.cpu_limit)
+        };
         let pid = PidAllocator::alloc();
         let kernel_stack = KernelStack::new(&pid);
         let kernel_stack_top = kernel_stack.top();
         let tcb = Arc::new(TaskControlBlock {
             pid,
             kernel_stack,
+            kernel_entry: None,
             inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    task_ctx: TaskContext::goto_trap_return(kernel_stack_top),
-                    task_status: TaskStatus::Ready,
-                    memory_set,
-                    trap_ctx_ppn,
-                    base_size: user_sp,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    syscall_count: [0; MAX_SYSCALL_NUM],
-                    start_time: 0,
-                    exit_code: 0,
-                    priority: 16,
-                    pass: Pass(0),
-                    fd_table: vec![
-                        Some(Arc::new(Stdin)),
-                        Some(Arc::new(Stdout)),
-                        Some(Arc::new(Stdout)),
-                    ],
-                })
+                KSpinLock::new(
+                    "task inner",
+                    TaskControlBlockInner {
+                        task_ctx: TaskContext::goto_trap_return(kernel_stack_top),
+                        task_status: TaskStatus::Ready,
+                        memory_set,
+                        trap_ctx_ppn: Some(trap_ctx_ppn),
+                        base_size: user_stack_top,
+                        user_stack: user_stack_top - USER_STACK_SIZE..user_stack_top,
+                        parent: Some(Arc::downgrade(self)),
+                        children: Vec::new(),
+                        syscall_count: SyscallCounts::new(),
+                        start_time: 0,
+                        exit_code: 0,
+                        priority: DEFAULT_PRIORITY,
+                        pass: Pass(0),
+                        lent_pass: None,
+                        signals: SignalFlags::empty(),
+                        itimer_real: IntervalTimer::default(),
+                        posix_timers: Vec::new(),
+                        trace: SyscallTrace::default(),
+                        umask,
+                        fd_table: stdio::initial_fd_table(),
+                        pgid,
+                        sid,
+                        stop_unreported: false,
+                        child_exit: WaitQueue::new(),
+                        env,
+                        ptrace: None,
+                        fp: FpState::default(),
+                        cpu_time: 0,
+                        cpu_limit,
+                    },
+                )
             },
         });
         // 2. 加入当前进程的子进程队列
@@ -189,86 +368,232 @@ impl TaskControlBlock {
         *trap_ctx = TrapContext::app_init_context(
             entry,
             user_sp,
-            KERNEL_SPACE.exclusive_access().satp(),
+            KERNEL_SPACE.lock().satp(),
             kernel_stack_top,
             trap::trap_handler as usize,
         );
+        trap_ctx.set_tp(tp);
+        trap_ctx.set_main_args(argc);
         let pid = tcb.pid();
         // 4. 子进程等待调度
+        manager::insert_into_pid2task(pid, Arc::clone(&tcb));
         TaskManager::add_task(tcb);
-        pid
+        Ok(pid)
+    }
+    /// 借用进程的可变部分。同一进程的借用不能嵌套，例如持有借用时不能再调用
+    /// [`Processor::current_user_satp`](super::Processor::current_user_satp) 这类会再次借用当前进程的函数，
+    /// 否则 panic；打开 `lock-debug` feature 时会同时报告两处借用的位置
+    #[track_caller]
+    pub fn inner_exclusive_access(&self) -> KSpinLockGuard<'_, TaskControlBlockInner> {
+        self.inner.lock()
     }
-    pub fn inner_exclusive_access(&self) -> RefMut<TaskControlBlockInner> {
-        self.inner.exclusive_access()
+    /// 阻塞当前任务，直到这个进程的某个子进程退出
+    pub fn wait_child_exit(self: &Arc<Self>) {
+        self.inner_exclusive_access()
+            .child_exit
+            .add_waiter(Arc::clone(self));
+        super::block_current_and_run_next();
+        self.inner_exclusive_access().child_exit.remove_waiter(self);
     }
     pub fn pid(&self) -> usize {
         self.pid.0
     }
+    /// 线程号。目前每个进程只有一个线程，线程号就是进程号；引入多线程后，
+    /// 同一进程的线程共享 [`pid`](Self::pid)，各自有不同的线程号
+    pub fn tid(&self) -> usize {
+        self.pid.0
+    }
+    pub fn is_kernel_thread(&self) -> bool {
+        self.kernel_entry.is_some()
+    }
 }
 
 pub struct TaskControlBlockInner {
     pub task_ctx: TaskContext,
     pub task_status: TaskStatus,
     pub memory_set: MemorySet,
-    /// Trap Context 所在的物理页号
-    pub trap_ctx_ppn: PhysPageNum,
+    /// Trap Context 所在的物理页号，内核线程没有 Trap Context
+    pub trap_ctx_ppn: Option<PhysPageNum>,
     /// 统计应用数据的大小，包括用户栈
     pub base_size: usize,
+    /// 用户栈的虚拟地址范围，其下方紧邻的一页是 Guard Page
+    pub user_stack: Range<usize>,
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
-    pub syscall_count: [u32; MAX_SYSCALL_NUM],
+    /// 每个系统调用的调用次数，可以用 `sys_task_info` 查询和清零
+    pub syscall_count: SyscallCounts,
     pub start_time: usize,
     pub exit_code: i32,
     pub priority: usize,
     pub pass: Pass,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    /// 接受其他任务让出的时间片之前的 pass，让出处理器时恢复，见 `TaskManager::donate`
+    pub lent_pass: Option<Pass>,
+    pub fd_table: Vec<Option<FileDescriptor>>,
+    /// 待处理的信号，在返回用户态之前检查
+    pub signals: SignalFlags,
+    /// `ITIMER_REAL` 间隔定时器，到期时发送 SIGALRM，由时钟中断检查
+    pub itimer_real: IntervalTimer,
+    /// `timer_create` 创建的定时器，下标即定时器 id，删除的定时器留下 `None`。fork 和 exec 时都不保留
+    pub posix_timers: Vec<Option<PosixTimer>>,
+    /// 系统调用跟踪的开关和限流状态
+    pub trace: SyscallTrace,
+    /// 文件创建掩码，新建文件的权限位会去掉其中的位
+    pub umask: u16,
+    /// 所在的进程组，进程组号为组长的 pid
+    pub pgid: usize,
+    /// 所在的会话，会话号为会话首进程的 pid
+    pub sid: usize,
+    /// 因 SIGTSTP 暂停后还没有被父进程的 `waitpid` 报告
+    pub stop_unreported: bool,
+    /// 在 `waitpid` 中等待子进程退出的任务
+    pub child_exit: WaitQueue,
+    /// 环境变量，每项形如 `NAME=value`。fork 和 spawn 时继承，exec 时可以替换
+    pub env: Vec<String>,
+    /// 被父进程跟踪时的跟踪状态，fork 和 spawn 的子进程不被跟踪
+    pub ptrace: Option<Ptrace>,
+    /// 浮点寄存器，见 [`fp`] 模块
+    pub fp: FpState,
+    /// 已经消耗的处理器时间，单位为 `time` 的周期，在任务让出处理器时累加
+    pub cpu_time: usize,
+    /// `RLIMIT_CPU`，fork 和 spawn 时继承
+    pub cpu_limit: RLimit,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+/// stride 调度中任务已经消耗的处理器时间，按优先级加权。pass 最小的任务最先被调度
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Pass(pub usize);
 
-impl Ord for Pass {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        Self::partial_cmp(&self, other).unwrap()
+impl Pass {
+    /// 记入优先级为 `priority` 的任务运行了 `cycles` 个 `time` 周期，
+    /// 一个时钟中断周期为 `cycles_per_tick`。
+    ///
+    /// pass 每次至少增加 1：优先级超过 `BIG_STRIDE` 或者运行时间很短时按比例算出的增量为零，
+    /// 这样的任务的 pass 一直不变，会让其它任务永远得不到调度
+    pub fn charge(&mut self, priority: usize, cycles: usize, cycles_per_tick: usize) {
+        let stride = (BIG_STRIDE / priority) as u128;
+        let pass = (stride * cycles as u128 / cycles_per_tick as u128) as usize;
+        self.0 += pass.max(1);
     }
 }
 
-impl PartialOrd for Pass {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        use core::cmp::Ordering;
-        if self.0 <= other.0 {
-            if other.0 - self.0 > BIG_STRIDE / 2 {
-                Some(Ordering::Greater)
-            } else {
-                Some(Ordering::Less)
-            }
-        } else {
-            if self.0 - other.0 > BIG_STRIDE / 2 {
-                Some(Ordering::Less)
-            } else {
-                Some(Ordering::Greater)
-            }
-        }
+/// 资源限制，与 Linux 的 `struct rlimit` 布局一致。目前只支持处理器时间，单位为秒
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RLimit {
+    /// 软限制，任务消耗的处理器时间达到它时被杀死
+    pub cur: u64,
+    /// 硬限制，软限制不能超过它
+    pub max: u64,
+}
+
+impl RLimit {
+    pub const INFINITY: Self = Self {
+        cur: u64::MAX,
+        max: u64::MAX,
+    };
+    /// 消耗了 `cycles` 个 `time` 周期后是否达到软限制，`clock_freq` 为每秒的周期数
+    pub fn exceeded_by(&self, cycles: usize, clock_freq: usize) -> bool {
+        self.cur != u64::MAX && cycles as u64 >= self.cur.saturating_mul(clock_freq as u64)
     }
 }
 
 impl TaskControlBlockInner {
     pub fn trap_ctx(&mut self) -> &'static mut TrapContext {
-        self.trap_ctx_ppn.as_mut()
+        self.trap_ctx_ppn
+            .expect("kernel thread has no trap context")
+            .as_mut()
     }
     pub fn user_satp(&self) -> usize {
         self.memory_set.satp()
     }
+    /// 时间片的时钟中断周期数。优先级越高的任务不仅更常被调度，每次也运行得更久：
+    /// 时间片与优先级成正比，默认优先级为一个周期
+    pub fn time_slice(&self) -> usize {
+        (self.priority / DEFAULT_PRIORITY).clamp(1, MAX_TIME_SLICE)
+    }
     pub fn is_zombie(&self) -> bool {
         self.task_status == TaskStatus::Zombie
     }
-    pub fn alloc_fd(&mut self) -> usize {
+    /// 判断 `va` 是否落在用户栈下方 `USER_STACK_GROW_PAGES` 页以内。
+    ///
+    /// 这样的缺页要么触发用户栈增长，要么说明用户栈溢出
+    pub fn in_stack_guard(&self, va: usize) -> bool {
+        let bottom = self.user_stack.start;
+        va < bottom && va >= bottom - USER_STACK_GROW_PAGES * PAGE_SIZE
+    }
+    /// 返回最小的空闲文件描述符，已经打开了 `MAX_FD` 个文件时返回 `None`
+    pub fn alloc_fd(&mut self) -> Option<usize> {
         for (fd, file) in self.fd_table.iter().enumerate() {
             if file.is_none() {
-                return fd;
+                return Some(fd);
             }
         }
+        if self.fd_table.len() >= MAX_FD {
+            return None;
+        }
         self.fd_table.push(None);
-        self.fd_table.len() - 1
+        Some(self.fd_table.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn pass_grows_with_cpu_time_and_priority() {
+        let mut low = Pass(0);
+        let mut high = Pass(0);
+        low.charge(2, 300, 100);
+        high.charge(8, 300, 100);
+        assert_eq!(low, Pass(3 * BIG_STRIDE / 2));
+        assert_eq!(high, Pass(3 * BIG_STRIDE / 8));
+        // 不足一个时钟周期也按比例计入
+        let mut short = Pass(0);
+        short.charge(2, 1, 100);
+        assert_eq!(short, Pass(BIG_STRIDE / 2 / 100));
+        // 优先级超过 BIG_STRIDE 时 pass 仍然增长
+        let mut huge = Pass(0);
+        huge.charge(BIG_STRIDE * 4, 300, 100);
+        huge.charge(isize::MAX as usize, 1, 100);
+        assert_eq!(huge, Pass(2));
+    }
+
+    #[test_case]
+    fn cpu_limit_is_reached_after_the_soft_limit() {
+        let limit = RLimit { cur: 2, max: 3 };
+        assert!(!limit.exceeded_by(199, 100));
+        assert!(limit.exceeded_by(200, 100));
+        assert!(!RLimit::INFINITY.exceeded_by(usize::MAX, 100));
+    }
+
+    #[test_case]
+    fn lower_pass_is_scheduled_first() {
+        let passes = [Pass(usize::MAX - 5), Pass(3), Pass(usize::MAX - 100)];
+        assert!(passes.iter().min() == Some(&Pass(3)));
+    }
+
+    #[test_case]
+    fn time_slice_follows_priority() {
+        let tcb = TaskControlBlock::new_kthread(|| {});
+        let slice = |priority| {
+            tcb.inner_exclusive_access().priority = priority;
+            tcb.inner_exclusive_access().time_slice()
+        };
+        assert_eq!(slice(2), 1);
+        assert_eq!(slice(DEFAULT_PRIORITY), 1);
+        assert_eq!(slice(DEFAULT_PRIORITY * 4), 4);
+        assert_eq!(slice(usize::MAX), MAX_TIME_SLICE);
+    }
+
+    #[test_case]
+    fn kernel_thread_has_no_user_memory() {
+        let tcb = TaskControlBlock::new_kthread(|| {});
+        assert!(tcb.is_kernel_thread());
+        let inner = tcb.inner_exclusive_access();
+        assert!(inner.trap_ctx_ppn.is_none());
+        let usage = inner.memory_set.usage();
+        assert_eq!(usage.resident, 0);
+        assert_eq!(usage.page_table, 0);
     }
 }
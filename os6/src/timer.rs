@@ -1,23 +1,310 @@
+//! 时钟与定时器
+//!
+//! 时钟中断不再周期性地到来。所有需要在某个时刻做的事情（调度器的时间片到期、睡眠的任务醒来、
+//! 间隔定时器到期、flusher 定期写回）都登记在一个按到期时间排序的定时器队列中，
+//! `mtimecmp` 总是设置为其中最早的到期时间。没有任务需要时钟中断时就不会被打扰。
+
+use alloc::{collections::BinaryHeap, sync::Weak, vec::Vec};
+use core::{
+    cmp::Ordering as CmpOrdering,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use crate::config::CLOCK_FREQ;
+use crate::dtb;
 use crate::sbi::set_timer;
+use crate::sync::UPSafeCell;
+use crate::task::{self, signal::SignalFlags, TaskControlBlock};
+use lazy_static::lazy_static;
 use riscv::register::time;
 
-const TICKS_PER_SEC: usize = 100;
+/// 每秒的时钟中断次数
+pub const TICKS_PER_SEC: usize = 100;
 const MILLI_PER_SEC: usize = 1_000;
 pub const MICRO_PER_SEC: usize = 1_000_000;
 
+/// `time` 寄存器每秒增加的次数
+static TIMEBASE_FREQ: AtomicUsize = AtomicUsize::new(CLOCK_FREQ);
+
+/// 从设备树中读取 `time` 寄存器的频率，需要在 `dtb::init` 之后调用
+pub fn init() {
+    TIMEBASE_FREQ.store(dtb::timebase_frequency(), Ordering::Relaxed);
+}
+
+pub fn clock_freq() -> usize {
+    TIMEBASE_FREQ.load(Ordering::Relaxed)
+}
+
 pub fn get_time() -> usize {
     time::read()
 }
 
 pub fn get_time_ms() -> usize {
-    time::read() / (CLOCK_FREQ / MILLI_PER_SEC)
+    (time::read() as u128 * MILLI_PER_SEC as u128 / clock_freq() as u128) as usize
 }
 
 pub fn get_time_us() -> usize {
-    (time::read() / (CLOCK_FREQ * 2 / MICRO_PER_SEC)) * 2
+    (time::read() as u128 * MICRO_PER_SEC as u128 / clock_freq() as u128) as usize
+}
+
+/// 启动以来经过的时钟中断周期数
+pub fn jiffies() -> usize {
+    get_time() / cycles_per_tick()
+}
+
+pub const fn ticks_to_ms(ticks: usize) -> usize {
+    ticks_to_us(ticks) / (MICRO_PER_SEC / MILLI_PER_SEC)
+}
+
+pub const fn ticks_to_us(ticks: usize) -> usize {
+    ticks * MICRO_PER_SEC / TICKS_PER_SEC
+}
+
+/// 一个时钟中断周期中 `time` 增加的次数
+pub fn cycles_per_tick() -> usize {
+    clock_freq() / TICKS_PER_SEC
+}
+
+fn us_to_cycles(us: usize) -> usize {
+    (us as u128 * clock_freq() as u128 / MICRO_PER_SEC as u128) as usize
+}
+
+/// 定时器到期时做的事
+#[derive(Clone)]
+pub enum TimerAction {
+    /// 唤醒阻塞的任务，任务已经退出或者没有阻塞时什么也不做
+    Wake(Weak<TaskControlBlock>),
+    /// 调用一个函数，它在时钟中断或者 idle 控制流中执行，不能阻塞
+    Call(fn()),
+}
+
+struct TimerEntry {
+    /// 到期时的 `time`
+    deadline: usize,
+    /// 登记的顺序，同时到期的定时器按登记的顺序处理
+    seq: usize,
+    action: TimerAction,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline, self.seq) == (other.deadline, other.seq)
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    /// `BinaryHeap` 是大顶堆，反过来比较使最早到期的定时器位于堆顶
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
 }
 
-pub fn set_next_trigger() {
-    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+/// 按到期时间排序的定时器，以及当前任务时间片到期的时间
+struct TimerQueue {
+    heap: BinaryHeap<TimerEntry>,
+    next_seq: usize,
+    /// 当前任务的时间片到期时的 `time`，没有任务在运行时为 `None`
+    preempt: Option<usize>,
+}
+
+impl TimerQueue {
+    fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            next_seq: 0,
+            preempt: None,
+        }
+    }
+    fn push(&mut self, deadline: usize, action: TimerAction) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(TimerEntry {
+            deadline,
+            seq,
+            action,
+        });
+    }
+    /// 取出在 `now` 之前到期的定时器，按到期时间排列
+    fn pop_expired(&mut self, now: usize) -> Vec<TimerAction> {
+        let mut expired = Vec::new();
+        while matches!(self.heap.peek(), Some(entry) if entry.deadline <= now) {
+            expired.push(self.heap.pop().unwrap().action);
+        }
+        expired
+    }
+    /// 最早需要时钟中断的时间
+    fn next_deadline(&self) -> Option<usize> {
+        let head = self.heap.peek().map(|entry| entry.deadline);
+        match (head, self.preempt) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+lazy_static! {
+    static ref TIMER_QUEUE: UPSafeCell<TimerQueue> = unsafe { UPSafeCell::new(TimerQueue::new()) };
+}
+
+/// 把 `mtimecmp` 设置为最早的到期时间，没有定时器时不再触发时钟中断
+fn program(queue: &TimerQueue) {
+    set_timer(queue.next_deadline().unwrap_or(usize::MAX));
+}
+
+/// 登记一个在启动以来 `deadline_us` 微秒时到期的定时器
+pub fn add_timer(deadline_us: usize, action: TimerAction) {
+    let mut queue = TIMER_QUEUE.exclusive_access();
+    queue.push(us_to_cycles(deadline_us), action);
+    program(&queue);
+}
+
+/// 当前任务的时间片在 `ticks` 个时钟中断周期之后到期，由调度器在切换到任务时调用
+pub fn set_preempt_after(ticks: usize) {
+    let mut queue = TIMER_QUEUE.exclusive_access();
+    queue.preempt = Some(get_time() + ticks * cycles_per_tick());
+    program(&queue);
+}
+
+/// 当前任务让出了处理器，取消它的时间片
+pub fn clear_preempt() {
+    TIMER_QUEUE.exclusive_access().preempt = None;
+}
+
+/// 为 idle 控制流等待中断设置 `mtimecmp`：最早的到期时间，但不晚于 `max_ticks` 个时钟中断周期之后。
+/// 醒来后由 [`handle_expired`] 重新设置
+pub fn program_idle(max_ticks: usize) {
+    let limit = get_time() + max_ticks * cycles_per_tick();
+    let deadline = TIMER_QUEUE.exclusive_access().next_deadline();
+    set_timer(deadline.map_or(limit, |deadline| deadline.min(limit)));
+}
+
+/// 执行所有已经到期的定时器并重新设置 `mtimecmp`。在时钟中断和 idle 控制流中调用。
+///
+/// 返回当前任务的时间片是否已经用完
+pub fn handle_expired() -> bool {
+    let now = get_time();
+    let (expired, preempt) = {
+        let mut queue = TIMER_QUEUE.exclusive_access();
+        let expired = queue.pop_expired(now);
+        let preempt = matches!(queue.preempt, Some(deadline) if deadline <= now);
+        if preempt {
+            queue.preempt = None;
+        }
+        (expired, preempt)
+    };
+    // 定时器的动作可能登记新的定时器，因此先释放借用
+    for action in expired {
+        match action {
+            TimerAction::Wake(task) => {
+                if let Some(task) = task.upgrade() {
+                    task::wakeup_task(task);
+                }
+            }
+            TimerAction::Call(f) => f(),
+        }
+    }
+    program(&TIMER_QUEUE.exclusive_access());
+    preempt
+}
+
+/// 间隔定时器（`ITIMER_REAL`），时间单位均为微秒
+#[derive(Copy, Clone, Default)]
+pub struct IntervalTimer {
+    /// 下次到期的时间，为 0 表示定时器未启用
+    pub expire: usize,
+    /// 到期后重新计时的间隔，为 0 表示只触发一次
+    pub interval: usize,
+}
+
+impl IntervalTimer {
+    /// 距离下次到期的剩余时间，定时器未启用时为 0
+    pub fn remaining(&self, now: usize) -> usize {
+        self.expire.saturating_sub(now)
+    }
+    /// 从 `now` 开始计时，`value` 为 0 时关闭定时器
+    pub fn set(&mut self, now: usize, value: usize, interval: usize) {
+        self.expire = if value == 0 { 0 } else { now + value };
+        self.interval = interval;
+    }
+    /// 检查定时器是否到期。到期时按 `interval` 重新计时或关闭定时器，并返回 true
+    pub fn poll(&mut self, now: usize) -> bool {
+        if self.expire == 0 || now < self.expire {
+            return false;
+        }
+        self.expire = if self.interval == 0 {
+            0
+        } else {
+            // 错过了多个周期时只触发一次
+            now + self.interval - (now - self.expire) % self.interval
+        };
+        true
+    }
+}
+
+/// `timer_create` 创建的定时器，到期时发送 `signal`
+#[derive(Copy, Clone)]
+pub struct PosixTimer {
+    pub timer: IntervalTimer,
+    /// 到期时发送的信号，为空时不通知，只能用 `timer_gettime` 查询
+    pub signal: SignalFlags,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn interval_timer_rearms() {
+        let mut timer = IntervalTimer::default();
+        assert!(!timer.poll(100));
+        timer.set(100, 50, 20);
+        assert_eq!(timer.remaining(120), 30);
+        assert!(!timer.poll(149));
+        assert!(timer.poll(150));
+        assert_eq!(timer.expire, 170);
+        // 错过多个周期时只触发一次，并对齐到下一个周期
+        assert!(timer.poll(215));
+        assert_eq!(timer.expire, 230);
+        timer.set(230, 0, 20);
+        assert!(!timer.poll(1000));
+    }
+
+    #[test_case]
+    fn timer_queue_pops_in_deadline_order() {
+        fn nop() {}
+        let mut queue = TimerQueue::new();
+        queue.push(30, TimerAction::Call(nop));
+        queue.push(10, TimerAction::Call(nop));
+        queue.push(20, TimerAction::Call(nop));
+        assert_eq!(queue.next_deadline(), Some(10));
+        queue.preempt = Some(5);
+        assert_eq!(queue.next_deadline(), Some(5));
+        assert_eq!(queue.pop_expired(20).len(), 2);
+        assert_eq!(queue.heap.peek().map(|entry| entry.deadline), Some(30));
+        assert!(queue.pop_expired(29).is_empty());
+    }
+
+    #[test_case]
+    fn ticks_convert_to_time() {
+        assert_eq!(ticks_to_ms(TICKS_PER_SEC), 1000);
+        assert_eq!(ticks_to_us(1), MICRO_PER_SEC / TICKS_PER_SEC);
+        assert_eq!(ticks_to_ms(0), 0);
+    }
+
+    #[test_case]
+    fn one_shot_timer_disarms() {
+        let mut timer = IntervalTimer::default();
+        timer.set(0, 10, 0);
+        assert!(timer.poll(10));
+        assert!(!timer.poll(100));
+        assert_eq!(timer.remaining(100), 0);
+    }
 }
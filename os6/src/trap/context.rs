@@ -1,4 +1,13 @@
-use riscv::register::sstatus::{self, Sstatus, SPP};
+use core::fmt;
+
+use riscv::register::sstatus::{self, Sstatus, FS, SPP};
+
+/// 通用寄存器的 ABI 名称
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
 
 #[repr(C)]
 pub struct TrapContext {
@@ -14,6 +23,18 @@ impl TrapContext {
     pub fn set_sp(&mut self, sp: usize) {
         self.x[2] = sp;
     }
+    /// 设置线程指针，指向应用的 TLS 块
+    pub fn set_tp(&mut self, tp: usize) {
+        self.x[4] = tp;
+    }
+    /// 按 `_start(argc, argv, envp)` 的约定设置参数寄存器，`sp` 处需要已经按 SysV ABI
+    /// 放好了 argc、argv 和 envp
+    pub fn set_main_args(&mut self, argc: usize) {
+        let sp = self.x[2];
+        self.x[10] = argc;
+        self.x[11] = sp + 8;
+        self.x[12] = sp + 8 * (argc + 2);
+    }
     pub fn app_init_context(
         entry: usize,
         sp: usize,
@@ -23,6 +44,8 @@ impl TrapContext {
     ) -> Self {
         let mut sstatus = sstatus::read();
         sstatus.set_spp(SPP::User);
+        // 第一次使用浮点指令时才打开浮点单元，见 `task::fp`
+        sstatus.set_fs(FS::Off);
         let mut ctx = Self {
             x: [0; 32],
             sstatus,
@@ -35,3 +58,24 @@ impl TrapContext {
         ctx
     }
 }
+
+impl fmt::Debug for TrapContext {
+    /// 每行四个寄存器，打印全部通用寄存器
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sepc: {:#018x}, spp: {:?}",
+            self.sepc,
+            self.sstatus.spp()
+        )?;
+        for (i, (name, value)) in REG_NAMES.iter().zip(self.x.iter()).enumerate() {
+            if i % 4 == 0 {
+                writeln!(f)?;
+            } else {
+                write!(f, "  ")?;
+            }
+            write!(f, "{:>4}: {:#018x}", name, value)?;
+        }
+        Ok(())
+    }
+}
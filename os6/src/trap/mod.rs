@@ -2,50 +2,138 @@ mod context;
 
 use crate::{
     config::{TRAMPOLINE, TRAP_CONTEXT},
+    drivers::plic,
+    fs, ipi,
+    ktrace::{self, EventKind},
+    mm::{
+        address::VirtAddr,
+        user::{self, with_user_access},
+    },
+    net,
     syscall::syscall,
-    task::{self, Processor},
+    task::{self, preempt, Processor},
     timer,
 };
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    sie, sscratch, sstatus, stval, stvec,
 };
 
 pub use context::TrapContext;
 
 core::arch::global_asm!(include_str!("trap.S"));
 
+/// trap 发生时处理器所处的特权级，由 `trap.S` 传给 [`trap_handler`]
+#[repr(usize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrapSource {
+    /// 来自用户态，现场保存在用户地址空间的 `TRAP_CONTEXT` 处，处理完后经 [`trap_return`] 返回
+    User = 0,
+    /// 来自内核态，现场保存在当前内核栈上，处理函数返回后由 `trap.S` 恢复
+    Kernel = 1,
+}
+
+/// 内核态和用户态的 trap 都从跳板页上的 `__alltraps` 进入，`sscratch` 为 0 表示当前处于内核态
 pub fn init() {
-    set_kernel_trap_entry();
+    unsafe {
+        sscratch::write(0);
+        stvec::write(TRAMPOLINE, TrapMode::Direct);
+        // 内核只在 `with_user_access` 的范围内打开 SUM
+        sstatus::clear_sum();
+    }
 }
 
+/// 所有 trap 的处理函数。`kernel_ctx` 只在来自内核态时有效，指向内核栈上保存的现场
 #[no_mangle]
-pub fn trap_handler() -> ! {
-    set_kernel_trap_entry();
+pub extern "C" fn trap_handler(source: TrapSource, kernel_ctx: *mut TrapContext) {
+    match source {
+        TrapSource::User => user_trap(),
+        TrapSource::Kernel => kernel_trap(unsafe { &mut *kernel_ctx }),
+    }
+}
+
+fn user_trap() -> ! {
+    task::fp::disable_in_kernel();
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
             let mut ctx = Processor::current_trap_ctx();
             ctx.sepc += 4;
-            let result = syscall(ctx.x[17], [ctx.x[10], ctx.x[11], ctx.x[12], ctx.x[13]]) as usize;
+            let args = [
+                ctx.x[10], ctx.x[11], ctx.x[12], ctx.x[13], ctx.x[14], ctx.x[15],
+            ];
+            ktrace::record_current(EventKind::SyscallEnter, ctx.x[17]);
+            // 系统调用期间响应时钟中断，在抢占点检查时间片
+            preempt::enable();
+            let result = syscall(ctx.x[17], args) as usize;
+            preempt::disable();
+            ktrace::record_current(EventKind::SyscallExit, result);
             ctx = Processor::current_trap_ctx();
             ctx.x[10] = result;
+            preempt::cond_resched();
         }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            log::error!("[kernel] PageFault in application, core dumped.");
-            task::exit_current_and_run_next(-2);
+            ktrace::record_current(EventKind::Fault, stval);
+            // `exit_current_and_run_next` 不会返回，所以这里不能长期持有当前任务的引用
+            let (pid, stack_fault) = {
+                let task = Processor::current_task().unwrap();
+                let stack_fault = task.inner_exclusive_access().in_stack_guard(stval);
+                (task.pid(), stack_fault)
+            };
+            let zero_fault = scause.cause() == Trap::Exception(Exception::StorePageFault)
+                && task::fault_in_zero_page(stval);
+            if zero_fault || (stack_fault && task::grow_user_stack(stval)) {
+                // 已为零页分配私有的页帧或者扩展了用户栈，返回用户态重新执行引发缺页的指令
+            } else if stack_fault {
+                log::error!(
+                    "[kernel] User stack overflow in application, pid = {}, sp = {:#x}, fault va = {:#x}, core dumped.",
+                    pid,
+                    Processor::current_trap_ctx().x[2],
+                    stval
+                );
+                dump_user_fault(scause.cause(), stval);
+                task::exit_current_and_run_next(-4);
+            } else {
+                log::error!("[kernel] PageFault in application, core dumped.");
+                dump_user_fault(scause.cause(), stval);
+                task::exit_current_and_run_next(-2);
+            }
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            // 单步执行插入的断点不推进 sepc，继续运行时执行的是恢复后的原指令
+            if !task::ptrace::handle_breakpoint(Processor::current_trap_ctx().sepc) {
+                log::error!("[kernel] Breakpoint in application, core dumped.");
+                dump_user_fault(scause.cause(), stval);
+                task::exit_current_and_run_next(-5);
+            }
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            log::error!("[kernel] IllegalInstruction in application, core dumped.");
-            task::exit_current_and_run_next(-3);
+            // 第一次使用浮点指令时打开浮点单元，返回用户态重新执行这条指令
+            if !task::fp::handle_fp_disabled() {
+                log::error!("[kernel] IllegalInstruction in application, core dumped.");
+                dump_user_fault(scause.cause(), stval);
+                task::exit_current_and_run_next(-3);
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            if ipi::handle() {
+                task::suspend_current_and_run_next();
+            }
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            plic::handle_interrupts();
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            timer::set_next_trigger();
-            task::suspend_current_and_run_next();
+            task::check_cpu_limit();
+            fs::stdio::poll_console();
+            net::poll();
+            if timer::handle_expired() {
+                task::suspend_current_and_run_next();
+            }
         }
         _ => {
             panic!(
@@ -55,13 +143,78 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    // 返回用户态之前处理待处理的信号。暂停的进程可能因致命信号而恢复，所以先处理 SIGTSTP
+    // 和跟踪暂停
+    task::handle_stop_signal();
+    task::ptrace::handle_trace_stop();
+    if let Some((exit_code, msg)) = task::current_signal_error() {
+        log::error!("[kernel] {}", msg);
+        task::exit_current_and_run_next(exit_code);
+    }
     trap_return()
 }
 
+/// 回溯用户栈时最多展开的栈帧数
+const USER_BACKTRACE_DEPTH: usize = 16;
+
+/// 打印出错的用户程序的现场：trap 原因、sepc、stval、所有通用寄存器、缺页地址所在的逻辑段，
+/// 以及基于帧指针的调用栈回溯
+fn dump_user_fault(cause: Trap, stval: usize) {
+    let ctx = Processor::current_trap_ctx();
+    log::error!("[kernel] {:?}, stval = {:#x}", cause, stval);
+    log::error!("{:?}", ctx);
+    if let Trap::Exception(
+        Exception::StoreFault | Exception::StorePageFault | Exception::LoadPageFault,
+    ) = cause
+    {
+        let area = Processor::current_task().and_then(|task| {
+            task.inner_exclusive_access()
+                .memory_set
+                .area_containing(VirtAddr(stval))
+        });
+        match area {
+            Some(area) => log::error!("[kernel] fault address is in {:?}", area),
+            None => log::error!("[kernel] fault address is not mapped"),
+        }
+    }
+    user_backtrace(Processor::current_user_satp(), ctx.sepc, ctx.x[8]);
+}
+
+/// 沿帧指针链回溯用户栈。
+///
+/// 依赖用户程序保留帧指针：`fp - 8` 处为返回地址，`fp - 16` 处为上一帧的 fp。
+/// 遇到未映射或用户不可访问的地址时停止。
+fn user_backtrace(satp: usize, pc: usize, mut fp: usize) {
+    log::error!("[kernel] user backtrace:");
+    log::error!("  #0 pc = {:#x}", pc);
+    with_user_access(satp, |user| {
+        for depth in 1..USER_BACKTRACE_DEPTH {
+            if fp == 0 || fp % core::mem::size_of::<usize>() != 0 {
+                break;
+            }
+            let read_user = |va: usize| user.read(va as *const usize);
+            let (ra, prev_fp) = match (read_user(fp - 8), read_user(fp - 16)) {
+                (Some(ra), Some(prev_fp)) => (ra, prev_fp),
+                _ => break,
+            };
+            if ra == 0 {
+                break;
+            }
+            log::error!("  #{} ra = {:#x}, fp = {:#x}", depth, ra, fp);
+            // 栈向低地址增长，上一帧的 fp 必然更高，否则帧链已损坏
+            if prev_fp <= fp {
+                break;
+            }
+            fp = prev_fp;
+        }
+    });
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
     log::trace!("trap return");
-    set_user_trap_entry();
+    debug_assert!(!user::in_user_access(), "user access window leaked");
+    task::fp::prepare_return();
     let trap_ctx_ptr = TRAP_CONTEXT;
     let user_satp = Processor::current_user_satp();
     extern "C" {
@@ -87,15 +240,27 @@ pub fn enable_timer_interrupt() {
     unsafe { sie::set_stimer() }
 }
 
-#[no_mangle]
-pub fn trap_from_kernel() -> ! {
-    panic!("a trap from kernel!");
-}
-
-fn set_kernel_trap_entry() {
-    unsafe { stvec::write(trap_from_kernel as usize, TrapMode::Direct) }
-}
-
-fn set_user_trap_entry() {
-    unsafe { stvec::write(TRAMPOLINE, TrapMode::Direct) }
+/// 处理来自内核态的 trap，返回后 `trap.S` 按 `ctx` 恢复现场。
+///
+/// 内核态只在系统调用期间响应时钟中断，见 [`preempt`]。处理期间 `stvec` 不变，
+/// 嵌套的 trap 同样保存在内核栈上
+fn kernel_trap(ctx: &mut TrapContext) {
+    let scause = scause::read();
+    let stval = stval::read();
+    match scause.cause() {
+        // 用户内存访问窗口中的缺页只可能来自用户给出的地址。不会回到出错的位置，由这里关闭窗口
+        Trap::Exception(Exception::LoadPageFault | Exception::StorePageFault)
+            if user::in_user_access() =>
+        {
+            unsafe { sstatus::clear_sum() };
+            oops!("page fault at {:#x} while accessing user memory", stval);
+        }
+        Trap::Interrupt(Interrupt::SupervisorTimer) => preempt::timer_interrupt(ctx.sepc),
+        _ => panic!(
+            "a trap from kernel! {:?}, stval = {:#x}\n{:?}",
+            scause.cause(),
+            stval,
+            ctx
+        ),
+    }
 }
@@ -4,10 +4,11 @@
 #[macro_use]
 extern crate user_lib;
 
+use user_lib::errno::{EEXIST, EINVAL};
 use user_lib::mmap;
 
 /*
-理想结果：对于错误的 mmap 返回 -1，最终输出 Test 04_4 test OK!
+理想结果：对于错误的 mmap 返回负的错误码，最终输出 Test 04_4 test OK!
 */
 
 #[no_mangle]
@@ -16,10 +17,10 @@ fn main() -> i32 {
     let len: usize = 4096;
     let prot: usize = 3;
     assert_eq!(0, mmap(start, len, prot));
-    assert_eq!(mmap(start - len, len + 1, prot), -1);
-    assert_eq!(mmap(start + len + 1, len, prot), -1);
-    assert_eq!(mmap(start + len, len, 0), -1);
-    assert_eq!(mmap(start + len, len, prot | 8), -1);
+    assert_eq!(mmap(start - len, len + 1, prot), -EEXIST);
+    assert_eq!(mmap(start + len + 1, len, prot), -EINVAL);
+    assert_eq!(mmap(start + len, len, 0), -EINVAL);
+    assert_eq!(mmap(start + len, len, prot | 8), -EINVAL);
     println!("Test 04_4 test OK!");
     0
 }
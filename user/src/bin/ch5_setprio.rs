@@ -3,6 +3,7 @@
 
 #[macro_use]
 extern crate user_lib;
+use user_lib::errno::EINVAL;
 use user_lib::set_priority;
 
 /// 正确输出：（无报错信息）
@@ -12,9 +13,9 @@ use user_lib::set_priority;
 pub fn main() -> i32 {
     assert_eq!(set_priority(10), 10);
     assert_eq!(set_priority(isize::MAX), isize::MAX);
-    assert_eq!(set_priority(0), -1);
-    assert_eq!(set_priority(1), -1);
-    assert_eq!(set_priority(-10), -1);
+    assert_eq!(set_priority(0), -EINVAL);
+    assert_eq!(set_priority(1), -EINVAL);
+    assert_eq!(set_priority(-10), -EINVAL);
     println!("Test set_priority OK!");
     0
 }
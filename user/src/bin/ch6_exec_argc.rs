@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, fork, waitpid};
+
+/*
+测试 exec 后 a0 中的 argc 与 argv 一致。
+不带参数运行时 fork 出子进程，以三个参数 exec 自己，子进程检查收到的参数
+*/
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc > 1 {
+        assert_eq!(argc, 3);
+        assert_eq!(argv, ["ch6_exec_argc", "first", "second"]);
+        return 0;
+    }
+    let pid = fork();
+    if pid == 0 {
+        exec(
+            "ch6_exec_argc\0",
+            &[
+                "ch6_exec_argc\0".as_ptr(),
+                "first\0".as_ptr(),
+                "second\0".as_ptr(),
+                core::ptr::null::<u8>(),
+            ],
+        );
+        panic!("exec failed");
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("Test exec argc OK!");
+    0
+}
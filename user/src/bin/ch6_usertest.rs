@@ -27,6 +27,7 @@ static TESTS: &[&str] = &[
     "ch6_file1\0",
     "ch6_file2\0",
     "ch6_file3\0",
+    "ch6_exec_argc\0",
 ];
 
 use user_lib::{spawn, waitpid};
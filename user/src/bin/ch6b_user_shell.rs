@@ -12,12 +12,42 @@ const DL: u8 = 0x7fu8;
 const BS: u8 = 0x08u8;
 
 use alloc::string::String;
+use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{exec, flush, fork, waitpid};
+use user_lib::{
+    exec, flush, fork, getpid, kill, setpgid, tcsetpgrp, waitpid_untraced, JOB_STOP_STATUS,
+    SIGCONT, STDIN,
+};
+
+/// 让作业 `pid` 在前台运行，直到它退出或者被 Ctrl-Z 暂停，之后 shell 收回控制台。
+/// `resume` 时先向作业发送 SIGCONT。作业暂停时返回 true
+fn run_foreground(pid: usize, shell_pgid: usize, resume: bool) -> bool {
+    tcsetpgrp(STDIN, pid);
+    if resume {
+        kill(-(pid as isize), SIGCONT);
+    }
+    let mut exit_code: i32 = 0;
+    let exit_pid = waitpid_untraced(pid, &mut exit_code);
+    tcsetpgrp(STDIN, shell_pgid);
+    assert_eq!(pid as isize, exit_pid);
+    if exit_code == JOB_STOP_STATUS {
+        println!("Shell: Process {} stopped, type fg to continue", pid);
+        true
+    } else {
+        println!("Shell: Process {} exited with code {}", pid, exit_code);
+        false
+    }
+}
 
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
+    // shell 自成一个进程组并占据控制台，Ctrl-C 和 Ctrl-Z 不会发给 shell 自己
+    let shell_pgid = getpid() as usize;
+    setpgid(0, 0);
+    tcsetpgrp(STDIN, shell_pgid);
+    // 被 Ctrl-Z 暂停的作业，`fg` 让最近暂停的一个继续运行
+    let mut stopped: Vec<usize> = Vec::new();
     let mut line: String = String::new();
     print!(">> ");
     flush();
@@ -26,21 +56,34 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 print!("\n");
-                if !line.is_empty() {
+                if line == "fg" {
+                    match stopped.pop() {
+                        Some(pid) => {
+                            if run_foreground(pid, shell_pgid, true) {
+                                stopped.push(pid);
+                            }
+                        }
+                        None => println!("Shell: no stopped job"),
+                    }
+                    line.clear();
+                } else if !line.is_empty() {
                     line.push('\0');
                     let pid = fork();
                     if pid == 0 {
                         // child process
-                        if exec(line.as_str(), &[0 as *const u8]) == -1 {
+                        // 每个命令自成一个进程组，运行期间作为前台进程组接收 Ctrl-C 和 Ctrl-Z
+                        setpgid(0, 0);
+                        if exec(line.as_str(), &[0 as *const u8]) < 0 {
                             println!("Error when executing!");
                             return -4;
                         }
                         unreachable!();
                     } else {
-                        let mut exit_code: i32 = 0;
-                        let exit_pid = waitpid(pid as usize, &mut exit_code);
-                        assert_eq!(pid, exit_pid);
-                        println!("Shell: Process {} exited with code {}", pid, exit_code);
+                        // 父子进程都设置一次，无论谁先运行，等待之前子进程都已在自己的进程组中
+                        setpgid(pid as usize, pid as usize);
+                        if run_foreground(pid as usize, shell_pgid, false) {
+                            stopped.push(pid as usize);
+                        }
                     }
                     line.clear();
                 }
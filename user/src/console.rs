@@ -8,7 +8,7 @@ pub const STDOUT: usize = 1;
 
 const CONSOLE_BUFFER_SIZE: usize = 256 * 10;
 
-use super::{read, write};
+use super::{errno, read, write};
 use lazy_static::*;
 
 struct ConsoleBuffer(VecDeque<u8>);
@@ -65,7 +65,8 @@ macro_rules! println {
 
 pub fn getchar() -> u8 {
     let mut c = [0u8; 1];
-    read(STDIN, &mut c);
+    // 等待输入时被信号打断，信号处理完后重新读取
+    while read(STDIN, &mut c) == -errno::EINTR {}
     c[0]
 }
 
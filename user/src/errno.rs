@@ -0,0 +1,20 @@
+//! Error numbers returned (negated) by the os6 kernel, same as Linux
+
+pub const EPERM: isize = 1;
+pub const ENOENT: isize = 2;
+pub const ESRCH: isize = 3;
+pub const EINTR: isize = 4;
+pub const ENOEXEC: isize = 8;
+pub const EBADF: isize = 9;
+pub const ECHILD: isize = 10;
+pub const EAGAIN: isize = 11;
+pub const ENOMEM: isize = 12;
+pub const EACCES: isize = 13;
+pub const EFAULT: isize = 14;
+pub const EEXIST: isize = 17;
+pub const ENOTDIR: isize = 20;
+pub const EINVAL: isize = 22;
+pub const EMFILE: isize = 24;
+pub const ESPIPE: isize = 29;
+pub const ENAMETOOLONG: isize = 36;
+pub const ENOSYS: isize = 38;
@@ -5,6 +5,7 @@
 
 #[macro_use]
 pub mod console;
+pub mod errno;
 mod lang_items;
 mod syscall;
 
@@ -171,6 +172,8 @@ bitflags! {
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// bits holding the file type; the rest are permission bits
+        const TYPE_MASK = 0o170000;
     }
 }
 
@@ -248,23 +251,42 @@ pub fn set_priority(prio: isize) -> isize {
     sys_set_priority(prio)
 }
 
-pub fn wait(exit_code: &mut i32) -> isize {
-    loop {
-        match sys_waitpid(-1, exit_code as *mut _) {
-            -2 => {
-                sys_yield();
-            }
-            n => {
-                return n;
-            }
-        }
+/// 把进程 `pid` 移入进程组 `pgid`，`pid` 为 0 表示当前进程，`pgid` 为 0 表示以 `pid` 为组长新建进程组
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+
+pub fn getpgid(pid: usize) -> isize {
+    sys_getpgid(pid)
+}
+
+/// 控制台 `fd` 的前台进程组
+pub fn tcgetpgrp(fd: usize) -> isize {
+    let mut pgid: i32 = 0;
+    match sys_ioctl(fd, TIOCGPGRP, &mut pgid as *mut _ as usize) {
+        0 => pgid as isize,
+        err => err,
     }
 }
 
-pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
+/// 把进程组 `pgid` 设为控制台 `fd` 的前台进程组，之后 Ctrl-C 和 Ctrl-Z 只发给这个进程组
+pub fn tcsetpgrp(fd: usize, pgid: usize) -> isize {
+    let pgid = pgid as i32;
+    sys_ioctl(fd, TIOCSPGRP, &pgid as *const _ as usize)
+}
+
+/// `waitpid` 的选项：子进程因 SIGTSTP 暂停时也返回，此时写入的状态为 [`JOB_STOP_STATUS`]
+pub const WUNTRACED: usize = 2;
+/// 子进程因 SIGTSTP 暂停时 os6 的 `waitpid` 写入的状态
+pub const JOB_STOP_STATUS: i32 = 0x147f;
+
+pub const SIGCONT: usize = 18;
+
+fn wait_options(pid: isize, exit_code: &mut i32, options: usize) -> isize {
     loop {
-        match sys_waitpid(pid as isize, exit_code as *mut _) {
-            -2 => {
+        match sys_waitpid(pid, exit_code as *mut _, options) {
+            // 其它章的内核在子进程都还没有结束时返回 -2，os6 在等待时被信号打断时返回 -EINTR
+            n if n == -2 || n == -errno::EINTR => {
                 sys_yield();
             }
             n => {
@@ -274,6 +296,24 @@ pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
     }
 }
 
+pub fn wait(exit_code: &mut i32) -> isize {
+    wait_options(-1, exit_code, 0)
+}
+
+pub fn waitpid(pid: usize, exit_code: &mut i32) -> isize {
+    wait_options(pid as isize, exit_code, 0)
+}
+
+/// 与 [`waitpid`] 相同，但子进程暂停时也返回，此时 `exit_code` 为 [`JOB_STOP_STATUS`]
+pub fn waitpid_untraced(pid: usize, exit_code: &mut i32) -> isize {
+    wait_options(pid as isize, exit_code, WUNTRACED)
+}
+
+/// 向进程 `pid` 发送信号 `sig`，`pid` 为负时发给进程组 `-pid`
+pub fn kill(pid: isize, sig: usize) -> isize {
+    sys_kill(pid, sig)
+}
+
 pub fn sleep_blocking(sleep_ms: usize) {
     sys_sleep(sleep_ms);
 }